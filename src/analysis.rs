@@ -0,0 +1,238 @@
+//! Reports about the selected notes that don't change the output.
+
+use crate::error::PianoRollError;
+use crate::formatting;
+use crate::midi::{note_durations, Midi, NoteAction, NoteEvent, NoteWithDuration, OverlapPolicy,
+    PedalEvent};
+use crate::note::{MidiNote, PITCH_CLASS_NAMES};
+use crate::tempo::{BeatGrid, TempoMap};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Octaves -1 through 9, in scientific pitch notation.
+const OCTAVES: usize = 11;
+
+/// The whole song, with nothing selected, for deciding what to select from it.
+pub struct SongSummary {
+    /// The lowest and highest note on each track and channel that has any.
+    pub ranges: BTreeMap<(usize, u8), (MidiNote, MidiNote)>,
+    /// When the last note ends, in ticks.
+    pub end: u64,
+}
+
+impl SongSummary {
+    /// Pair up every note in the file, as for a roll that selected all of them.
+    pub fn new(midi: &Midi, time_base: u16, pedal: &[PedalEvent], overlap: OverlapPolicy)
+        -> Self
+    {
+        let (notes, _) = note_durations(midi.notes(), pedal, time_base, overlap, |_| Some((0, 0)));
+        let mut ranges = BTreeMap::<_, (MidiNote, MidiNote)>::new();
+        for note in &notes {
+            ranges.entry((note.track, note.channel))
+                .and_modify(|(low, high)| {
+                    *low = (*low).min(note.note);
+                    *high = (*high).max(note.note);
+                })
+                .or_insert((note.note, note.note));
+        }
+        let end = notes.iter().map(|note| note.timestamp + note.duration).max().unwrap_or(0);
+        Self { ranges, end }
+    }
+}
+
+/// How often each note is used, by pitch class and octave.
+pub struct PitchUsage {
+    counts: [[u64; OCTAVES]; 12],
+    seconds: [[f64; OCTAVES]; 12],
+}
+
+impl PitchUsage {
+    pub fn new(notes: &[NoteWithDuration], tempo_map: &TempoMap) -> Self {
+        let mut usage = Self {
+            counts: [[0; OCTAVES]; 12],
+            seconds: [[0.; OCTAVES]; 12],
+        };
+        for note in notes {
+            let pitch_class = usize::from(note.note.pitch_class());
+            let octave = (note.note.octave() + 1) as usize;
+            let micros = tempo_map.micros_at(note.timestamp + note.duration)
+                - tempo_map.micros_at(note.timestamp);
+            usage.counts[pitch_class][octave] += 1;
+            usage.seconds[pitch_class][octave] += micros / 1_000_000.;
+        }
+        usage
+    }
+
+    /// The octaves that have any notes in them, as indexes into the tables.
+    fn used_octaves(&self) -> Vec<usize> {
+        (0 .. OCTAVES)
+            .filter(|&octave| self.counts.iter().any(|row| row[octave] > 0))
+            .collect()
+    }
+
+    /// A table with a row for each pitch class and a column for each octave used, giving the
+    /// number of notes and how many seconds they sound for in total.
+    pub fn table(&self) -> String {
+        const WIDTH: usize = 18;
+        let octaves = self.used_octaves();
+        let mut out = String::new();
+        write!(out, "{:<4}", "").unwrap();
+        for &octave in &octaves {
+            write!(out, "{:>WIDTH$}", format!("octave {}", octave as i8 - 1)).unwrap();
+        }
+        writeln!(out).unwrap();
+        for (pitch_class, name) in PITCH_CLASS_NAMES.iter().enumerate() {
+            write!(out, "{:<4}", name).unwrap();
+            for &octave in &octaves {
+                let count = self.counts[pitch_class][octave];
+                let cell = if count == 0 {
+                    "-".to_owned()
+                } else {
+                    format!("{} ({})", formatting::count(count),
+                        formatting::duration(self.seconds[pitch_class][octave]))
+                };
+                write!(out, "{:>WIDTH$}", cell).unwrap();
+            }
+            writeln!(out).unwrap();
+        }
+        out
+    }
+
+    /// Write the non-empty cells of the table as CSV.
+    pub fn write_csv(&self, path: &Path) -> Result<(), PianoRollError> {
+        let mut csv = String::from("pitch_class,octave,note_name,count,seconds\n");
+        for (pitch_class, name) in PITCH_CLASS_NAMES.iter().enumerate() {
+            for octave in 0 .. OCTAVES {
+                let count = self.counts[pitch_class][octave];
+                if count > 0 {
+                    writeln!(csv, "{},{},{}{},{},{:.3}",
+                        pitch_class, octave as i8 - 1, name, octave as i8 - 1, count,
+                        self.seconds[pitch_class][octave]).unwrap();
+                }
+            }
+        }
+        std::fs::write(path, csv)
+            .map_err(|source| PianoRollError::Write { path: path.to_owned(), source })
+    }
+}
+
+/// Find measures of `beat_grid` whose notes are identical to an earlier measure's: the same
+/// pitches, starting at the same ticks within the measure and lasting as long. Notes belong to the
+/// measure they start in, and any before the first downbeat are left out. Returns `(earlier,
+/// later)` pairs of measure numbers, counting from 1, pairing each repeat with the first measure
+/// it matches. Empty measures aren't reported.
+pub fn find_repeated_measures(notes: &[NoteWithDuration], beat_grid: &BeatGrid)
+    -> Vec<(usize, usize)>
+{
+    let mut measures = BTreeMap::<usize, Vec<(u64, u64, MidiNote)>>::new();
+    for note in notes {
+        let Some(downbeat) = beat_grid.downbeat_at(note.timestamp) else {
+            continue;
+        };
+        measures.entry(downbeat.measure)
+            .or_default()
+            .push((note.timestamp - downbeat.timestamp, note.duration, note.note));
+    }
+
+    let mut first_seen = BTreeMap::<Vec<(u64, u64, MidiNote)>, usize>::new();
+    let mut repeats = vec![];
+    for (measure, mut contents) in measures {
+        contents.sort();
+        match first_seen.get(&contents) {
+            Some(&earlier) => repeats.push((earlier, measure)),
+            None => { first_seen.insert(contents, measure); }
+        }
+    }
+    repeats
+}
+
+/// A gap between consecutive notes in one roll channel.
+#[derive(Debug, Clone)]
+pub struct Rest {
+    pub note: MidiNote,
+    pub timestamp: u64,
+    pub duration: u64,
+}
+
+/// Find every gap of at least `min_ticks` between notes in the same channel. Only gaps between
+/// notes count, not the silence before a channel's first note or after its last.
+pub fn find_rests(notes: &[NoteWithDuration], min_ticks: u64) -> Vec<Rest> {
+    let mut by_note = BTreeMap::<MidiNote, Vec<&NoteWithDuration>>::new();
+    for note in notes {
+        by_note.entry(note.note).or_default().push(note);
+    }
+
+    let mut rests = vec![];
+    for (note, mut channel_notes) in by_note {
+        channel_notes.sort_by_key(|note| note.timestamp);
+        let mut sounding_until = None;
+        for channel_note in channel_notes {
+            if let Some(end) = sounding_until {
+                if channel_note.timestamp >= end + min_ticks {
+                    rests.push(Rest {
+                        note,
+                        timestamp: end,
+                        duration: channel_note.timestamp - end,
+                    });
+                }
+            }
+            let end = channel_note.timestamp + channel_note.duration;
+            sounding_until = Some(sounding_until.map_or(end, |until: u64| until.max(end)));
+        }
+    }
+    rests
+}
+
+/// Which of two channels carries the melody in each measure of `beat_grid`, for merging channels
+/// that take turns with it. Channels are given as (track, channel) pairs, and the result is an
+/// index into `channels` for each measure, from the first.
+///
+/// Where only one of them is playing, it's that one. Where both are, it's the one playing higher
+/// on average, or lower if `prefer_higher` is false. Where neither is, it stays with the last one.
+pub fn alternating_voice<'a>(
+    events: impl Iterator<Item = &'a NoteEvent>,
+    channels: [(usize, u8); 2],
+    beat_grid: &BeatGrid,
+    prefer_higher: bool,
+) -> Vec<usize> {
+    // Number of notes and total of their pitches, per measure, per channel.
+    let mut activity = Vec::<[(u64, u64); 2]>::new();
+    for event in events.filter(|event| event.action == NoteAction::On) {
+        let Some(voice) = channels.iter().position(|&c| c == (event.track, event.channel)) else {
+            continue;
+        };
+        let measure = beat_grid.downbeat_at(event.timestamp).map_or(0, |beat| beat.measure - 1);
+        if activity.len() <= measure {
+            activity.resize(measure + 1, [(0, 0); 2]);
+        }
+        activity[measure][voice].0 += 1;
+        activity[measure][voice].1 += u64::from(event.note.as_u8());
+    }
+
+    let mut choice = activity.iter()
+        .find_map(|measure| measure.iter().position(|&(count, _)| count > 0))
+        .unwrap_or(0);
+    activity.iter()
+        .map(|[(count_a, total_a), (count_b, total_b)]| {
+            choice = match (*count_a > 0, *count_b > 0) {
+                (true, false) => 0,
+                (false, true) => 1,
+                (false, false) => choice,
+                (true, true) => {
+                    // Compare averages without dividing: a/m > b/n exactly when a*n > b*m.
+                    let a = total_a * count_b;
+                    let b = total_b * count_a;
+                    if a == b {
+                        choice
+                    } else if (a > b) == prefer_higher {
+                        0
+                    } else {
+                        1
+                    }
+                }
+            };
+            choice
+        })
+        .collect()
+}
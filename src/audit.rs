@@ -0,0 +1,157 @@
+//! A record of what's done to each note on its way from the song to the roll, for checking that
+//! the roll is faithful to the song.
+//!
+//! Notes are followed by the event that pressed them (`NoteWithDuration::source`) and their pitch
+//! on the roll, which nothing changes once they're paired up. Each transform is run through
+//! `Audit::apply`, which compares the notes before and after it, so the transforms themselves
+//! don't need to know they're being watched.
+
+use crate::midi::{NoteEvent, NoteWithDuration};
+use crate::note::MidiNote;
+use std::collections::BTreeMap;
+
+/// Identifies a note throughout: the event that pressed it, and its pitch on the roll. One event
+/// can put a note on the roll more than once, but only at different pitches.
+pub type NoteKey = (usize, MidiNote);
+
+/// A note as it was read from the song.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Original {
+    pub timestamp: u64,
+    pub note: MidiNote,
+    pub velocity: u8,
+    pub track: usize,
+    pub channel: u8,
+}
+
+/// Where and how long a note is at some point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoteState {
+    pub timestamp: u64,
+    pub duration: u64,
+    pub note: MidiNote,
+}
+
+impl From<&NoteWithDuration> for NoteState {
+    fn from(note: &NoteWithDuration) -> Self {
+        Self { timestamp: note.timestamp, duration: note.duration, note: note.note }
+    }
+}
+
+/// One transform's change to a note. A note the transform removed, by merging it into another or
+/// dropping it, has nothing after.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub transform: String,
+    pub before: NoteState,
+    pub after: Option<NoteState>,
+}
+
+/// Everything that happened to one note.
+#[derive(Debug, Clone)]
+pub struct History {
+    pub original: Original,
+    pub steps: Vec<Step>,
+}
+
+/// The histories of the notes, or nothing if no report was asked for, so that following the notes
+/// costs nothing when it isn't needed.
+#[derive(Debug, Default)]
+pub struct Audit {
+    histories: Option<BTreeMap<NoteKey, History>>,
+}
+
+fn key(note: &NoteWithDuration) -> Option<NoteKey> {
+    note.source.map(|source| (source, note.note))
+}
+
+impl Audit {
+    /// An audit that records nothing.
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Start following the notes paired up from `events`, which must be the events given to
+    /// `note_durations`, in the same order. A note moved to another pitch gets an "offset" step.
+    pub fn new(events: &[&NoteEvent], notes: &[NoteWithDuration]) -> Self {
+        let mut histories = BTreeMap::new();
+        for note in notes {
+            let (Some(key), Some(event)) = (key(note), note.source.and_then(|i| events.get(i)))
+            else {
+                continue;
+            };
+            let mut steps = vec![];
+            if event.note != note.note {
+                steps.push(Step {
+                    transform: "offset".to_owned(),
+                    before: NoteState { note: event.note, ..NoteState::from(note) },
+                    after: Some(NoteState::from(note)),
+                });
+            }
+            histories.insert(key, History {
+                original: Original {
+                    timestamp: event.timestamp,
+                    note: event.note,
+                    velocity: event.velocity,
+                    track: event.track,
+                    channel: event.channel,
+                },
+                steps,
+            });
+        }
+        Self { histories: Some(histories) }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.histories.is_some()
+    }
+
+    /// Run a transform on the notes, and record what it changed.
+    pub fn apply<T>(
+        &mut self,
+        transform: &str,
+        notes: &mut Vec<NoteWithDuration>,
+        f: impl FnOnce(&mut Vec<NoteWithDuration>) -> T,
+    ) -> T {
+        let before = self.histories.as_ref().map(|_| notes.clone());
+        let result = f(notes);
+        if let Some(before) = before {
+            self.record(transform, &before, notes);
+        }
+        result
+    }
+
+    /// Record the changes between two versions of the notes, as the work of the named transform.
+    pub fn record(&mut self, transform: &str, before: &[NoteWithDuration],
+        after: &[NoteWithDuration])
+    {
+        let Some(ref mut histories) = self.histories else {
+            return;
+        };
+        let mut after_by_key = BTreeMap::new();
+        for note in after {
+            if let Some(key) = key(note) {
+                after_by_key.entry(key).or_insert_with(|| NoteState::from(note));
+            }
+        }
+        for note in before {
+            let Some(history) = key(note).and_then(|key| histories.get_mut(&key)) else {
+                continue;
+            };
+            let before = NoteState::from(note);
+            let after = after_by_key.get(&key(note).unwrap()).copied();
+            if after != Some(before) {
+                history.steps.push(Step { transform: transform.to_owned(), before, after });
+            }
+        }
+    }
+
+    pub fn history(&self, key: NoteKey) -> Option<&History> {
+        self.histories.as_ref()?.get(&key)
+    }
+
+    /// Every note followed, in order of the events that pressed them.
+    pub fn histories(&self) -> impl Iterator<Item = (&NoteKey, &History)> {
+        self.histories.iter().flatten()
+    }
+}
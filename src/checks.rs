@@ -0,0 +1,379 @@
+//! Checks on the finished roll, in categories that can each be made an error, a warning, or
+//! turned off. A build can then fail on problems that would ruin the punched roll, and only report
+//! the ones that are a matter of taste.
+//!
+//! Each category that fails as an error sets its own bit in the exit status, so scripts can tell
+//! what went wrong without reading the output.
+
+use crate::geometry::POINTS_PER_INCH;
+use crate::midi::NoteWithDuration;
+use crate::tempo::TempoMap;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Category {
+    /// Holes too short to punch, or too close together in the same channel.
+    Geometry,
+    /// Notes pressed again before being released, released without being pressed, or never
+    /// released.
+    Overlap,
+    /// Notes outside the range of the roll.
+    Range,
+    /// More notes sounding at once than is musically sensible.
+    Polyphony,
+    /// More holes open over the tracker bar at once than the pump can keep up with.
+    Vacuum,
+    /// Long silences in the middle of the song.
+    DeadAir,
+}
+
+impl Category {
+    pub const ALL: [Category; 6] = [
+        Category::Geometry,
+        Category::Overlap,
+        Category::Range,
+        Category::Polyphony,
+        Category::Vacuum,
+        Category::DeadAir,
+    ];
+
+    /// The same name as used on the command line, in the project file, and in the JSON output.
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::Geometry => "geometry",
+            Category::Overlap => "overlap",
+            Category::Range => "range",
+            Category::Polyphony => "polyphony",
+            Category::Vacuum => "vacuum",
+            Category::DeadAir => "dead-air",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        Self::ALL.iter()
+            .copied()
+            .find(|category| category.name() == name)
+            .ok_or_else(|| format!("unknown check category {:?}; expected one of {}",
+                name, Self::ALL.map(Category::name).join(", ")))
+    }
+
+    /// The bit set in the exit status when a check in this category fails as an error. The
+    /// lowest bit is left for every other kind of failure.
+    pub fn exit_bit(self) -> i32 {
+        2 << self as i32
+    }
+}
+
+/// What to do when a check fails.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warning,
+    Off,
+}
+
+impl Level {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "error" => Ok(Level::Error),
+            "warning" => Ok(Level::Warning),
+            "off" => Ok(Level::Off),
+            _ => Err(format!("check level must be \"error\", \"warning\", or \"off\", not {:?}",
+                name)),
+        }
+    }
+
+    fn prefix(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warning | Level::Off => "WARNING",
+        }
+    }
+}
+
+/// The level of each category. Every category is a warning unless made something else, so that
+/// nothing fails that didn't before.
+#[derive(Debug, Clone, Default)]
+pub struct CheckPolicy {
+    levels: BTreeMap<Category, Level>,
+}
+
+impl CheckPolicy {
+    pub fn level(&self, category: Category) -> Level {
+        self.levels.get(&category).copied().unwrap_or(Level::Warning)
+    }
+
+    pub fn set(&mut self, category: Category, level: Level) {
+        self.levels.insert(category, level);
+    }
+
+    /// Parse a "category=level" setting, as given to `--check`.
+    pub fn parse_setting(setting: &str) -> Result<(Category, Level), String> {
+        let (category, level) = setting.split_once('=')
+            .ok_or_else(|| format!("expected \"<category>=<level>\", not {:?}", setting))?;
+        Ok((Category::from_name(category)?, Level::from_name(level)?))
+    }
+}
+
+/// Limits the checks hold the roll to.
+#[derive(Debug, Clone, Copy)]
+pub struct Thresholds {
+    /// Shortest hole that can be punched, in points.
+    pub min_hole: f64,
+    /// Narrowest strip of paper that can be left between two holes in the same channel, in
+    /// points.
+    pub min_bridge: f64,
+    /// Most notes that should sound at once.
+    pub max_polyphony: usize,
+    /// Most holes that can be open over the tracker bar at once.
+    pub max_open_holes: usize,
+    /// Longest silence allowed between the first note and the last, in seconds.
+    pub max_dead_air: f64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            min_hole: 2.,
+            min_bridge: 2.,
+            max_polyphony: 10,
+            max_open_holes: 16,
+            max_dead_air: 5.,
+        }
+    }
+}
+
+/// Thresholds that were set in one place, to be laid over the ones from another. Lengths are in
+/// inches, as the user gives them.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ThresholdOverrides {
+    pub min_hole: Option<f64>,
+    pub min_bridge: Option<f64>,
+    pub max_polyphony: Option<usize>,
+    pub max_open_holes: Option<usize>,
+    pub max_dead_air: Option<f64>,
+}
+
+impl ThresholdOverrides {
+    pub fn apply(&self, thresholds: &mut Thresholds) {
+        let points_per_inch = f64::from(POINTS_PER_INCH);
+        if let Some(inches) = self.min_hole {
+            thresholds.min_hole = inches * points_per_inch;
+        }
+        if let Some(inches) = self.min_bridge {
+            thresholds.min_bridge = inches * points_per_inch;
+        }
+        if let Some(notes) = self.max_polyphony {
+            thresholds.max_polyphony = notes;
+        }
+        if let Some(holes) = self.max_open_holes {
+            thresholds.max_open_holes = holes;
+        }
+        if let Some(seconds) = self.max_dead_air {
+            thresholds.max_dead_air = seconds;
+        }
+    }
+}
+
+/// A hole on the roll: its channel, and where it starts and ends, in points.
+pub type HoleSpan = (u8, f64, f64);
+
+fn inches(points: f64) -> f64 {
+    points / f64::from(POINTS_PER_INCH)
+}
+
+/// Make sure two sets of holes are the same, whatever order they're in: on the same channels, and
+/// starting and ending within `tolerance` points of each other.
+pub fn compare_holes(expected: &[HoleSpan], actual: &[HoleSpan], tolerance: f64)
+    -> Result<(), String>
+{
+    let sorted = |holes: &[HoleSpan]| {
+        let mut holes = holes.to_vec();
+        holes.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)).then(a.2.total_cmp(&b.2)));
+        holes
+    };
+    let expected = sorted(expected);
+    let actual = sorted(actual);
+    if expected.len() != actual.len() {
+        return Err(format!("expected {} holes but found {}", expected.len(), actual.len()));
+    }
+    for (want, got) in expected.iter().zip(&actual) {
+        if want.0 != got.0 || (want.1 - got.1).abs() > tolerance
+            || (want.2 - got.2).abs() > tolerance
+        {
+            return Err(format!("expected a hole in channel {} from {:.3} in to {:.3} in, \
+                but found one in channel {} from {:.3} in to {:.3} in",
+                want.0, inches(want.1), inches(want.2), got.0, inches(got.1), inches(got.2)));
+        }
+    }
+    Ok(())
+}
+
+/// Holes too short to punch, and holes too close to the one before them in the same channel.
+pub fn geometry(holes: &[HoleSpan], thresholds: &Thresholds) -> Vec<String> {
+    let mut sorted = holes.to_vec();
+    sorted.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)));
+    let mut problems = vec![];
+    for (i, &(channel, start, end)) in sorted.iter().enumerate() {
+        if end - start < thresholds.min_hole {
+            problems.push(format!("hole in channel {} at {:.2} in is only {:.3} in long",
+                channel, inches(start), inches(end - start)));
+        }
+        if let Some(&(prev_channel, _, prev_end)) = i.checked_sub(1).map(|i| &sorted[i]) {
+            if prev_channel == channel && start - prev_end < thresholds.min_bridge {
+                problems.push(format!("hole in channel {} at {:.2} in leaves only {:.3} in of \
+                    paper after the one before it",
+                    channel, inches(start), inches((start - prev_end).max(0.))));
+            }
+        }
+    }
+    problems
+}
+
+/// Each stretch where more than `max` things overlap, given where each starts (+1) and ends (-1),
+/// with where the stretch starts and the most there are at once during it.
+fn crowded_spans(mut events: Vec<(f64, i32)>, max: usize) -> Vec<(f64, usize)> {
+    // Ends sort before starts at the same place, so notes that only touch aren't counted as
+    // sounding together.
+    events.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    let mut spans: Vec<(f64, usize)> = vec![];
+    let mut sounding = 0usize;
+    let mut in_span = false;
+    for (at, change) in events {
+        if change > 0 {
+            sounding += 1;
+        } else {
+            sounding -= 1;
+        }
+        if sounding > max {
+            if in_span {
+                let peak = &mut spans.last_mut().unwrap().1;
+                *peak = (*peak).max(sounding);
+            } else {
+                spans.push((at, sounding));
+                in_span = true;
+            }
+        } else {
+            in_span = false;
+        }
+    }
+    spans
+}
+
+/// Places where more notes sound at once than `max_polyphony`.
+pub fn polyphony(notes: &[NoteWithDuration], thresholds: &Thresholds) -> Vec<String> {
+    let events = notes.iter()
+        .flat_map(|note| [
+            (note.timestamp as f64, 1),
+            ((note.timestamp + note.duration) as f64, -1),
+        ])
+        .collect();
+    crowded_spans(events, thresholds.max_polyphony).into_iter()
+        .map(|(tick, peak)| format!("at tick {}, {} notes sound at once (limit {})",
+            tick, peak, thresholds.max_polyphony))
+        .collect()
+}
+
+/// Places where more holes are open over the tracker bar at once than `max_open_holes`. Unlike
+/// `polyphony`, this goes by the holes themselves, including any label, as they pass the bar.
+pub fn vacuum(holes: &[HoleSpan], thresholds: &Thresholds) -> Vec<String> {
+    let events = holes.iter()
+        .flat_map(|&(_, start, end)| [(start, 1), (end, -1)])
+        .collect();
+    crowded_spans(events, thresholds.max_open_holes).into_iter()
+        .map(|(at, peak)| format!("at {:.2} in, {} holes are open at once (limit {})",
+            inches(at), peak, thresholds.max_open_holes))
+        .collect()
+}
+
+/// Silences between the first note and the last that are longer than `max_dead_air`.
+pub fn dead_air(notes: &[NoteWithDuration], tempo_map: &TempoMap, thresholds: &Thresholds)
+    -> Vec<String>
+{
+    let mut sorted = notes.iter().collect::<Vec<_>>();
+    sorted.sort_by_key(|note| note.timestamp);
+    let mut problems = vec![];
+    let mut sounding_until = None;
+    for note in sorted {
+        if let Some(until) = sounding_until {
+            if note.timestamp > until {
+                let seconds = (tempo_map.micros_at(note.timestamp) - tempo_map.micros_at(until))
+                    / 1_000_000.;
+                if seconds > thresholds.max_dead_air {
+                    problems.push(format!("{:.1} seconds of silence from tick {} to {}",
+                        seconds, until, note.timestamp));
+                }
+            }
+        }
+        let end = note.timestamp + note.duration;
+        sounding_until = Some(sounding_until.map_or(end, |until: u64| until.max(end)));
+    }
+    problems
+}
+
+/// A check that failed, at the level its category was set to.
+#[derive(Debug, Clone, Serialize)]
+pub struct Failure {
+    pub category: Category,
+    pub level: Level,
+    pub message: String,
+}
+
+/// Everything the checks found, sorted out by the policy.
+#[derive(Debug, Default)]
+pub struct Report {
+    policy: CheckPolicy,
+    failures: Vec<Failure>,
+}
+
+impl Report {
+    pub fn new(policy: CheckPolicy) -> Self {
+        Self { policy, failures: vec![] }
+    }
+
+    /// Record the problems found by the checks in a category, unless it's turned off.
+    pub fn add(&mut self, category: Category, problems: Vec<String>) {
+        let level = self.policy.level(category);
+        if level == Level::Off {
+            return;
+        }
+        self.failures.extend(problems.into_iter()
+            .map(|message| Failure { category, level, message }));
+    }
+
+    pub fn failures(&self) -> &[Failure] {
+        &self.failures
+    }
+
+    /// Lines to print for the failures: up to `max_repeats` of each category, and then a count of
+    /// the rest.
+    pub fn lines(&self, max_repeats: Option<usize>) -> Vec<String> {
+        let mut lines = vec![];
+        for category in Category::ALL {
+            let failures = self.failures.iter()
+                .filter(|failure| failure.category == category)
+                .collect::<Vec<_>>();
+            let shown = max_repeats.unwrap_or(failures.len()).min(failures.len());
+            for failure in &failures[.. shown] {
+                lines.push(format!("{} [{}]: {}",
+                    failure.level.prefix(), category.name(), failure.message));
+            }
+            if let Some(first) = failures.get(shown) {
+                lines.push(format!("{} [{}]: {} more like this",
+                    first.level.prefix(), category.name(), failures.len() - shown));
+            }
+        }
+        lines
+    }
+
+    /// The exit status for the failures: the bits of every category that failed as an error.
+    pub fn exit_code(&self) -> i32 {
+        self.failures.iter()
+            .filter(|failure| failure.level == Level::Error)
+            .fold(0, |code, failure| code | failure.category.exit_bit())
+    }
+}
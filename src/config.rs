@@ -1,39 +1,1275 @@
+use crate::checks::{CheckPolicy, ThresholdOverrides, Thresholds};
+use crate::error::PianoRollError;
+use crate::geometry::{PaperSize, POINTS_PER_INCH};
+use crate::label::Label;
+use crate::midi::{Backend, Midi, OverlapPolicy};
+use crate::note::MidiNote;
+use crate::program::MIDI_PROGRAM;
+use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::ffi::{OsStr, OsString};
-use std::path::PathBuf;
+use std::fmt;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Configuration {
     pub input: PathBuf,
     pub output: PathBuf,
     pub selectors: Vec<ChannelSelector>,
-    pub time_divisor: f32,
+    pub time_divisor: f64,
+    /// Length to make the roll, in points, by working out the time divisor from the song instead
+    /// of being given one.
+    pub fit_length: Option<f64>,
+    pub strip_silence_start: bool,
+    pub apply_smpte_offset: bool,
+    pub preview_tempo: PreviewTempo,
+    pub output_format: OutputFormat,
+    /// Which parser to read the MIDI file with.
+    pub backend: Backend,
+    /// Another MIDI file to take the tempo changes and time signatures from.
+    pub conductor: Option<PathBuf>,
+    /// Convert the conductor file's ticks to the song's, if their time bases differ.
+    pub rescale_ticks: bool,
+    pub sustain_pedal: bool,
+    pub real_time_spacing: bool,
+    /// Two (track, channel) pairs to merge into one voice, taking whichever has the melody.
+    pub merge_alternating: Option<[(usize, u8); 2]>,
+    /// When both merged channels are playing, take the higher one rather than the lower.
+    pub merge_prefer_higher: bool,
+    /// What to do with a note pressed again while it's held down.
+    pub overlap: OverlapPolicy,
+    /// Longest a PDF page can be before the roll is split onto more pages, in points.
+    pub max_page_length: f64,
+    /// How much split pages overlap, in points.
+    pub page_overlap: f64,
+    /// Draw the roll across the page, with the music going from left to right.
+    pub horizontal: bool,
+    /// Paper to tile the roll across, instead of drawing it on one long page.
+    pub tile: Option<PaperSize>,
+    /// Longest an SVG file can be before the roll is split into more files, in points.
+    pub svg_page_length: Option<f64>,
+    pub export_csound: Option<PathBuf>,
+    pub export_sc3: Option<PathBuf>,
+    pub export_chuck: Option<PathBuf>,
+    pub export_etf: Option<PathBuf>,
+    pub catalog_image: Option<PathBuf>,
+    /// Width of the catalog image, in pixels.
+    pub image_width: u32,
+    /// How much of the roll to show in the catalog image, if not all of it.
+    pub image_feet: Option<f64>,
+    pub segment_export_json: Option<PathBuf>,
+    /// A perforator's punches per second and paper feed in inches per second, to estimate how
+    /// long it takes to punch the roll.
+    pub punching_rates: Option<(f64, f64)>,
+    /// Whether channels on the command line are numbered 1-16 instead of 0-15.
+    pub channel_one_indexed: bool,
+    /// Maximum note length, in beats.
+    pub clamp_duration: Option<f64>,
+    /// Factor to multiply every note's length by, unless its selector has its own.
+    pub articulation: f64,
+    /// Factor to multiply every note's start and length by.
+    pub time_stretch: Option<f64>,
+    /// Work out the time stretch so the song plays for this many seconds.
+    pub stretch_to: Option<f64>,
+    /// Snap note starts to this grid.
+    pub quantize: Option<Quantize>,
+    /// Drop notes shorter than this, as key bounces and accidental touches.
+    pub min_duration: Option<MinDuration>,
+    /// With `quantize`, snap note ends too.
+    pub quantize_durations: bool,
+    pub diagnostics_json: Option<PathBuf>,
+    /// Write what was done to each note on its way to the roll here.
+    pub fidelity_report: Option<PathBuf>,
+    /// How many of each repeated diagnostic to print, or `None` to print them all.
+    pub max_repeats: Option<usize>,
+    /// Move each selector's notes to fit as many as possible on the roll.
+    pub auto_transpose: Option<AutoTranspose>,
+    /// Warn if the selected notes span more notes than the roll has.
+    pub max_note_range: bool,
+    /// Make exceeding the roll's range an error instead of a warning.
+    pub strict_range: bool,
+    pub self_check: bool,
+    pub export_percussion_map: Option<PathBuf>,
+    pub note_map: Option<PathBuf>,
+    /// Only list the tracks and channels, and don't draw anything.
+    pub list_tracks: bool,
+    /// The same, as JSON.
+    pub list_tracks_json: bool,
+    /// Only print what's in the file: the tracks, each channel's range of notes, and how long
+    /// the song is.
+    pub info: bool,
+    pub pitch_usage: bool,
+    pub pitch_usage_csv: Option<PathBuf>,
+    pub note_range_display: bool,
+    pub roll_label: Label,
+    /// Title to print on the leader, instead of the name of the MIDI file's first named track.
+    pub title: Option<String>,
+    pub composer: Option<String>,
+    pub detect_repeated_sections: bool,
+    pub show_key_positions: bool,
+    /// Which end of the roll to draw a keyboard at, lined up with the channels, if either.
+    pub keyboard_legend: Option<RollEnd>,
+    /// Tempo to use if the MIDI file doesn't set one, in microseconds per beat.
+    pub default_tempo: Option<u32>,
+    pub tempo_marking: bool,
+    pub show_rests: bool,
+    /// Shortest rest to mark with `show_rests`, in beats.
+    pub min_rest: f64,
+    /// Draw a line across the roll at each measure.
+    pub grid_measures: bool,
+    /// Also draw a lighter line at each beat.
+    pub grid_beats: bool,
+    /// Number each measure in the margin, with or without the grid lines.
+    pub label_measures: bool,
+    /// Also number each beat within its measure.
+    pub label_beats: bool,
+    pub note_shape_by_duration: bool,
+    /// Fill holes in shades of gray by how hard their notes are played.
+    pub velocity_shading: bool,
+    /// Fill holes in a different color for each track and channel the notes came from.
+    pub color_by_track: bool,
+    /// Move whole phrases that go off the roll by octaves, instead of dropping the notes that
+    /// don't fit.
+    pub fold_phrases: bool,
+    /// Longest gap between notes in the same phrase for `fold_phrases`, in beats.
+    pub phrase_gap: f64,
+    /// How much further along the roll the odd channels' holes are, in points, for a tracker bar
+    /// with two staggered rows of holes.
+    pub row_offset: f32,
+    /// Longest slot to cut before breaking a long note into a chain of them, in points.
+    pub max_slot: Option<f64>,
+    /// Paper to leave between the slots in a chain, in points.
+    pub bridge: f64,
+    /// Channel of the roll to punch a tune-change hole in after the song, to trigger the player's
+    /// rewind or advance.
+    pub tune_change_channel: Option<u8>,
+    /// How long the tune-change hole is, in points.
+    pub tune_change_length: f64,
+    /// A hole after the song to set off the player's rewind.
+    pub rewind_hole: Option<EndHole>,
+    /// A hole after the song to turn the player off.
+    pub shutoff_hole: Option<EndHole>,
+    /// Whether each category of check is an error, a warning, or off.
+    pub checks: CheckPolicy,
+    pub thresholds: Thresholds,
+    /// Notes to self for proofreading, written beside the measures they're about.
+    pub editor_notes: Vec<EditorNote>,
+    /// The settings to draw a proof with as well, if one was asked for with `--proof`: the same
+    /// as these, with the proofing options laid over them.
+    pub proof: Option<Box<Configuration>>,
 }
 
-#[derive(Debug)]
+/// What `--auto-transpose` may move notes by.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum AutoTranspose {
+    /// Any number of semitones, trying whole octaves first.
+    Semitones,
+    /// Only whole octaves, so the key never changes.
+    Octaves,
+}
+
+/// The shortest note to keep.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MinDuration {
+    Ticks(u64),
+    Millis(f64),
+}
+
+impl fmt::Display for MinDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MinDuration::Ticks(ticks) => write!(f, "{} ticks", ticks),
+            MinDuration::Millis(millis) => write!(f, "{} ms", millis),
+        }
+    }
+}
+
+/// The grid to quantize notes to.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Quantize {
+    Ticks(u64),
+    /// A note value, as the denominator of a fraction of a whole note: 16 for sixteenth notes.
+    NoteValue(u64),
+}
+
+impl Quantize {
+    /// The size of a step of the grid, in ticks, never less than one.
+    pub fn grid_ticks(self, time_base: u16) -> u64 {
+        match self {
+            Quantize::Ticks(ticks) => ticks,
+            Quantize::NoteValue(value) => ((u64::from(time_base) * 4 + value / 2) / value).max(1),
+        }
+    }
+}
+
+impl fmt::Display for Quantize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Quantize::Ticks(ticks) => write!(f, "{} ticks", ticks),
+            Quantize::NoteValue(value) => write!(f, "1/{} notes", value),
+        }
+    }
+}
+
+/// How tempo changes are represented in the preview MIDI file.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PreviewTempo {
+    /// Keep the notes at their original ticks and write out every tempo change.
+    Original,
+    /// Write a single tempo, and move the notes so they play at the same real times.
+    Flattened,
+}
+
+/// One end of the roll or the other.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RollEnd {
+    Start,
+    End,
+}
+
+impl RollEnd {
+    fn parse(value: &str, flag: &str) -> Result<Self, String> {
+        match value {
+            "start" => Ok(RollEnd::Start),
+            "end" => Ok(RollEnd::End),
+            _ => Err(format!("{} must be \"start\" or \"end\", not {:?}", flag, value)),
+        }
+    }
+}
+
+/// The kind of file the roll is drawn in.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum OutputFormat {
+    Pdf,
+    Svg,
+    /// Not a drawing, but a table of the notes and where their holes are.
+    Csv,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Pdf => "pdf",
+            OutputFormat::Svg => "svg",
+            OutputFormat::Csv => "csv",
+        }
+    }
+}
+
+/// A selector given on the command line, or as a `[[selector]]` table in the project file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct ChannelSelector {
+    /// The name reports and per-selector options know the selector by: given as `<label>=` before
+    /// the selector, or `s1`, `s2`, and so on, by its position, if not.
+    #[serde(default)]
+    pub label: String,
     pub midi_track: usize,
     pub midi_channel: u8,
+    #[serde(default)]
     pub offset: i8,
+    /// Factor to shorten the selected notes by, overriding `Configuration::articulation`.
+    #[serde(default)]
+    pub articulation: Option<f64>,
+    /// Only select the notes played in some of the channel's program segments.
+    #[serde(skip)]
+    pub segment: Option<SegmentSelector>,
+    /// For a selector that picks its track by name, the name to look for. `midi_track`, and
+    /// `midi_channel` if it wasn't given, are filled in by `resolve_track_name`.
+    #[serde(skip)]
+    pub track_name: Option<TrackName>,
+    /// Whether the track or the channel was given as `*`, to be filled in by `expand_wildcards`.
+    #[serde(skip)]
+    pub any_track: bool,
+    #[serde(skip)]
+    pub any_channel: bool,
+}
+
+/// A track picked by its name rather than its number, so that selectors keep working when an
+/// editor renumbers the tracks.
+#[derive(Debug, Clone)]
+pub struct TrackName {
+    pub name: String,
+    pub channel: Option<u8>,
+}
+
+impl ChannelSelector {
+    /// Look up the track a selector picks by name, once the MIDI file has been read. The name
+    /// matches any track whose name contains it, ignoring case, and has to match exactly one. If
+    /// no channel was given, the track has to have notes on only one.
+    pub fn resolve_track_name(&mut self, midi: &Midi, channel_base: u8)
+        -> Result<(), PianoRollError>
+    {
+        let Some(ref track_name) = self.track_name else {
+            return Ok(());
+        };
+        let wanted = track_name.name.to_lowercase();
+        let matches = midi.tracks()
+            .filter(|track| track.name.as_ref()
+                .is_some_and(|name| name.to_lowercase().contains(&wanted)))
+            .collect::<Vec<_>>();
+        let track = match matches[..] {
+            [track] => track,
+            [] => return Err(PianoRollError::ConfigParse(format!(
+                "no track's name contains {:?}", track_name.name))),
+            _ => return Err(PianoRollError::ConfigParse(format!(
+                "{} tracks' names contain {:?}: {}",
+                matches.len(), track_name.name,
+                matches.iter()
+                    .map(|track| format!("{} ({:?})", track.midi_track,
+                        track.name.as_deref().unwrap_or("")))
+                    .collect::<Vec<_>>()
+                    .join(", ")))),
+        };
+        self.midi_track = track.midi_track;
+        self.midi_channel = match track_name.channel {
+            Some(channel) => channel,
+            None => {
+                let channels = midi.channels()
+                    .filter(|channel| channel.midi_track == track.midi_track
+                        && channel.program_segments.iter().any(|segment| segment.notes > 0))
+                    .map(|channel| channel.midi_channel)
+                    .collect::<Vec<_>>();
+                match channels[..] {
+                    [channel] => channel,
+                    [] => return Err(PianoRollError::ConfigParse(format!(
+                        "track {} ({:?}) has no notes", track.midi_track, track_name.name))),
+                    _ => return Err(PianoRollError::ConfigParse(format!(
+                        "track {} ({:?}) has notes on channels {}; pick one with \
+                        name={},<channel>",
+                        track.midi_track, track_name.name,
+                        channels.iter()
+                            .map(|channel| (channel + channel_base).to_string())
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                        track_name.name))),
+                }
+            }
+        };
+        Ok(())
+    }
+}
+
+/// Replace each selector with a `*` for its track or channel by one for every channel with notes
+/// that it matches, once the MIDI file has been read. They're labeled with the selector's label,
+/// a '.', and a number counting from 1.
+pub fn expand_wildcards(selectors: Vec<ChannelSelector>, midi: &Midi) -> Vec<ChannelSelector> {
+    let mut with_notes = midi.channels()
+        .filter(|channel| channel.program_segments.iter().any(|segment| segment.notes > 0))
+        .map(|channel| (channel.midi_track, channel.midi_channel))
+        .collect::<Vec<_>>();
+    with_notes.sort();
+    let mut expanded = vec![];
+    for selector in selectors {
+        if !selector.any_track && !selector.any_channel {
+            expanded.push(selector);
+            continue;
+        }
+        let matches = with_notes.iter()
+            .filter(|(track, channel)| (selector.any_track || *track == selector.midi_track)
+                && (selector.any_channel || *channel == selector.midi_channel))
+            .collect::<Vec<_>>();
+        if matches.is_empty() {
+            log::warn!("selector {} matches no channel with notes", selector.label);
+        }
+        for (i, &&(midi_track, midi_channel)) in matches.iter().enumerate() {
+            expanded.push(ChannelSelector {
+                label: format!("{}.{}", selector.label, i + 1),
+                midi_track,
+                midi_channel,
+                any_track: false,
+                any_channel: false,
+                ..selector.clone()
+            });
+        }
+    }
+    expanded
+}
+
+/// Whether the selector has the label, or came from one with it that stood for several.
+fn has_label(selector: &ChannelSelector, label: &str) -> bool {
+    selector.label.strip_prefix(label)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+}
+
+/// A hole punched after the end of the song for the player to act on, rather than a note.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EndHole {
+    /// Channel of the roll, counting the channels of the note holes from 8.
+    pub channel: u8,
+    /// How far after the end of the last note the hole starts, in points.
+    pub offset: f64,
+}
+
+impl EndHole {
+    /// Parse "<channel>,<inches>".
+    fn parse(value: &OsStr, flag: &str) -> Result<Self, String> {
+        let parsed = value.to_str()
+            .and_then(|s| s.split_once(','))
+            .and_then(|(channel, inches)| Some((channel.parse::<u8>().ok()?,
+                inches.parse::<f64>().ok()?)))
+            .filter(|&(_, inches)| inches.is_finite() && inches >= 0.);
+        let Some((channel, inches)) = parsed else {
+            return Err(format!("{} must be a channel and a number of inches after the last note, \
+                like \"87,2\", not {:?}", flag, value));
+        };
+        if MidiNote::from_pianoroll_channel(channel).is_none() {
+            return Err(format!("{} must be in one of the roll's note channels, {} to {}, not {}",
+                flag, MidiNote::PIANOROLL_LOWEST.pianoroll_channel().unwrap(),
+                MidiNote::PIANOROLL_HIGHEST.pianoroll_channel().unwrap(), channel));
+        }
+        Ok(Self { channel, offset: inches * f64::from(POINTS_PER_INCH) })
+    }
+}
+
+/// A note to self about a measure, like "m42: bass muddy, consider -12", kept with the proofs
+/// rather than on paper.
+#[derive(Debug, Clone)]
+pub struct EditorNote {
+    /// Counting from 1, as in the score.
+    pub measure: usize,
+    pub text: String,
+}
+
+impl EditorNote {
+    /// Parse "m<measure>: <text>".
+    pub fn parse(arg: &str) -> Result<Self, String> {
+        let (measure, text) = arg.split_once(':')
+            .ok_or_else(|| format!("editor's note must be \"m<measure>: <text>\", not {:?}", arg))?;
+        let measure = measure.trim();
+        let measure = measure.strip_prefix(['m', 'M'])
+            .and_then(|measure| measure.parse().ok())
+            .filter(|measure| *measure > 0)
+            .ok_or_else(|| format!("editor's note must start with a measure number, like m42, \
+                not {:?}", measure))?;
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(format!("editor's note for measure {} is empty", measure));
+        }
+        Ok(Self { measure, text: text.to_owned() })
+    }
 }
 
-pub fn parse_configuration(args: impl Iterator<Item = OsString>) -> Result<Configuration, String> {
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SegmentSelector {
+    /// Segments played with this program.
+    Program(u8),
+    /// The segment with this index, counting from zero.
+    Index(usize),
+}
+
+/// What an option takes after its name.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum OptionValue {
+    /// Nothing: it's a switch, given as `name = true` in a project file.
+    Switch,
+    /// A number, in the given units.
+    Number(&'static str),
+    /// A whole number, in the given units.
+    Integer(&'static str),
+    /// A string in the given form.
+    Text(&'static str),
+    /// A whole number of ticks, or a note value as a string, like `"1/16"`.
+    NoteValue,
+    Path,
+    /// Two strings in the given form, as an array of two in a project file.
+    Pair(&'static str),
+}
+
+impl OptionValue {
+    /// What a value for the option has to be, as said in error messages and the schema.
+    pub fn describe(self) -> String {
+        match self {
+            OptionValue::Switch => "true or false".to_owned(),
+            OptionValue::Number(units) => format!("a number of {}", units),
+            OptionValue::Integer(units) => format!("a whole number of {}", units),
+            OptionValue::Text(form) => format!("a string: {}", form),
+            OptionValue::NoteValue => {
+                "a whole number of ticks, or a string: a note value like 1/16".to_owned()
+            }
+            OptionValue::Path => "a path".to_owned(),
+            OptionValue::Pair(form) => format!("an array of two strings: {}", form),
+        }
+    }
+}
+
+/// An option that can be given on the command line as `--<name>`, or in a project file as
+/// `<name> = <value>`.
+#[derive(Debug, Clone, Copy)]
+pub struct OptionSpec {
+    pub name: &'static str,
+    pub value: OptionValue,
+    /// Whether it can be given more than once, as an array in a project file.
+    pub repeatable: bool,
+    /// The value used if the option isn't given, if there is one worth showing.
+    pub default: Option<&'static str>,
+}
+
+const fn option(name: &'static str, value: OptionValue, default: Option<&'static str>)
+    -> OptionSpec
+{
+    OptionSpec { name, value, repeatable: false, default }
+}
+
+const fn switch(name: &'static str) -> OptionSpec {
+    option(name, OptionValue::Switch, None)
+}
+
+const INCHES: OptionValue = OptionValue::Number("inches");
+const BEATS: OptionValue = OptionValue::Number("beats");
+
+/// Every option the parser accepts. This is where the parser checks for unknown options, so a
+/// misspelled one is an error instead of quietly doing nothing, and what the project file and
+/// `--print-config-schema` go by.
+pub const OPTION_TABLE: &[OptionSpec] = &[
+    switch("strip-silence-start"),
+    switch("apply-smpte-offset"),
+    option("preview-tempo", OptionValue::Text("original|flattened"), Some("original")),
+    option("format", OptionValue::Text("pdf|svg|csv"), None),
+    option("backend", OptionValue::Text("ghakuf"), Some("ghakuf")),
+    option("conductor", OptionValue::Path, None),
+    switch("rescale-ticks"),
+    switch("real-time-spacing"),
+    switch("tick-spacing"),
+    option("merge-alternating", OptionValue::Pair("track,channel"), None),
+    option("merge-prefer", OptionValue::Text("higher|lower"), Some("higher")),
+    option("overlap", OptionValue::Text("error|merge|longest|first"), Some("error")),
+    switch("sustain-pedal"),
+    option("fit-length", OptionValue::Text("<inches>, <n>in, or <n>ft"), None),
+    switch("fit-page"),
+    option("max-page-length", INCHES, Some("200")),
+    option("page-overlap", INCHES, Some("0.5")),
+    option("svg-page-length", INCHES, None),
+    switch("horizontal"),
+    option("tile", OptionValue::Text("letter|a4"), None),
+    option("export-csound", OptionValue::Path, None),
+    option("export-sc3", OptionValue::Path, None),
+    option("export-chuck", OptionValue::Path, None),
+    option("export-etf", OptionValue::Path, None),
+    option("catalog-image", OptionValue::Path, None),
+    option("image-width", OptionValue::Integer("pixels"), Some("800")),
+    option("image-feet", OptionValue::Number("feet"), None),
+    option("segment-export-json", OptionValue::Path, None),
+    option("punch-rate", OptionValue::Number("punches per second"), None),
+    option("feed-rate", OptionValue::Number("inches per second"), None),
+    switch("channel-zero-indexed"),
+    switch("channel-one-indexed"),
+    option("clamp-duration", BEATS, None),
+    option("articulation", OptionValue::Number("times the length"), Some("1")),
+    option("time-stretch", OptionValue::Number("times"), None),
+    option("stretch-to", OptionValue::Text("m:ss"), None),
+    option("quantize", OptionValue::NoteValue, None),
+    option("min-duration-ticks", OptionValue::Integer("ticks"), None),
+    option("min-duration-ms", OptionValue::Number("milliseconds"), None),
+    switch("quantize-durations"),
+    option("diagnostics-json", OptionValue::Path, None),
+    option("fidelity-report", OptionValue::Path, None),
+    option("max-repeats", OptionValue::Integer("lines"), Some("5")),
+    switch("no-aggregate"),
+    switch("auto-transpose"),
+    switch("auto-transpose=semitones"),
+    switch("auto-transpose=octaves"),
+    switch("max-note-range"),
+    switch("strict-range"),
+    switch("self-check"),
+    option("export-percussion-map", OptionValue::Path, None),
+    option("note-map", OptionValue::Path, None),
+    switch("list-tracks"),
+    switch("list-tracks-json"),
+    switch("info"),
+    switch("pitch-usage"),
+    option("pitch-usage-csv", OptionValue::Path, None),
+    switch("note-range-display"),
+    option("roll-label-punches", OptionValue::Text("up to 16 digits, capitals, spaces, and -"),
+        None),
+    switch("title-leader"),
+    option("leader-length", INCHES, Some("12")),
+    option("title", OptionValue::Text("text"), None),
+    option("composer", OptionValue::Text("text"), None),
+    switch("detect-repeated-sections"),
+    option("default-tempo", OptionValue::Number("beats per minute"), None),
+    switch("tempo-marking"),
+    switch("show-key-positions"),
+    option("keyboard-legend", OptionValue::Text("start|end"), None),
+    switch("show-rests"),
+    option("min-rest", BEATS, Some("1")),
+    switch("grid-measures"),
+    switch("grid-beats"),
+    switch("label-measures"),
+    switch("label-beats"),
+    OptionSpec {
+        name: "check",
+        value: OptionValue::Text("<category>=error|warning|off"),
+        repeatable: true,
+        default: None,
+    },
+    option("min-hole", INCHES, None),
+    option("min-bridge", INCHES, None),
+    option("max-polyphony", OptionValue::Integer("notes"), None),
+    option("max-open-holes", OptionValue::Integer("holes"), None),
+    option("max-dead-air", OptionValue::Number("seconds"), None),
+    switch("note-shape-by-duration"),
+    switch("velocity-shading"),
+    switch("color-by-track"),
+    option("proof", OptionValue::Path, None),
+    option("proof-options", OptionValue::Text("drawing options"), Some(PROOF_OPTIONS)),
+    switch("fold-phrases"),
+    option("phrase-gap", BEATS, Some("1")),
+    option("row-offset", INCHES, Some("0")),
+    option("max-slot", INCHES, None),
+    option("bridge", INCHES, Some("0.0625")),
+    option("tune-change-channel", OptionValue::Integer("the roll's channel"), None),
+    option("tune-change-length", INCHES, Some("1")),
+    option("rewind-hole", OptionValue::Text("channel,inches"), None),
+    option("shutoff-hole", OptionValue::Text("channel,inches"), None),
+    OptionSpec {
+        name: "note",
+        value: OptionValue::Text("m<measure>: <text>"),
+        repeatable: true,
+        default: None,
+    },
+    OptionSpec { name: "notes-file", value: OptionValue::Path, repeatable: true, default: None },
+];
+
+/// What a proof is drawn with unless `--proof-options` says otherwise.
+const PROOF_OPTIONS: &str = "--grid-measures --label-beats --color-by-track --note-range-display \
+    --show-key-positions --keyboard-legend start --tempo-marking";
+
+/// The settings a project file can have besides the options, with what they take.
+const PROJECT_SETTINGS: &[(&str, &str)] = &[
+    ("input", "a path"),
+    ("output", "a path"),
+    ("time_divisor", "a number of ticks per point"),
+    ("[[selector]]", "tables of label, midi_track, midi_channel, offset, and articulation"),
+    ("[checks]", "a table of <category> = \"error\"|\"warning\"|\"off\""),
+    ("[thresholds]", "a table of min-hole, min-bridge, max-polyphony, max-open-holes, and \
+        max-dead-air"),
+];
+
+/// Everything a project file can have, one setting to a line, generated from `OPTION_TABLE`.
+pub fn schema() -> String {
+    let mut schema = String::new();
+    for (name, value) in PROJECT_SETTINGS {
+        schema.push_str(&format!("{} = {}\n", name, value));
+    }
+    for spec in OPTION_TABLE {
+        schema.push_str(&format!("{} = {}", spec.name, spec.value.describe()));
+        if spec.repeatable {
+            schema.push_str(", or an array of them");
+        }
+        if let Some(default) = spec.default {
+            schema.push_str(&format!(" (default: {})", default));
+        }
+        schema.push('\n');
+    }
+    schema
+}
+
+/// The option or project setting with the name most like `name`, if any is close enough to be
+/// what was meant.
+fn closest_name(name: &str) -> Option<&'static str> {
+    // How many single characters have to be added, removed, or changed to turn one into the
+    // other.
+    fn distance(a: &str, b: &str) -> usize {
+        let b = b.chars().collect::<Vec<_>>();
+        let mut row = (0 ..= b.len()).collect::<Vec<_>>();
+        for (i, a) in a.chars().enumerate() {
+            let mut diagonal = row[0];
+            row[0] = i + 1;
+            for (j, &b) in b.iter().enumerate() {
+                let substitution = diagonal + usize::from(a != b);
+                diagonal = row[j + 1];
+                row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+            }
+        }
+        row[b.len()]
+    }
+
+    let name = name.replace('_', "-");
+    OPTION_TABLE.iter()
+        .map(|spec| spec.name)
+        .chain(["input", "output", "time_divisor", "selector", "checks", "thresholds"])
+        .map(|candidate| (distance(&name, &candidate.replace('_', "-")), candidate))
+        .filter(|&(distance, _)| distance <= 2.max(name.len() / 4))
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+pub fn parse_configuration(args: impl Iterator<Item = OsString>)
+    -> Result<Configuration, PianoRollError>
+{
+    parse_arguments(args).map_err(PianoRollError::ConfigParse)
+}
+
+fn parse_arguments(args: impl Iterator<Item = OsString>) -> Result<Configuration, String> {
+    let args = args.skip(1).collect::<Vec<_>>();
+    // The project file is read first, so its flags can go before the command line's, which then
+    // win over them.
+    let project_path = match args.iter().rposition(|arg| arg == OsStr::new("--config")) {
+        Some(i) => Some(PathBuf::from(args.get(i + 1)
+            .ok_or_else(|| "--config must be followed by another argument".to_owned())?)),
+        None => None,
+    };
+    let mut project = match project_path {
+        Some(ref path) => read_project(path)?,
+        None => Project::default(),
+    };
+    let project_error = |e: String| {
+        format!("project file {:?}: {}", project_path.as_ref().unwrap(), e)
+    };
+    let flags = project_flags(&project.flags).map_err(project_error)?;
+
     let mut input = None;
     let mut output = None;
     let mut selectors = vec![];
     let mut time_divisor = None;
+    let mut fit_length = None;
+    let mut fit_page = false;
+    let mut strip_silence_start = false;
+    let mut apply_smpte_offset = false;
+    let mut preview_tempo = PreviewTempo::Original;
+    let mut output_format = None;
+    let mut sustain_pedal = false;
+    let mut real_time_spacing = true;
+    let mut merge_alternating = None;
+    let mut merge_prefer_higher = true;
+    let mut overlap = OverlapPolicy::Error;
+    let mut backend = Backend::default();
+    let mut conductor = None;
+    let mut rescale_ticks = false;
+    let mut max_page_length = 200. * f64::from(POINTS_PER_INCH);
+    let mut page_overlap = 0.5 * f64::from(POINTS_PER_INCH);
+    let mut horizontal = false;
+    let mut tile = None;
+    let mut svg_page_length = None;
+    let mut export_csound = None;
+    let mut export_sc3 = None;
+    let mut export_chuck = None;
+    let mut export_etf = None;
+    let mut catalog_image = None;
+    let mut image_width = 800;
+    let mut image_feet = None;
+    let mut segment_export_json = None;
+    let mut punch_rate = None;
+    let mut feed_rate = None;
+    let mut channel_one_indexed = false;
+    let mut clamp_duration = None;
+    let mut articulation = 1.;
+    let mut selector_articulations = vec![];
+    let mut time_stretch = None;
+    let mut stretch_to = None;
+    let mut quantize = None;
+    let mut min_duration_ticks = None;
+    let mut min_duration_ms = None;
+    let mut quantize_durations = false;
+    let mut diagnostics_json = None;
+    let mut fidelity_report = None;
+    let mut max_repeats = Some(5);
+    let mut auto_transpose = None;
+    let mut max_note_range = false;
+    let mut strict_range = false;
+    let mut self_check = false;
+    let mut export_percussion_map = None;
+    let mut note_map = None;
+    let mut list_tracks = false;
+    let mut list_tracks_json = false;
+    let mut info = false;
+    let mut pitch_usage = false;
+    let mut pitch_usage_csv = None;
+    let mut note_range_display = false;
+    let mut roll_label = Label::default();
+    let mut title_leader = false;
+    let mut leader_length = 12. * POINTS_PER_INCH;
+    let mut title = None;
+    let mut composer = None;
+    let mut detect_repeated_sections = false;
+    let mut show_key_positions = false;
+    let mut keyboard_legend = None;
+    let mut default_tempo = None;
+    let mut tempo_marking = false;
+    let mut show_rests = false;
+    let mut min_rest = 1.;
+    let mut grid_measures = false;
+    let mut grid_beats = false;
+    let mut label_measures = false;
+    let mut label_beats = false;
+    let mut note_shape_by_duration = false;
+    let mut velocity_shading = false;
+    let mut color_by_track = false;
+    let mut proof = None;
+    let mut proof_options = None;
+    let mut fold_phrases = false;
+    let mut phrase_gap = 1.;
+    let mut row_offset = 0.;
+    let mut max_slot = None;
+    let mut bridge = f64::from(POINTS_PER_INCH) / 16.;
+    let mut tune_change_channel = None;
+    let mut tune_change_length = f64::from(POINTS_PER_INCH);
+    let mut rewind_hole = None;
+    let mut shutoff_hole = None;
+    let mut editor_notes = vec![];
+    // Settings from the command line, which win over the project file's.
+    let mut check_settings = vec![];
+    let mut threshold_overrides = ThresholdOverrides::default();
 
-    let mut skip = 0;
-    let mut args = args.skip(1).peekable();
+    let mut args = flags.into_iter().chain(args);
     while let Some(arg) = args.next() {
-        if skip > 0 {
-            skip -= 1;
-            continue;
+        if let Some(name) = arg.to_str().and_then(|arg| arg.strip_prefix("--")) {
+            if name != "config" && !OPTION_TABLE.iter().any(|spec| spec.name == name) {
+                return Err(match closest_name(name) {
+                    Some(closest) => format!("unknown option {:?}; did you mean \"--{}\"?",
+                        arg, closest),
+                    None => format!("unknown option {:?}", arg),
+                });
+            }
         }
         if arg == OsStr::new("-o") {
-            let next_arg = args.peek()
-                .ok_or_else(|| "-o must be followed by another argument".to_owned())?;
-            output = Some(PathBuf::from(next_arg));
-            skip = 1;
+            output = Some(PathBuf::from(flag_value(&mut args, "-o")?));
+        } else if arg == OsStr::new("--strip-silence-start") {
+            strip_silence_start = true;
+        } else if arg == OsStr::new("--preview-tempo") {
+            let value = flag_value(&mut args, "--preview-tempo")?;
+            preview_tempo = match value.to_str() {
+                Some("original") => PreviewTempo::Original,
+                Some("flattened") => PreviewTempo::Flattened,
+                _ => return Err(format!(
+                    "--preview-tempo must be \"original\" or \"flattened\", not {:?}", value)),
+            };
+        } else if arg == OsStr::new("--format") {
+            let value = flag_value(&mut args, "--format")?;
+            output_format = Some(match value.to_str() {
+                Some("pdf") => OutputFormat::Pdf,
+                Some("svg") => OutputFormat::Svg,
+                Some("csv") => OutputFormat::Csv,
+                _ => return Err(format!("--format must be \"pdf\", \"svg\", or \"csv\", not {:?}",
+                    value)),
+            });
+        } else if arg == OsStr::new("--real-time-spacing") {
+            real_time_spacing = true;
+        } else if arg == OsStr::new("--tick-spacing") {
+            real_time_spacing = false;
+        } else if arg == OsStr::new("--merge-alternating") {
+            let mut channel = || {
+                let value = flag_value(&mut args, "--merge-alternating")?;
+                value.to_str()
+                    .ok_or_else(|| format!("non-utf8 argument {:?}", value))
+                    .and_then(parse_track_channel)
+                    .map_err(|e| format!("--merge-alternating needs two track,channel pairs: {}",
+                        e))
+            };
+            merge_alternating = Some([channel()?, channel()?]);
+        } else if arg == OsStr::new("--merge-prefer") {
+            let value = flag_value(&mut args, "--merge-prefer")?;
+            merge_prefer_higher = match value.to_str() {
+                Some("higher") => true,
+                Some("lower") => false,
+                _ => return Err(format!(
+                    "--merge-prefer must be \"higher\" or \"lower\", not {:?}", value)),
+            };
+        } else if arg == OsStr::new("--overlap") {
+            let value = flag_value(&mut args, "--overlap")?;
+            overlap = match value.to_str() {
+                Some("error") => OverlapPolicy::Error,
+                Some("merge") => OverlapPolicy::MergeUnion,
+                Some("longest") => OverlapPolicy::KeepLongest,
+                Some("first") => OverlapPolicy::KeepFirst,
+                _ => return Err(format!("--overlap must be \"error\", \"merge\", \"longest\", or \
+                    \"first\", not {:?}", value)),
+            };
+        } else if arg == OsStr::new("--backend") {
+            let value = flag_value(&mut args, "--backend")?;
+            backend = match value.to_str() {
+                Some("ghakuf") => Backend::Ghakuf,
+                Some("nom-midi") => return Err("this build of pianoroll doesn't have the \
+                    nom-midi backend; only \"ghakuf\" is available".to_owned()),
+                _ => return Err(format!("--backend must be \"ghakuf\", not {:?}", value)),
+            };
+        } else if arg == OsStr::new("--conductor") {
+            conductor = Some(PathBuf::from(flag_value(&mut args, "--conductor")?));
+        } else if arg == OsStr::new("--rescale-ticks") {
+            rescale_ticks = true;
+        } else if arg == OsStr::new("--sustain-pedal") {
+            sustain_pedal = true;
+        } else if arg == OsStr::new("--fit-length") {
+            let value = flag_value(&mut args, "--fit-length")?;
+            let parsed = value.to_str().and_then(|s| {
+                let (number, unit) = match s.strip_suffix("ft") {
+                    Some(feet) => (feet, 12.),
+                    None => (s.strip_suffix("in").unwrap_or(s), 1.),
+                };
+                number.trim().parse::<f64>().ok().map(|n| n * unit)
+            });
+            let inches = parsed
+                .filter(|inches| inches.is_finite() && *inches > 0.)
+                .ok_or_else(|| format!("--fit-length must be a length in inches, like 150 or \
+                    150in, or in feet, like 12.5ft, not {:?}", value))?;
+            fit_length = Some(inches * f64::from(POINTS_PER_INCH));
+        } else if arg == OsStr::new("--fit-page") {
+            fit_page = true;
+        } else if arg == OsStr::new("--max-page-length") {
+            let value = flag_value(&mut args, "--max-page-length")?;
+            let inches = value.to_str()
+                .and_then(|s| s.parse::<f64>().ok())
+                .filter(|inches| inches.is_finite() && *inches >= 1.)
+                .ok_or_else(|| format!(
+                    "--max-page-length must be a number of inches, at least 1, not {:?}", value))?;
+            max_page_length = inches * f64::from(POINTS_PER_INCH);
+        } else if arg == OsStr::new("--svg-page-length") {
+            let value = flag_value(&mut args, "--svg-page-length")?;
+            let inches = value.to_str()
+                .and_then(|s| s.parse::<f64>().ok())
+                .filter(|inches| inches.is_finite() && *inches >= 1.)
+                .ok_or_else(|| format!(
+                    "--svg-page-length must be a number of inches, at least 1, not {:?}", value))?;
+            svg_page_length = Some(inches * f64::from(POINTS_PER_INCH));
+        } else if arg == OsStr::new("--horizontal") {
+            horizontal = true;
+        } else if arg == OsStr::new("--tile") {
+            let value = flag_value(&mut args, "--tile")?;
+            tile = Some(match value.to_str() {
+                Some("letter") => PaperSize::Letter,
+                Some("a4") => PaperSize::A4,
+                _ => return Err(format!("--tile must be \"letter\" or \"a4\", not {:?}", value)),
+            });
+        } else if arg == OsStr::new("--page-overlap") {
+            let value = flag_value(&mut args, "--page-overlap")?;
+            let inches = value.to_str()
+                .and_then(|s| s.parse::<f64>().ok())
+                .filter(|inches| inches.is_finite() && *inches >= 0.)
+                .ok_or_else(|| format!(
+                    "--page-overlap must be a number of inches, not {:?}", value))?;
+            page_overlap = inches * f64::from(POINTS_PER_INCH);
+        } else if arg == OsStr::new("--export-csound") {
+            export_csound = Some(PathBuf::from(flag_value(&mut args, "--export-csound")?));
+        } else if arg == OsStr::new("--export-sc3") {
+            export_sc3 = Some(PathBuf::from(flag_value(&mut args, "--export-sc3")?));
+        } else if arg == OsStr::new("--export-chuck") {
+            export_chuck = Some(PathBuf::from(flag_value(&mut args, "--export-chuck")?));
+        } else if arg == OsStr::new("--export-etf") {
+            export_etf = Some(PathBuf::from(flag_value(&mut args, "--export-etf")?));
+        } else if arg == OsStr::new("--catalog-image") {
+            catalog_image = Some(PathBuf::from(flag_value(&mut args, "--catalog-image")?));
+        } else if arg == OsStr::new("--image-width") {
+            let value = flag_value(&mut args, "--image-width")?;
+            image_width = value.to_str()
+                .and_then(|s| s.parse().ok())
+                .filter(|width| *width > 0)
+                .ok_or_else(|| format!(
+                    "--image-width must be a positive number of pixels, not {:?}", value))?;
+        } else if arg == OsStr::new("--image-feet") {
+            let value = flag_value(&mut args, "--image-feet")?;
+            image_feet = Some(value.to_str()
+                .and_then(|s| s.parse().ok())
+                .filter(|feet| *feet > 0.)
+                .ok_or_else(|| format!(
+                    "--image-feet must be a positive number of feet, not {:?}", value))?);
+        } else if arg == OsStr::new("--segment-export-json") {
+            segment_export_json = Some(PathBuf::from(
+                flag_value(&mut args, "--segment-export-json")?));
+        } else if arg == OsStr::new("--punch-rate") {
+            let value = flag_value(&mut args, "--punch-rate")?;
+            punch_rate = Some(rate(&value)
+                .ok_or_else(|| format!(
+                    "--punch-rate must be a positive number of punches per second, not {:?}",
+                    value))?);
+        } else if arg == OsStr::new("--feed-rate") {
+            let value = flag_value(&mut args, "--feed-rate")?;
+            feed_rate = Some(rate(&value)
+                .ok_or_else(|| format!(
+                    "--feed-rate must be a positive number of inches per second, not {:?}",
+                    value))?);
+        } else if arg == OsStr::new("--channel-zero-indexed") {
+            channel_one_indexed = false;
+        } else if arg == OsStr::new("--channel-one-indexed") {
+            channel_one_indexed = true;
+        } else if arg == OsStr::new("--clamp-duration") {
+            let value = flag_value(&mut args, "--clamp-duration")?;
+            let beats: f64 = value.to_str()
+                .and_then(|s| s.parse().ok())
+                .filter(|beats| *beats > 0.)
+                .ok_or_else(|| format!(
+                    "--clamp-duration must be a positive number of beats, not {:?}", value))?;
+            clamp_duration = Some(beats);
+        } else if arg == OsStr::new("--articulation") {
+            let value = flag_value(&mut args, "--articulation")?;
+            let value = value.to_str()
+                .ok_or_else(|| format!("non-utf8 articulation {:?}", value))?;
+            match value.split_once('=') {
+                Some((label, factor)) => selector_articulations.push(
+                    (label.to_owned(), parse_articulation(factor)?)),
+                None => articulation = parse_articulation(value)?,
+            }
+        } else if arg == OsStr::new("--time-stretch") {
+            let value = flag_value(&mut args, "--time-stretch")?;
+            let factor: f64 = value.to_str()
+                .and_then(|s| s.parse().ok())
+                .filter(|factor: &f64| factor.is_finite() && *factor > 0.)
+                .ok_or_else(|| format!(
+                    "--time-stretch must be a positive number, not {:?}", value))?;
+            time_stretch = Some(factor);
+        } else if arg == OsStr::new("--stretch-to") {
+            let value = flag_value(&mut args, "--stretch-to")?;
+            stretch_to = Some(value.to_str()
+                .and_then(parse_duration)
+                .ok_or_else(|| format!(
+                    "--stretch-to must be a length of time like 2:30 or 1:02:30.5, not {:?}",
+                    value))?);
+        } else if arg == OsStr::new("--quantize") {
+            let value = flag_value(&mut args, "--quantize")?;
+            let positive = |s: &str| s.parse::<u64>().ok().filter(|n| *n > 0);
+            quantize = Some(value.to_str()
+                .and_then(|s| match s.strip_prefix("1/") {
+                    Some(note_value) => positive(note_value).map(Quantize::NoteValue),
+                    None => positive(s).map(Quantize::Ticks),
+                })
+                .ok_or_else(|| format!(
+                    "--quantize must be a positive number of ticks or a note value like 1/16, \
+                    not {:?}", value))?);
+        } else if arg == OsStr::new("--min-duration-ticks") {
+            let value = flag_value(&mut args, "--min-duration-ticks")?;
+            let ticks: u64 = value.to_str()
+                .and_then(|s| s.parse().ok())
+                .filter(|ticks| *ticks > 0)
+                .ok_or_else(|| format!(
+                    "--min-duration-ticks must be a positive number of ticks, not {:?}", value))?;
+            min_duration_ticks = Some(ticks);
+        } else if arg == OsStr::new("--min-duration-ms") {
+            let value = flag_value(&mut args, "--min-duration-ms")?;
+            min_duration_ms = Some(positive_number(&value)
+                .ok_or_else(|| format!(
+                    "--min-duration-ms must be a positive number of milliseconds, not {:?}",
+                    value))?);
+        } else if arg == OsStr::new("--quantize-durations") {
+            quantize_durations = true;
+        } else if arg == OsStr::new("--diagnostics-json") {
+            diagnostics_json = Some(PathBuf::from(flag_value(&mut args, "--diagnostics-json")?));
+        } else if arg == OsStr::new("--fidelity-report") {
+            fidelity_report = Some(PathBuf::from(flag_value(&mut args, "--fidelity-report")?));
+        } else if arg == OsStr::new("--max-repeats") {
+            let value = flag_value(&mut args, "--max-repeats")?;
+            max_repeats = Some(value.to_str()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(|| format!("--max-repeats must be a number, not {:?}", value))?);
+        } else if arg == OsStr::new("--no-aggregate") {
+            max_repeats = None;
+        } else if arg == OsStr::new("--auto-transpose")
+            || arg == OsStr::new("--auto-transpose=semitones")
+        {
+            auto_transpose = Some(AutoTranspose::Semitones);
+        } else if arg == OsStr::new("--auto-transpose=octaves") {
+            auto_transpose = Some(AutoTranspose::Octaves);
+        } else if arg == OsStr::new("--max-note-range") {
+            max_note_range = true;
+        } else if arg == OsStr::new("--strict-range") {
+            strict_range = true;
+        } else if arg == OsStr::new("--self-check") {
+            self_check = true;
+        } else if arg == OsStr::new("--export-percussion-map") {
+            export_percussion_map = Some(PathBuf::from(
+                flag_value(&mut args, "--export-percussion-map")?));
+        } else if arg == OsStr::new("--note-map") {
+            note_map = Some(PathBuf::from(flag_value(&mut args, "--note-map")?));
+        } else if arg == OsStr::new("--list-tracks") {
+            list_tracks = true;
+        } else if arg == OsStr::new("--list-tracks-json") {
+            list_tracks_json = true;
+        } else if arg == OsStr::new("--info") {
+            info = true;
+        } else if arg == OsStr::new("--pitch-usage") {
+            pitch_usage = true;
+        } else if arg == OsStr::new("--pitch-usage-csv") {
+            pitch_usage_csv = Some(PathBuf::from(flag_value(&mut args, "--pitch-usage-csv")?));
+        } else if arg == OsStr::new("--note-range-display") {
+            note_range_display = true;
+        } else if arg == OsStr::new("--roll-label-punches") {
+            let value = flag_value(&mut args, "--roll-label-punches")?;
+            let text = value.to_str()
+                .ok_or_else(|| format!("non-utf8 label {:?}", value))?;
+            roll_label = Label::new(text)?;
+        } else if arg == OsStr::new("--title-leader") {
+            title_leader = true;
+        } else if arg == OsStr::new("--leader-length") {
+            let value = flag_value(&mut args, "--leader-length")?;
+            let inches = value.to_str()
+                .and_then(|s| s.parse::<f32>().ok())
+                .filter(|inches| inches.is_finite() && *inches > 0.)
+                .ok_or_else(|| format!(
+                    "--leader-length must be a number of inches, not {:?}", value))?;
+            leader_length = inches * POINTS_PER_INCH;
+            title_leader = true;
+        } else if arg == OsStr::new("--title") {
+            let value = flag_value(&mut args, "--title")?;
+            title = Some(value.into_string()
+                .map_err(|value| format!("non-utf8 title {:?}", value))?);
+            title_leader = true;
+        } else if arg == OsStr::new("--composer") {
+            let value = flag_value(&mut args, "--composer")?;
+            composer = Some(value.into_string()
+                .map_err(|value| format!("non-utf8 composer {:?}", value))?);
+            title_leader = true;
+        } else if arg == OsStr::new("--detect-repeated-sections") {
+            detect_repeated_sections = true;
+        } else if arg == OsStr::new("--default-tempo") {
+            let value = flag_value(&mut args, "--default-tempo")?;
+            let bpm = value.to_str()
+                .and_then(|s| s.parse::<f64>().ok())
+                .filter(|bpm| bpm.is_finite() && *bpm >= 1.)
+                .ok_or_else(|| format!(
+                    "--default-tempo must be a number of beats per minute, not {:?}", value))?;
+            default_tempo = Some((60_000_000. / bpm).round() as u32);
+        } else if arg == OsStr::new("--tempo-marking") {
+            tempo_marking = true;
+        } else if arg == OsStr::new("--show-key-positions") {
+            show_key_positions = true;
+        } else if arg == OsStr::new("--keyboard-legend") {
+            let value = flag_value(&mut args, "--keyboard-legend")?;
+            keyboard_legend = Some(RollEnd::parse(&value.to_string_lossy(), "--keyboard-legend")?);
+        } else if arg == OsStr::new("--show-rests") {
+            show_rests = true;
+        } else if arg == OsStr::new("--min-rest") {
+            let value = flag_value(&mut args, "--min-rest")?;
+            min_rest = value.to_str()
+                .and_then(|s| s.parse().ok())
+                .filter(|beats| *beats > 0.)
+                .ok_or_else(|| format!(
+                    "--min-rest must be a positive number of beats, not {:?}", value))?;
+        } else if arg == OsStr::new("--grid-measures") {
+            grid_measures = true;
+        } else if arg == OsStr::new("--grid-beats") {
+            grid_measures = true;
+            grid_beats = true;
+        } else if arg == OsStr::new("--label-measures") {
+            label_measures = true;
+        } else if arg == OsStr::new("--label-beats") {
+            label_measures = true;
+            label_beats = true;
+        } else if arg == OsStr::new("--config") {
+            // Already read.
+            flag_value(&mut args, "--config")?;
+        } else if arg == OsStr::new("--check") {
+            let value = flag_value(&mut args, "--check")?;
+            let setting = value.to_str()
+                .ok_or_else(|| format!("non-utf8 argument {:?}", value))?;
+            check_settings.push(CheckPolicy::parse_setting(setting)
+                .map_err(|e| format!("--check: {}", e))?);
+        } else if arg == OsStr::new("--min-hole") {
+            let value = flag_value(&mut args, "--min-hole")?;
+            threshold_overrides.min_hole = Some(positive_number(&value)
+                .ok_or_else(|| format!(
+                    "--min-hole must be a positive number of inches, not {:?}", value))?);
+        } else if arg == OsStr::new("--min-bridge") {
+            let value = flag_value(&mut args, "--min-bridge")?;
+            threshold_overrides.min_bridge = Some(positive_number(&value)
+                .ok_or_else(|| format!(
+                    "--min-bridge must be a positive number of inches, not {:?}", value))?);
+        } else if arg == OsStr::new("--max-polyphony") {
+            let value = flag_value(&mut args, "--max-polyphony")?;
+            threshold_overrides.max_polyphony = Some(value.to_str()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(|| format!(
+                    "--max-polyphony must be a number of notes, not {:?}", value))?);
+        } else if arg == OsStr::new("--max-open-holes") {
+            let value = flag_value(&mut args, "--max-open-holes")?;
+            threshold_overrides.max_open_holes = Some(value.to_str()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(|| format!(
+                    "--max-open-holes must be a number of holes, not {:?}", value))?);
+        } else if arg == OsStr::new("--max-dead-air") {
+            let value = flag_value(&mut args, "--max-dead-air")?;
+            threshold_overrides.max_dead_air = Some(positive_number(&value)
+                .ok_or_else(|| format!(
+                    "--max-dead-air must be a positive number of seconds, not {:?}", value))?);
+        } else if arg == OsStr::new("--note-shape-by-duration") {
+            note_shape_by_duration = true;
+        } else if arg == OsStr::new("--velocity-shading") {
+            velocity_shading = true;
+        } else if arg == OsStr::new("--color-by-track") {
+            color_by_track = true;
+        } else if arg == OsStr::new("--proof") {
+            proof = Some(PathBuf::from(flag_value(&mut args, "--proof")?));
+        } else if arg == OsStr::new("--proof-options") {
+            let value = flag_value(&mut args, "--proof-options")?;
+            proof_options = Some(value.into_string()
+                .map_err(|value| format!("--proof-options must be UTF-8, not {:?}", value))?);
+        } else if arg == OsStr::new("--fold-phrases") {
+            fold_phrases = true;
+        } else if arg == OsStr::new("--phrase-gap") {
+            let value = flag_value(&mut args, "--phrase-gap")?;
+            phrase_gap = value.to_str()
+                .and_then(|s| s.parse().ok())
+                .filter(|beats| *beats >= 0.)
+                .ok_or_else(|| format!(
+                    "--phrase-gap must be a number of beats, not {:?}", value))?;
+        } else if arg == OsStr::new("--tune-change-channel") {
+            let value = flag_value(&mut args, "--tune-change-channel")?;
+            let channel = value.to_str()
+                .and_then(|s| s.parse::<u8>().ok())
+                .filter(|&channel| MidiNote::from_pianoroll_channel(channel).is_some())
+                .ok_or_else(|| format!("--tune-change-channel must be one of the roll's note \
+                    channels, {} to {}, not {:?}",
+                    MidiNote::PIANOROLL_LOWEST.pianoroll_channel().unwrap(),
+                    MidiNote::PIANOROLL_HIGHEST.pianoroll_channel().unwrap(), value))?;
+            tune_change_channel = Some(channel);
+        } else if arg == OsStr::new("--tune-change-length") {
+            let value = flag_value(&mut args, "--tune-change-length")?;
+            let inches = value.to_str()
+                .and_then(|s| s.parse::<f64>().ok())
+                .filter(|inches| inches.is_finite() && *inches > 0.)
+                .ok_or_else(|| format!(
+                    "--tune-change-length must be a number of inches, not {:?}", value))?;
+            tune_change_length = inches * f64::from(POINTS_PER_INCH);
+        } else if arg == OsStr::new("--rewind-hole") {
+            let value = flag_value(&mut args, "--rewind-hole")?;
+            rewind_hole = Some(EndHole::parse(&value, "--rewind-hole")?);
+        } else if arg == OsStr::new("--shutoff-hole") {
+            let value = flag_value(&mut args, "--shutoff-hole")?;
+            shutoff_hole = Some(EndHole::parse(&value, "--shutoff-hole")?);
+        } else if arg == OsStr::new("--max-slot") {
+            let value = flag_value(&mut args, "--max-slot")?;
+            let inches = value.to_str()
+                .and_then(|s| s.parse::<f64>().ok())
+                .filter(|inches| inches.is_finite() && *inches > 0.)
+                .ok_or_else(|| format!(
+                    "--max-slot must be a number of inches, not {:?}", value))?;
+            max_slot = Some(inches * f64::from(POINTS_PER_INCH));
+        } else if arg == OsStr::new("--bridge") {
+            let value = flag_value(&mut args, "--bridge")?;
+            let inches = value.to_str()
+                .and_then(|s| s.parse::<f64>().ok())
+                .filter(|inches| inches.is_finite() && *inches > 0.)
+                .ok_or_else(|| format!(
+                    "--bridge must be a number of inches, not {:?}", value))?;
+            bridge = inches * f64::from(POINTS_PER_INCH);
+        } else if arg == OsStr::new("--row-offset") {
+            let value = flag_value(&mut args, "--row-offset")?;
+            let inches = value.to_str()
+                .and_then(|s| s.parse::<f32>().ok())
+                .filter(|inches| inches.is_finite() && *inches >= 0.)
+                .ok_or_else(|| format!(
+                    "--row-offset must be a number of inches, not {:?}", value))?;
+            row_offset = inches * POINTS_PER_INCH;
+        } else if arg == OsStr::new("--note") {
+            let value = flag_value(&mut args, "--note")?;
+            let note = value.to_str()
+                .ok_or_else(|| format!("non-utf8 editor's note {:?}", value))?;
+            editor_notes.push(EditorNote::parse(note)?);
+        } else if arg == OsStr::new("--notes-file") {
+            let path = PathBuf::from(flag_value(&mut args, "--notes-file")?);
+            let text = std::fs::read_to_string(&path)
+                .map_err(|e| format!("failed to read notes file {:?}: {}", path, e))?;
+            for (i, line) in text.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let note = EditorNote::parse(line)
+                    .map_err(|e| format!("notes file {:?} line {}: {}", path, i + 1, e))?;
+                editor_notes.push(note);
+            }
+        } else if arg == OsStr::new("--apply-smpte-offset") {
+            apply_smpte_offset = true;
+        } else if arg.to_str().is_some_and(|arg| arg.starts_with("--")) {
+            return Err(format!("unknown option {:?}", arg));
         } else if input.is_none() {
             input = Some(PathBuf::from(arg));
         } else {
@@ -43,33 +1279,639 @@ pub fn parse_configuration(args: impl Iterator<Item = OsString>) -> Result<Confi
                 time_divisor = Some(num.parse()
                     .map_err(|e| format!("time divisor parse error: {}", e))?);
             } else {
-                let selector = parse_track_selector(arg)
+                let expanded = parse_track_selector(arg)
                     .map_err(|e| format!("malformed track selector \"{}\": {}", arg, e))?;
-                selectors.push(selector);
+                selectors.extend(expanded);
             }
         }
     }
 
-    let input = input.ok_or_else(|| "missing input argument".to_owned())?;
-    let output = output.unwrap_or_else(|| input.with_extension("pdf"));
+    if selectors.is_empty() {
+        selectors = std::mem::take(&mut project.selectors);
+        for selector in selectors.iter().filter(|selector| !selector.label.is_empty()) {
+            check_label(&selector.label)?;
+        }
+    }
+    label_selectors(&mut selectors)?;
+    for (label, factor) in selector_articulations {
+        let mut labeled = selectors.iter_mut()
+            .filter(|selector| has_label(selector, &label))
+            .peekable();
+        if labeled.peek().is_none() {
+            return Err(format!("--articulation: no selector is labeled {:?}", label));
+        }
+        for selector in labeled {
+            selector.articulation = Some(factor);
+        }
+    }
+    if channel_one_indexed {
+        for selector in &mut selectors {
+            match selector.track_name {
+                Some(TrackName { ref name, channel: Some(ref mut channel) }) => {
+                    *channel = channel.checked_sub(1)
+                        .ok_or_else(|| format!("channel numbers start at 1 with \
+                            --channel-one-indexed, but track {:?} has a selector for channel 0",
+                            name))?;
+                }
+                Some(TrackName { channel: None, .. }) => (),
+                None if selector.any_channel => (),
+                None => {
+                    selector.midi_channel = selector.midi_channel.checked_sub(1)
+                        .ok_or_else(|| format!("channel numbers start at 1 with \
+                            --channel-one-indexed, but track {} has a selector for channel 0",
+                            selector.midi_track))?;
+                }
+            }
+        }
+    }
+
+    if channel_one_indexed {
+        for (track, channel) in merge_alternating.iter_mut().flatten() {
+            *channel = channel.checked_sub(1)
+                .ok_or_else(|| format!("channel numbers start at 1 with --channel-one-indexed, \
+                    but --merge-alternating has channel 0 on track {}", track))?;
+        }
+    }
+
+    let input = input.or(project.input)
+        .ok_or_else(|| "missing input argument".to_owned())?;
+    if let Some(max_slot) = max_slot {
+        if bridge >= max_slot {
+            let inches = |points: f64| points / f64::from(POINTS_PER_INCH);
+            return Err(format!("--bridge of {} inches is too long for slots of at most {} inches",
+                inches(bridge), inches(max_slot)));
+        }
+    }
+    if page_overlap > max_page_length / 2. {
+        let inches = |points: f64| points / f64::from(POINTS_PER_INCH);
+        return Err(format!("--page-overlap of {} inches is too long for pages of {} inches",
+            inches(page_overlap), inches(max_page_length)));
+    }
+
+    // Without --format, go by the output file's extension.
+    let output_format = output_format.unwrap_or_else(|| {
+        match output.as_ref().and_then(|path: &PathBuf| path.extension()) {
+            Some(extension) if extension.eq_ignore_ascii_case("svg") => OutputFormat::Svg,
+            Some(extension) if extension.eq_ignore_ascii_case("csv") => OutputFormat::Csv,
+            _ => OutputFormat::Pdf,
+        }
+    });
+    if horizontal && (tile.is_some() || output_format != OutputFormat::Pdf) {
+        return Err("--horizontal only works for PDF output on one long page, not SVG, CSV, or \
+            tiles".to_owned());
+    }
+    let output = output.or(project.output)
+        .unwrap_or_else(|| input.with_extension(output_format.extension()));
+    if fit_page {
+        if fit_length.is_some() {
+            return Err("--fit-length and --fit-page can't both be given".to_owned());
+        }
+        // Just under the page limit, so it isn't split over a rounding error.
+        fit_length = Some(max_page_length - f64::from(POINTS_PER_INCH));
+    }
+    let time_divisor = time_divisor.or(project.time_divisor);
+    if let (Some(time_divisor), true) = (time_divisor, fit_length.is_some()) {
+        return Err(format!("a time divisor (/{}) and --fit-length or --fit-page can't both be \
+            given: the time divisor is what fitting the roll works out", time_divisor));
+    }
     let time_divisor = time_divisor.unwrap_or(1.);
-    Ok(Configuration {
+    if time_stretch.is_some() && stretch_to.is_some() {
+        return Err("--time-stretch and --stretch-to can't both be given".to_owned());
+    }
+    let min_duration = match (min_duration_ticks, min_duration_ms) {
+        (Some(_), Some(_)) => return Err(
+            "--min-duration-ticks and --min-duration-ms can't both be given".to_owned()),
+        (Some(ticks), None) => Some(MinDuration::Ticks(ticks)),
+        (None, Some(millis)) => Some(MinDuration::Millis(millis)),
+        (None, None) => None,
+    };
+    let punching_rates = match (punch_rate, feed_rate) {
+        (Some(punch_rate), Some(feed_rate)) => Some((punch_rate, feed_rate)),
+        (None, None) => None,
+        _ => return Err("--punch-rate and --feed-rate have to be given together".to_owned()),
+    };
+
+    // The command line wins over the project file, which wins over the defaults.
+    let mut checks = CheckPolicy::default();
+    for (category, level) in &project.checks {
+        let (category, level) = CheckPolicy::parse_setting(&format!("{}={}", category, level))
+            .map_err(project_error)?;
+        checks.set(category, level);
+    }
+    for (category, level) in check_settings {
+        checks.set(category, level);
+    }
+    let mut thresholds = Thresholds::default();
+    project.thresholds.apply(&mut thresholds);
+    threshold_overrides.apply(&mut thresholds);
+    if rewind_hole.is_some() && tune_change_channel.is_some() {
+        return Err("--rewind-hole and --tune-change-channel can't be used together: a tune-change \
+            hole already rewinds the roll at the end of the last song".to_owned());
+    }
+    if let (Some(rewind), Some(shutoff)) = (rewind_hole, shutoff_hole) {
+        if rewind.channel == shutoff.channel {
+            return Err(format!("--rewind-hole and --shutoff-hole are both in channel {}",
+                rewind.channel));
+        }
+    }
+    if title_leader {
+        roll_label = roll_label.with_printed_leader(leader_length);
+    }
+    let mut cfg = Configuration {
         input,
         output,
         selectors,
         time_divisor,
-    })
+        fit_length,
+        strip_silence_start,
+        apply_smpte_offset,
+        preview_tempo,
+        output_format,
+        sustain_pedal,
+        real_time_spacing,
+        merge_alternating,
+        merge_prefer_higher,
+        overlap,
+        backend,
+        conductor,
+        rescale_ticks,
+        max_page_length,
+        page_overlap,
+        horizontal,
+        tile,
+        svg_page_length,
+        export_csound,
+        export_sc3,
+        export_chuck,
+        export_etf,
+        catalog_image,
+        image_width,
+        image_feet,
+        segment_export_json,
+        punching_rates,
+        channel_one_indexed,
+        clamp_duration,
+        articulation,
+        time_stretch,
+        stretch_to,
+        quantize,
+        min_duration,
+        quantize_durations,
+        diagnostics_json,
+        fidelity_report,
+        max_repeats,
+        auto_transpose,
+        max_note_range,
+        strict_range,
+        self_check,
+        export_percussion_map,
+        note_map,
+        list_tracks,
+        list_tracks_json,
+        info,
+        pitch_usage,
+        pitch_usage_csv,
+        note_range_display,
+        roll_label,
+        title,
+        composer,
+        detect_repeated_sections,
+        show_key_positions,
+        keyboard_legend,
+        default_tempo,
+        tempo_marking,
+        show_rests,
+        min_rest,
+        grid_measures,
+        grid_beats,
+        label_measures,
+        label_beats,
+        note_shape_by_duration,
+        velocity_shading,
+        color_by_track,
+        fold_phrases,
+        phrase_gap,
+        row_offset,
+        max_slot,
+        bridge,
+        tune_change_channel,
+        tune_change_length,
+        rewind_hole,
+        shutoff_hole,
+        checks,
+        thresholds,
+        editor_notes,
+        proof: None,
+    };
+    if let Some(path) = proof {
+        let mut proof = cfg.clone();
+        proof.output = path;
+        proof.output_format = OutputFormat::Pdf;
+        proof.horizontal = false;
+        proof.tile = None;
+        apply_proof_options(&mut proof, proof_options.as_deref().unwrap_or(PROOF_OPTIONS))?;
+        cfg.proof = Some(Box::new(proof));
+    }
+    if !cfg.list_tracks && !cfg.list_tracks_json && !cfg.info {
+        check_output_collisions(&cfg)?;
+    }
+    Ok(cfg)
+}
+
+impl Configuration {
+    /// Lay out the roll, and the proof with it, at another time divisor.
+    pub fn set_time_divisor(&mut self, time_divisor: f64) {
+        self.time_divisor = time_divisor;
+        if let Some(ref mut proof) = self.proof {
+            proof.time_divisor = time_divisor;
+        }
+    }
+
+    /// The preview MIDI file goes next to the output, named after it: "song.pdf" gets
+    /// "song_pianoroll.mid".
+    ///
+    /// This works on the `OsStr` of the file name, so that names that aren't valid UTF-8 survive,
+    /// and appends the extension rather than using `with_extension`, which would treat the end of
+    /// a name like "song.v2" as an extension and replace it.
+    pub fn preview_midi_path(&self) -> PathBuf {
+        let mut filename = self.output.file_stem().unwrap_or_default().to_owned();
+        filename.push("_pianoroll.mid");
+        self.output.with_file_name(filename)
+    }
+
+    /// Every file a run would write, with what writes it.
+    pub fn planned_outputs(&self) -> Vec<(&'static str, PathBuf)> {
+        let mut outputs = vec![
+            ("the roll", self.output.clone()),
+            ("the preview MIDI file", self.preview_midi_path()),
+        ];
+        if let Some(ref proof) = self.proof {
+            outputs.push(("--proof", proof.output.clone()));
+        }
+        let optional = [
+            ("--export-csound", &self.export_csound),
+            ("--export-sc3", &self.export_sc3),
+            ("--export-chuck", &self.export_chuck),
+            ("--export-etf", &self.export_etf),
+            ("--catalog-image", &self.catalog_image),
+            ("--segment-export-json", &self.segment_export_json),
+            ("--diagnostics-json", &self.diagnostics_json),
+            ("--fidelity-report", &self.fidelity_report),
+            ("--export-percussion-map", &self.export_percussion_map),
+            ("--pitch-usage-csv", &self.pitch_usage_csv),
+        ];
+        outputs.extend(optional.iter()
+            .filter_map(|(feature, path)| path.as_ref().map(|path| (*feature, path.clone()))));
+        outputs
+    }
+}
+
+/// Make sure no two outputs would be written to the same file, and none over the input, before
+/// anything is written. Every collision is reported, not just the first.
+fn check_output_collisions(cfg: &Configuration) -> Result<(), String> {
+    let mut files = vec![("the input", cfg.input.clone())];
+    files.extend(cfg.conductor.iter().map(|path| ("the conductor file", path.clone())));
+    files.extend(cfg.planned_outputs());
+    let normalized = files.iter().map(|(_, path)| normalize_path(path)).collect::<Vec<_>>();
+    let mut collisions = vec![];
+    for (i, (feature, path)) in files.iter().enumerate() {
+        for (j, (other, _)) in files[.. i].iter().enumerate() {
+            if normalized[j] == normalized[i] {
+                collisions.push(format!("{} and {} would both be {:?}", other, feature, path));
+            }
+        }
+    }
+    if collisions.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("output files collide: {}", collisions.join("; ")))
+    }
+}
+
+/// A path as the file system would resolve it, for telling whether two paths are the same file:
+/// absolute, with the directory's symlinks and ".." followed where it exists, and on Windows,
+/// lowercased, since its file names ignore case.
+fn normalize_path(path: &Path) -> PathBuf {
+    let absolute = std::env::current_dir()
+        .map(|dir| dir.join(path))
+        .unwrap_or_else(|_| path.to_owned());
+    let directory = absolute.parent()
+        .and_then(|dir| dir.canonicalize().ok())
+        .unwrap_or_else(|| {
+            // Go through the components by hand for a directory that doesn't exist yet.
+            let mut resolved = PathBuf::new();
+            for component in absolute.parent().unwrap_or(&absolute).components() {
+                match component {
+                    std::path::Component::ParentDir => {
+                        resolved.pop();
+                    }
+                    std::path::Component::CurDir => (),
+                    _ => resolved.push(component),
+                }
+            }
+            resolved
+        });
+    let path = match absolute.file_name() {
+        Some(name) => directory.join(name),
+        None => directory,
+    };
+    if cfg!(windows) {
+        PathBuf::from(path.to_string_lossy().to_lowercase())
+    } else {
+        path
+    }
+}
+
+/// Lay the options from `--proof-options` over a copy of the configuration, to draw the proof
+/// with. Only options that change what's drawn can be given, since the notes are the same.
+fn apply_proof_options(cfg: &mut Configuration, options: &str) -> Result<(), String> {
+    let mut options = options.split_whitespace();
+    while let Some(option) = options.next() {
+        match option {
+            "--grid-measures" => cfg.grid_measures = true,
+            "--grid-beats" => {
+                cfg.grid_measures = true;
+                cfg.grid_beats = true;
+            }
+            "--label-measures" => cfg.label_measures = true,
+            "--label-beats" => {
+                cfg.label_measures = true;
+                cfg.label_beats = true;
+            }
+            "--note-range-display" => cfg.note_range_display = true,
+            "--show-key-positions" => cfg.show_key_positions = true,
+            "--keyboard-legend" => {
+                let value = options.next()
+                    .ok_or("--keyboard-legend must be followed by another argument")?;
+                cfg.keyboard_legend = Some(RollEnd::parse(value, "--keyboard-legend")?);
+            }
+            "--tempo-marking" => cfg.tempo_marking = true,
+            "--show-rests" => cfg.show_rests = true,
+            "--min-rest" => {
+                let value = options.next()
+                    .ok_or("--min-rest must be followed by another argument")?;
+                cfg.min_rest = value.parse()
+                    .ok()
+                    .filter(|beats| *beats > 0.)
+                    .ok_or_else(|| format!(
+                        "--min-rest must be a positive number of beats, not {:?}", value))?;
+            }
+            "--note-shape-by-duration" => cfg.note_shape_by_duration = true,
+            "--velocity-shading" => cfg.velocity_shading = true,
+            "--color-by-track" => cfg.color_by_track = true,
+            _ => return Err(format!("--proof-options can only have options that change how the \
+                roll is drawn, not {:?}", option)),
+        }
+    }
+    Ok(())
+}
+
+/// Settings kept with a song in a TOML file, so they don't have to be given every time.
+#[derive(Debug, Default, Deserialize)]
+struct Project {
+    input: Option<PathBuf>,
+    output: Option<PathBuf>,
+    time_divisor: Option<f64>,
+    /// Used if none are given on the command line.
+    #[serde(default, rename = "selector")]
+    selectors: Vec<ChannelSelector>,
+    /// Level for each category of check, by name.
+    #[serde(default)]
+    checks: BTreeMap<String, String>,
+    #[serde(default)]
+    thresholds: ThresholdOverrides,
+    /// Any other flag, by its name without the dashes.
+    #[serde(flatten)]
+    flags: BTreeMap<String, toml::Value>,
+}
+
+/// The project file's other settings as command line flags: `name = true` is `--name`,
+/// `name = <value>` is `--name <value>`, and an array of values gives the flag once for each.
+fn project_flags(flags: &BTreeMap<String, toml::Value>) -> Result<Vec<OsString>, String> {
+    fn push_flag(args: &mut Vec<OsString>, name: &str, value: &toml::Value)
+        -> Result<(), String>
+    {
+        let flag = OsString::from(format!("--{}", name));
+        match value {
+            toml::Value::Boolean(true) => args.push(flag),
+            toml::Value::Boolean(false) => (),
+            toml::Value::String(value) => args.extend([flag, value.into()]),
+            toml::Value::Integer(value) => args.extend([flag, value.to_string().into()]),
+            toml::Value::Float(value) => args.extend([flag, value.to_string().into()]),
+            toml::Value::Array(values) => {
+                let pair = OPTION_TABLE.iter()
+                    .any(|spec| spec.name == name && matches!(spec.value, OptionValue::Pair(_)));
+                if pair {
+                    args.push(flag);
+                    args.extend(values.iter().filter_map(|value| value.as_str()).map(Into::into));
+                } else {
+                    for value in values {
+                        push_flag(args, name, value)?;
+                    }
+                }
+            }
+            toml::Value::Datetime(_) | toml::Value::Table(_) => {
+                return Err(format!("{} isn't a setting", name));
+            }
+        }
+        Ok(())
+    }
+
+    let mut args = vec![];
+    for (name, value) in flags {
+        push_flag(&mut args, name, value)?;
+    }
+    Ok(args)
+}
+
+fn read_project(path: &Path) -> Result<Project, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read project file {:?}: {}", path, e))?;
+    let project: Project = toml::from_str(&text)
+        .map_err(|e| format!("failed to parse project file {:?}: {}", path, e))?;
+    for (name, value) in &project.flags {
+        check_project_flag(name, value, &text)
+            .map_err(|e| format!("failed to parse project file {:?}: {}", path, e))?;
+    }
+    Ok(project)
+}
+
+/// Check that a setting in a project file is one of the options, with the right type of value.
+fn check_project_flag(name: &str, value: &toml::Value, text: &str) -> Result<(), String> {
+    // Where the setting is, to point to in the error. The options all go at the top level, before
+    // any table.
+    let location = || {
+        text.lines()
+            .take_while(|line| !line.trim_start().starts_with('['))
+            .enumerate()
+            .find_map(|(i, line)| {
+                let setting = line.trim_start();
+                let rest = setting.strip_prefix(name)
+                    .or_else(|| setting.strip_prefix(&format!("{:?}", name)))?;
+                let column = line.len() - setting.len() + 1;
+                rest.trim_start().starts_with('=')
+                    .then(|| format!(" at line {} column {}", i + 1, column))
+            })
+            .unwrap_or_default()
+    };
+    let spec = match OPTION_TABLE.iter().find(|spec| spec.name == name) {
+        Some(spec) => spec,
+        None if name == "config" => return Err("a project file can't load another".to_owned()),
+        None => {
+            return Err(match closest_name(name) {
+                Some(closest) => format!("unknown setting `{}`{}; did you mean `{}`?",
+                    name, location(), closest),
+                None => format!("unknown setting `{}`{}", name, location()),
+            });
+        }
+    };
+    let fits = |value: &toml::Value| {
+        matches!((spec.value, value),
+            (OptionValue::Switch, toml::Value::Boolean(_))
+            | (OptionValue::Number(_), toml::Value::Integer(_) | toml::Value::Float(_))
+            | (OptionValue::Integer(_) | OptionValue::NoteValue, toml::Value::Integer(_))
+            | (OptionValue::Text(_) | OptionValue::NoteValue | OptionValue::Path,
+                toml::Value::String(_)))
+    };
+    let ok = match value {
+        toml::Value::Array(values) if spec.repeatable => values.iter().all(fits),
+        toml::Value::Array(values) if matches!(spec.value, OptionValue::Pair(_)) => {
+            values.len() == 2 && values.iter().all(toml::Value::is_str)
+        }
+        value => fits(value),
+    };
+    if ok {
+        Ok(())
+    } else {
+        Err(format!("`{}`{} should be {}{}, not {}", name, location(), spec.value.describe(),
+            if spec.repeatable { ", or an array of them" } else { "" }, value.type_str()))
+    }
+}
+
+/// A length of time as minutes and seconds, or hours, minutes, and seconds, separated by colons,
+/// like `formatting::duration` writes it, in seconds.
+fn parse_duration(arg: &str) -> Option<f64> {
+    let parts = arg.split(':').collect::<Vec<_>>();
+    let (seconds, larger) = match parts[..] {
+        [minutes, seconds] => (seconds, vec![minutes]),
+        [hours, minutes, seconds] => (seconds, vec![hours, minutes]),
+        _ => return None,
+    };
+    let mut total = 0.;
+    for part in larger {
+        total = total * 60. + f64::from(part.parse::<u32>().ok()?);
+    }
+    let seconds = seconds.parse::<f64>().ok().filter(|seconds| (0. .. 60.).contains(seconds))?;
+    Some(total * 60. + seconds).filter(|total| *total > 0.)
+}
+
+fn positive_number(value: &OsStr) -> Option<f64> {
+    value.to_str()
+        .and_then(|s| s.parse().ok())
+        .filter(|n: &f64| *n > 0. && n.is_finite())
+}
+
+/// A rate something is done at, which the time it takes is divided by, so it can't be so small
+/// that the time comes out infinite.
+fn rate(value: &OsStr) -> Option<f64> {
+    positive_number(value).filter(|rate| (1. / rate).is_finite())
+}
+
+fn flag_value(args: &mut impl Iterator<Item = OsString>, flag: &str) -> Result<OsString, String> {
+    args.next()
+        .ok_or_else(|| format!("{} must be followed by another argument", flag))
+}
+
+/// Give every selector without a label one by its position, counting from 1, and make sure no two
+/// have the same label.
+fn label_selectors(selectors: &mut [ChannelSelector]) -> Result<(), String> {
+    for (i, selector) in selectors.iter_mut().enumerate() {
+        if selector.label.is_empty() {
+            selector.label = format!("s{}", i + 1);
+        }
+    }
+    for (i, selector) in selectors.iter().enumerate() {
+        if selectors[.. i].iter().any(|other| other.label == selector.label) {
+            return Err(format!("more than one selector is labeled {:?}", selector.label));
+        }
+    }
+    Ok(())
+}
+
+/// Labels are used before '=' in selectors and per-selector options, so they're kept to letters,
+/// digits, '_', and '-', and can't be "name", which picks a track by its name instead.
+fn check_label(label: &str) -> Result<(), String> {
+    if label == "name" {
+        Err("\"name\" can't be used as a selector label".to_owned())
+    } else if label.is_empty()
+        || !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        Err(format!("selector labels can only have letters, digits, '_', and '-', not {:?}",
+            label))
+    } else {
+        Ok(())
+    }
+}
+
+/// A selector, with an optional "<label>=" in front. A range of tracks gives a selector for each,
+/// labeled with the label, a '.', and a number counting from 1.
+fn parse_track_selector(arg: &str) -> Result<Vec<ChannelSelector>, String> {
+    match arg.split_once('=') {
+        Some((label, arg)) if check_label(label).is_ok() => {
+            let mut selectors = parse_unlabeled_selector(arg)?;
+            let numbered = selectors.len() > 1;
+            for (i, selector) in selectors.iter_mut().enumerate() {
+                selector.label = if numbered {
+                    format!("{}.{}", label, i + 1)
+                } else {
+                    label.to_owned()
+                };
+            }
+            Ok(selectors)
+        }
+        _ => parse_unlabeled_selector(arg),
+    }
 }
 
-fn parse_track_selector(arg: &str) -> Result<ChannelSelector, String> {
+fn parse_unlabeled_selector(arg: &str) -> Result<Vec<ChannelSelector>, String> {
+    let (arg, segment) = match arg.split_once(':') {
+        Some((arg, segment)) => (arg, Some(parse_segment_selector(segment)?)),
+        None => (arg, None),
+    };
+    if let Some(name) = arg.strip_prefix("name=") {
+        return parse_track_name_selector(name, segment).map(|selector| vec![selector]);
+    }
     let mut track_parts = arg.splitn(2, ',');
-    let track: usize = track_parts.next()
-        .ok_or_else(|| "expected a ','".to_owned())?
-        .parse()
-        .map_err(|e| format!("bad track number: {}", e))?;
+    let tracks = track_parts.next()
+        .ok_or_else(|| "expected a ','".to_owned())?;
     let channel_rest = track_parts.next()
         .ok_or_else(|| "expected a ','".to_owned())?;
-    let (channel, offset): (u8, i8) = match channel_rest.find(|c| c == '+' || c == '-') {
+    let (any_track, tracks) = match tracks.split_once('-') {
+        _ if tracks == "*" => (true, 0 ..= 0),
+        Some((first, last)) => {
+            let parse = |track: &str| track.parse::<usize>()
+                .map_err(|e| format!("bad track number {:?}: {}", track, e));
+            let (first, last) = (parse(first)?, parse(last)?);
+            if first > last {
+                return Err(format!("the track range {}-{} is backwards", first, last));
+            }
+            (false, first ..= last)
+        }
+        None => {
+            let track = tracks.parse().map_err(|e| format!("bad track number: {}", e))?;
+            (false, track ..= track)
+        }
+    };
+    // A "*" channel stands in for channel 0 until it's filled in, so the rest parses the same.
+    let (any_channel, channel_rest) = match channel_rest.strip_prefix('*') {
+        Some(rest) => (true, format!("0{}", rest)),
+        None => (false, channel_rest.to_owned()),
+    };
+    let (channel_rest, articulation) = match channel_rest.split_once('*') {
+        Some((channel_rest, factor)) => (channel_rest, Some(parse_articulation(factor)?)),
+        None => (channel_rest.as_str(), None),
+    };
+    let (channel, offset): (u8, i8) = match channel_rest.find(['+', '-']) {
         Some(plusminus_pos) => {
             let (channel_str, offset_str) = channel_rest.split_at(plusminus_pos);
             let channel: u8 = channel_str.parse()
@@ -84,9 +1926,91 @@ fn parse_track_selector(arg: &str) -> Result<ChannelSelector, String> {
             (channel, 0)
         }
     };
+    Ok(tracks
+        .map(|midi_track| ChannelSelector {
+            label: String::new(),
+            midi_track,
+            midi_channel: channel,
+            offset,
+            articulation,
+            segment,
+            track_name: None,
+            any_track,
+            any_channel,
+        })
+        .collect())
+}
+
+/// The rest of a "name=<track name>[,channel][+/-offset][*articulation]" selector. Track names
+/// can have commas and hyphens in them, so the optional parts are taken off the end only if they
+/// parse.
+fn parse_track_name_selector(arg: &str, segment: Option<SegmentSelector>)
+    -> Result<ChannelSelector, String>
+{
+    let (arg, articulation) = match arg.rsplit_once('*') {
+        Some((arg, factor)) => (arg, Some(parse_articulation(factor)?)),
+        None => (arg, None),
+    };
+    let (arg, offset) = match arg.rfind(['+', '-']) {
+        Some(pos) => match arg[pos ..].parse::<i8>() {
+            Ok(offset) => (&arg[.. pos], offset),
+            Err(_) => (arg, 0),
+        },
+        None => (arg, 0),
+    };
+    let (name, channel) = match arg.rsplit_once(',') {
+        Some((name, channel)) => match channel.parse::<u8>() {
+            Ok(channel) => (name, Some(channel)),
+            Err(_) => (arg, None),
+        },
+        None => (arg, None),
+    };
+    if name.is_empty() {
+        return Err("expected a track name after \"name=\"".to_owned());
+    }
     Ok(ChannelSelector {
-        midi_track: track,
-        midi_channel: channel,
+        label: String::new(),
+        midi_track: 0,
+        midi_channel: 0,
         offset,
+        articulation,
+        segment,
+        track_name: Some(TrackName { name: name.to_owned(), channel }),
+        any_track: false,
+        any_channel: false,
     })
 }
+
+/// A plain "track,channel" pair.
+fn parse_track_channel(arg: &str) -> Result<(usize, u8), String> {
+    let (track, channel) = arg.split_once(',')
+        .ok_or_else(|| format!("expected a ',' in {:?}", arg))?;
+    let track = track.parse().map_err(|e| format!("bad track number {:?}: {}", track, e))?;
+    let channel = channel.parse()
+        .map_err(|e| format!("bad channel number {:?}: {}", channel, e))?;
+    Ok((track, channel))
+}
+
+fn parse_articulation(arg: &str) -> Result<f64, String> {
+    arg.parse()
+        .ok()
+        .filter(|factor| *factor > 0. && *factor <= 1.)
+        .ok_or_else(|| format!(
+            "articulation must be a number greater than 0 and at most 1, not {:?}", arg))
+}
+
+fn parse_segment_selector(arg: &str) -> Result<SegmentSelector, String> {
+    if let Some(name) = arg.strip_prefix("prog=") {
+        MIDI_PROGRAM.iter()
+            .position(|program| program.eq_ignore_ascii_case(name))
+            .map(|program| SegmentSelector::Program(program as u8))
+            .ok_or_else(|| format!("unknown MIDI instrument {:?}", name))
+    } else if let Some(index) = arg.strip_prefix("seg=") {
+        index.parse()
+            .map(SegmentSelector::Index)
+            .map_err(|e| format!("bad segment number: {}", e))
+    } else {
+        Err(format!("expected \"prog=<instrument>\" or \"seg=<number>\" after ':', not {:?}",
+            arg))
+    }
+}
@@ -0,0 +1,323 @@
+use crate::checks::{self, Failure};
+use crate::config::EditorNote;
+use crate::error::PianoRollError;
+use crate::formatting;
+use crate::note::MidiNote;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+
+/// A problem found while processing the song.
+#[derive(Debug, Clone)]
+pub enum Diagnostic {
+    /// Offsetting a note put it outside of the range of the piano roll, so it was dropped.
+    NoteOutOfRange {
+        timestamp: u64,
+        track: usize,
+        channel: u8,
+        note: MidiNote,
+        offset: i8,
+    },
+    /// A note was pressed while it was already held down by an earlier press.
+    NoteAlreadyPressed {
+        timestamp: u64,
+        track: usize,
+        channel: u8,
+        note: MidiNote,
+        prev_timestamp: u64,
+        prev_track: usize,
+        prev_channel: u8,
+    },
+    /// A note was released without being held down.
+    NoteNotPressed {
+        timestamp: u64,
+        track: usize,
+        channel: u8,
+        note: MidiNote,
+    },
+    /// A note was pressed and never released, so it was dropped.
+    StuckNote {
+        timestamp: u64,
+        track: usize,
+        channel: u8,
+        note: MidiNote,
+    },
+    /// A phrase went off the roll, and no octave shift could fit all of it back on, so it was
+    /// dropped. The note is the first of the phrase.
+    PhraseDropped {
+        timestamp: u64,
+        end: u64,
+        track: usize,
+        channel: u8,
+        note: MidiNote,
+        notes: usize,
+    },
+}
+
+/// Stable identifiers for each kind of diagnostic, for machine-readable output.
+///
+/// These strings are part of the `--diagnostics-json` format: don't change them, only add new
+/// ones.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Serialize)]
+pub enum Code {
+    #[serde(rename = "out-of-range")]
+    OutOfRange,
+    #[serde(rename = "overlap")]
+    Overlap,
+    #[serde(rename = "not-pressed")]
+    NotPressed,
+    #[serde(rename = "stuck-note")]
+    StuckNote,
+    #[serde(rename = "phrase-dropped")]
+    PhraseDropped,
+    /// An editor's note given with `--note`, rather than a problem found.
+    #[serde(rename = "editorial")]
+    Editorial,
+}
+
+impl Code {
+    /// The same identifier as in the JSON output.
+    pub fn name(self) -> &'static str {
+        match self {
+            Code::OutOfRange => "out-of-range",
+            Code::Overlap => "overlap",
+            Code::NotPressed => "not-pressed",
+            Code::StuckNote => "stuck-note",
+            Code::PhraseDropped => "phrase-dropped",
+            Code::Editorial => "editorial",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Note,
+}
+
+impl Severity {
+    fn prefix(self) -> &'static str {
+        match self {
+            Severity::Error => "ERROR",
+            Severity::Note => "NOTE",
+        }
+    }
+}
+
+impl Diagnostic {
+    pub fn code(&self) -> Code {
+        match self {
+            Diagnostic::NoteOutOfRange { .. } => Code::OutOfRange,
+            Diagnostic::NoteAlreadyPressed { .. } => Code::Overlap,
+            Diagnostic::NoteNotPressed { .. } => Code::NotPressed,
+            Diagnostic::StuckNote { .. } => Code::StuckNote,
+            Diagnostic::PhraseDropped { .. } => Code::PhraseDropped,
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        match *self {
+            Diagnostic::NoteOutOfRange { timestamp, .. }
+                | Diagnostic::NoteAlreadyPressed { timestamp, .. }
+                | Diagnostic::NoteNotPressed { timestamp, .. }
+                | Diagnostic::StuckNote { timestamp, .. }
+                | Diagnostic::PhraseDropped { timestamp, .. } => timestamp,
+        }
+    }
+
+    pub fn track(&self) -> usize {
+        match *self {
+            Diagnostic::NoteOutOfRange { track, .. }
+                | Diagnostic::NoteAlreadyPressed { track, .. }
+                | Diagnostic::NoteNotPressed { track, .. }
+                | Diagnostic::StuckNote { track, .. }
+                | Diagnostic::PhraseDropped { track, .. } => track,
+        }
+    }
+
+    pub fn channel(&self) -> u8 {
+        match *self {
+            Diagnostic::NoteOutOfRange { channel, .. }
+                | Diagnostic::NoteAlreadyPressed { channel, .. }
+                | Diagnostic::NoteNotPressed { channel, .. }
+                | Diagnostic::StuckNote { channel, .. }
+                | Diagnostic::PhraseDropped { channel, .. } => channel,
+        }
+    }
+
+    pub fn note(&self) -> MidiNote {
+        match *self {
+            Diagnostic::NoteOutOfRange { note, .. }
+                | Diagnostic::NoteAlreadyPressed { note, .. }
+                | Diagnostic::NoteNotPressed { note, .. }
+                | Diagnostic::StuckNote { note, .. }
+                | Diagnostic::PhraseDropped { note, .. } => note,
+        }
+    }
+
+    /// The human-readable description, without the severity prefix.
+    pub fn message(&self) -> String {
+        match self {
+            Diagnostic::NoteOutOfRange { timestamp, track, channel, note, offset } => format!(
+                "at {}, offsetting note {:?} on track {} channel {} by {} puts it outside of \
+                piano roll range",
+                timestamp, note, track, channel, offset),
+            Diagnostic::NoteAlreadyPressed {
+                timestamp, track, channel, note, prev_timestamp, prev_track, prev_channel,
+            } => format!(
+                "at {}, note {:?} on track {} channel {} already pressed at {} by {},{}",
+                timestamp, note, track, channel, prev_timestamp, prev_track, prev_channel),
+            Diagnostic::NoteNotPressed { timestamp, track, channel, note } => format!(
+                "at {} on track {} channel {}, note {:?} is not pressed yet",
+                timestamp, track, channel, note),
+            Diagnostic::StuckNote { timestamp, track, channel, note } => format!(
+                "at {} on track {} channel {}, note {:?} is pressed and never released",
+                timestamp, track, channel, note),
+            Diagnostic::PhraseDropped { timestamp, end, track, channel, note, notes } => format!(
+                "from {} to {} on track {} channel {}, the phrase of {} notes starting with {:?} \
+                can't be moved by octaves to fit on the roll, so it was dropped",
+                timestamp, end, track, channel, notes, note),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.severity().prefix(), self.message())
+    }
+}
+
+/// Lines to print for the diagnostics, with repeats cut short.
+///
+/// Diagnostics with the same code, track, channel, and note are repeats of each other. Each is
+/// printed in order until `max_repeats` of it have been, and then the rest are counted up in one
+/// line at the end, giving the first and last tick it happened at.
+pub fn aggregated_report(diagnostics: &[Diagnostic], max_repeats: usize) -> Vec<String> {
+    struct Repeats {
+        severity: Severity,
+        count: usize,
+        first: u64,
+        last: u64,
+    }
+
+    let mut lines = vec![];
+    // Kept in order of first occurrence, so the summary lines come out in a stable order.
+    let mut groups = Vec::<((Code, usize, u8, MidiNote), Repeats)>::new();
+    for diagnostic in diagnostics {
+        let key = (diagnostic.code(), diagnostic.track(), diagnostic.channel(), diagnostic.note());
+        let repeats = match groups.iter_mut().find(|(group, _)| *group == key) {
+            Some((_, repeats)) => repeats,
+            None => {
+                groups.push((key, Repeats {
+                    severity: diagnostic.severity(),
+                    count: 0,
+                    first: diagnostic.timestamp(),
+                    last: 0,
+                }));
+                &mut groups.last_mut().unwrap().1
+            }
+        };
+        repeats.count += 1;
+        repeats.last = diagnostic.timestamp();
+        if repeats.count <= max_repeats {
+            lines.push(diagnostic.to_string());
+        }
+    }
+
+    for ((code, track, channel, note), repeats) in groups {
+        if repeats.count > max_repeats {
+            lines.push(format!("{}: {} more {} errors for note {:?} on track {} channel {} \
+                ({} in all, from {} to {})",
+                repeats.severity.prefix(), formatting::count((repeats.count - max_repeats) as u64),
+                code.name(), note, track, channel, formatting::count(repeats.count as u64),
+                repeats.first, repeats.last));
+        }
+    }
+    lines
+}
+
+#[derive(Serialize)]
+struct JsonDiagnostic {
+    severity: Severity,
+    code: Code,
+    tick: u64,
+    beat: f64,
+    /// Editor's notes are about a measure, not any particular note, so they have none of these.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    track: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    note: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    note_name: Option<String>,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    diagnostics: Vec<JsonDiagnostic>,
+    summary: BTreeMap<Code, usize>,
+    /// Every failed check, whether an error or a warning.
+    checks: &'a [Failure],
+    /// The exit status the checks give; see `checks::Category::exit_bit`.
+    exit_code: i32,
+}
+
+/// Write all the diagnostics to a JSON file, followed by the editor's notes at the ticks their
+/// measures start, along with a count of each kind and the results of the checks.
+pub fn write_json(
+    path: &Path,
+    diagnostics: &[Diagnostic],
+    editor_notes: &[(u64, &EditorNote)],
+    checks: &checks::Report,
+    time_base: u16,
+) -> Result<(), PianoRollError> {
+    let mut summary = BTreeMap::new();
+    for diagnostic in diagnostics {
+        *summary.entry(diagnostic.code()).or_insert(0) += 1;
+    }
+    if !editor_notes.is_empty() {
+        summary.insert(Code::Editorial, editor_notes.len());
+    }
+    let report = JsonReport {
+        diagnostics: diagnostics.iter()
+            .map(|diagnostic| JsonDiagnostic {
+                severity: diagnostic.severity(),
+                code: diagnostic.code(),
+                tick: diagnostic.timestamp(),
+                beat: diagnostic.timestamp() as f64 / f64::from(time_base),
+                track: Some(diagnostic.track()),
+                channel: Some(diagnostic.channel()),
+                note: Some(diagnostic.note().as_u8()),
+                note_name: Some(format!("{:?}", diagnostic.note())),
+                message: diagnostic.message(),
+            })
+            .chain(editor_notes.iter().map(|&(tick, note)| JsonDiagnostic {
+                severity: Severity::Note,
+                code: Code::Editorial,
+                tick,
+                beat: tick as f64 / f64::from(time_base),
+                track: None,
+                channel: None,
+                note: None,
+                note_name: None,
+                message: format!("m{}: {}", note.measure, note.text),
+            }))
+            .collect(),
+        summary,
+        checks: checks.failures(),
+        exit_code: checks.exit_code(),
+    };
+    let error = |source| PianoRollError::Write { path: path.to_owned(), source };
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(path).map_err(error)?);
+    serde_json::to_writer_pretty(&mut writer, &report).map_err(|e| error(e.into()))?;
+    writer.flush().map_err(error)
+}
@@ -0,0 +1,109 @@
+//! The error type for reading and writing MIDI files, parsing the command line, and drawing the
+//! roll, so that callers can tell what kind of thing went wrong without picking apart a message.
+
+use crate::note::MidiNote;
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum PianoRollError {
+    /// A file couldn't be opened or read.
+    Read { path: PathBuf, source: std::io::Error },
+    /// A file couldn't be created or written.
+    Write { path: PathBuf, source: std::io::Error },
+    /// A MIDI file was read but isn't valid.
+    MidiParse { path: PathBuf, detail: String },
+    /// The notes can't be represented in a MIDI file.
+    MidiWrite { path: PathBuf, detail: String },
+    /// The command line or project file is wrong.
+    ConfigParse(String),
+    /// The MIDI file doesn't say how many ticks there are in a beat.
+    NoTimeBase,
+    /// The MIDI file doesn't set a tempo, and no default was given.
+    NoTempo,
+    /// None of the track selectors matched any notes.
+    NoNotesSelected,
+    /// The PDF couldn't be created or written.
+    PdfWrite { path: PathBuf, source: std::io::Error },
+    /// With `--strict-range`, the selected notes span more notes than the roll has, so no
+    /// transposition can fit them all.
+    NoteOutOfRange { lowest: MidiNote, highest: MidiNote },
+    /// A note map file was read but isn't valid.
+    NoteMapParse { path: PathBuf, detail: String },
+    /// The MIDI file written with `--self-check` doesn't have the notes it should.
+    SelfCheck(String),
+    /// The roll can't be laid out at the time divisor or length asked for.
+    RollLength(String),
+}
+
+impl PianoRollError {
+    /// The exit status for the error. It's always odd: the lowest bit says that something went
+    /// wrong other than a failed check (see `checks::Category::exit_bit`), and the rest say what.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            PianoRollError::ConfigParse(_) => 1,
+            PianoRollError::Read { .. } => 3,
+            PianoRollError::Write { .. } => 5,
+            PianoRollError::MidiParse { .. } => 7,
+            PianoRollError::MidiWrite { .. } => 9,
+            PianoRollError::NoTimeBase => 11,
+            PianoRollError::NoTempo => 13,
+            PianoRollError::NoNotesSelected => 15,
+            PianoRollError::PdfWrite { .. } => 17,
+            PianoRollError::NoteOutOfRange { .. } => 19,
+            PianoRollError::NoteMapParse { .. } => 21,
+            PianoRollError::SelfCheck(_) => 23,
+            PianoRollError::RollLength(_) => 25,
+        }
+    }
+}
+
+impl fmt::Display for PianoRollError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PianoRollError::Read { path, source } => {
+                write!(f, "failed to read {:?}: {}", path, source)
+            }
+            PianoRollError::Write { path, source } => {
+                write!(f, "failed to write {:?}: {}", path, source)
+            }
+            PianoRollError::MidiParse { path, detail } => {
+                write!(f, "failed to parse MIDI file {:?}: {}", path, detail)
+            }
+            PianoRollError::MidiWrite { path, detail } => {
+                write!(f, "can't write MIDI file {:?}: {}", path, detail)
+            }
+            PianoRollError::ConfigParse(message) => f.write_str(message),
+            PianoRollError::NoTimeBase => f.write_str("no time base set in MIDI file"),
+            PianoRollError::NoTempo => {
+                f.write_str("no tempo set in MIDI file; give one with --default-tempo")
+            }
+            PianoRollError::NoNotesSelected => f.write_str("no notes selected"),
+            PianoRollError::PdfWrite { path, source } => {
+                write!(f, "failed to write PDF file {:?}: {}", path, source)
+            }
+            PianoRollError::NoteOutOfRange { lowest, highest } => {
+                write!(f, "selected notes {:?} to {:?} span {} notes, but the roll only has {}; \
+                    no transposition can fit them all. Try offsetting some of the track \
+                    selectors by an octave.", lowest, highest,
+                    highest.as_u8() - lowest.as_u8() + 1, MidiNote::PIANOROLL_NOTES)
+            }
+            PianoRollError::NoteMapParse { path, detail } => {
+                write!(f, "failed to parse note map {:?}: {}", path, detail)
+            }
+            PianoRollError::SelfCheck(message) => write!(f, "self-check failed: {}", message),
+            PianoRollError::RollLength(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for PianoRollError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PianoRollError::Read { source, .. }
+                | PianoRollError::Write { source, .. }
+                | PianoRollError::PdfWrite { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
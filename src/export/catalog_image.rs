@@ -0,0 +1,161 @@
+use crate::error::PianoRollError;
+use crate::geometry::{hole_left, row_offset, HOLE_WIDTH, PAGE_MARGIN, PAGE_WIDTH, POINTS_PER_INCH};
+use crate::label::{self, Label, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::midi::NoteWithDuration;
+use std::path::Path;
+
+const PAPER: [f32; 3] = [236., 224., 196.];
+const HOLE: [f32; 3] = [62., 52., 40.];
+const EDGE: [f32; 3] = [170., 150., 115.];
+
+/// Pixel coverage of everything drawn so far, from 0 (bare paper) to 1 (solid).
+struct Coverage {
+    width: usize,
+    height: usize,
+    holes: Vec<f32>,
+    edges: Vec<f32>,
+}
+
+impl Coverage {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            holes: vec![0.; width * height],
+            edges: vec![0.; width * height],
+        }
+    }
+
+    /// Add a rectangle, in pixels, crediting each pixel with the fraction of it that's covered.
+    /// This is a box filter, so holes much smaller than a pixel still show up as a tint.
+    fn add(layer: &mut [f32], width: usize, height: usize, x: f32, y: f32, w: f32, h: f32) {
+        let x_end = (x + w).min(width as f32);
+        let y_end = (y + h).min(height as f32);
+        let x = x.max(0.);
+        let y = y.max(0.);
+        let mut row = y.floor();
+        while row < y_end {
+            let row_cover = (row + 1.).min(y_end) - row.max(y);
+            let mut column = x.floor();
+            while column < x_end {
+                let column_cover = (column + 1.).min(x_end) - column.max(x);
+                let pixel = &mut layer[row as usize * width + column as usize];
+                *pixel = (*pixel + row_cover * column_cover).min(1.);
+                column += 1.;
+            }
+            row += 1.;
+        }
+    }
+
+    fn hole(&mut self, x: f32, y: f32, w: f32, h: f32) {
+        Self::add(&mut self.holes, self.width, self.height, x, y, w, h);
+    }
+
+    fn edge(&mut self, x: f32, y: f32, w: f32, h: f32) {
+        Self::add(&mut self.edges, self.width, self.height, x, y, w, h);
+    }
+
+    fn rgb(&self) -> Vec<u8> {
+        let mix = |base: [f32; 3], color: [f32; 3], amount: f32| {
+            [0, 1, 2].map(|i| base[i] + (color[i] - base[i]) * amount)
+        };
+        self.holes.iter().zip(&self.edges)
+            .flat_map(|(&hole, &edge)| mix(mix(PAPER, EDGE, edge), HOLE, hole))
+            .map(|channel| channel.round() as u8)
+            .collect()
+    }
+}
+
+/// Draw a picture of the roll for a catalog: the title at the top in dot-matrix letters, then the
+/// roll from its start downward, on paper-colored background with the edges of the roll marked.
+///
+/// The image is `width` pixels across the full width of the roll, and as long as it needs to be
+/// to show the whole roll, or only the first `max_feet` of it. Odd channels' holes are moved
+/// `odd_row_offset` points further along, as for `geometry::row_offset`.
+#[allow(clippy::too_many_arguments)]
+pub fn write_png(
+    path: &Path,
+    notes: &[NoteWithDuration],
+    time_divisor: f64,
+    odd_row_offset: f32,
+    roll_label: &Label,
+    title: Option<&str>,
+    width: u32,
+    max_feet: Option<f64>,
+) -> Result<(), PianoRollError> {
+    let scale = width as f32 / PAGE_WIDTH;
+    let leader = f64::from(roll_label.leader_length);
+    let position = |ticks: u64| leader + ticks as f64 / time_divisor;
+    let mut roll_length = notes.iter()
+        .map(|note| position(note.timestamp + note.duration) + f64::from(odd_row_offset))
+        .fold(leader, f64::max);
+    if let Some(feet) = max_feet {
+        roll_length = roll_length.min(feet * 12. * f64::from(POINTS_PER_INCH));
+    }
+
+    let title = title.unwrap_or("").to_uppercase();
+    let dot = (scale * 3.).max(1.);
+    // Two blank rows of dots above and below the title.
+    let title_height = if title.is_empty() {
+        0.
+    } else {
+        dot * f32::from(GLYPH_HEIGHT + 4)
+    };
+    let height = (title_height + roll_length as f32 * scale).ceil().max(1.) as u32;
+    let mut coverage = Coverage::new(width as usize, height as usize);
+
+    // Skip anything that can't be drawn rather than failing; it's only a picture.
+    let glyphs = title.chars()
+        .map(|c| label::glyph(c).unwrap_or(label::glyph(' ').unwrap()))
+        .collect::<Vec<_>>();
+    let glyph_pitch = usize::from(GLYPH_WIDTH + 1);
+    let title_width = (glyphs.len() * glyph_pitch) as f32 * dot;
+    let title_left = (width as f32 - title_width).max(0.) / 2.;
+    for (i, rows) in glyphs.iter().enumerate() {
+        for (row, bits) in rows.iter().enumerate() {
+            for column in 0 .. usize::from(GLYPH_WIDTH) {
+                if bits & (1 << (usize::from(GLYPH_WIDTH) - 1 - column)) != 0 {
+                    coverage.hole(
+                        title_left + (i * glyph_pitch + column) as f32 * dot,
+                        (row + 2) as f32 * dot,
+                        dot * 0.8,
+                        dot * 0.8);
+                }
+            }
+        }
+    }
+
+    let roll_top = title_height;
+    let y = |points: f64| roll_top + points as f32 * scale;
+    let edge_width = (scale * 0.5).max(1.);
+    for x in [PAGE_MARGIN, PAGE_WIDTH - PAGE_MARGIN] {
+        coverage.edge(x * scale - edge_width / 2., roll_top, edge_width, height as f32 - roll_top);
+    }
+    for punch in &roll_label.punches {
+        // The label reads upright with the music after it, which is upward on the PDF page but
+        // downward here, so flip it within the leader.
+        let start = f64::from(roll_label.leader_length - punch.y - punch.height);
+        coverage.hole(hole_left(punch.channel) * scale, y(start), HOLE_WIDTH * scale,
+            punch.height * scale);
+    }
+    for note in notes {
+        let channel = note.note.pianoroll_channel().expect("note out of range");
+        let row = f64::from(row_offset(channel, odd_row_offset));
+        let start = position(note.timestamp) + row;
+        let end = position(note.timestamp + note.duration) + row;
+        if start >= roll_length {
+            continue;
+        }
+        coverage.hole(hole_left(channel) * scale, y(start), HOLE_WIDTH * scale,
+            (end - start) as f32 * scale);
+    }
+
+    let error = |source| PianoRollError::Write { path: path.to_owned(), source };
+    let file = std::fs::File::create(path).map_err(error)?;
+    let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()
+        .and_then(|mut writer| writer.write_image_data(&coverage.rgb()))
+        .map_err(|e| error(e.into()))
+}
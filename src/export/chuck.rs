@@ -0,0 +1,65 @@
+use crate::error::PianoRollError;
+use crate::midi::NoteWithDuration;
+use crate::tempo::TempoMap;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Each note is played by its own shred, so this sets up the instrument for one note.
+const PLAY_FUNCTION: &str = "\
+fun void play(int note, int velocity, dur length)
+{
+    SinOsc s => ADSR e => dac;
+    Std.mtof(note) => s.freq;
+    velocity / 127.0 * 0.1 => s.gain;
+    e.set(5::ms, 10::ms, 0.8, 20::ms);
+    e.keyOn();
+    length => now;
+    e.keyOff();
+    e.releaseTime() => now;
+}
+";
+
+/// Write a ChucK program that plays the notes on sine oscillators, runnable with `chuck`.
+pub fn write_program(
+    path: &Path,
+    notes: &[NoteWithDuration],
+    tempo_map: &TempoMap,
+    title: Option<&str>,
+) -> Result<(), PianoRollError> {
+    let mut program = String::new();
+    program_text(&mut program, notes, tempo_map, title)
+        .expect("formatting to a String can't fail");
+    std::fs::write(path, program)
+        .map_err(|source| PianoRollError::Write { path: path.to_owned(), source })
+}
+
+fn program_text(
+    out: &mut String,
+    notes: &[NoteWithDuration],
+    tempo_map: &TempoMap,
+    title: Option<&str>,
+) -> std::fmt::Result {
+    let seconds = |timestamp: u64| tempo_map.micros_at(timestamp) / 1_000_000.;
+
+    if let Some(title) = title {
+        writeln!(out, "// {}", title)?;
+    }
+    writeln!(out, "// tempo: {} beats per minute", 60_000_000 / tempo_map.tempo_at(0))?;
+    writeln!(out)?;
+    writeln!(out, "{}", PLAY_FUNCTION)?;
+    writeln!(out, "now => time start;")?;
+
+    let mut notes = notes.iter().collect::<Vec<_>>();
+    notes.sort_by_key(|note| note.timestamp);
+    let mut end = 0.;
+    for note in notes {
+        let start = seconds(note.timestamp);
+        let length = seconds(note.timestamp + note.duration) - start;
+        writeln!(out, "start + {:.6}::second => now; spork ~ play({}, {}, {:.6}::second);",
+            start, note.note.as_u8(), note.velocity_or_default(), length)?;
+        end = f64::max(end, start + length);
+    }
+    writeln!(out)?;
+    writeln!(out, "// Keep running until the last notes finish.")?;
+    writeln!(out, "start + {:.6}::second + 100::ms => now;", end)
+}
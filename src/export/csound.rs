@@ -0,0 +1,43 @@
+use crate::error::PianoRollError;
+use crate::midi::NoteWithDuration;
+use crate::tempo::TempoMap;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Write a Csound score with one `i1` statement per note.
+///
+/// Start times and durations are in seconds (Csound's default tempo of 60 beats per minute makes
+/// a beat one second long), p4 is the MIDI note number, p5 the velocity, and p6 the pitch in
+/// Csound's octave.pitch-class notation.
+pub fn write_score(
+    path: &Path,
+    notes: &[NoteWithDuration],
+    tempo_map: &TempoMap,
+    title: Option<&str>,
+) -> Result<(), PianoRollError> {
+    let mut score = String::new();
+    score_text(&mut score, notes, tempo_map, title).expect("formatting to a String can't fail");
+    std::fs::write(path, score)
+        .map_err(|source| PianoRollError::Write { path: path.to_owned(), source })
+}
+
+fn score_text(
+    out: &mut String,
+    notes: &[NoteWithDuration],
+    tempo_map: &TempoMap,
+    title: Option<&str>,
+) -> std::fmt::Result {
+    if let Some(title) = title {
+        writeln!(out, "; {}", title)?;
+    }
+    writeln!(out, "; tempo: {} beats per minute", 60_000_000 / tempo_map.tempo_at(0))?;
+    writeln!(out, "; p4: MIDI note number, p5: velocity, p6: pitch (octave.pitch-class)")?;
+    for note in notes {
+        let start = tempo_map.micros_at(note.timestamp) / 1_000_000.;
+        let end = tempo_map.micros_at(note.timestamp + note.duration) / 1_000_000.;
+        writeln!(out, "i1 {:.6} {:.6} {} {} {}.{:02}",
+            start, end - start, note.note.as_u8(), note.velocity_or_default(),
+            note.note.octave() + 4, note.note.pitch_class())?;
+    }
+    writeln!(out, "e")
+}
@@ -0,0 +1,49 @@
+use crate::error::PianoRollError;
+use crate::midi::NoteWithDuration;
+use crate::note::MidiNote;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Finale measures durations in EDUs ("Enigma durational units"), 1024 to the quarter note.
+const EDUS_PER_BEAT: u64 = 1024;
+
+/// Write a minimal Finale Enigma Transportable File: one page, a piano grand staff in C major and
+/// 4/4, and an `^Entry^` record for each note.
+///
+/// Entries give the staff (1 for treble, 2 for bass, split at middle C), the start and duration
+/// in EDUs, the MIDI note number, and the layer, which is always 1.
+pub fn write_etf(
+    path: &Path,
+    notes: &[NoteWithDuration],
+    time_base: u16,
+    title: Option<&str>,
+) -> Result<(), PianoRollError> {
+    let mut etf = String::new();
+    etf_text(&mut etf, notes, time_base, title).expect("formatting to a String can't fail");
+    std::fs::write(path, etf)
+        .map_err(|source| PianoRollError::Write { path: path.to_owned(), source })
+}
+
+fn etf_text(
+    out: &mut String,
+    notes: &[NoteWithDuration],
+    time_base: u16,
+    title: Option<&str>,
+) -> std::fmt::Result {
+    let edus = |ticks: u64| {
+        (ticks * EDUS_PER_BEAT + u64::from(time_base) / 2) / u64::from(time_base)
+    };
+    writeln!(out, "^title({})", title.unwrap_or(""))?;
+    writeln!(out, "^ScorePageLayout^ 1 portrait")?;
+    writeln!(out, "^StaffSpec^ 1 treble")?;
+    writeln!(out, "^StaffSpec^ 2 bass")?;
+    writeln!(out, "^KeySig^ 0")?;
+    writeln!(out, "^TimeSig^ 4 4")?;
+    for note in notes {
+        let staff = if note.note >= MidiNote::C4 { 1 } else { 2 };
+        let start = edus(note.timestamp);
+        let end = edus(note.timestamp + note.duration);
+        writeln!(out, "^Entry^ {} {} {} {} 1", staff, start, end - start, note.note.as_u8())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use crate::audit::{Audit, NoteState};
+use crate::error::PianoRollError;
+use crate::geometry::{row_offset, POINTS_PER_INCH};
+use crate::label::Label;
+use crate::midi::NoteWithDuration;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+#[derive(Serialize)]
+struct JsonState {
+    tick: u64,
+    duration_ticks: u64,
+    note: u8,
+    note_name: String,
+}
+
+impl From<NoteState> for JsonState {
+    fn from(state: NoteState) -> Self {
+        Self {
+            tick: state.timestamp,
+            duration_ticks: state.duration,
+            note: state.note.as_u8(),
+            note_name: format!("{:?}", state.note),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonStep {
+    transform: String,
+    before: JsonState,
+    /// Missing if the transform removed the note.
+    after: Option<JsonState>,
+}
+
+#[derive(Serialize)]
+struct Placement {
+    pianoroll_channel: u8,
+    start_inches: f64,
+    length_inches: f64,
+}
+
+#[derive(Serialize)]
+struct JsonNote {
+    source_event: usize,
+    track: usize,
+    channel: u8,
+    tick: u64,
+    note: u8,
+    note_name: String,
+    velocity: u8,
+    steps: Vec<JsonStep>,
+    /// Where the note's hole is on the roll, or nothing if it didn't make it there.
+    roll: Option<Placement>,
+}
+
+/// Write the history of every note to a JSON file: how it was in the song, each change made to
+/// it on the way to the roll, and where its hole ended up, worked out as for `notes_csv`.
+pub fn write_json(
+    path: &Path,
+    audit: &Audit,
+    roll_notes: &[NoteWithDuration],
+    time_divisor: f64,
+    odd_row_offset: f32,
+    label: &Label,
+) -> Result<(), PianoRollError> {
+    let leader = f64::from(label.leader_length);
+    let inches = |points: f64| points / f64::from(POINTS_PER_INCH);
+    let mut placements = roll_notes.iter()
+        .filter_map(|note| {
+            let channel = note.note.pianoroll_channel().expect("note out of range");
+            let start = leader + note.timestamp as f64 / time_divisor
+                + f64::from(row_offset(channel, odd_row_offset));
+            Some(((note.source?, note.note), Placement {
+                pianoroll_channel: channel,
+                start_inches: inches(start),
+                length_inches: inches(note.duration as f64 / time_divisor),
+            }))
+        })
+        .collect::<BTreeMap<_, _>>();
+    let notes = audit.histories()
+        .map(|(key, history)| JsonNote {
+            source_event: key.0,
+            track: history.original.track,
+            channel: history.original.channel,
+            tick: history.original.timestamp,
+            note: history.original.note.as_u8(),
+            note_name: format!("{:?}", history.original.note),
+            velocity: history.original.velocity,
+            steps: history.steps.iter()
+                .map(|step| JsonStep {
+                    transform: step.transform.clone(),
+                    before: step.before.into(),
+                    after: step.after.map(JsonState::from),
+                })
+                .collect(),
+            roll: placements.remove(key),
+        })
+        .collect::<Vec<_>>();
+
+    let error = |source| PianoRollError::Write { path: path.to_owned(), source };
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(path).map_err(error)?);
+    serde_json::to_writer_pretty(&mut writer, &notes).map_err(|e| error(e.into()))?;
+    writer.flush().map_err(error)
+}
@@ -0,0 +1,11 @@
+//! Writers for formats other than the PDF roll and the preview MIDI file.
+
+pub mod catalog_image;
+pub mod chuck;
+pub mod csound;
+pub mod etf;
+pub mod fidelity;
+pub mod notes_csv;
+pub mod segments;
+pub mod supercollider;
+pub mod tracks;
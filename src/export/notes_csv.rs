@@ -0,0 +1,36 @@
+use crate::error::PianoRollError;
+use crate::geometry::{row_offset, POINTS_PER_INCH};
+use crate::label::Label;
+use crate::midi::NoteWithDuration;
+use std::fmt::Write as _;
+use std::path::Path;
+
+/// Write a row for each note on the roll to a CSV file, with where its hole starts and how long
+/// it is, for checking against a scan of another roll. Positions are in inches from the start of
+/// the roll, worked out the same way as for drawing it: after the label's leader, and with odd
+/// channels' holes moved `odd_row_offset` points further along. Channels are numbered from
+/// `channel_base`.
+pub fn write_csv(
+    path: &Path,
+    notes: &[NoteWithDuration],
+    time_divisor: f64,
+    odd_row_offset: f32,
+    label: &Label,
+    channel_base: u8,
+) -> Result<(), PianoRollError> {
+    let leader = f64::from(label.leader_length);
+    let inches = |points: f64| points / f64::from(POINTS_PER_INCH);
+    let mut csv = String::from("timestamp_ticks,duration_ticks,start_inches,length_inches,\
+        midi_note,note_name,pianoroll_channel,source_track,source_channel\n");
+    for note in notes {
+        let channel = note.note.pianoroll_channel().expect("note out of range");
+        let start = leader + note.timestamp as f64 / time_divisor
+            + f64::from(row_offset(channel, odd_row_offset));
+        writeln!(csv, "{},{},{:.4},{:.4},{},{:?},{},{},{}",
+            note.timestamp, note.duration, inches(start),
+            inches(note.duration as f64 / time_divisor), note.note.as_u8(), note.note, channel,
+            note.track, note.channel + channel_base).unwrap();
+    }
+    std::fs::write(path, csv)
+        .map_err(|source| PianoRollError::Write { path: path.to_owned(), source })
+}
@@ -0,0 +1,93 @@
+use crate::error::PianoRollError;
+use crate::geometry::{hole_center, row_offset, MM_PER_POINT};
+use crate::label::Label;
+use crate::midi::NoteWithDuration;
+use serde::Serialize;
+use std::io::Write;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Hole {
+    pub channel: u8,
+    /// Center of the hole, from the left edge of the paper.
+    pub x_mm: f64,
+    /// Start and end of the hole, from the start of the roll.
+    pub y_start_mm: f64,
+    pub y_end_mm: f64,
+}
+
+#[derive(Serialize)]
+struct Segments<'a> {
+    holes: &'a [Hole],
+}
+
+/// How long a perforator takes to punch a roll.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PunchingTime {
+    pub punches: usize,
+    /// How far the paper is fed, in inches.
+    pub feed_inches: f64,
+    pub seconds: f64,
+}
+
+/// The position of every hole in the roll, in the order they reach the machine as the paper feeds
+/// through it. The label's punches come first, and the music follows its leader. Odd channels'
+/// holes are moved `odd_row_offset` points further along, as for `geometry::row_offset`.
+pub fn holes(notes: &[NoteWithDuration], time_divisor: f64, odd_row_offset: f32, label: &Label)
+    -> Vec<Hole>
+{
+    let leader = f64::from(label.leader_length);
+    let mm = |points: f64| points * f64::from(MM_PER_POINT);
+    let label_holes = label.punches.iter()
+        .map(|punch| Hole {
+            channel: punch.channel,
+            x_mm: f64::from(hole_center(punch.channel) * MM_PER_POINT),
+            y_start_mm: mm(f64::from(punch.y)),
+            y_end_mm: mm(f64::from(punch.y + punch.height)),
+        });
+    let position = |ticks: u64| leader + ticks as f64 / time_divisor;
+    let mut holes = notes.iter()
+        .map(|note| {
+            let channel = note.note.pianoroll_channel().expect("note out of range");
+            let row = f64::from(row_offset(channel, odd_row_offset));
+            Hole {
+                channel,
+                x_mm: f64::from(hole_center(channel) * MM_PER_POINT),
+                y_start_mm: mm(position(note.timestamp) + row),
+                y_end_mm: mm(position(note.timestamp + note.duration) + row),
+            }
+        })
+        .chain(label_holes)
+        .collect::<Vec<_>>();
+    holes.sort_by(|a, b| a.y_start_mm.total_cmp(&b.y_start_mm)
+        .then(a.channel.cmp(&b.channel)));
+    holes
+}
+
+/// Estimate how long it takes to punch the holes, in the order given, with one stroke of the punch
+/// for each hole, and the paper fed from the start of the roll to each hole in turn, backwards
+/// too if the order calls for it.
+pub fn punching_time(holes: &[Hole], punches_per_second: f64, inches_per_second: f64)
+    -> PunchingTime
+{
+    let mut position = 0.;
+    let mut feed_mm = 0.;
+    for hole in holes {
+        feed_mm += (hole.y_start_mm - position).abs();
+        position = hole.y_start_mm;
+    }
+    let feed_inches = feed_mm / 25.4;
+    PunchingTime {
+        punches: holes.len(),
+        feed_inches,
+        seconds: holes.len() as f64 / punches_per_second + feed_inches / inches_per_second,
+    }
+}
+
+/// Write the holes to a JSON file for a cutting machine.
+pub fn write_json(path: &Path, holes: &[Hole]) -> Result<(), PianoRollError> {
+    let error = |source| PianoRollError::Write { path: path.to_owned(), source };
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(path).map_err(error)?);
+    serde_json::to_writer_pretty(&mut writer, &Segments { holes }).map_err(|e| error(e.into()))?;
+    writer.flush().map_err(error)
+}
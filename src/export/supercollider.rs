@@ -0,0 +1,91 @@
+use crate::error::PianoRollError;
+use crate::midi::NoteWithDuration;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Write a SuperCollider 3 script that plays the notes as a `Ppar` of `Pbind`s, one per MIDI note
+/// number.
+///
+/// Durations are in beats, so the song plays at the given tempo (in microseconds per beat).
+pub fn write_pattern(
+    path: &Path,
+    notes: &[NoteWithDuration],
+    time_base: u16,
+    tempo: u32,
+    title: Option<&str>,
+) -> Result<(), PianoRollError> {
+    let mut script = String::new();
+    pattern_text(&mut script, notes, time_base, tempo, title)
+        .expect("formatting to a String can't fail");
+    std::fs::write(path, script)
+        .map_err(|source| PianoRollError::Write { path: path.to_owned(), source })
+}
+
+fn pattern_text(
+    out: &mut String,
+    notes: &[NoteWithDuration],
+    time_base: u16,
+    tempo: u32,
+    title: Option<&str>,
+) -> std::fmt::Result {
+    let beats = |ticks: u64| ticks as f64 / f64::from(time_base);
+    let amp = |note: &NoteWithDuration| f64::from(note.velocity_or_default()) / 127.;
+
+    let mut by_note = BTreeMap::<u8, Vec<&NoteWithDuration>>::new();
+    for note in notes {
+        by_note.entry(note.note.as_u8()).or_default().push(note);
+    }
+
+    if let Some(title) = title {
+        writeln!(out, "// {}", title)?;
+    }
+    writeln!(out, "TempoClock.default.tempo = {:.4} / 60;", 60_000_000. / f64::from(tempo))?;
+    writeln!(out)?;
+    writeln!(out, "Ppar([")?;
+    let count = by_note.len();
+    for (n, (midinote, mut notes)) in by_note.into_iter().enumerate() {
+        notes.sort_by_key(|note| note.timestamp);
+
+        let mut midinotes = vec![];
+        let mut durs = vec![];
+        let mut legatos = vec![];
+        let mut amps = vec![];
+
+        // Each note lasts until the next one of the same pitch starts, with legato making up the
+        // difference. A rest covers the time before the first one.
+        let first_timestamp = notes[0].timestamp;
+        if first_timestamp > 0 {
+            midinotes.push(format!("Rest({})", midinote));
+            durs.push(beats(first_timestamp));
+            legatos.push(1.);
+            amps.push(0.);
+        }
+        for (i, note) in notes.iter().enumerate() {
+            let dur = match notes.get(i + 1) {
+                Some(next) if next.timestamp > note.timestamp => next.timestamp - note.timestamp,
+                _ => note.duration,
+            };
+            midinotes.push(midinote.to_string());
+            durs.push(beats(dur));
+            legatos.push(if dur == 0 { 1. } else { note.duration as f64 / dur as f64 });
+            amps.push(amp(note));
+        }
+
+        writeln!(out, "    Pbind(")?;
+        writeln!(out, "        \\midinote, Pseq([{}]),", midinotes.join(", "))?;
+        writeln!(out, "        \\dur, Pseq([{}]),", join_numbers(&durs))?;
+        writeln!(out, "        \\legato, Pseq([{}]),", join_numbers(&legatos))?;
+        writeln!(out, "        \\amp, Pseq([{}])", join_numbers(&amps))?;
+        // SuperCollider doesn't allow a trailing comma in an array literal.
+        writeln!(out, "    ){}", if n + 1 < count { "," } else { "" })?;
+    }
+    writeln!(out, "]).play;")
+}
+
+fn join_numbers(numbers: &[f64]) -> String {
+    numbers.iter()
+        .map(|n| format!("{:.4}", n))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
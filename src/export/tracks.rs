@@ -0,0 +1,87 @@
+use crate::midi::Midi;
+use crate::program::{instrument_name, MIDI_PROGRAM, PERCUSSION_CHANNEL};
+use serde::Serialize;
+use std::io::Write;
+
+#[derive(Serialize)]
+struct Segment {
+    start: u64,
+    program: u8,
+    instrument: Option<&'static str>,
+    notes: u64,
+}
+
+#[derive(Serialize)]
+struct Channel {
+    /// Numbered from 0 or 1, as the channels are on the command line.
+    channel: u8,
+    percussion: bool,
+    /// The bank select MSB.
+    bank: u8,
+    bank_lsb: u8,
+    program: u8,
+    /// The General MIDI name of the program, if it's one or a variation of one.
+    instrument: Option<String>,
+    notes: u64,
+    /// Only when the channel changes program mid-song.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    segments: Vec<Segment>,
+}
+
+#[derive(Serialize)]
+struct Track {
+    track: usize,
+    name: Option<String>,
+    instrument_name: Option<String>,
+    channels: Vec<Channel>,
+}
+
+#[derive(Serialize)]
+struct Tracks {
+    one_indexed_channels: bool,
+    tracks: Vec<Track>,
+}
+
+/// Write the same listing of tracks and channels as the console output, as JSON, for picking
+/// selectors with a script.
+pub fn write_json(midi: &Midi, channel_one_indexed: bool, writer: impl Write)
+    -> std::io::Result<()>
+{
+    let counts = midi.note_counts();
+    let tracks = midi.tracks()
+        .map(|track| Track {
+            track: track.midi_track,
+            name: track.name.clone(),
+            instrument_name: track.instrument.clone(),
+            channels: midi.channels()
+                .filter(|channel| channel.midi_track == track.midi_track)
+                .map(|channel| Channel {
+                    channel: channel.midi_channel + u8::from(channel_one_indexed),
+                    percussion: channel.midi_channel == PERCUSSION_CHANNEL,
+                    bank: channel.bank,
+                    bank_lsb: channel.bank_lsb,
+                    program: channel.program,
+                    instrument: instrument_name(channel.bank, channel.bank_lsb, channel.program),
+                    notes: counts.get(&(channel.midi_track, channel.midi_channel))
+                        .copied()
+                        .unwrap_or(0),
+                    segments: if channel.program_segments.len() > 1 {
+                        channel.program_segments.iter()
+                            .map(|segment| Segment {
+                                start: segment.start,
+                                program: segment.program,
+                                instrument: MIDI_PROGRAM.get(usize::from(segment.program))
+                                    .copied(),
+                                notes: segment.notes,
+                            })
+                            .collect()
+                    } else {
+                        vec![]
+                    },
+                })
+                .collect(),
+        })
+        .collect();
+    let listing = Tracks { one_indexed_channels: channel_one_indexed, tracks };
+    serde_json::to_writer_pretty(writer, &listing).map_err(std::io::Error::from)
+}
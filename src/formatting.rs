@@ -0,0 +1,89 @@
+//! Number formatting for reports meant for people. Machine-readable outputs (CSV, JSON) write
+//! plain numbers instead.
+//!
+//! These don't depend on the system locale: thousands are always separated with commas and
+//! decimals with a point, so that reports are the same everywhere.
+
+use crate::geometry::{MM_PER_POINT, POINTS_PER_INCH};
+use crate::tempo::{self, TimeSignature};
+
+/// A whole number with thousands separators, like "12,345".
+pub fn count(n: u64) -> String {
+    let digits = n.to_string();
+    let mut out = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(digit);
+    }
+    out
+}
+
+/// A length along the roll, in feet and inches and in millimeters, like "16 ft 6.8 in (5,052 mm)".
+pub fn length(points: f64) -> String {
+    let inches = points / f64::from(POINTS_PER_INCH);
+    let mm = (points * f64::from(MM_PER_POINT)).round() as u64;
+    // Round before splitting into feet so that it never says "12.0 in".
+    let tenths = (inches * 10.).round() as u64;
+    let (feet, tenths) = (tenths / 120, tenths % 120);
+    let inches = format!("{}.{} in", tenths / 10, tenths % 10);
+    if feet > 0 {
+        format!("{} ft {} ({} mm)", count(feet), inches, count(mm))
+    } else {
+        format!("{} ({} mm)", inches, count(mm))
+    }
+}
+
+/// A span of time, like "1:05.25", or "1:02:05.25" if it's an hour or more.
+pub fn duration(seconds: f64) -> String {
+    let hundredths = (seconds * 100.).round() as u64;
+    let (minutes, hundredths) = (hundredths / 6000, hundredths % 6000);
+    let seconds = format!("{:02}.{:02}", hundredths / 100, hundredths % 100);
+    if minutes >= 60 {
+        format!("{}:{:02}:{}", minutes / 60, minutes % 60, seconds)
+    } else {
+        format!("{}:{}", minutes, seconds)
+    }
+}
+
+/// A metronome marking for the beat of the given meter, like "♩.=80" in 6/8.
+pub fn metronome(micros_per_quarter: u32, signature: &TimeSignature) -> String {
+    // A dotted beat is three of the denominator's notes, so it's a dot on the note twice as long.
+    let (value, dot) = if signature.is_compound() {
+        (signature.denominator / 2, ".")
+    } else {
+        (signature.denominator, "")
+    };
+    let note = match value {
+        1 => "𝅝".to_owned(),
+        2 => "𝅗𝅥".to_owned(),
+        4 => "♩".to_owned(),
+        8 => "♪".to_owned(),
+        16 => "𝅘𝅥𝅯".to_owned(),
+        value => format!("1/{} note", value),
+    };
+    format!("{}{}={}", note, dot, rate(signature.beats_per_minute(micros_per_quarter)))
+}
+
+/// A tempo in quarter notes a minute, as SetTempo events give it, and in beats of the meter if
+/// they're something else, like "♩=120, ♩.=80 in 6/8".
+pub fn tempo(micros_per_quarter: u32, signature: &TimeSignature) -> String {
+    let quarters = format!("♩={}", rate(tempo::quarters_per_minute(micros_per_quarter)));
+    let meter = format!("{}/{}", signature.numerator, signature.denominator);
+    if signature.beat_quarters() == 1. {
+        format!("{} in {}", quarters, meter)
+    } else {
+        format!("{}, {} in {}", quarters, metronome(micros_per_quarter, signature), meter)
+    }
+}
+
+/// A rate to a tenth, leaving off the decimal if it's whole.
+fn rate(per_minute: f64) -> String {
+    let tenths = (per_minute * 10.).round() as u64;
+    if tenths.is_multiple_of(10) {
+        count(tenths / 10)
+    } else {
+        format!("{}.{}", count(tenths / 10), tenths % 10)
+    }
+}
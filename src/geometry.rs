@@ -0,0 +1,236 @@
+//! Physical layout of the piano roll. All lengths are in PDF points.
+
+pub const POINTS_PER_INCH: f32 = 72.;
+pub const MM_PER_POINT: f32 = 25.4 / POINTS_PER_INCH;
+pub const PAGE_WIDTH: f32 = POINTS_PER_INCH * 11.25;
+pub const CHANNEL_WIDTH: f32 = POINTS_PER_INCH / 9.;
+pub const PAGE_MARGIN: f32 = (PAGE_WIDTH - CHANNEL_WIDTH * 98.) / 2.;
+pub const HOLE_WIDTH: f32 = CHANNEL_WIDTH / 2.;
+pub const HOLE_MARGIN: f32 = CHANNEL_WIDTH / 4.;
+/// Length of a rewind or shutoff hole: long enough for the player's valve to catch it at any roll
+/// speed.
+pub const END_HOLE_LENGTH: f32 = POINTS_PER_INCH / 2.;
+
+/// Distance from the left edge of the paper to the left edge of a channel's holes.
+pub fn hole_left(channel: u8) -> f32 {
+    f32::from(channel) * CHANNEL_WIDTH + HOLE_MARGIN + PAGE_MARGIN
+}
+
+/// Distance from the left edge of the paper to the center of a channel's holes.
+pub fn hole_center(channel: u8) -> f32 {
+    hole_left(channel) + HOLE_WIDTH / 2.
+}
+
+/// Distance to move a channel's holes along the roll, for a tracker bar with its odd channels in a
+/// second row `row_offset` further along than the first.
+pub fn row_offset(channel: u8, row_offset: f32) -> f32 {
+    if channel % 2 == 1 {
+        row_offset
+    } else {
+        0.
+    }
+}
+
+/// Where each page starts, splitting a roll `length` long with holes spanning `holes` (start and
+/// end along the roll) into pages no longer than `max_page`. Pages end in a gap between holes if
+/// there's one in the second half of the page, and otherwise go straight through the holes there.
+pub fn page_breaks(holes: &[(f64, f64)], length: f64, max_page: f64) -> Vec<f64> {
+    let mut holes = holes.to_vec();
+    holes.sort_by(|a, b| a.0.total_cmp(&b.0));
+    // Merge holes that overlap or touch into the spans that a page break would cut through.
+    let mut spans: Vec<(f64, f64)> = vec![];
+    for (start, end) in holes {
+        match spans.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => spans.push((start, end)),
+        }
+    }
+
+    let mut breaks = vec![0.];
+    let mut page_start = 0.;
+    while length - page_start > max_page {
+        let limit = page_start + max_page;
+        let page_break = spans.iter()
+            .find(|(start, end)| *start < limit && limit < *end)
+            .map(|(start, _)| *start)
+            .filter(|start| *start > page_start + max_page / 2.)
+            .unwrap_or(limit);
+        breaks.push(page_break);
+        page_start = page_break;
+    }
+    breaks
+}
+
+/// Most a hole may be moved by rounding its position to the single-precision coordinates of the
+/// PDF. Positions are worked out in f64, and `as f32` rounds them to the nearest f32, ties to
+/// even, so this is half the gap between f32 values at the end of the roll.
+pub const MAX_ROUNDING: f64 = 0.05;
+
+/// Shortest the music on a roll may be: any shorter, and the time divisor is surely a mistake.
+pub const MIN_MUSIC_LENGTH: f64 = POINTS_PER_INCH as f64;
+
+/// Check that a time divisor lays out a song ending at `end_ticks` on a sensible length of roll
+/// after a leader `leader` long: not so long that holes are placed imprecisely, and not so short
+/// that the music is smaller than a hole or two. The error suggests a divisor that would work.
+pub fn check_time_divisor(end_ticks: u64, time_divisor: f64, leader: f64) -> Result<(), String> {
+    // Half the gap between the f32 value nearest `points` and the next one up.
+    let rounding = |points: f64| {
+        let nearest = points as f32;
+        f64::from(f32::from_bits(nearest.to_bits() + 1) - nearest) / 2.
+    };
+    let music = end_ticks as f64 / time_divisor;
+    let length = leader + music;
+    if rounding(length) > MAX_ROUNDING {
+        // The longest roll that's still precise enough: the gaps double at each power of two.
+        let mut longest = 1.;
+        while rounding(longest * 2.) <= MAX_ROUNDING {
+            longest *= 2.;
+        }
+        return Err(format!("a time divisor of {} makes the roll {} long, too long to place holes \
+            within {} points of where they belong; use a time divisor of at least {}",
+            time_divisor, crate::formatting::length(length), MAX_ROUNDING,
+            (end_ticks as f64 / (longest - leader)).ceil()));
+    }
+    if music < MIN_MUSIC_LENGTH {
+        return Err(format!("a time divisor of {} makes the music only {} long on the roll; use a \
+            time divisor of at most {}",
+            time_divisor, crate::formatting::length(music),
+            (end_ticks as f64 / MIN_MUSIC_LENGTH).floor().max(1.)));
+    }
+    Ok(())
+}
+
+/// The time divisor that makes a song ending at `end_ticks` come out `length` long on the roll,
+/// including the leader `leader` long before it.
+pub fn fit_time_divisor(end_ticks: u64, length: f64, leader: f64) -> Result<f64, String> {
+    let music = length - leader;
+    if music < MIN_MUSIC_LENGTH {
+        return Err(format!("a roll {} long leaves only {} for the music after the leader",
+            crate::formatting::length(length), crate::formatting::length(music.max(0.))));
+    }
+    Ok(end_ticks.max(1) as f64 / music)
+}
+
+/// Paper to tile a roll template across with `--tile`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PaperSize {
+    Letter,
+    A4,
+}
+
+impl PaperSize {
+    /// Width and height of the paper, upright.
+    pub fn size(self) -> (f32, f32) {
+        match self {
+            PaperSize::Letter => (8.5 * POINTS_PER_INCH, 11. * POINTS_PER_INCH),
+            PaperSize::A4 => (210. / MM_PER_POINT, 297. / MM_PER_POINT),
+        }
+    }
+}
+
+/// Blank space left around each tile for printers that can't print to the edge of the paper,
+/// and for the crop marks and segment number.
+pub const TILE_MARGIN: f32 = POINTS_PER_INCH / 4.;
+
+/// Least amount that neighboring tiles overlap by, to glue them together.
+pub const TILE_OVERLAP: f32 = POINTS_PER_INCH / 2.;
+
+/// One sheet of paper's worth of the roll. Positions are on the roll, like everywhere else: across
+/// it from the left edge of the paper, and along it from its start.
+#[derive(Debug, Clone)]
+pub struct Tile {
+    pub column: usize,
+    pub x: f32,
+    pub y: f64,
+    pub width: f32,
+    pub height: f64,
+    /// Where to glue this tile to its neighbors, in the middle of the overlap with each of them:
+    /// before and after it along the roll, and to its left and right.
+    pub glue_before: Option<f64>,
+    pub glue_after: Option<f64>,
+    pub glue_left: Option<f32>,
+    pub glue_right: Option<f32>,
+}
+
+/// Tile a roll `roll_length` long across sheets of paper, upright, in rows along the roll and as
+/// many columns as it takes to cover its width. Neighboring tiles overlap by at least
+/// `TILE_OVERLAP`, and columns are spread evenly across the roll.
+pub fn tiles(roll_length: f64, paper: PaperSize) -> Vec<Tile> {
+    let (paper_width, paper_height) = paper.size();
+    let width = paper_width - 2. * TILE_MARGIN;
+    let height = f64::from(paper_height - 2. * TILE_MARGIN);
+    let overlap = f64::from(TILE_OVERLAP);
+
+    let columns = if PAGE_WIDTH <= width {
+        1
+    } else {
+        ((PAGE_WIDTH - TILE_OVERLAP) / (width - TILE_OVERLAP)).ceil() as usize
+    };
+    let column_step = if columns == 1 {
+        0.
+    } else {
+        (PAGE_WIDTH - width) / (columns - 1) as f32
+    };
+    let row_step = height - overlap;
+    let rows = ((roll_length - overlap) / row_step).ceil().max(1.) as usize;
+
+    let mut tiles = vec![];
+    for row in 0 .. rows {
+        for column in 0 .. columns {
+            let x = column as f32 * column_step;
+            let y = row as f64 * row_step;
+            tiles.push(Tile {
+                column,
+                x,
+                y,
+                width,
+                height,
+                glue_before: (row > 0).then(|| y + (height - row_step) / 2.),
+                glue_after: (row + 1 < rows).then(|| y + (height + row_step) / 2.),
+                glue_left: (column > 0).then(|| x + (width - column_step) / 2.),
+                glue_right: (column + 1 < columns).then(|| x + (width + column_step) / 2.),
+            });
+        }
+    }
+    tiles
+}
+
+/// Break a slot from `start` to `end` into a chain of slots no longer than `max_slot`, with
+/// `bridge` of paper left between each, so that a long note doesn't tear the roll. The last slot
+/// still reaches the end of the note, unless it would be less than a quarter of `max_slot` long,
+/// in which case the one before it is stretched to the end instead, rather than cutting a sliver.
+pub fn chain_slots(start: f64, end: f64, max_slot: f64, bridge: f64) -> Vec<(f64, f64)> {
+    let mut slots = vec![];
+    let mut slot_start = start;
+    while end - slot_start > max_slot {
+        slots.push((slot_start, slot_start + max_slot));
+        slot_start += max_slot + bridge;
+    }
+    match slots.last_mut() {
+        Some(last) if end - slot_start < max_slot / 4. => last.1 = end,
+        _ => slots.push((slot_start, end)),
+    }
+    slots
+}
+
+/// How a note's hole is drawn with `--note-shape-by-duration`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum HoleShape {
+    /// Shorter than an eighth note: a round hole the width of the channel's holes.
+    Circle,
+    /// The usual rectangular slot.
+    Rectangle,
+    /// Longer than a whole note: a slot with a rounded end.
+    RoundedTop,
+}
+
+pub fn hole_shape(duration: u64, time_base: u16) -> HoleShape {
+    let beat = u64::from(time_base);
+    if duration * 2 < beat {
+        HoleShape::Circle
+    } else if duration > beat * 4 {
+        HoleShape::RoundedTop
+    } else {
+        HoleShape::Rectangle
+    }
+}
@@ -0,0 +1,135 @@
+//! Text punched into the leader of the roll as a dot-matrix pattern of small holes, the way some
+//! antique rolls carry their catalog number or tempo, and room after it for a printed title.
+
+use crate::geometry::{CHANNEL_WIDTH, HOLE_MARGIN, HOLE_WIDTH};
+
+pub const GLYPH_WIDTH: u8 = 5;
+pub const GLYPH_HEIGHT: u8 = 7;
+
+/// Total number of channels across the roll, including the unused ones at either edge.
+const ROLL_CHANNELS: u8 = 98;
+
+/// Rows of a 5x7 glyph, top to bottom. The low 5 bits of each row are its columns, most
+/// significant bit leftmost.
+pub fn glyph(c: char) -> Option<[u8; GLYPH_HEIGHT as usize]> {
+    Some(match c {
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x11, 0x1F, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        ' ' => [0x00; GLYPH_HEIGHT as usize],
+        _ => return None,
+    })
+}
+
+/// One small square hole of a label.
+#[derive(Debug, Clone, Copy)]
+pub struct Punch {
+    pub channel: u8,
+    /// Start of the hole, from the start of the roll.
+    pub y: f32,
+    /// Size of the hole along the roll; it is `HOLE_WIDTH` across.
+    pub height: f32,
+}
+
+/// A label laid out in the leader of the roll.
+#[derive(Debug, Clone, Default)]
+pub struct Label {
+    pub punches: Vec<Punch>,
+    /// Length of the leader holding the label. The music has to be moved along by this much so
+    /// that it starts after the label.
+    pub leader_length: f32,
+    /// Length of the end of the leader kept blank for printing the title, composer, and tempo on,
+    /// as commercial rolls have. It's included in `leader_length`.
+    pub printed_length: f32,
+}
+
+impl Label {
+    /// Lay out the text in dot-matrix glyphs, one channel per dot across the roll and one channel
+    /// pitch per dot along it, centered on the roll with a blank row either side.
+    pub fn new(text: &str) -> Result<Self, String> {
+        let glyphs = text.chars()
+            .map(|c| glyph(c).ok_or_else(|| format!(
+                "label {:?}: {:?} can't be punched; only digits, capital letters, spaces, and \
+                hyphens can be", text, c)))
+            .collect::<Result<Vec<_>, String>>()?;
+        if glyphs.is_empty() {
+            return Ok(Self::default());
+        }
+
+        // One blank column between glyphs.
+        let columns = glyphs.len() * usize::from(GLYPH_WIDTH + 1) - 1;
+        if columns > usize::from(ROLL_CHANNELS) {
+            return Err(format!("label {:?} is too long: at most {} characters fit across the roll",
+                text, (ROLL_CHANNELS + 1) / (GLYPH_WIDTH + 1)));
+        }
+        let first_channel = (ROLL_CHANNELS - columns as u8) / 2;
+
+        let mut punches = vec![];
+        for (i, rows) in glyphs.iter().enumerate() {
+            let glyph_channel = first_channel + i as u8 * (GLYPH_WIDTH + 1);
+            for (row, bits) in rows.iter().enumerate() {
+                // Top row of the glyph is furthest along the roll, so the text reads upright with
+                // the music above it.
+                let y = f32::from(GLYPH_HEIGHT - row as u8) * CHANNEL_WIDTH + HOLE_MARGIN;
+                for column in 0 .. GLYPH_WIDTH {
+                    if bits & (1 << (GLYPH_WIDTH - 1 - column)) != 0 {
+                        punches.push(Punch {
+                            channel: glyph_channel + column,
+                            y,
+                            height: HOLE_WIDTH,
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            punches,
+            leader_length: f32::from(GLYPH_HEIGHT + 2) * CHANNEL_WIDTH,
+            printed_length: 0.,
+        })
+    }
+
+    /// Make the leader longer by `length`, after any punched text, to print the title in.
+    pub fn with_printed_leader(self, length: f32) -> Self {
+        Self {
+            leader_length: self.leader_length + length,
+            printed_length: self.printed_length + length,
+            ..self
+        }
+    }
+}
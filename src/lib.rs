@@ -0,0 +1,38 @@
+//! Pianoroll :: Make player piano rolls from MIDI files
+//! https://github.com/wfraser/pianoroll
+//!
+//! The MIDI reading, note pairing, processing, and drawing behind the `pianoroll` program, for
+//! use from other programs. Start with `Midi::read` and `note_durations`, and draw the roll with
+//! `render_pdf`, or do everything the program does at once with `pipeline::run`.
+//!
+//! Nothing here prints: what the program shows as it goes, like the song's tempo or warnings about
+//! its tracks, is logged through the `log` crate instead, with warnings at `Warn` level.
+
+pub mod analysis;
+pub mod audit;
+pub mod checks;
+pub mod config;
+pub mod diagnostics;
+pub mod error;
+pub mod export;
+pub mod formatting;
+pub mod geometry;
+pub mod label;
+pub mod midi;
+mod midi_impl_ghakuf;
+pub mod note;
+pub mod note_map;
+pub mod pipeline;
+pub mod processing;
+pub mod program;
+pub mod render;
+pub mod svg;
+pub mod tempo;
+
+pub use crate::config::{parse_configuration, ChannelSelector, Configuration};
+pub use crate::diagnostics::Diagnostic;
+pub use crate::error::PianoRollError;
+pub use crate::midi::{note_durations, Midi, NoteAction, NoteEvent, NoteWithDuration};
+pub use crate::note::MidiNote;
+pub use crate::render::render_pdf;
+pub use crate::tempo::TempoMap;
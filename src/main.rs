@@ -1,165 +1,381 @@
-/// Pianoroll :: Make player piano rolls from MIDI files
-/// https://github.com/wfraser/pianoroll
+//! Pianoroll :: Make player piano rolls from MIDI files
+//! https://github.com/wfraser/pianoroll
 
-mod config;
-mod midi;
-mod midi_impl_ghakuf;
-mod midi_impl { pub use crate::midi_impl_ghakuf::*; }
-mod note;
-mod program;
+use pianoroll::checks::Category;
+use pianoroll::error::PianoRollError;
+use pianoroll::{config, parse_configuration, pipeline};
 
-use crate::config::{Configuration, parse_configuration};
-use crate::midi::{note_durations, Midi, NoteAction, NoteWithDuration};
-use std::collections::btree_map::*;
+/// Prints what the library logs along with everything else, the same way: warnings and errors
+/// with their prefix to stderr, like the program's own errors, and everything else as it is to
+/// stdout. Other crates' logging is left out.
+struct ConsoleLogger;
+
+impl log::Log for ConsoleLogger {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        metadata.level() <= log::Level::Info && metadata.target().starts_with("pianoroll")
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match record.level() {
+            log::Level::Error => eprintln!("ERROR: {}", record.args()),
+            log::Level::Warn => eprintln!("WARNING: {}", record.args()),
+            _ => println!("{}", record.args()),
+        }
+    }
+
+    fn flush(&self) {}
+}
 
 fn usage() {
-    eprintln!("usage: {} <input.mid> [track,channel[+/-offset]...] [/timediv] [-o output.pdf]",
+    eprintln!("usage: {} <input.mid> \
+        [[label=]track,channel|name=track name[,channel][+/-offset][*articulation]\
+        [:prog=instrument|:seg=n]...] \
+        [/timediv] [-o output.pdf] [options]",
         std::env::args().next().unwrap());
+    eprint!("{}", OPTIONS);
 }
 
-fn render(notes: &[NoteWithDuration], cfg: &Configuration) {
-    println!("Writing output to {:?}", cfg.output);
-    let f = std::fs::File::create(&cfg.output)
-        .unwrap_or_else(|e| panic!("failed to create PDF file {:?}: {}", &cfg.output, e));
-    let mut pdf = pdf_canvas::Pdf::new(f)
-        .expect("failed to create PDF");
-
-    const POINTS_PER_INCH: f32 = 72.;
-    const PAGE_WIDTH: f32 = POINTS_PER_INCH * 11.25;
-    const CHANNEL_WIDTH: f32 = POINTS_PER_INCH / 9.;
-    const PAGE_MARGIN: f32 = (PAGE_WIDTH - CHANNEL_WIDTH * 98.) / 2.;
-    const HOLE_WIDTH: f32 = CHANNEL_WIDTH / 2.;
-    const HOLE_MARGIN: f32 = CHANNEL_WIDTH / 4.;
-
-    fn note_rectangle(canvas: &mut pdf_canvas::Canvas, channel: u8, start: f32, height: f32)
-        -> Result<(), std::io::Error>
-    {
-        canvas.rectangle(
-            f32::from(channel) * CHANNEL_WIDTH + HOLE_MARGIN + PAGE_MARGIN,
-            start,
-            HOLE_WIDTH,
-            height,
-        )
-    }
+const OPTIONS: &str = "\
+Instead of by number, a track can be picked by its name, or any part of it, ignoring case, with
+name=<track name>; this keeps working when an editor renumbers the tracks. The channel can be left
+out if the track only has notes on one.
 
-    let end_timestamp = notes.iter()
-        .map(|elem| elem.timestamp + elem.duration)
-        .max()
-        .unwrap();
+A selector can be given a label to refer to it by, with <label>= in front, e.g. rh=1,0 or
+lh=name=piano,1-12. Labels can have letters, digits, '_', and '-'; unlabeled selectors are
+s1, s2, and so on, by position. Reports name the selectors by their labels.
 
-    let page_height = end_timestamp as f32 / cfg.time_divisor;
-    println!("piano roll length: {} inches", page_height / POINTS_PER_INCH);
-    if page_height / POINTS_PER_INCH > 200. {
-        println!("WARNING: exceeding PDF page height limit of 200 inches");
-    }
+The track can be a range, e.g. 1-3,0 for channel 0 of tracks 1, 2, and 3, and the track or the
+channel can be *, for every one that has notes, e.g. 1,* or *,*. These stand for a selector for
+each track and channel, labeled with the label, a '.', and a number, e.g. rh.1 and rh.2; options
+given for the label apply to all of them.
+
+A channel that changes program mid-song is split into segments, numbered from 0 and listed with
+the tracks. Select only some of them with :prog=<instrument name> or :seg=<number>; notes belong
+to the segment they start in.
 
-    pdf.render_page(PAGE_WIDTH, page_height,
-        |canvas| {
-            canvas.set_fill_color(pdf_canvas::graphicsstate::Color::gray(0))?;
-            for note in notes {
-                note_rectangle(
-                    canvas,
-                    note.note.pianoroll_channel().expect("note out of range"), // shouldn't happen
-                    note.timestamp as f32 / cfg.time_divisor,
-                    note.duration as f32 / cfg.time_divisor)?;
-                canvas.fill()?;
-            }
-
-            Ok(())
-        })
-        .expect("failed to render page");
-
-    pdf.finish()
-        .expect("failed to finish PDF");
+options:
+    --strip-silence-start
+        Remove any silence before the first selected note.
+    --apply-smpte-offset
+        Add the file's SMPTE offset to the start of the roll as silence, to line it up with the
+        original recording.
+    --real-time-spacing, --tick-spacing
+        Space the holes by how long the notes take to play, following tempo changes, so a roll
+        played at a constant speed speeds up and slows down with the song; or space them by MIDI
+        ticks, as if the whole song were at its starting tempo. (default: real time)
+    --merge-alternating <track,channel> <track,channel>
+        Merge two selected channels that take turns with the melody into one voice: measure by
+        measure, take whichever one is playing, or if both are, the higher one.
+    --merge-prefer higher|lower
+        Which channel --merge-alternating takes when both are playing. (default: higher)
+    --overlap error|merge|longest|first
+        What to do with a note that's pressed again while it's held down, as when two selected
+        tracks double each other: report it as an error and end the note at the first release;
+        merge the presses into one note, from the first press to the last release; keep only
+        the longest press; or keep only the first. (default: error)
+    --backend ghakuf
+        Which MIDI file parser to read the song with, for comparing them without rebuilding.
+        This build only has ghakuf. (default: ghakuf)
+    --conductor <other.mid>
+        Take the tempo changes and time signatures from another MIDI file instead of the song,
+        for songs whose tempo map was recorded separately from the notes. Its markers are
+        logged as it's read.
+    --rescale-ticks
+        Convert the --conductor file's ticks to the song's time base if the two differ, instead
+        of refusing to use it.
+    --sustain-pedal
+        Hold notes on for as long as the sustain pedal holds them, instead of only while their
+        keys are down.
+    --fit-length <inches>|<n>in|<n>ft
+        Instead of taking a time divisor, work out the one that makes the roll this long, from
+        the start of the leader to the end of the last note, and print it. Holes added after the
+        song, like --rewind-hole, make it that much longer.
+    --fit-page
+        Like --fit-length, but make the roll just short of --max-page-length, an inch under it,
+        so it fits on one PDF page.
+    --max-page-length <inches>
+        Split a PDF roll longer than this across several pages, where possible between notes
+        rather than through them. Many viewers can't show longer pages. (default: 200)
+    --page-overlap <inches>
+        How much of the start of the next page to repeat at the end of each page of a split roll,
+        for splicing them together. (default: 0.5)
+    --svg-page-length <inches>
+        Split an SVG roll longer than this into several files, numbered after the output file's
+        name. Like --max-page-length, it's split between notes where possible.
+    --horizontal
+        Draw the roll across the page instead of down it, with the music going from left to right
+        and the lowest notes at the bottom, as on screen. Only holes are drawn.
+    --tile letter|a4
+        Instead of one long page, tile the roll across pages of letter or A4 paper that can be
+        printed at full size and glued together along the dashed lines where they overlap.
+    --format pdf|svg|csv
+        Draw the roll as a PDF or an SVG file, or list its notes in a CSV file with where each
+        one's hole starts and how long it is, in inches from the start of the roll. (default: by
+        the output file name's extension, or pdf)
+    --preview-tempo original|flattened
+        Keep the tempo changes in the preview MIDI file, or write a single tempo and move the
+        notes so they keep their timing. (default: original)
+    --export-csound <score.sco>
+        Also write the selected notes as a Csound score.
+    --export-sc3 <pattern.scd>
+        Also write the selected notes as a SuperCollider 3 pattern.
+    --export-chuck <program.ck>
+        Also write a ChucK program that plays the selected notes.
+    --export-etf <score.etf>
+        Also write the selected notes as a Finale Enigma Transportable File on a piano staff.
+    --catalog-image <image.png>
+        Also draw a picture of the finished roll, with its title, for a catalog.
+    --image-width <pixels>
+        Width of the catalog image. (default: 800)
+    --image-feet <feet>
+        Only show this much of the start of the roll in the catalog image.
+    --segment-export-json <cuts.json>
+        Also write the position of every hole in millimeters, for a cutting machine.
+    --punch-rate <punches per second> --feed-rate <inches per second>
+        Estimate how long a perforator that punches and feeds the paper this fast takes to punch
+        the roll, one stroke for each hole, in the order of --segment-export-json.
+    --channel-zero-indexed, --channel-one-indexed
+        Whether channels are numbered 0-15 (as in the MIDI spec) or 1-16 (as in some editors)
+        in track selectors and the track listing. (default: zero-indexed)
+    --clamp-duration <beats>
+        Shorten notes longer than this many beats, ending them on a beat boundary.
+    --min-duration-ticks <ticks>, --min-duration-ms <milliseconds>
+        Drop notes shorter than this, like the key bounces and accidental touches in a live
+        recording, which would punch tiny, fragile holes. Milliseconds follow the tempo
+        changes.
+    --articulation <factor>
+        Shorten every note to this fraction of its length, between 0 and 1, so that repeated
+        notes re-strike reliably. A selector can give its own with *<factor>, e.g. 1,0*0.85,
+        or with --articulation <label>=<factor>, e.g. --articulation rh=0.85. (default: 1)
+    --time-stretch <factor>
+        Multiply every note's start and length by this, in the roll and the preview MIDI file:
+        more than 1 plays slower, less than 1 faster. Unlike the time divisor, this changes the
+        timing of the music, not just the scale of the roll.
+    --stretch-to <m:ss>
+        Work out the --time-stretch that makes the song play for exactly this long, from the
+        start (or the first note, with --strip-silence-start) to the end of the last note, not
+        counting any SMPTE offset, e.g. for a mechanism that turns at a fixed speed. The tempo
+        markings are left alone. Combine with --fit-length to set the length of the roll too.
+    --quantize <ticks>|1/<note value>
+        Snap the start of every note to the nearest multiple of this many ticks, in the roll and
+        the preview MIDI file, to tidy up the timing of a live recording. A note that would run
+        into the next one of the same pitch is shortened to make room. Given as a note value,
+        e.g. 1/16 for sixteenth notes, snap to that grid, and round the length of every note to
+        a whole number of steps too, keeping it at least one step long.
+    --quantize-durations
+        With --quantize in ticks, snap the end of every note too, keeping it at least one step
+        long.
+    --diagnostics-json <path>
+        Write all errors and warnings about the selected notes to a JSON file, along with every
+        failed check, the exit status, and any editor's notes.
+    --fidelity-report <path>
+        Write the history of every selected note to a JSON file: its tick, pitch, and velocity in
+        the song, each change made to it on the way to the roll with the values before and after,
+        and the channel and position of its hole.
+    --note \"m<measure>: <text>\"
+        Write a note to yourself beside the start of a measure in the PDF, for proofreading, e.g.
+        --note \"m42: bass muddy, consider -12\". Can be given more than once. The notes are also
+        printed, and written to --diagnostics-json, but never drawn on the SVG or tiled pages or
+        anything else meant for punching.
+    --notes-file <path>
+        Read editor's notes from a file, one per line like --note. Blank lines and lines starting
+        with # are skipped.
+    --config <path>
+        Read settings from a TOML project file: input, output, and time_divisor; a [[selector]]
+        table for each selector, with midi_track, midi_channel, and optionally label, offset,
+        and articulation, used if none are given on the command line; a [checks] table of
+        category = \"level\"; a [thresholds] table with any of min-hole, min-bridge,
+        max-polyphony, max-open-holes, and max-dead-air; and any other flag by its name without
+        the dashes, set to true, a value, or an array of values for a flag given more than once,
+        e.g. grid-measures = true or note = [\"m42: check the bass\"]. Flags given on the
+        command line win.
+    --print-config-schema
+        Print every setting a project file can have, with what it takes and its default, and
+        exit.
+    --check <category>=<error|warning|off>
+        Set what happens when a category of check fails. The categories are geometry (holes too
+        short or too close together), overlap, range, polyphony, vacuum (too many holes open at
+        once), and dead-air. Each category that fails as an error sets its own bit in the exit
+        status: geometry 2, overlap 4, range 8, polyphony 16, vacuum 32, and dead-air 64. Every
+        category is a warning unless set otherwise.
+    --min-hole <inches>
+        Shortest hole the geometry check allows. (default: 0.028)
+    --min-bridge <inches>
+        Least paper the geometry check allows between holes in the same channel.
+        (default: 0.028)
+    --max-polyphony <notes>
+        Most notes the polyphony check allows to sound at once. (default: 10)
+    --max-open-holes <holes>
+        Most holes the vacuum check allows to be open at once. (default: 16)
+    --max-dead-air <seconds>
+        Longest silence the dead-air check allows in the middle of the song. (default: 5)
+    --max-repeats <n>
+        Print only the first n of each repeated error (the same problem with the same note on
+        the same track and channel) and count up the rest. The diagnostics JSON file always
+        has all of them. (default: 5)
+    --no-aggregate
+        Print every error, however many times it repeats.
+    --auto-transpose[=octaves]
+        Shift each selector's notes up or down by whatever fits the most of them on the roll,
+        trying whole octaves first and other semitones only if they fit more. With =octaves,
+        only shift by whole octaves, so the key never changes.
+    --fold-phrases
+        Instead of dropping notes that are off the roll, move each phrase they're in up or down
+        by the fewest octaves that fit the whole phrase on it, so a melody keeps its shape. A
+        phrase that can't be fit is dropped.
+    --phrase-gap <beats>
+        Longest gap between notes of the same phrase for --fold-phrases. (default: 1)
+    --max-note-range
+        Warn if the selected notes span more notes than the roll can represent.
+    --strict-range
+        Like --max-note-range, but stop with an error instead.
+    --self-check
+        After writing the preview MIDI file, read it back and make sure it has the same notes.
+    --note-range-display
+        Mark the lowest and highest notes used on the roll.
+    --roll-label-punches <text>
+        Punch the text into the leader of the roll as a pattern of small holes, ahead of the
+        music. Up to 16 digits, capital letters, spaces, and hyphens.
+    --title-leader
+        Start the roll with a printed leader, as commercial rolls have: the title, composer, and
+        tempo dial setting, printed across a blank stretch of paper before the music. The title
+        is the name of the first named track unless --title gives another.
+    --leader-length <inches>
+        How long the printed leader is, implying --title-leader. (default: 12)
+    --title <text>
+        The title to print on the leader, implying --title-leader.
+    --composer <text>
+        The composer to print on the leader under the title, implying --title-leader.
+    --detect-repeated-sections
+        List measures whose notes are the same as an earlier measure's. Measures follow the
+        file's time signatures, and are numbered from 1 at the first downbeat.
+    --default-tempo <bpm>
+        Tempo to assume if the MIDI file doesn't set one. Without this, such a file is an error.
+        (The MIDI standard's default is 120.)
+    --tempo-marking
+        Print the suggested tempo dial setting at the start of the roll, as \"Tempo N\". It's
+        always reported on the console.
+    --show-key-positions
+        Print where middle C, concert A, and every other C are across the roll, and mark their
+        channels at each end of it.
+    --keyboard-legend start|end
+        Draw a keyboard across the start or end of the roll, with each key in its note's channel,
+        named, and shaded if the song uses it.
+    --show-rests
+        Mark gaps between notes in the same channel on the roll, labeled with their length in
+        beats, for proofreading against the score.
+    --min-rest <beats>
+        Shortest gap for --show-rests to mark. (default: 1)
+    --grid-measures
+        Draw a thin gray line across the roll at the start of each measure, following the time
+        signatures in the file, with the measure number in the margin, to find your place when
+        proofreading against the score. Leave it off for the roll to be punched.
+    --grid-beats
+        As well as the measure lines, draw a lighter line at each beat. In compound meters like
+        6/8, the beat is a dotted note, three of the bottom number's notes long.
+    --label-measures
+        Number each measure in the margin, as --grid-measures does, but without drawing lines
+        across the roll, to find a bar when punching corrections by hand.
+    --label-beats
+        As well as the measure numbers, number each beat within its measure.
+    --note-shape-by-duration
+        Draw notes shorter than an eighth note as circles, and notes longer than a whole note
+        with a rounded end.
+    --velocity-shading
+        Fill each hole in a shade of gray by how hard its note is played, from black for the
+        hardest to light gray for the softest, to see the dynamics when proofreading. Not for
+        rolls that will be punched.
+    --color-by-track
+        Fill each hole in a color for the track and channel its note came from, to see which
+        part is which when proofreading. Not for rolls that will be punched.
+    --proof <proof.pdf>
+        Also draw a proof of the roll as a PDF, with the marks for proofreading that the roll to
+        be punched leaves off. Both are drawn from the same notes in one run, so they can't
+        drift apart.
+    --proof-options \"<options>\"
+        The drawing options to draw the proof with, on top of the ones for the roll, e.g.
+        \"--grid-beats --show-rests\". (default: \"--grid-measures --label-beats
+        --color-by-track --note-range-display --show-key-positions --keyboard-legend start
+        --tempo-marking\")
+    --row-offset <inches>
+        Move the holes of odd channels this much further along the roll, for a tracker bar with
+        its holes in two staggered rows. The notes still sound together.
+    --max-slot <inches>
+        Break holes longer than this into a chain of shorter slots, so the paper doesn't tear.
+        Only the drawn roll is affected, not the preview MIDI file.
+    --bridge <inches>
+        Paper to leave between the slots of a chain with --max-slot. (default: 0.0625)
+    --tune-change-channel <channel>
+        Punch a tune-change hole in this channel of the roll (counting the channels of the note
+        holes from 8) after the song, to trigger a multi-tune player's rewind or advance. It's
+        kept --min-bridge clear of the notes, with more room made between songs if needed.
+    --tune-change-length <inches>
+        How long the tune-change hole is. (default: 1)
+    --rewind-hole <channel>,<inches>
+        Punch a hole in this channel of the roll (counting the channels of the note holes from 8)
+        this far after the last note, to set off the player's rewind, e.g. 87,2. The roll is
+        made longer to fit it. Positions differ between makers, so check the player's tracker
+        bar. Not with --tune-change-channel, whose hole already rewinds the roll.
+    --shutoff-hole <channel>,<inches>
+        Punch a hole in this channel this far after the last note, to turn the player off.
+    --export-percussion-map <path>
+        Print a suggested pitched note for each General MIDI percussion sound, and write it as a
+        note map file for --note-map.
+    --note-map <path>
+        Replace notes on the percussion channel (channel 9, counting from 0) using a file with
+        lines of \"<percussion note number> <pitched note number>\".
+    --list-tracks
+        Only print the tracks and channels in the file, with their instruments and how many notes
+        each has, to help pick track selectors; don't draw a roll.
+    --list-tracks-json
+        Like --list-tracks, but print the listing as JSON, for scripts.
+    --info
+        Like --list-tracks, but also print the range of notes on each channel and how long the
+        song is; don't draw a roll. This is also what happens if no track selectors are given.
+    --pitch-usage
+        Print how many times each note is used and for how long, by pitch class and octave.
+    --pitch-usage-csv <path>
+        Write the same information as --pitch-usage to a CSV file.
+";
+
+/// Print the error and exit with its status.
+fn fail(e: PianoRollError) -> ! {
+    eprintln!("ERROR: {}", e);
+    std::process::exit(e.exit_code());
 }
 
 fn main() {
+    log::set_logger(&ConsoleLogger).expect("failed to set up logging");
+    log::set_max_level(log::LevelFilter::Info);
+
+    if std::env::args_os().skip(1).any(|arg| arg == "--print-config-schema") {
+        print!("{}", config::schema());
+        return;
+    }
+
     let cfg = parse_configuration(std::env::args_os()).unwrap_or_else(|e| {
         eprintln!("{}", e);
         usage();
-        std::process::exit(1);
+        std::process::exit(e.exit_code());
     });
 
-    let mut midi = Midi::new();
-    midi.read(&cfg.input).unwrap();
-
-    let time_base = midi.time_base().expect("no time base set in MIDI file?!");
-    let tempo = midi.tempo().expect("no tempo set in MIDI file");
-
-    let mut stats = std::collections::BTreeMap::<(usize, u8), u64>::new();
-    let mut durations = note_durations(midi.notes(), time_base, |event| {
-        // Make stats on how many notes are in each track/channel.
-        if event.action == NoteAction::On {
-            *stats.entry((event.track, event.channel)).or_insert(0) += 1;
-        }
-
-        for selector in &cfg.selectors {
-            if event.track == selector.midi_track
-                && event.channel == selector.midi_channel
-            {
-                return Some(selector.offset);
-            }
-        }
-
-        None
-    });
-    durations.sort_by_key(|event| event.timestamp);
-
-    let channels_by_track: BTreeMap<usize, Vec<&midi::ChannelInfo>>
-        = midi.channels()
-            .fold(BTreeMap::new(), |mut map, item| {
-                match map.entry(item.midi_track) {
-                    Entry::Occupied(mut entry) => { entry.get_mut().push(item); }
-                    Entry::Vacant(entry) => { entry.insert(vec![item]); }
-                }
-                map
-            });
-
-    // Print info on the tracks and channels.
-    for track in midi.tracks() {
-        print!("track {}:", track.midi_track);
-        if let Some(ref name) = track.name {
-            print!(" title: \"{}\"", name);
-        }
-        if let Some(ref instrument) = track.instrument {
-            print!(" instrument name: \"{}\", ", instrument);
-        }
-        println!();
-        let channels_iter = channels_by_track
-            .get(&track.midi_track)
-            .map(|x| x.iter())
-            .unwrap_or_else(|| [].iter());
-        for channel in channels_iter {
-            println!("track {}, channel {}:", channel.midi_track, channel.midi_channel);
-            if channel.midi_channel == 9 {
-                println!("\tPercussion");
-            } else if (channel.bank == 0 || channel.bank == 121) && channel.program < 128 {
-                println!("\tMIDI instrument \"{}\"",
-                    program::MIDI_PROGRAM[channel.program as usize]);
-            } else {
-                println!("\tunknown MIDI instrument: bank {}, program {}",
-                    channel.bank, channel.program);
-            }
-            if let Some(count) = stats.get(&(channel.midi_track, channel.midi_channel)) {
-                println!("\t{} notes", count);
-            } else {
-                println!("\tno notes");
-            }
-        }
+    if cfg.list_tracks_json {
+        // Keep the JSON clean of what reading the file prints. Warnings go to stderr, so they
+        // can still be shown.
+        log::set_max_level(log::LevelFilter::Warn);
     }
 
-    if durations.is_empty() {
-        println!("no notes selected!");
-    } else {
-        let mut output_filename = cfg.output.file_stem().unwrap().to_owned();
-        output_filename.push(std::ffi::OsStr::new("_pianoroll"));
-
-        let midi_output = cfg.output
-            .with_file_name(output_filename)
-            .with_extension("mid");
-
-        midi::Midi::write(&midi_output, &durations, time_base, tempo).unwrap();
-
-        render(&durations, &cfg);
+    let checks = pipeline::run(cfg, std::io::stdout()).unwrap_or_else(|e| fail(e));
+    let exit_code = checks.exit_code();
+    if exit_code != 0 {
+        let failed = Category::ALL.iter()
+            .filter(|category| exit_code & category.exit_bit() != 0)
+            .map(|category| category.name())
+            .collect::<Vec<_>>();
+        eprintln!("ERROR: failed checks: {}", failed.join(", "));
+        std::process::exit(exit_code);
     }
 }
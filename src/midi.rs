@@ -1,5 +1,14 @@
-use crate::midi_impl;
+use crate::diagnostics::Diagnostic;
+use crate::error::PianoRollError;
+use crate::midi_impl_ghakuf;
 use crate::note::MidiNote;
+use crate::tempo::{TempoChange, TempoMap, TimeSignature};
+
+/// Velocity the score exporters play a note at if it doesn't have its own.
+pub const DEFAULT_VELOCITY: u8 = 90; // arbitrary but seems to sound good
+
+/// Velocity written for note releases: the MIDI spec's value for when it isn't known.
+pub const RELEASE_VELOCITY: u8 = 64;
 
 #[derive(Debug, Clone)]
 pub struct NoteEvent {
@@ -8,16 +17,59 @@ pub struct NoteEvent {
     pub channel: u8,
     pub note: MidiNote,
     pub action: NoteAction,
+    /// How hard the note was pressed, from 1 to 127. Releases have 0.
+    pub velocity: u8,
+    /// Index into the channel's `program_segments` of the segment the note was pressed in.
+    pub segment: usize,
+    /// The program the note was pressed with.
+    pub program: u8,
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum NoteAction { On, Off }
 
-#[derive(Debug)]
+/// The sustain pedal (controller 64) being pressed or released.
+#[derive(Debug, Clone, Copy)]
+pub struct PedalEvent {
+    pub timestamp: u64,
+    pub track: usize,
+    pub channel: u8,
+    pub down: bool,
+}
+
+#[derive(Debug, Clone)]
 pub struct NoteWithDuration {
     pub timestamp: u64,
     pub duration: u64,
     pub note: MidiNote,
+    /// The MIDI track the note came from.
+    pub track: usize,
+    /// The MIDI channel the note came from.
+    pub channel: u8,
+    /// How hard the note was pressed, from 1 to 127.
+    pub velocity: u8,
+    /// Where the event that pressed the note was among those given to `note_durations`, to follow
+    /// the note through what's done to it afterward. Notes that weren't read from the song have
+    /// none.
+    pub source: Option<usize>,
+    /// Which of the selectors `note_durations` was given picked the note. Notes that weren't read
+    /// from the song have none.
+    pub selector: Option<usize>,
+}
+
+impl NoteWithDuration {
+    /// The note's velocity, or `DEFAULT_VELOCITY` for a note made without one.
+    pub fn velocity_or_default(&self) -> u8 {
+        if self.velocity == 0 { DEFAULT_VELOCITY } else { self.velocity }
+    }
+}
+
+/// Notes to write to one track of a MIDI file, all on one channel.
+#[derive(Debug, Clone, Copy)]
+pub struct OutputTrack<'a> {
+    pub name: Option<&'a str>,
+    pub channel: u8,
+    pub notes: &'a [NoteWithDuration],
 }
 
 #[derive(Debug)]
@@ -31,42 +83,263 @@ pub struct TrackInfo {
 pub struct ChannelInfo {
     pub midi_track: usize,
     pub midi_channel: u8,
+    /// The bank select MSB (CC#0).
     pub bank: u8,
+    /// The bank select LSB (CC#32), which Roland GS and Yamaha XG use for variations.
+    pub bank_lsb: u8,
+    pub program: u8,
+    pub program_segments: Vec<ProgramSegment>,
+}
+
+/// The part of a channel played with one instrument, from one ProgramChange to the next.
+#[derive(Debug, Clone)]
+pub struct ProgramSegment {
+    pub start: u64,
     pub program: u8,
+    /// Number of notes pressed in this segment.
+    pub notes: u64,
+}
+
+/// The SMPTEOffset meta event: the time code at which the song starts, for lining it up with a
+/// recording.
+#[derive(Debug, Clone, Copy)]
+pub struct SmpteOffset {
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub frames: u8,
+    /// Hundredths of a frame.
+    pub fractional_frames: u8,
+    pub frames_per_second: f64,
+}
+
+impl SmpteOffset {
+    /// Decode the event's five data bytes. The frame rate is in the top bits of the hours byte.
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let &[hours, minutes, seconds, frames, fractional_frames] = data else {
+            return None;
+        };
+        let frames_per_second = match (hours >> 5) & 0b11 {
+            0 => 24.,
+            1 => 25.,
+            2 => 30_000. / 1001.,
+            _ => 30.,
+        };
+        Some(Self {
+            hours: hours & 0b1_1111,
+            minutes,
+            seconds,
+            frames,
+            fractional_frames,
+            frames_per_second,
+        })
+    }
+
+    pub fn as_seconds(&self) -> f64 {
+        f64::from(self.hours) * 3600.
+            + f64::from(self.minutes) * 60.
+            + f64::from(self.seconds)
+            + (f64::from(self.frames) + f64::from(self.fractional_frames) / 100.)
+                / self.frames_per_second
+    }
+}
+
+impl std::fmt::Display for SmpteOffset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02}:{:02}:{:02}:{:02} ({:.2} fps)",
+            self.hours, self.minutes, self.seconds, self.frames, self.frames_per_second)
+    }
+}
+
+/// A division given in timecode rather than ticks per beat: some number of ticks in each frame
+/// of video.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimecodeDivision {
+    /// 24, 25, 29 (meaning 29.97, drop-frame), or 30.
+    pub frames_per_second: u8,
+    pub ticks_per_frame: u8,
+}
+
+impl TimecodeDivision {
+    /// Decode the header's division, if it's in timecode: the top bit is set, and the top byte is
+    /// the negative of the frames per second.
+    pub fn from_division(division: u16) -> Option<Self> {
+        if division & 0x8000 == 0 {
+            return None;
+        }
+        let [frames, ticks] = division.to_be_bytes();
+        Some(Self {
+            frames_per_second: (frames as i8).unsigned_abs(),
+            ticks_per_frame: ticks,
+        })
+    }
+
+    /// The ticks per beat and microseconds per beat that space the ticks the same, so the file can
+    /// be read as if it were metrical, with a beat of one second (or 1.001 seconds, at 29.97
+    /// frames per second). Nothing if the frame rate isn't a standard one.
+    pub fn metrical(self) -> Option<(u16, u32)> {
+        let ticks_per_frame = u16::from(self.ticks_per_frame);
+        match self.frames_per_second {
+            _ if ticks_per_frame == 0 => None,
+            24 | 25 | 30 => Some((u16::from(self.frames_per_second) * ticks_per_frame, 1_000_000)),
+            29 => Some((30 * ticks_per_frame, 1_001_000)),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for TimecodeDivision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.frames_per_second {
+            29 => f.write_str("29.97 drop-frame")?,
+            fps => write!(f, "{}", fps)?,
+        }
+        write!(f, " frames per second, {} ticks per frame", self.ticks_per_frame)
+    }
+}
+
+/// A MIDI file parser. Each `midi_impl_*` module has one, and `Midi` reads with whichever
+/// `Backend` it's given.
+pub trait MidiBackend: std::fmt::Debug {
+    fn read(&mut self, path: &::std::path::Path) -> Result<(), PianoRollError>;
+    fn tracks(&self) -> &[TrackInfo];
+    fn channels(&self) -> &[ChannelInfo];
+    fn notes(&self) -> &[NoteEvent];
+    fn pedal_events(&self) -> &[PedalEvent];
+    fn time_base(&self) -> Option<u16>;
+    fn tempo(&self) -> Option<u32>;
+    fn tempo_changes(&self) -> &[TempoChange];
+    fn smpte_offset(&self) -> Option<SmpteOffset>;
+    fn time_signatures(&self) -> &[TimeSignature];
+}
+
+/// The MIDI file parsers this build has.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum Backend {
+    #[default]
+    Ghakuf,
+}
+
+impl Backend {
+    fn parser(self) -> Box<dyn MidiBackend> {
+        match self {
+            Backend::Ghakuf => Box::new(midi_impl_ghakuf::MidiImpl::new()),
+        }
+    }
+}
+
+/// Tempo and meter taken from another file by `Midi::take_timing_from`, in place of the file's
+/// own.
+#[derive(Debug)]
+struct Timing {
+    tempo: Option<u32>,
+    tempo_changes: Vec<TempoChange>,
+    time_signatures: Vec<TimeSignature>,
 }
 
 #[derive(Debug)]
 pub struct Midi {
-    midi_impl: midi_impl::MidiImpl,
+    midi_impl: Box<dyn MidiBackend>,
+    timing: Option<Timing>,
+}
+
+impl Default for Midi {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Midi {
     pub fn new() -> Self {
+        Self::with_backend(Backend::default())
+    }
+
+    pub fn with_backend(backend: Backend) -> Self {
         Self {
-            midi_impl: midi_impl::MidiImpl::new(),
+            midi_impl: backend.parser(),
+            timing: None,
+        }
+    }
+
+    /// Use the tempo changes and time signatures of `conductor`, read from `path`, instead of this
+    /// file's own, for songs recorded with the click in a file of its own. The files have to have
+    /// the same time base, unless `rescale` is given to convert the conductor's ticks to this
+    /// file's.
+    pub fn take_timing_from(
+        &mut self,
+        conductor: &Midi,
+        path: &::std::path::Path,
+        rescale: bool,
+    ) -> Result<(), PianoRollError> {
+        let ours = self.time_base().ok_or(PianoRollError::NoTimeBase)?;
+        let theirs = conductor.time_base().ok_or(PianoRollError::NoTimeBase)?;
+        if ours != theirs && !rescale {
+            return Err(PianoRollError::MidiParse {
+                path: path.to_owned(),
+                detail: format!("its time base of {} ticks per beat doesn't match the song's {}; \
+                    use --rescale-ticks to convert its timing", theirs, ours),
+            });
+        }
+        let scale = |ticks: u64| {
+            let (ours, theirs) = (u128::from(ours), u128::from(theirs));
+            ((u128::from(ticks) * ours + theirs / 2) / theirs) as u64
+        };
+        if conductor.tempo().is_none() && conductor.time_signatures().is_empty() {
+            log::warn!("{:?} has no tempo or time signature to take", path);
         }
+        self.timing = Some(Timing {
+            tempo: conductor.tempo(),
+            tempo_changes: conductor.midi_impl.tempo_changes().iter()
+                .map(|change| TempoChange { timestamp: scale(change.timestamp), ..*change })
+                .collect(),
+            time_signatures: conductor.time_signatures().iter()
+                .map(|signature| TimeSignature {
+                    timestamp: scale(signature.timestamp),
+                    ..*signature
+                })
+                .collect(),
+        });
+        Ok(())
     }
 
-    pub fn read(&mut self, path: &::std::path::Path) -> Result<(), String> {
+    pub fn read(&mut self, path: &::std::path::Path) -> Result<(), PianoRollError> {
         self.midi_impl.read(path)
     }
 
-    pub fn write(path: &::std::path::Path, notes: &[NoteWithDuration], time_base: u16, tempo: u32)
-        -> Result<(), String>
-    {
-        midi_impl::MidiImpl::write(path, notes, time_base, tempo)
+    /// Write a format 1 MIDI file, with the tempo changes in the first track and each group of
+    /// notes in a track of its own after it.
+    pub fn write(
+        path: &::std::path::Path,
+        tracks: &[OutputTrack<'_>],
+        time_base: u16,
+        tempo_changes: &[TempoChange],
+    ) -> Result<(), PianoRollError> {
+        midi_impl_ghakuf::MidiImpl::write(path, tracks, time_base, tempo_changes)
     }
 
     pub fn tracks(&self) -> impl Iterator<Item = &TrackInfo> {
-        self.midi_impl.tracks()
+        self.midi_impl.tracks().iter()
     }
 
     pub fn channels(&self) -> impl Iterator<Item = &ChannelInfo> {
-        self.midi_impl.channels()
+        self.midi_impl.channels().iter()
     }
 
     pub fn notes(&self) -> impl Iterator<Item = &NoteEvent> {
-        self.midi_impl.notes()
+        self.midi_impl.notes().iter()
+    }
+
+    /// How many notes are pressed on each track and channel.
+    pub fn note_counts(&self) -> std::collections::BTreeMap<(usize, u8), u64> {
+        let mut counts = std::collections::BTreeMap::new();
+        for event in self.notes().filter(|event| event.action == NoteAction::On) {
+            *counts.entry((event.track, event.channel)).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    pub fn pedal_events(&self) -> &[PedalEvent] {
+        self.midi_impl.pedal_events()
     }
 
     pub fn time_base(&self) -> Option<u16> {
@@ -74,16 +347,111 @@ impl Midi {
     }
 
     pub fn tempo(&self) -> Option<u32> {
-        self.midi_impl.tempo()
+        match self.timing {
+            Some(ref timing) => timing.tempo,
+            None => self.midi_impl.tempo(),
+        }
+    }
+
+    /// The tempo the song starts at, or `default` if the file doesn't set one, in microseconds
+    /// per beat.
+    pub fn tempo_or(&self, default: Option<u32>) -> Result<u32, PianoRollError> {
+        self.tempo().or(default).ok_or(PianoRollError::NoTempo)
+    }
+
+    pub fn smpte_offset(&self) -> Option<SmpteOffset> {
+        self.midi_impl.smpte_offset()
+    }
+
+    /// All the time signature changes in the song, in order.
+    pub fn time_signatures(&self) -> &[TimeSignature] {
+        match self.timing {
+            Some(ref timing) => &timing.time_signatures,
+            None => self.midi_impl.time_signatures(),
+        }
+    }
+
+    /// The time signature the song starts in.
+    pub fn time_signature(&self) -> TimeSignature {
+        self.time_signatures().first()
+            .filter(|signature| signature.timestamp == 0)
+            .copied()
+            .unwrap_or(TimeSignature::DEFAULT)
+    }
+
+    /// All the tempo changes in the song. Returns `None` if there is no time base set.
+    pub fn tempo_map(&self) -> Option<TempoMap> {
+        let time_base = self.time_base()?;
+        let changes = match self.timing {
+            Some(ref timing) => &timing.tempo_changes,
+            None => self.midi_impl.tempo_changes(),
+        };
+        Some(TempoMap::new(time_base, changes.to_vec()))
     }
 }
 
-pub fn note_durations<'a>(
+/// Convert a span of MIDI ticks to seconds, given the ticks per beat and microseconds per beat.
+pub fn ticks_to_seconds(ticks: u64, time_base: u16, tempo: u32) -> f64 {
+    ticks as f64 * f64::from(tempo) / f64::from(time_base) / 1_000_000.
+}
+
+/// What to do with a note that's pressed again while it's already held down, as when two selected
+/// tracks double each other.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum OverlapPolicy {
+    /// Report it, unless the presses are very close together, and end the note at the first
+    /// release.
+    Error,
+    /// Make one note from the first press to the last release of the overlapping presses.
+    MergeUnion,
+    /// Keep only the longest of the overlapping presses, pairing presses with releases in order.
+    KeepLongest,
+    /// Keep only the first press, ending at the first release.
+    KeepFirst,
+}
+
+/// Pair up note on and off events into notes with durations.
+///
+/// The filter gives the selectors that pick each event, by position, with the offset each one
+/// applies: none if it isn't selected, or more than one to put it on the roll several times. Each
+/// selector's roll notes are paired up separately, so that a channel doubled onto the same roll
+/// note by two selectors makes two notes rather than an overlap.
+///
+/// A note released while the sustain pedal on its track and channel is down is held until the
+/// pedal comes up, or until the same note is struck again if that's sooner. Lifting the pedal ends
+/// the sustain even if it's pressed again straight away, as pianists do to clear it. Pass no pedal
+/// events to use the notes' own lengths.
+///
+/// Presses of a note that's already held down are handled according to `overlap`. Only
+/// `OverlapPolicy::Error` reports them.
+pub fn note_durations<'a, Offsets: IntoIterator<Item = (usize, i8)>>(
     notes: impl Iterator<Item = &'a NoteEvent>,
+    pedal: &[PedalEvent],
     time_base: u16,
-    mut filter: impl FnMut(&NoteEvent) -> Option<i8>,
-) -> Vec<NoteWithDuration> {
+    overlap: OverlapPolicy,
+    mut filter: impl FnMut(&NoteEvent) -> Offsets,
+) -> (Vec<NoteWithDuration>, Vec<Diagnostic>) {
     use std::collections::btree_map::*;
+    use std::collections::VecDeque;
+
+    let mut pedal_by_channel = BTreeMap::<(usize, u8), Vec<PedalEvent>>::new();
+    for event in pedal {
+        pedal_by_channel.entry((event.track, event.channel)).or_default().push(*event);
+    }
+    // When the pedal holding a note released at the given time comes up, if it's down then.
+    let sustain_end = |track: usize, channel: u8, timestamp: u64| {
+        let events = pedal_by_channel.get(&(track, channel))?;
+        let next = events.partition_point(|event| event.timestamp <= timestamp);
+        if next == 0 || !events[next - 1].down {
+            return None;
+        }
+        // A pedal that's never lifted doesn't hold anything; there's nothing to say for how long.
+        events[next ..].iter().find(|event| !event.down).map(|event| event.timestamp)
+    };
+
+    // Notes still sounding on the pedal after being released, as indexes into `finished_notes`, by
+    // selector and roll note.
+    let mut sustained = BTreeMap::<(usize, MidiNote), usize>::new();
 
     // If notes overlap by this many ticks or less, don't print an error.
     // Experimentally determined: a third of a beat sounds about right.
@@ -91,76 +459,211 @@ pub fn note_durations<'a>(
 
     // And then keep track of notes that we had multiple presses on, so that the release doesn't
     // also cause an error to be printed.
-    let mut error_suppressed = BTreeMap::<MidiNote, usize>::new();
+    let mut error_suppressed = BTreeMap::<(usize, MidiNote), usize>::new();
 
-    #[derive(Debug)]
+    #[derive(Debug, Clone, Copy)]
     struct InFlightInfo {
         midi_track: usize,
         midi_channel: u8,
         timestamp: u64,
+        velocity: u8,
+        source: usize,
+    }
+
+    /// Overlapping presses of the same note, for the policies other than `Error`.
+    struct Cluster {
+        /// Presses not released yet, oldest first.
+        held: VecDeque<InFlightInfo>,
+        first: InFlightInfo,
+        first_release: Option<u64>,
+        /// The longest press and its release so far.
+        longest: Option<(InFlightInfo, u64)>,
     }
 
     let mut finished_notes: Vec<NoteWithDuration> = vec![];
-    let mut in_flight = BTreeMap::<MidiNote, InFlightInfo>::new();
-    for event in notes {
-        let offset = match filter(event) {
-            Some(offset) => offset,
-            None => continue,
-        };
+    let mut diagnostics = vec![];
+    // Notes held down, by selector and roll note.
+    let mut in_flight = BTreeMap::<(usize, MidiNote), InFlightInfo>::new();
+    let mut clusters = BTreeMap::<(usize, MidiNote), Cluster>::new();
+    let selected = notes.enumerate()
+        .flat_map(|(source, event)| {
+            filter(event).into_iter()
+                .map(move |(selector, offset)| (source, event, selector, offset))
+        });
+    for (source, event, selector, offset) in selected {
 
         let note = match event.note.checked_offset(offset) {
             Some(note) if note.pianoroll_channel().is_some() => note,
             Some(_) | None => {
-                println!("ERROR: at {}, offsetting note {:?} on track {} channel {} by {} puts it
-                        outside of piano roll range",
-                        event.timestamp, event.note, event.track, event.channel, offset);
+                diagnostics.push(Diagnostic::NoteOutOfRange {
+                    timestamp: event.timestamp,
+                    track: event.track,
+                    channel: event.channel,
+                    note: event.note,
+                    offset,
+                });
                 continue;
             }
         };
 
-        match (event.action, in_flight.entry(note)) {
+        if event.action == NoteAction::On {
+            // Striking a note again cuts off its sustain.
+            if let Some(index) = sustained.remove(&(selector, note)) {
+                let finished = &mut finished_notes[index];
+                let end = finished.timestamp + finished.duration;
+                if end > event.timestamp {
+                    finished.duration = event.timestamp - finished.timestamp;
+                }
+            }
+        }
+
+        let pressed = InFlightInfo {
+            midi_track: event.track,
+            midi_channel: event.channel,
+            timestamp: event.timestamp,
+            velocity: event.velocity,
+            source,
+        };
+        if overlap != OverlapPolicy::Error {
+            match (event.action, clusters.entry((selector, note))) {
+                (NoteAction::On, Entry::Vacant(entry)) => {
+                    entry.insert(Cluster {
+                        held: VecDeque::from([pressed]),
+                        first: pressed,
+                        first_release: None,
+                        longest: None,
+                    });
+                }
+                (NoteAction::On, Entry::Occupied(mut entry)) => {
+                    entry.get_mut().held.push_back(pressed);
+                }
+                (NoteAction::Off, Entry::Vacant(_)) => {
+                    diagnostics.push(Diagnostic::NoteNotPressed {
+                        timestamp: event.timestamp,
+                        track: event.track,
+                        channel: event.channel,
+                        note,
+                    });
+                }
+                (NoteAction::Off, Entry::Occupied(mut entry)) => {
+                    let cluster = entry.get_mut();
+                    let press = cluster.held.pop_front().expect("empty cluster");
+                    cluster.first_release.get_or_insert(event.timestamp);
+                    let longer = cluster.longest.is_none_or(|(longest, release)| {
+                        event.timestamp - press.timestamp > release - longest.timestamp
+                    });
+                    if longer {
+                        cluster.longest = Some((press, event.timestamp));
+                    }
+                    if cluster.held.is_empty() {
+                        let cluster = entry.remove();
+                        let (pressed, release) = match overlap {
+                            OverlapPolicy::MergeUnion => (cluster.first, event.timestamp),
+                            OverlapPolicy::KeepFirst => {
+                                (cluster.first, cluster.first_release.unwrap())
+                            }
+                            OverlapPolicy::KeepLongest | OverlapPolicy::Error => {
+                                cluster.longest.unwrap()
+                            }
+                        };
+                        let end = match sustain_end(pressed.midi_track, pressed.midi_channel,
+                            release)
+                        {
+                            Some(pedal_up) => {
+                                sustained.insert((selector, note), finished_notes.len());
+                                pedal_up
+                            }
+                            None => release,
+                        };
+                        finished_notes.push(NoteWithDuration {
+                            timestamp: pressed.timestamp,
+                            duration: end - pressed.timestamp,
+                            note,
+                            track: pressed.midi_track,
+                            channel: pressed.midi_channel,
+                            velocity: pressed.velocity,
+                            source: Some(pressed.source),
+                            selector: Some(selector),
+                        });
+                    }
+                }
+            }
+            continue;
+        }
+
+        match (event.action, in_flight.entry((selector, note))) {
             (NoteAction::On, Entry::Vacant(entry)) => {
-                entry.insert(InFlightInfo {
-                    midi_track: event.track,
-                    midi_channel: event.channel,
-                    timestamp: event.timestamp,
-                });
+                entry.insert(pressed);
             }
             (NoteAction::On, Entry::Occupied(entry)) => {
                 let prev = entry.get();
                 if event.timestamp - prev.timestamp > fudge_factor_ticks {
-                    println!("ERROR: at {}, note {:?} on track {} channel {} already pressed at {} by {},{}",
-                        event.timestamp, note, event.track, event.channel,
-                        prev.timestamp, prev.midi_track, prev.midi_channel);
+                    diagnostics.push(Diagnostic::NoteAlreadyPressed {
+                        timestamp: event.timestamp,
+                        track: event.track,
+                        channel: event.channel,
+                        note,
+                        prev_timestamp: prev.timestamp,
+                        prev_track: prev.midi_track,
+                        prev_channel: prev.midi_channel,
+                    });
                     // TODO: maybe print errors in terms of measures & beats instead of timestamp?
                 }
-                let suppress_count = error_suppressed.entry(event.note).or_insert(0);
+                let suppress_count = error_suppressed.entry((selector, note)).or_insert(0);
                 *suppress_count += 1;
             }
             (NoteAction::Off, Entry::Vacant(_)) => {
-                match error_suppressed.get_mut(&event.note) {
+                match error_suppressed.get_mut(&(selector, note)) {
                     Some(ref mut suppress_count) if **suppress_count > 0 => {
                         // Double-dereference is necessary to avoid a "moves value into pattern
                         // guard" error.
                         **suppress_count -= 1;
                     }
                     _ => {
-                        println!("ERROR: at {} on track {} channel {}, note {:?} is not pressed yet",
-                            event.timestamp, event.track, event.channel, note);
+                        diagnostics.push(Diagnostic::NoteNotPressed {
+                            timestamp: event.timestamp,
+                            track: event.track,
+                            channel: event.channel,
+                            note,
+                        });
                     }
                 }
             }
             (NoteAction::Off, Entry::Occupied(entry)) => {
-                let start_timestamp = entry.remove().timestamp;
-                let duration = event.timestamp - start_timestamp;
+                let pressed = entry.remove();
+                let start_timestamp = pressed.timestamp;
+                let end = match sustain_end(event.track, event.channel, event.timestamp) {
+                    Some(pedal_up) => {
+                        sustained.insert((selector, note), finished_notes.len());
+                        pedal_up
+                    }
+                    None => event.timestamp,
+                };
+                let duration = end - start_timestamp;
                 finished_notes.push(NoteWithDuration {
                     timestamp: start_timestamp,
                     duration,
                     note,
+                    track: pressed.midi_track,
+                    channel: pressed.midi_channel,
+                    velocity: pressed.velocity,
+                    source: Some(pressed.source),
+                    selector: Some(selector),
                 });
             }
         }
     }
 
-    finished_notes
+    let unreleased = clusters.into_iter()
+        .filter_map(|(key, cluster)| cluster.held.front().map(|&info| (key, info)));
+    for ((_, note), info) in in_flight.into_iter().chain(unreleased) {
+        diagnostics.push(Diagnostic::StuckNote {
+            timestamp: info.timestamp,
+            track: info.midi_track,
+            channel: info.midi_channel,
+            note,
+        });
+    }
+
+    (finished_notes, diagnostics)
 }
@@ -1,15 +1,22 @@
+use crate::error::PianoRollError;
 use crate::midi::*;
 use crate::note::MidiNote;
+use crate::tempo::{TempoChange, TimeSignature};
 use ghakuf::{self, messages::{Message, MetaEvent, MidiEvent}};
 use std::collections::btree_map::*;
+use std::convert::TryFrom;
 
 #[derive(Debug)]
 pub struct MidiImpl {
     track_info: Vec<TrackInfo>,
     channel_info: Vec<ChannelInfo>,
     note_events: Vec<NoteEvent>,
+    pedal_events: Vec<PedalEvent>,
     time_base: Option<u16>,
     tempo: Option<u32>,
+    tempo_changes: Vec<TempoChange>,
+    time_signatures: Vec<TimeSignature>,
+    smpte_offset: Option<SmpteOffset>,
 }
 
 impl MidiImpl {
@@ -18,137 +25,126 @@ impl MidiImpl {
             track_info: vec![],
             channel_info: vec![],
             note_events: vec![],
+            pedal_events: vec![],
             time_base: None,
             tempo: None,
+            tempo_changes: vec![],
+            time_signatures: vec![],
+            smpte_offset: None,
         }
     }
 
-    pub fn read(&mut self, path: &::std::path::Path) -> Result<(), String> {
-        let mut song_info_handler = SongInfoHandler::new();
-        let mut notes_handler = NotesHandler::new();
-        let mut channel_handler = ChannelInfoHandler::new();
-
-        {
-            let mut g = ghakuf::reader::Reader::new(&mut song_info_handler, path)
-                .map_err(|e| format!("failed to read MIDI file {:?}: {}", path, e))?;
-
-            g.push_handler(&mut notes_handler);
-            g.push_handler(&mut channel_handler);
-
-            g.read()
-                .map_err(|e| format!("failed to parse MIDI file {:?}: {}", path, e))?;
-        }
-
-        self.note_events = notes_handler.events;
-        self.channel_info = channel_handler.channel_info().collect();
-        self.track_info = channel_handler.track_info().collect();
-        self.time_base = song_info_handler.time_base;
-        self.tempo = song_info_handler.tempo;
-
-        Ok(())
-    }
-
-    pub fn tracks(&self) -> impl Iterator<Item = &TrackInfo> {
-        self.track_info.iter()
-    }
-
-    pub fn channels(&self) -> impl Iterator<Item = &ChannelInfo> {
-        self.channel_info.iter()
-    }
-
-    pub fn notes(&self) -> impl Iterator<Item = &NoteEvent> {
-        self.note_events.iter()
-    }
-
-    pub fn time_base(&self) -> Option<u16> {
-        self.time_base
-    }
-
-    pub fn tempo(&self) -> Option<u32> {
-        self.tempo
-    }
-
-    pub fn write(path: &::std::path::Path, notes: &[NoteWithDuration], time_base: u16, tempo: u32)
-        -> Result<(), String>
-    {
-        const VELOCITY: u8 = 90; // arbitrary but seems to sound good
-
-        let mut messages = vec![
-            Message::MetaEvent {
-                delta_time: 0,
+    pub fn write(
+        path: &::std::path::Path,
+        tracks: &[OutputTrack<'_>],
+        time_base: u16,
+        tempo_changes: &[TempoChange],
+    ) -> Result<(), PianoRollError> {
+        let mut messages = vec![];
+        let mut last_timestamp = 0;
+        for change in tempo_changes {
+            let tempo = change.micros_per_beat;
+            messages.push(Message::MetaEvent {
+                delta_time: delta_time(path, last_timestamp, change.timestamp)?,
                 event: MetaEvent::SetTempo,
                 data: [(tempo >> 16) as u8, (tempo >> 8) as u8, tempo as u8].to_vec(),
-            },
-            Message::MetaEvent {
-                delta_time: 0,
-                event: MetaEvent::EndOfTrack,
-                data: Vec::new(),
-            },
-            Message::TrackChange,
-            Message::MidiEvent {
-                delta_time: 0,
-                event: MidiEvent::ControlChange {
-                    ch: 0,
-                    control: 0,
-                    data: 0,
-                }
-            },
-            Message::MidiEvent {
-                delta_time: 0,
-                event: MidiEvent::ProgramChange {
-                    ch: 0,
-                    program: 1,
-                },
-            },
-        ];
-
-        let mut note_events = vec![];
-        for note in notes {
-            note_events.push(NoteEvent {
-                timestamp: note.timestamp,
-                track: 0,
-                channel: 0,
-                note: note.note,
-                action: NoteAction::On,
-            });
-            note_events.push(NoteEvent {
-                timestamp: note.timestamp + note.duration,
-                track: 0,
-                channel: 0,
-                note: note.note,
-                action: NoteAction::Off,
             });
+            last_timestamp = change.timestamp;
         }
-        note_events.sort_by_key(|event| event.timestamp);
-
-        let mut last_timestamp = 0;
-        for note in note_events {
-            let event = match note.action {
-                NoteAction::On => MidiEvent::NoteOn {
-                    ch: note.channel,
-                    note: note.note.as_u8(),
-                    velocity: VELOCITY,
+        messages.push(Message::MetaEvent {
+            delta_time: 0,
+            event: MetaEvent::EndOfTrack,
+            data: Vec::new(),
+        });
+
+        for track in tracks {
+            let ch = track.channel;
+            messages.push(Message::TrackChange);
+            if let Some(name) = track.name {
+                messages.push(Message::MetaEvent {
+                    delta_time: 0,
+                    event: MetaEvent::SequenceOrTrackName,
+                    data: name.as_bytes().to_vec(),
+                });
+            }
+            messages.extend(vec![
+                Message::MidiEvent {
+                    delta_time: 0,
+                    event: MidiEvent::ControlChange {
+                        ch,
+                        control: 0,
+                        data: 0,
+                    }
                 },
-                NoteAction::Off => MidiEvent::NoteOff {
-                    ch: note.channel,
-                    note: note.note.as_u8(),
-                    velocity: VELOCITY,
+                Message::MidiEvent {
+                    delta_time: 0,
+                    event: MidiEvent::ProgramChange {
+                        ch,
+                        program: 1,
+                    },
                 },
-            };
-            let msg = Message::MidiEvent {
-                delta_time: (note.timestamp - last_timestamp) as u32,
-                event,
-            };
-            messages.push(msg);
-            last_timestamp = note.timestamp;
-        }
-        messages.push(
-            Message::MetaEvent {
-                delta_time: 0,
-                event: MetaEvent::EndOfTrack,
-                data: Vec::new(),
+            ]);
+
+            let mut note_events = vec![];
+            for note in track.notes {
+                note_events.push(NoteEvent {
+                    timestamp: note.timestamp,
+                    track: 0,
+                    channel: ch,
+                    note: note.note,
+                    action: NoteAction::On,
+                    velocity: note.velocity,
+                    segment: 0,
+                    program: 1,
+                });
+                note_events.push(NoteEvent {
+                    timestamp: note.timestamp + note.duration,
+                    track: 0,
+                    channel: ch,
+                    note: note.note,
+                    action: NoteAction::Off,
+                    velocity: 0,
+                    segment: 0,
+                    program: 1,
+                });
+            }
+            // Releases go before presses at the same tick, so a note struck again right as it
+            // ends isn't cut off by its own release; then by note, so chords always come out the
+            // same way.
+            note_events.sort_by_key(|event| {
+                (event.timestamp, event.action == NoteAction::On, event.note)
             });
 
+            let mut last_timestamp = 0;
+            for note in note_events {
+                let event = match note.action {
+                    // A press at velocity 0 would be read as a release.
+                    NoteAction::On => MidiEvent::NoteOn {
+                        ch: note.channel,
+                        note: note.note.as_u8(),
+                        velocity: note.velocity.clamp(1, 127),
+                    },
+                    NoteAction::Off => MidiEvent::NoteOff {
+                        ch: note.channel,
+                        note: note.note.as_u8(),
+                        velocity: RELEASE_VELOCITY,
+                    },
+                };
+                let msg = Message::MidiEvent {
+                    delta_time: delta_time(path, last_timestamp, note.timestamp)?,
+                    event,
+                };
+                messages.push(msg);
+                last_timestamp = note.timestamp;
+            }
+            messages.push(
+                Message::MetaEvent {
+                    delta_time: 0,
+                    event: MetaEvent::EndOfTrack,
+                    data: Vec::new(),
+                });
+        }
+
         let mut writer = ghakuf::writer::Writer::new();
         writer.time_base(time_base);
         for message in &messages {
@@ -156,191 +152,277 @@ impl MidiImpl {
         }
 
         writer.write(path)
-            .map_err(|e| format!("Error writing MIDI: {}", e))
+            .map_err(|source| PianoRollError::Write { path: path.to_owned(), source })
     }
 }
 
-struct NotesHandler {
-    timestamp: u64,
-    track: usize,
-    events: Vec<NoteEvent>,
-    headers_finished: bool,
-}
-
-impl NotesHandler {
-    pub fn new() -> Self {
-        Self {
-            timestamp: 0,
-            track: 0,
-            events: vec![],
-            headers_finished: false,
+impl MidiBackend for MidiImpl {
+    fn read(&mut self, path: &::std::path::Path) -> Result<(), PianoRollError> {
+        let mut handler = ReadHandler::new();
+        let parse_result = {
+            let mut g = ghakuf::reader::Reader::new(&mut handler, path)
+                .map_err(|e| read_error(path, e))?;
+            g.read()
+                .map_err(|e| read_error(path, e))
+        };
+
+        // ghakuf reads every track chunk up to the end of the file, regardless of how many the
+        // header says there are, and the tracks are numbered in that order. If it chokes on
+        // something after the first track, keep what it got so far.
+        let track_chunks = handler.track_chunks;
+        if let Err(e) = parse_result {
+            if track_chunks == 0 {
+                return Err(e);
+            }
+            log::warn!("{}; using the {} tracks read before the error (the last one may \
+                be incomplete)", e, track_chunks);
+        }
+        if let Some(declared) = handler.declared_tracks {
+            if usize::from(declared) != track_chunks {
+                log::warn!("header claims {} tracks, found {}; tracks are numbered from 0 \
+                    in the order they appear in the file", declared, track_chunks);
+            }
         }
+
+        self.channel_info = handler.channel_info().collect();
+        self.track_info = handler.track_info().collect();
+        self.note_events = handler.events;
+        self.pedal_events = handler.pedal_events;
+        self.time_base = handler.time_base;
+        self.tempo = handler.tempo;
+        self.tempo_changes = handler.tempo_changes;
+        self.time_signatures = handler.time_signatures;
+        self.time_signatures.sort_by_key(|signature| signature.timestamp);
+        self.smpte_offset = handler.smpte_offset;
+
+        Ok(())
     }
-}
 
-impl ghakuf::reader::Handler for NotesHandler {
-    fn meta_event(
-        &mut self,
-        delta_time: u32,
-        _event: &MetaEvent,
-        _data: &Vec<u8>,
-    ) {
-        self.timestamp += u64::from(delta_time);
+    fn tracks(&self) -> &[TrackInfo] {
+        &self.track_info
     }
 
-    fn midi_event(
-        &mut self,
-        delta_time: u32,
-        event: &MidiEvent,
-    ) {
-        self.timestamp += u64::from(delta_time);
+    fn channels(&self) -> &[ChannelInfo] {
+        &self.channel_info
+    }
 
-        match event {
-            MidiEvent::NoteOn { ch, note, velocity } => {
-                let action = if *velocity == 0 {
-                    NoteAction::Off
-                } else {
-                    NoteAction::On
-                };
+    fn notes(&self) -> &[NoteEvent] {
+        &self.note_events
+    }
 
-                let note = MidiNote::try_from(*note).unwrap();
+    fn pedal_events(&self) -> &[PedalEvent] {
+        &self.pedal_events
+    }
 
-                self.events.push(NoteEvent {
-                    timestamp: self.timestamp,
-                    track: self.track,
-                    channel: *ch,
-                    note,
-                    action,
-                });
-            }
-            MidiEvent::NoteOff { ch, note, .. } => {
-                let note = MidiNote::try_from(*note).unwrap();
+    fn time_base(&self) -> Option<u16> {
+        self.time_base
+    }
 
-                self.events.push(NoteEvent {
-                    timestamp: self.timestamp,
-                    track: self.track,
-                    channel: *ch,
-                    note,
-                    action: NoteAction::Off,
-                });
-            }
-            /*
-            MidiEvent::ControlChange { ch, control, data } => {
-                let off_on = |data: &u8| if *data < 64 { "off" } else { "on" };
-                let info = match control {
-                    64 => Some(format!("sustain {}", off_on(data))),
-                    65 => Some(format!("portamento {}", off_on(data))),
-                    66 => Some(format!("sostenuto {}", off_on(data))),
-                    67 => Some(format!("soft pedal {}", off_on(data))),
-                    68 => Some(format!("legato {}", off_on(data))),
-                    _ => None,
-                };
-                if let Some(info) = info {
-                    println!("track {}, channel {}, time {}: {}",
-                        self.track, ch, self.timestamp, info);
-                }
-            }
-            */
-            MidiEvent::ControlChange { .. } => (),
-            MidiEvent::ChannelPressure { .. }
-                | MidiEvent::PitchBendChange { .. }
-                | MidiEvent::PolyphonicKeyPressure { .. }
-                | MidiEvent::ProgramChange { .. } => (),
-            _ => {
-                println!("track {}, time {}, {:?}", self.track, self.timestamp, event);
-            }
-        }
+    fn tempo(&self) -> Option<u32> {
+        self.tempo
     }
 
-    fn sys_ex_event(
-        &mut self,
-        delta_time: u32,
-        _event: &ghakuf::messages::SysExEvent,
-        _data: &Vec<u8>,
-    ) {
-        self.timestamp += u64::from(delta_time);
+    fn tempo_changes(&self) -> &[TempoChange] {
+        &self.tempo_changes
     }
 
-    fn track_change(&mut self) {
-        if self.headers_finished {
-            self.track += 1;
-            self.timestamp = 0;
-        } else {
-            self.headers_finished = true;
+    fn smpte_offset(&self) -> Option<SmpteOffset> {
+        self.smpte_offset
+    }
+
+    fn time_signatures(&self) -> &[TimeSignature] {
+        &self.time_signatures
+    }
+}
+
+/// Convert a ghakuf read error. Its `Display` impl canonicalizes the file's path and panics if
+/// that fails, and we print the path ourselves anyway.
+fn read_error(path: &::std::path::Path, e: ghakuf::reader::ReadError<'_>) -> PianoRollError {
+    use ghakuf::reader::ReadError;
+    let detail = match e {
+        ReadError::InvalidHeaderTag { tag, .. } => format!("invalid header tag {:?}", tag),
+        ReadError::InvalidIdentifyCode { code, .. } => {
+            format!("invalid header length {} (expected 6)", code)
+        }
+        ReadError::InvalidTrackTag { tag, .. } => format!("invalid track tag {:?}", tag),
+        ReadError::Io(source) => {
+            return PianoRollError::Read { path: path.to_owned(), source };
+        }
+        ReadError::NoValidHandler => "no handlers".to_owned(),
+        ReadError::UnknownMessageStatus { status, .. } => {
+            format!("unknown message status {:#x}", status)
         }
+    };
+    PianoRollError::MidiParse { path: path.to_owned(), detail }
+}
+
+/// The longest time between two events that a MIDI file can represent: delta times are stored as
+/// variable-length quantities of at most four bytes, with seven bits in each.
+const MAX_DELTA_TIME: u64 = 0x0FFF_FFFF;
+
+fn delta_time(path: &::std::path::Path, last_timestamp: u64, timestamp: u64)
+    -> Result<u32, PianoRollError>
+{
+    let delta = timestamp - last_timestamp;
+    if delta > MAX_DELTA_TIME {
+        Err(PianoRollError::MidiWrite {
+            path: path.to_owned(),
+            detail: format!("the gap of {} ticks between events at {} and {} is too long for a \
+                MIDI file (the maximum is {} ticks)",
+                delta, last_timestamp, timestamp, MAX_DELTA_TIME),
+        })
+    } else {
+        Ok(delta as u32)
     }
 }
 
+/// The controller number of the sustain (damper) pedal.
+const SUSTAIN_PEDAL: u8 = 64;
+
+/// The controller numbers of the bank select MSB and LSB. In Roland GS and Yamaha XG, CC#0 is the
+/// MSB, and CC#32 is the LSB.
+const BANK_SELECT: u8 = 0;
+const BANK_SELECT_LSB: u8 = 32;
+
 struct TrackName {
     name: Option<String>,
     instrument: Option<String>,
 }
 
+#[derive(Default)]
 struct ChannelName {
     bank: Option<u8>,
+    bank_lsb: Option<u8>,
     program: Option<u8>,
 }
 
-struct ChannelInfoHandler {
+/// Everything read from the file, in one pass, with the track and the time in it kept in one
+/// place so that every kind of event is numbered and timed the same way.
+struct ReadHandler {
+    /// The current track, counting from 0, and the time in it.
     track: usize,
-    headers_finished: bool,
+    timestamp: u64,
+    /// Number of tracks the header says there are.
+    declared_tracks: Option<u16>,
+    /// Number of track chunks actually found.
+    track_chunks: usize,
+
+    time_base: Option<u16>,
+    /// Set if the file is timed by timecode, whose tempo is fixed.
+    timecode: Option<TimecodeDivision>,
+    tempo: Option<u32>,
+    tempo_changes: Vec<TempoChange>,
+    time_signatures: Vec<TimeSignature>,
+    smpte_offset: Option<SmpteOffset>,
+
+    events: Vec<NoteEvent>,
+    pedal_events: Vec<PedalEvent>,
+    segments: BTreeMap<(usize, u8), Vec<ProgramSegment>>,
+    /// Notes held down in the current track, and the segment they were pressed in.
+    held: BTreeMap<(u8, MidiNote), usize>,
+
     tracks: BTreeMap<usize, TrackName>,
     channels: BTreeMap<(usize, u8), ChannelName>,
 }
 
-impl ChannelInfoHandler {
+impl ReadHandler {
     pub fn new() -> Self {
         Self {
             track: 0,
-            headers_finished: false,
+            timestamp: 0,
+            declared_tracks: None,
+            track_chunks: 0,
+            time_base: None,
+            timecode: None,
+            tempo: None,
+            tempo_changes: vec![],
+            time_signatures: vec![],
+            smpte_offset: None,
+            events: vec![],
+            pedal_events: vec![],
+            segments: BTreeMap::new(),
+            held: BTreeMap::new(),
             tracks: BTreeMap::new(),
             channels: BTreeMap::new(),
         }
     }
 
-    pub fn track_info(&self) -> impl Iterator<Item = TrackInfo> + '_ {
-        self.tracks.iter().map(move |(track, v)| {
-            TrackInfo {
-                midi_track: *track,
-                name: v.name.clone(),
-                instrument: v.instrument.clone(),
+    /// The channel's current program segment, starting one with the default program if there
+    /// hasn't been a ProgramChange yet.
+    fn current_segment(&mut self, channel: u8) -> (usize, &mut ProgramSegment) {
+        let timestamp = self.timestamp;
+        let segments = self.segments.entry((self.track, channel)).or_default();
+        if segments.is_empty() {
+            segments.push(ProgramSegment { start: timestamp, program: 0, notes: 0 });
+        }
+        let index = segments.len() - 1;
+        (index, &mut segments[index])
+    }
+
+    fn program_change(&mut self, channel: u8, program: u8) {
+        let entry = self.channels.entry((self.track, channel)).or_default();
+        if entry.program.is_none() {
+            entry.program = Some(program);
+        } else {
+            log::warn!("track {} set to another program ({}) mid-song", self.track, program);
+        }
+
+        let timestamp = self.timestamp;
+        let segments = self.segments.entry((self.track, channel)).or_default();
+        match segments.last_mut() {
+            // Changing program before playing anything just picks the instrument for the segment.
+            Some(segment) if segment.notes == 0 => {
+                segment.start = timestamp;
+                segment.program = program;
             }
-        })
+            _ => segments.push(ProgramSegment { start: timestamp, program, notes: 0 }),
+        }
     }
 
-    pub fn channel_info(&self) -> impl Iterator<Item = ChannelInfo> + '_ {
-        self.channels.iter().map(move |((track, channel), v)| {
-            let bank = match v.bank {
-                Some(bank) => bank,
-                None => {
-                    println!("ERROR: track {} channel {} has no MIDI bank set", track, channel);
-                    0 // use a default value
-                }
-            };
-            let program = match v.program {
-                Some(program) => program,
-                None => {
-                    println!("ERROR: track {} channel {} has no MIDI program set", track, channel);
-                    0 // use a default value
-                }
-            };
-            ChannelInfo {
-                midi_track: *track,
-                midi_channel: *channel,
-                bank,
-                program,
+    fn bank_select(&mut self, channel: u8, control: u8, bank: u8) {
+        let entry = self.channels.entry((self.track, channel)).or_default();
+        let (field, which) = if control == BANK_SELECT {
+            (&mut entry.bank, "MSB")
+        } else {
+            (&mut entry.bank_lsb, "LSB")
+        };
+        if field.is_none() {
+            *field = Some(bank);
+        } else {
+            log::warn!("track {} set to another bank select {} ({}) mid-song",
+                self.track, which, bank);
+        }
+    }
+
+    fn push_event(&mut self, channel: u8, note: MidiNote, action: NoteAction, velocity: u8) {
+        let segment = match action {
+            NoteAction::On => {
+                let (index, segment) = self.current_segment(channel);
+                segment.notes += 1;
+                self.held.insert((channel, note), index);
+                index
             }
-        })
+            // A note released after a program change still belongs to the segment it was pressed
+            // in.
+            NoteAction::Off => match self.held.remove(&(channel, note)) {
+                Some(index) => index,
+                None => self.current_segment(channel).0,
+            },
+        };
+        let program = self.segments[&(self.track, channel)][segment].program;
+        self.events.push(NoteEvent {
+            timestamp: self.timestamp,
+            track: self.track,
+            channel,
+            note,
+            action,
+            velocity,
+            segment,
+            program,
+        });
     }
-}
 
-impl ghakuf::reader::Handler for ChannelInfoHandler {
-    fn meta_event(
-        &mut self,
-        _delta_time: u32,
-        event: &ghakuf::messages::MetaEvent,
-        data: &Vec<u8>,
-    ) {
+    fn name_track(&mut self, event: &MetaEvent, data: &[u8]) {
         let track_entry = self.tracks.entry(self.track)
             .or_insert_with(||
                 TrackName {
@@ -353,7 +435,7 @@ impl ghakuf::reader::Handler for ChannelInfoHandler {
                 if track_entry.name.is_none() {
                     track_entry.name = Some(name);
                 } else {
-                    println!("WARNING: track {} given multiple names: {:?}",
+                    log::warn!("track {} given multiple names: {:?}",
                                 self.track, name);
                 }
             }
@@ -362,7 +444,7 @@ impl ghakuf::reader::Handler for ChannelInfoHandler {
                 if track_entry.instrument.is_none() {
                     track_entry.instrument = Some(name);
                 } else {
-                    println!("WARNING: track {} given multiple instrument names: {:?}",
+                    log::warn!("track {} given multiple instrument names: {:?}",
                         self.track, name);
                 }
             },
@@ -370,95 +452,91 @@ impl ghakuf::reader::Handler for ChannelInfoHandler {
         }
     }
 
-    fn midi_event(
-        &mut self,
-        _delta_time: u32,
-        event: &MidiEvent,
-    ) {
-        match event {
-            MidiEvent::ControlChange { ch, control, data } if *control == 0 => {
-                let entry = self.channels.entry((self.track, *ch))
-                    .or_insert(ChannelName { bank: None, program: None });
-                if entry.bank.is_none() {
-                    entry.bank = Some(*data);
-                } else {
-                    println!("WARNING: track {} set to another bank ({}) mid-song",
-                        self.track, data);
-                }
-            }
-            /*MidiEvent::ControlChange { control, .. } if *control == 32 => {
-                // In Roland GS, CC#0 is the bank select MSB, and CC#32 is the bank select LSB
-                // Should probably handle this...
-                println!("{:#?}", event);
-            }*/
-            MidiEvent::ProgramChange { ch, program } => {
-                let entry = self.channels.entry((self.track, *ch))
-                    .or_insert(ChannelName { bank: None, program: None });
-                if entry.program.is_none() {
-                    entry.program = Some(*program);
-                } else {
-                    println!("WARNING: track {} set to another program ({}) mid-song",
-                        self.track, program);
-                }
-            }
-            MidiEvent::NoteOn { ch, .. } => {
-                let _entry = self.channels.entry((self.track, *ch))
-                    .or_insert(ChannelName { bank: None, program: None });
-                // do nothing with it; just make one if there wasn't one before.
+    pub fn track_info(&self) -> impl Iterator<Item = TrackInfo> + '_ {
+        self.tracks.iter().map(move |(track, v)| {
+            TrackInfo {
+                midi_track: *track,
+                name: v.name.clone(),
+                instrument: v.instrument.clone(),
             }
-            _ => (),
-        }
-    }
-
-    fn track_change(&mut self) {
-        if self.headers_finished {
-            self.track += 1;
-        } else {
-            self.headers_finished = true;
-        }
+        })
     }
-}
 
-struct SongInfoHandler {
-    time_base: Option<u16>,
-    tempo: Option<u32>,
-}
-
-impl SongInfoHandler {
-    pub fn new() -> Self {
-        Self {
-            time_base: None,
-            tempo: None,
-        }
+    pub fn channel_info(&self) -> impl Iterator<Item = ChannelInfo> + '_ {
+        self.channels.iter().map(move |((track, channel), v)| {
+            let bank = match v.bank {
+                Some(bank) => bank,
+                None => {
+                    log::error!("track {} channel {} has no MIDI bank set", track, channel);
+                    0 // use a default value
+                }
+            };
+            let program = match v.program {
+                Some(program) => program,
+                None => {
+                    log::error!("track {} channel {} has no MIDI program set", track, channel);
+                    0 // use a default value
+                }
+            };
+            ChannelInfo {
+                midi_track: *track,
+                midi_channel: *channel,
+                bank,
+                // Files for plain General MIDI don't bother with it.
+                bank_lsb: v.bank_lsb.unwrap_or(0),
+                program,
+                program_segments: self.segments.get(&(*track, *channel))
+                    .cloned()
+                    .unwrap_or_default(),
+            }
+        })
     }
 }
 
-impl ghakuf::reader::Handler for SongInfoHandler {
+impl ghakuf::reader::Handler for ReadHandler {
     fn header(&mut self, format: u16, track: u16, time_base: u16) {
-        print!("MIDI file format: ");
+        self.declared_tracks = Some(track);
         match format {
-            0 => println!("single track"),
-            1 => println!("multiple track ({})", track),
-            2 => println!("multiple song ({})", track),
-            _ => println!("unknown!"),
+            0 => log::info!("MIDI file format: single track"),
+            1 => log::info!("MIDI file format: multiple track ({})", track),
+            2 => log::info!("MIDI file format: multiple song ({})", track),
+            _ => log::info!("MIDI file format: unknown!"),
         }
-        if time_base > 0 {
-            self.time_base = Some(time_base);
-            println!("{} MIDI ticks per metronome beat", time_base);
-        } else {
-            println!("WARNING: unsupported timecode-based MIDI file");
+        match TimecodeDivision::from_division(time_base) {
+            None if time_base > 0 => {
+                self.time_base = Some(time_base);
+                log::info!("{} MIDI ticks per metronome beat", time_base);
+            }
+            None => log::warn!("MIDI file has a time base of 0 ticks per beat"),
+            Some(timecode) => match timecode.metrical() {
+                Some((ticks, micros)) => {
+                    log::info!("Timecode-based MIDI file at {}: reading it as {} ticks per beat \
+                        at {} microseconds per beat", timecode, ticks, micros);
+                    self.time_base = Some(ticks);
+                    self.timecode = Some(timecode);
+                    self.tempo = Some(micros);
+                    self.tempo_changes.push(TempoChange { timestamp: 0, micros_per_beat: micros });
+                }
+                None => log::warn!("unsupported timecode-based MIDI file: {}", timecode),
+            },
         }
     }
 
     fn meta_event(
         &mut self,
-        _delta_time: u32,
+        delta_time: u32,
         event: &ghakuf::messages::MetaEvent,
         data: &Vec<u8>,
     ) {
+        self.timestamp += u64::from(delta_time);
+        self.name_track(event, data);
         match event {
             MetaEvent::CopyrightNotice => {
-                println!("Copyright: {:?}", String::from_utf8_lossy(data));
+                log::info!("Copyright: {:?}", String::from_utf8_lossy(data));
+            }
+            MetaEvent::SetTempo if self.timecode.is_some() => {
+                log::info!("Ignoring the tempo change at {}, since the file is timed by timecode",
+                    self.timestamp);
             }
             MetaEvent::SetTempo => {
                 let mut micros = 0u32; // microseconds per beat
@@ -467,18 +545,133 @@ impl ghakuf::reader::Handler for SongInfoHandler {
                     micros += u32::from(*byte);
                 }
                 if self.tempo.is_some() {
-                    println!("WARNING: tempo changes are not supported; using new tempo");
+                    log::info!("Tempo change at {}: {} quarter notes per minute",
+                        self.timestamp, 60_000_000 / micros);
+                } else {
+                    log::info!("Tempo: {} quarter notes per minute", 60_000_000 / micros);
                 }
                 self.tempo = Some(micros);
-                println!("Tempo: {} beats per minute", 60_000_000 / micros);
+                self.tempo_changes.push(TempoChange {
+                    timestamp: self.timestamp,
+                    micros_per_beat: micros,
+                });
+            }
+            MetaEvent::TimeSignature => {
+                match TimeSignature::from_bytes(self.timestamp, data) {
+                    Some(signature) => {
+                        if self.time_signatures.is_empty() {
+                            log::info!("Time signature: {}/{}",
+                                signature.numerator, signature.denominator);
+                        } else {
+                            log::info!("Time signature change at {}: {}/{}",
+                                self.timestamp, signature.numerator, signature.denominator);
+                        }
+                        self.time_signatures.push(signature);
+                    }
+                    None => log::warn!("malformed time signature {:?}", data),
+                }
+            }
+            MetaEvent::SMTPEOffset => {
+                match SmpteOffset::from_bytes(data) {
+                    Some(offset) => {
+                        log::info!("SMPTE offset: {}", offset);
+                        self.smpte_offset = Some(offset);
+                    }
+                    None => log::warn!("malformed SMPTE offset {:?}", data),
+                }
             }
             MetaEvent::Marker => {
-                println!("Marker: {:?}", String::from_utf8_lossy(data));
+                log::info!("Marker: {:?}", String::from_utf8_lossy(data));
             }
             MetaEvent::TextEvent => {
-                println!("Text: {:?}", String::from_utf8_lossy(data));
+                log::info!("Text: {:?}", String::from_utf8_lossy(data));
             }
             _ => ()
         }
     }
+
+    fn midi_event(
+        &mut self,
+        delta_time: u32,
+        event: &MidiEvent,
+    ) {
+        self.timestamp += u64::from(delta_time);
+
+        match event {
+            MidiEvent::NoteOn { ch, note, velocity } => {
+                // Make sure the channel is listed, even if it never picks a program.
+                self.channels.entry((self.track, *ch)).or_default();
+                let action = if *velocity == 0 {
+                    NoteAction::Off
+                } else {
+                    NoteAction::On
+                };
+
+                let note = MidiNote::try_from(*note).unwrap();
+                self.push_event(*ch, note, action, *velocity);
+            }
+            MidiEvent::NoteOff { ch, note, .. } => {
+                let note = MidiNote::try_from(*note).unwrap();
+                self.push_event(*ch, note, NoteAction::Off, 0);
+            }
+            MidiEvent::ProgramChange { ch, program } => {
+                self.program_change(*ch, *program);
+            }
+            /*
+            MidiEvent::ControlChange { ch, control, data } => {
+                let off_on = |data: &u8| if *data < 64 { "off" } else { "on" };
+                let info = match control {
+                    64 => Some(format!("sustain {}", off_on(data))),
+                    65 => Some(format!("portamento {}", off_on(data))),
+                    66 => Some(format!("sostenuto {}", off_on(data))),
+                    67 => Some(format!("soft pedal {}", off_on(data))),
+                    68 => Some(format!("legato {}", off_on(data))),
+                    _ => None,
+                };
+                if let Some(info) = info {
+                    log::info!("track {}, channel {}, time {}: {}",
+                        self.track, ch, self.timestamp, info);
+                }
+            }
+            */
+            MidiEvent::ControlChange { ch, control: SUSTAIN_PEDAL, data } => {
+                self.pedal_events.push(PedalEvent {
+                    timestamp: self.timestamp,
+                    track: self.track,
+                    channel: *ch,
+                    down: *data >= 64,
+                });
+            }
+            MidiEvent::ControlChange { ch, control: control @ (BANK_SELECT | BANK_SELECT_LSB),
+                data } =>
+            {
+                self.bank_select(*ch, *control, *data);
+            }
+            MidiEvent::ControlChange { .. } => (),
+            MidiEvent::ChannelPressure { .. }
+                | MidiEvent::PitchBendChange { .. }
+                | MidiEvent::PolyphonicKeyPressure { .. } => (),
+            _ => {
+                log::info!("track {}, time {}, {:?}", self.track, self.timestamp, event);
+            }
+        }
+    }
+
+    fn sys_ex_event(
+        &mut self,
+        delta_time: u32,
+        _event: &ghakuf::messages::SysExEvent,
+        _data: &Vec<u8>,
+    ) {
+        self.timestamp += u64::from(delta_time);
+    }
+
+    fn track_change(&mut self) {
+        // Called at the start of every track chunk, including the first, so anything before it
+        // counts as track 0 too.
+        self.track = self.track_chunks;
+        self.track_chunks += 1;
+        self.timestamp = 0;
+        self.held.clear();
+    }
 }
@@ -1,3 +1,10 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Names of the notes in an octave, indexed by pitch class.
+pub const PITCH_CLASS_NAMES: [&str; 12] =
+    ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
 #[repr(u8)]
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Copy, Clone)]
 #[allow(dead_code)] // because the names are used for the Debug impl
@@ -138,14 +145,15 @@ pub enum MidiNote {
 }
 
 impl MidiNote {
-    pub fn try_from(raw: u8) -> Option<Self> {
-        if raw <= 0x7f {
-            // Safe because bounds checked.
-            Some(unsafe { ::std::mem::transmute(raw) })
-        } else {
-            None
-        }
-    }
+    /// The lowest note that can be represented on a piano roll.
+    pub const PIANOROLL_LOWEST: MidiNote = MidiNote::C1;
+
+    /// The highest note that can be represented on a piano roll.
+    pub const PIANOROLL_HIGHEST: MidiNote = MidiNote::G7;
+
+    /// The number of different notes that can be represented on a piano roll.
+    pub const PIANOROLL_NOTES: u8 =
+        MidiNote::PIANOROLL_HIGHEST as u8 - MidiNote::PIANOROLL_LOWEST as u8 + 1;
 
     pub fn as_u8(self) -> u8 {
         self as u8
@@ -155,23 +163,101 @@ impl MidiNote {
         self as i8
     }
 
+    /// The note's position within its octave, from 0 (C) through 11 (B).
+    ///
+    /// For example, `MidiNote::C4.pitch_class()` is 0 and `MidiNote::A4.pitch_class()` is 9.
+    pub fn pitch_class(self) -> u8 {
+        self.as_u8() % 12
+    }
+
+    /// The note's octave in scientific pitch notation, from -1 through 9.
+    ///
+    /// For example, `MidiNote::C4.octave()` (MIDI note 60) is 4, and `MidiNote::B1n.octave()`
+    /// (MIDI note 11) is -1.
+    pub fn octave(self) -> i8 {
+        (self.as_u8() / 12) as i8 - 1
+    }
+
     pub fn pianoroll_channel(self) -> Option<u8> {
-        if self < MidiNote::C1
-            || self > MidiNote::G7
+        if self < MidiNote::PIANOROLL_LOWEST
+            || self > MidiNote::PIANOROLL_HIGHEST
         {
             None
         } else {
-            Some(self.as_u8() - MidiNote::C1.as_u8() + 8)
+            Some(self.as_u8() - MidiNote::PIANOROLL_LOWEST.as_u8() + 8)
         }
     }
 
+    /// The note whose holes go in the given channel of the roll, if it's one of the note
+    /// channels.
+    pub fn from_pianoroll_channel(channel: u8) -> Option<Self> {
+        let raw = channel.checked_sub(8)?.checked_add(MidiNote::PIANOROLL_LOWEST.as_u8())?;
+        let note = Self::try_from(raw).ok()?;
+        note.pianoroll_channel().map(|_| note)
+    }
+
     pub fn checked_offset(self, rhs: i8) -> Option<Self> {
         let value = self.as_i8().checked_add(rhs)?;
         if value >= 0 {
             // Safe because bounds checked.
-            Some(unsafe { std::mem::transmute(value) })
+            Some(unsafe { std::mem::transmute::<i8, MidiNote>(value) })
         } else {
             None
         }
     }
 }
+
+impl TryFrom<u8> for MidiNote {
+    type Error = String;
+
+    fn try_from(raw: u8) -> Result<Self, String> {
+        if raw <= 0x7f {
+            // Safe because bounds checked.
+            Ok(unsafe { ::std::mem::transmute::<u8, MidiNote>(raw) })
+        } else {
+            Err(format!("MIDI note numbers go up to 127, not {}", raw))
+        }
+    }
+}
+
+/// Parse a note name: a letter from A to G, then optionally a sharp (`#`) or flat (`b`), then the
+/// octave, from 0 to 9, in scientific pitch notation, where C4 is middle C (MIDI note 60).
+impl TryFrom<&str> for MidiNote {
+    type Error = String;
+
+    fn try_from(name: &str) -> Result<Self, String> {
+        let invalid = || format!("{:?} isn't a note name like \"C4\" or \"Bb3\"", name);
+        let mut chars = name.chars();
+        let pitch_class = match chars.next() {
+            Some('C') => 0,
+            Some('D') => 2,
+            Some('E') => 4,
+            Some('F') => 5,
+            Some('G') => 7,
+            Some('A') => 9,
+            Some('B') => 11,
+            _ => return Err(invalid()),
+        };
+        let rest = chars.as_str();
+        let (accidental, octave) = match rest.chars().next() {
+            Some('#') => (1, &rest[1 ..]),
+            Some('b') => (-1, &rest[1 ..]),
+            _ => (0, rest),
+        };
+        let octave = match octave.as_bytes() {
+            &[digit @ b'0' ..= b'9'] => i16::from(digit - b'0'),
+            _ => return Err(invalid()),
+        };
+        let raw = (octave + 1) * 12 + pitch_class + accidental;
+        u8::try_from(raw).ok()
+            .and_then(|raw| Self::try_from(raw).ok())
+            .ok_or_else(|| format!("{:?} is above the highest MIDI note, G9", name))
+    }
+}
+
+/// The note's name, as parsed by `try_from`, using sharps: for example, "C#4".
+impl fmt::Display for MidiNote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", PITCH_CLASS_NAMES[usize::from(self.pitch_class())], self.octave())
+    }
+}
@@ -0,0 +1,53 @@
+//! Note maps replace notes on the percussion channel with pitched notes, so that a drum part can
+//! be played on the roll.
+
+use crate::error::PianoRollError;
+use crate::note::MidiNote;
+use crate::program::GM_PERCUSSION;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt::Write;
+use std::path::Path;
+
+pub type NoteMap = BTreeMap<MidiNote, MidiNote>;
+
+/// Read a note map file. Each line has a percussion note number and the note number to play
+/// instead, separated by whitespace. Anything after a `#` is a comment.
+pub fn read(path: &Path) -> Result<NoteMap, PianoRollError> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|source| PianoRollError::Read { path: path.to_owned(), source })?;
+    let error = |detail| PianoRollError::NoteMapParse { path: path.to_owned(), detail };
+    let mut map = NoteMap::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let parse_note = |s: Option<&str>| {
+            s.and_then(|s| s.parse().ok())
+                .and_then(|raw: u8| MidiNote::try_from(raw).ok())
+                .ok_or_else(|| error(format!("line {}: expected two MIDI note numbers (0-127)",
+                    i + 1)))
+        };
+        let mut fields = line.split_whitespace();
+        let from = parse_note(fields.next())?;
+        let to = parse_note(fields.next())?;
+        if fields.next().is_some() {
+            return Err(error(format!("line {}: unexpected text after the second note number",
+                i + 1)));
+        }
+        map.insert(from, to);
+    }
+    Ok(map)
+}
+
+/// Write the suggested pitched note for every General MIDI percussion sound as a note map file.
+pub fn write_percussion_map(path: &Path) -> Result<(), PianoRollError> {
+    let mut text = String::new();
+    writeln!(text, "# General MIDI percussion note -> pitched note").unwrap();
+    for (percussion, name, note) in GM_PERCUSSION.iter() {
+        writeln!(text, "{:<3} {:<3} # {} -> {:?}", percussion, note.as_u8(), name, note).unwrap();
+    }
+    std::fs::write(path, text)
+        .map_err(|source| PianoRollError::Write { path: path.to_owned(), source })
+}
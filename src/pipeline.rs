@@ -0,0 +1,890 @@
+//! Everything the `pianoroll` program does with its configuration, from reading the song to
+//! drawing the roll and checking it, so that other programs can do the same.
+
+use crate::{analysis, checks, config, diagnostics, export, formatting, geometry, midi, note_map,
+    processing, program, render, tempo};
+use crate::audit::Audit;
+use crate::checks::Category;
+use crate::config::{AutoTranspose, ChannelSelector, Configuration, MinDuration, PreviewTempo,
+    Quantize, SegmentSelector};
+use crate::geometry::POINTS_PER_INCH;
+use crate::midi::{note_durations, Midi, NoteAction, NoteEvent, NoteWithDuration, OutputTrack,
+    OverlapPolicy};
+use crate::error::PianoRollError;
+use crate::note::MidiNote;
+use crate::note_map::NoteMap;
+use crate::program::PERCUSSION_CHANNEL;
+use crate::tempo::{BeatGrid, TempoChange, TempoMap};
+use std::collections::btree_map::*;
+use std::io::Write;
+
+/// Work out the tempo dial setting that plays the roll at the song's average tempo, and report it
+/// along with how far off rounding it to a marking leaves the timing. If the tempo changes a lot,
+/// also report the settings the slowest and fastest parts would need on their own.
+///
+/// Returns the rounded setting.
+fn suggest_tempo_marking(
+    notes: &[NoteWithDuration],
+    tempo_map: &TempoMap,
+    time_base: u16,
+    time_divisor: f64,
+) -> f64 {
+    // Differences in the dial setting under this fraction aren't worth mentioning.
+    const VARIATION: f64 = 0.1;
+
+    let end = notes.iter()
+        .map(|note| note.timestamp + note.duration)
+        .max()
+        .unwrap_or(0);
+    let exact = tempo::dial_setting(tempo_map.average_tempo(end), time_base, time_divisor);
+    let dial = tempo::round_dial(exact);
+    let error = (dial / exact - 1.) * 100.;
+    if error.abs() < 0.05 {
+        log::info!("suggested tempo marking: Tempo {}", dial);
+    } else {
+        log::info!("suggested tempo marking: Tempo {} (exactly {:.1}; plays {:.1}% {})",
+            dial, exact, error.abs(), if error < 0. { "slow" } else { "fast" });
+    }
+
+    let (slowest, fastest) = tempo_map.tempo_range(end);
+    let slowest = tempo::dial_setting(f64::from(slowest), time_base, time_divisor);
+    let fastest = tempo::dial_setting(f64::from(fastest), time_base, time_divisor);
+    if fastest - slowest > exact * VARIATION {
+        log::info!("tempo varies: slowest section needs Tempo {}, fastest needs Tempo {}",
+            tempo::round_dial(slowest), tempo::round_dial(fastest));
+    }
+    dial
+}
+
+/// Log where the landmark notes are across the roll.
+fn report_key_positions() {
+    for note in render::key_landmarks() {
+        let channel = note.pianoroll_channel().unwrap();
+        log::info!("Channel {} ({:?}) is at x = {:.2} inches from left edge",
+            channel, note, geometry::hole_center(channel) / POINTS_PER_INCH);
+    }
+}
+
+/// How reports refer to a selector: by its label, followed by what it selects.
+fn selector_name(selector: &ChannelSelector, channel_base: u8) -> String {
+    format!("{} ({},{}{:+})", selector.label, selector.midi_track,
+        selector.midi_channel + channel_base, selector.offset)
+}
+
+/// All the selectors that select a note event, with their positions on the command line. Several
+/// selectors can pick the same channel with different offsets, to double it.
+fn matching_selectors<'a>(selectors: &'a [ChannelSelector], event: &'a NoteEvent)
+    -> impl Iterator<Item = (usize, &'a ChannelSelector)> + 'a
+{
+    selectors.iter()
+        .enumerate()
+        .filter(move |(_, selector)| event.track == selector.midi_track
+            && event.channel == selector.midi_channel
+            && match selector.segment {
+                Some(SegmentSelector::Program(program)) => event.program == program,
+                Some(SegmentSelector::Index(index)) => event.segment == index,
+                None => true,
+            })
+}
+
+/// The offset a selector applies to a note event.
+fn selector_offset(selector: &ChannelSelector, note_map: &NoteMap, event: &NoteEvent) -> i8 {
+    match note_map.get(&event.note) {
+        Some(mapped) if event.channel == PERCUSSION_CHANNEL => {
+            selector.offset.saturating_add(mapped.as_i8() - event.note.as_i8())
+        }
+        _ => selector.offset,
+    }
+}
+
+/// Every note the selectors pick, with its pitch after the selector's offset and its entry in
+/// `transposes`, for working out phrases and transpositions.
+fn selected_notes<'a>(
+    notes: impl Iterator<Item = &'a NoteEvent>,
+    selectors: &[ChannelSelector],
+    note_map: &NoteMap,
+    transposes: &[i8],
+) -> Vec<processing::SelectedNote> {
+    let mut held = BTreeMap::<(usize, usize, u8, MidiNote), (u64, i16)>::new();
+    let mut selected = vec![];
+    for event in notes {
+        for (i, selector) in matching_selectors(selectors, event) {
+            let key = (i, event.track, event.channel, event.note);
+            match event.action {
+                NoteAction::On => {
+                    let offset = selector_offset(selector, note_map, event);
+                    let pitch = i16::from(event.note.as_i8()) + i16::from(offset)
+                        + i16::from(transposes[i]);
+                    held.insert(key, (event.timestamp, pitch));
+                }
+                NoteAction::Off => {
+                    if let Some((timestamp, pitch)) = held.remove(&key) {
+                        selected.push(processing::SelectedNote {
+                            selector: i,
+                            track: event.track,
+                            channel: event.channel,
+                            timestamp,
+                            end: event.timestamp,
+                            note: event.note,
+                            pitch,
+                        });
+                    }
+                }
+            }
+        }
+    }
+    selected
+}
+
+/// The lowest and highest notes selected, after applying their offsets.
+fn selected_note_range<'a>(
+    notes: impl Iterator<Item = &'a NoteEvent>,
+    selectors: &[ChannelSelector],
+    note_map: &NoteMap,
+) -> Option<(MidiNote, MidiNote)> {
+    notes
+        .filter(|event| event.action == NoteAction::On)
+        .flat_map(|event| {
+            matching_selectors(selectors, event)
+                .filter_map(move |(_, selector)| {
+                    event.note.checked_offset(selector_offset(selector, note_map, event))
+                })
+        })
+        .fold(None, |range, note| match range {
+            None => Some((note, note)),
+            Some((lowest, highest)) => Some((note.min(lowest), note.max(highest))),
+        })
+}
+
+/// Write the preview MIDI file with the notes from each track and channel of the song in a track
+/// of their own, named after the original. Notes from the percussion channel have been made into
+/// pitched ones to be on the roll, so they go on channel 0 instead, so as not to play as drums.
+fn write_preview(
+    path: &std::path::Path,
+    notes: &[NoteWithDuration],
+    midi: &Midi,
+    time_base: u16,
+    tempo_changes: &[TempoChange],
+) -> Result<(), PianoRollError> {
+    let mut groups = BTreeMap::<(usize, u8), Vec<NoteWithDuration>>::new();
+    for note in notes {
+        groups.entry((note.track, note.channel)).or_default().push(note.clone());
+    }
+    let tracks = groups.iter()
+        .map(|(&(track, channel), notes)| OutputTrack {
+            name: midi.tracks()
+                .find(|info| info.midi_track == track)
+                .and_then(|info| info.name.as_deref()),
+            channel: if channel == PERCUSSION_CHANNEL { 0 } else { channel },
+            notes,
+        })
+        .collect::<Vec<_>>();
+    Midi::write(path, &tracks, time_base, tempo_changes)
+}
+
+/// Read back the preview MIDI file and make sure its notes make the same holes as `roll`, the
+/// music as drawn on the roll, give or take a tick. The notes are moved onto the roll's timeline,
+/// `roll_tempo_map`, by way of real time, since either one can have been flattened to one tempo.
+fn self_check(
+    path: &std::path::Path,
+    roll: &[checks::HoleSpan],
+    roll_tempo_map: &TempoMap,
+    cfg: &Configuration,
+) -> Result<(), String> {
+    let mut midi = Midi::new();
+    midi.read(path).map_err(|e| e.to_string())?;
+    let time_base = midi.time_base().ok_or_else(|| "no time base in written file".to_owned())?;
+    let tempo_map = midi.tempo_map().expect("there's a time base");
+    let (notes, diagnostics) = note_durations(midi.notes(), &[], time_base, OverlapPolicy::Error,
+        |_| Some((0, 0)));
+    if let Some(diagnostic) = diagnostics.first() {
+        return Err(format!("reading back the written file: {}", diagnostic));
+    }
+
+    let on_roll = |timestamp| roll_tempo_map.timestamp_at(tempo_map.micros_at(timestamp));
+    let notes = notes.into_iter()
+        .map(|note| {
+            let timestamp = on_roll(note.timestamp);
+            let duration = on_roll(note.timestamp + note.duration) - timestamp;
+            NoteWithDuration { timestamp, duration, ..note }
+        })
+        .collect::<Vec<_>>();
+    // A tick, with a little to spare for rounding.
+    let tolerance = 1.01 / cfg.time_divisor;
+    checks::compare_holes(roll, &render::hole_spans(&notes, cfg), tolerance)
+}
+
+/// Log info on the tracks and channels: their names, instruments, and how many notes they have.
+fn report_tracks(midi: &Midi, channel_one_indexed: bool) {
+    let channel_base = u8::from(channel_one_indexed);
+    let counts = midi.note_counts();
+    let channels_by_track: BTreeMap<usize, Vec<&midi::ChannelInfo>>
+        = midi.channels()
+            .fold(BTreeMap::new(), |mut map, item| {
+                match map.entry(item.midi_track) {
+                    Entry::Occupied(mut entry) => { entry.get_mut().push(item); }
+                    Entry::Vacant(entry) => { entry.insert(vec![item]); }
+                }
+                map
+            });
+
+    if channel_one_indexed {
+        log::info!("channels are numbered 1-16");
+    } else {
+        log::info!("channels are numbered 0-15");
+    }
+    for track in midi.tracks() {
+        let mut line = format!("track {}:", track.midi_track);
+        if let Some(ref name) = track.name {
+            line += &format!(" title: \"{}\"", name);
+        }
+        if let Some(ref instrument) = track.instrument {
+            line += &format!(" instrument name: \"{}\", ", instrument);
+        }
+        log::info!("{}", line);
+        let channels_iter = channels_by_track
+            .get(&track.midi_track)
+            .map(|x| x.iter())
+            .unwrap_or_else(|| [].iter());
+        for channel in channels_iter {
+            log::info!("track {}, channel {}:",
+                channel.midi_track, channel.midi_channel + channel_base);
+            if channel.midi_channel == PERCUSSION_CHANNEL {
+                log::info!("\tPercussion");
+            } else if let Some(name) = program::instrument_name(
+                channel.bank, channel.bank_lsb, channel.program)
+            {
+                log::info!("\tMIDI instrument \"{}\"", name);
+            } else {
+                log::info!("\tunknown MIDI instrument: bank {} (LSB {}), program {}",
+                    channel.bank, channel.bank_lsb, channel.program);
+            }
+            if let Some(count) = counts.get(&(channel.midi_track, channel.midi_channel)) {
+                log::info!("\t{} notes", formatting::count(*count));
+            } else {
+                log::info!("\tno notes");
+            }
+            if channel.program_segments.len() > 1 {
+                let segments = &channel.program_segments;
+                for (i, segment) in segments.iter().enumerate() {
+                    let end = segments.get(i + 1)
+                        .map(|next| formatting::count(next.start))
+                        .unwrap_or_else(|| "end".to_owned());
+                    let name = program::MIDI_PROGRAM.get(usize::from(segment.program))
+                        .unwrap_or(&"unknown instrument");
+                    log::info!("\tsegment {}: \"{}\", ticks {} to {}, {} notes",
+                        i, name, formatting::count(segment.start), end,
+                        formatting::count(segment.notes));
+                }
+            }
+        }
+    }
+}
+
+/// Log the track listing, followed by the range of notes on each channel and how long the song
+/// is, for `--info`.
+fn report_info(midi: &Midi, cfg: &Configuration, time_base: u16, tempo_map: &TempoMap) {
+    report_tracks(midi, cfg.channel_one_indexed);
+    let channel_base = u8::from(cfg.channel_one_indexed);
+    let pedal = if cfg.sustain_pedal { midi.pedal_events() } else { &[] };
+    let summary = analysis::SongSummary::new(midi, time_base, pedal, cfg.overlap);
+    for (&(track, channel), (low, high)) in &summary.ranges {
+        log::info!("track {}, channel {}: notes {} to {}",
+            track, channel + channel_base, low, high);
+    }
+    log::info!("length: {} ticks, {:.2} beats, {}", formatting::count(summary.end),
+        summary.end as f64 / f64::from(time_base),
+        formatting::duration(tempo_map.micros_at(summary.end) / 1_000_000.));
+}
+
+/// Do everything the `pianoroll` program does with a configuration: read the song, select and
+/// process its notes, write the roll and whatever else was asked for, and run the checks on it.
+/// What it finds along the way is logged. `--list-tracks-json` writes its listing to `listing`.
+///
+/// Returns the checks' report, whose `exit_code` says which of them failed.
+pub fn run(mut cfg: Configuration, mut listing: impl Write)
+    -> Result<checks::Report, PianoRollError>
+{
+    let mut midi = Midi::with_backend(cfg.backend);
+    midi.read(&cfg.input)?;
+    if let Some(ref path) = cfg.conductor {
+        log::info!("taking the tempo and time signatures from {:?}", path);
+        let mut conductor = Midi::with_backend(cfg.backend);
+        conductor.read(path)?;
+        midi.take_timing_from(&conductor, path, cfg.rescale_ticks)?;
+    }
+
+    if cfg.list_tracks_json {
+        export::tracks::write_json(&midi, cfg.channel_one_indexed, &mut listing)
+            .and_then(|()| writeln!(listing))
+            .map_err(|source| PianoRollError::Write { path: "<track listing>".into(), source })?;
+        return Ok(checks::Report::default());
+    }
+    if cfg.list_tracks {
+        report_tracks(&midi, cfg.channel_one_indexed);
+        return Ok(checks::Report::default());
+    }
+
+    let info = cfg.info || cfg.selectors.is_empty();
+    let channel_base = u8::from(cfg.channel_one_indexed);
+    for selector in &mut cfg.selectors {
+        if let Err(e) = selector.resolve_track_name(&midi, channel_base) {
+            return Err(PianoRollError::ConfigParse(format!("track selector {} (name={}): {}",
+                selector.label, selector.track_name.as_ref().unwrap().name, e)));
+        }
+    }
+    cfg.selectors = config::expand_wildcards(std::mem::take(&mut cfg.selectors), &midi);
+    let cfg = cfg;
+
+    let time_base = midi.time_base().ok_or(PianoRollError::NoTimeBase)?;
+    let tempo = midi.tempo_or(cfg.default_tempo)?;
+    let mut tempo_map = midi.tempo_map().unwrap();
+    if midi.tempo().is_none() {
+        log::warn!("no tempo set in MIDI file; assuming {}",
+            formatting::tempo(tempo, &midi.time_signature()));
+        let tempo_change = TempoChange { timestamp: 0, micros_per_beat: tempo };
+        tempo_map = TempoMap::new(time_base, vec![tempo_change]);
+    } else {
+        log::info!("tempo: {}", formatting::tempo(tempo, &midi.time_signature()));
+    }
+
+    if let Some(ref path) = cfg.export_percussion_map {
+        log::info!("Suggested percussion note map:");
+        for (percussion, name, note) in program::GM_PERCUSSION.iter() {
+            log::info!("\t{} ({}) -> {} ({:?})", percussion, name, note.as_u8(), note);
+        }
+        log::info!("Writing percussion note map to {:?}", path);
+        note_map::write_percussion_map(path)?;
+    }
+
+    if info {
+        report_info(&midi, &cfg, time_base, &tempo_map);
+        return Ok(checks::Report::default());
+    }
+
+    let note_map = match cfg.note_map {
+        Some(ref path) => note_map::read(path)?,
+        None => NoteMap::new(),
+    };
+
+    let selected_range = selected_note_range(midi.notes(), &cfg.selectors, &note_map);
+
+    if cfg.max_note_range || cfg.strict_range {
+        if let Some((lowest, highest)) = selected_range {
+            let span = highest.as_u8() - lowest.as_u8() + 1;
+            if span > MidiNote::PIANOROLL_NOTES {
+                let e = PianoRollError::NoteOutOfRange { lowest, highest };
+                if cfg.strict_range {
+                    return Err(e);
+                }
+                log::warn!("{}", e);
+            } else if lowest < MidiNote::PIANOROLL_LOWEST || highest > MidiNote::PIANOROLL_HIGHEST {
+                log::warn!("selected notes {:?} to {:?} go outside the roll's range of {:?} \
+                    to {:?}; try --auto-transpose.",
+                    lowest, highest, MidiNote::PIANOROLL_LOWEST, MidiNote::PIANOROLL_HIGHEST);
+            }
+        }
+    }
+
+    let transposes = match cfg.auto_transpose {
+        Some(mode) => {
+            let selected = selected_notes(
+                midi.notes(), &cfg.selectors, &note_map, &vec![0; cfg.selectors.len()]);
+            cfg.selectors.iter().enumerate()
+                .map(|(i, selector)| {
+                    let pitches = selected.iter()
+                        .filter(|note| note.selector == i)
+                        .map(|note| note.pitch)
+                        .collect::<Vec<_>>();
+                    let (transpose, off_roll) = processing::best_transposition(
+                        &pitches, mode == AutoTranspose::Octaves);
+                    let name = selector_name(selector, channel_base);
+                    if transpose == 0 {
+                        log::info!("selector {}: not transposing", name);
+                    } else {
+                        log::info!("selector {}: transposing by {:+} semitones", name, transpose);
+                    }
+                    if off_roll > 0 {
+                        log::warn!("selector {}: {} of {} notes are still off the roll",
+                            name, formatting::count(off_roll as u64),
+                            formatting::count(pitches.len() as u64));
+                    }
+                    transpose
+                })
+                .collect()
+        }
+        None => vec![0; cfg.selectors.len()],
+    };
+
+    let mut selector_stats = vec![0u64; cfg.selectors.len()];
+    // Of those, how many are within the range of the roll and make it onto it.
+    let mut selector_kept = vec![0u64; cfg.selectors.len()];
+    let mut merge = cfg.merge_alternating.map(|channels| {
+        let end = midi.notes().map(|event| event.timestamp).max().unwrap_or(0);
+        let beat_grid = BeatGrid::new(midi.time_signatures(), time_base, end);
+        let voices = analysis::alternating_voice(
+            midi.notes(), channels, &beat_grid, cfg.merge_prefer_higher);
+        let mut last = None;
+        for (measure, &voice) in voices.iter().enumerate() {
+            if last != Some(voice) {
+                let (track, channel) = channels[voice];
+                log::info!("merging alternating channels: measure {} takes track {} channel {}",
+                    measure + 1, track, channel + u8::from(cfg.channel_one_indexed));
+                last = Some(voice);
+            }
+        }
+        processing::AlternatingMerge::new(channels, beat_grid, voices)
+    });
+
+    let mut fold_diagnostics = vec![];
+    let mut fold = if cfg.fold_phrases {
+        let max_gap = (cfg.phrase_gap * f64::from(time_base)).round() as u64;
+        let (fold, phrases) = processing::PhraseFold::new(
+            selected_notes(midi.notes(), &cfg.selectors, &note_map, &transposes), max_gap);
+        for phrase in phrases {
+            match phrase.shift {
+                Some(shift) => log::info!("folded the phrase of {} notes on track {} channel {} \
+                    from {} to {} by {:+} semitones",
+                    phrase.notes, phrase.track, phrase.channel + u8::from(cfg.channel_one_indexed),
+                    phrase.start, phrase.end, shift),
+                None => fold_diagnostics.push(diagnostics::Diagnostic::PhraseDropped {
+                    timestamp: phrase.start,
+                    end: phrase.end,
+                    track: phrase.track,
+                    channel: phrase.channel,
+                    note: phrase.first_note,
+                    notes: phrase.notes,
+                }),
+            }
+        }
+        Some(fold)
+    } else {
+        None
+    };
+
+    let pedal = if cfg.sustain_pedal { midi.pedal_events() } else { &[] };
+    let (mut durations, mut diagnostics) = note_durations(midi.notes(), pedal, time_base,
+        cfg.overlap, |event| {
+        let mut offsets = vec![];
+        if let Some(ref mut merge) = merge {
+            if !merge.keep(event) {
+                return offsets;
+            }
+        }
+        for (i, selector) in matching_selectors(&cfg.selectors, event) {
+            let mut offset = selector_offset(selector, &note_map, event)
+                .saturating_add(transposes[i]);
+            if let Some(ref mut fold) = fold {
+                match fold.shift(i, event) {
+                    Some(shift) => offset = offset.saturating_add(shift),
+                    None => continue,
+                }
+            }
+            if event.action == NoteAction::On {
+                selector_stats[i] += 1;
+                let note = event.note.checked_offset(offset);
+                if note.is_some_and(|note| note.pianoroll_channel().is_some()) {
+                    selector_kept[i] += 1;
+                }
+            }
+            offsets.push((i, offset));
+        }
+        offsets
+    });
+    diagnostics.append(&mut fold_diagnostics);
+    processing::canonical_order(&mut durations);
+    let mut audit = if cfg.fidelity_report.is_some() {
+        Audit::new(&midi.notes().collect::<Vec<_>>(), &durations)
+    } else {
+        Audit::disabled()
+    };
+
+    if let Some(min_duration) = cfg.min_duration {
+        let too_short = |note: &NoteWithDuration| match min_duration {
+            MinDuration::Ticks(ticks) => note.duration < ticks,
+            MinDuration::Millis(millis) => {
+                let micros = tempo_map.micros_at(note.timestamp + note.duration)
+                    - tempo_map.micros_at(note.timestamp);
+                micros < millis * 1000.
+            }
+        };
+        let dropped = audit.apply("minimum duration", &mut durations,
+            |notes| processing::drop_short_notes(notes, too_short));
+        if dropped > 0 {
+            log::info!("dropped {} notes shorter than {}", formatting::count(dropped as u64),
+                min_duration);
+        }
+    }
+
+    if let Some(quantize) = cfg.quantize {
+        let quantum = quantize.grid_ticks(time_base);
+        let merged = audit.apply("quantize", &mut durations, |notes| match quantize {
+            Quantize::Ticks(_) => processing::quantize(notes, quantum, cfg.quantize_durations),
+            Quantize::NoteValue(_) => processing::quantize_notes(notes, quantum),
+        });
+        match quantize {
+            Quantize::Ticks(_) => {
+                log::info!("quantized notes to {} ticks", formatting::count(quantum));
+            }
+            Quantize::NoteValue(_) => log::info!("quantized notes to {} ({} ticks)", quantize,
+                formatting::count(quantum)),
+        }
+        if merged > 0 {
+            log::warn!("{} notes landed on another of the same pitch and were merged",
+                formatting::count(merged as u64));
+        }
+    }
+
+    let end = durations.iter().map(|note| note.timestamp + note.duration).max().unwrap_or(0);
+    let mut beat_grid = BeatGrid::new(midi.time_signatures(), time_base, end);
+    let mut editor_notes = vec![];
+    for note in &cfg.editor_notes {
+        match tempo::measure_start(beat_grid.beats(), note.measure) {
+            Some(tick) => {
+                log::info!("editor's note at measure {} (tick {}): {}",
+                    note.measure, formatting::count(tick), note.text);
+                editor_notes.push((tick, note));
+            }
+            None => log::warn!("the song ends before measure {}, so the editor's note \
+                {:?} isn't shown", note.measure, note.text),
+        }
+    }
+
+    // The articulation factor for each selector's notes.
+    let articulations = cfg.selectors.iter()
+        .map(|selector| selector.articulation.unwrap_or(cfg.articulation))
+        .collect::<Vec<_>>();
+    if articulations.iter().any(|&factor| factor != 1.) {
+        // The shortest hole the geometry check allows, in ticks, as it converts them to points.
+        let min_hole = (cfg.thresholds.min_hole * cfg.time_divisor).ceil() as u64;
+        audit.apply("articulate", &mut durations, |notes| processing::articulate(notes, min_hole,
+            |note| note.selector.map_or(1., |selector| articulations[selector])));
+    }
+
+    match cfg.max_repeats {
+        Some(max_repeats) => {
+            for line in diagnostics::aggregated_report(&diagnostics, max_repeats) {
+                log::info!("{}", line);
+            }
+        }
+        None => {
+            for diagnostic in &diagnostics {
+                log::info!("{}", diagnostic);
+            }
+        }
+    }
+    let mut checks = checks::Report::new(cfg.checks.clone());
+    let count_of = |codes: &[diagnostics::Code]| {
+        diagnostics.iter().filter(|diagnostic| codes.contains(&diagnostic.code())).count()
+    };
+    let out_of_range = count_of(&[
+        diagnostics::Code::OutOfRange,
+        diagnostics::Code::PhraseDropped,
+    ]);
+    if out_of_range > 0 {
+        checks.add(Category::Range, vec![format!("{} notes are outside the roll's range",
+            formatting::count(out_of_range as u64))]);
+    }
+    let overlaps = count_of(&[
+        diagnostics::Code::Overlap,
+        diagnostics::Code::NotPressed,
+        diagnostics::Code::StuckNote,
+    ]);
+    if overlaps > 0 {
+        checks.add(Category::Overlap, vec![format!("{} notes are pressed or released out of turn",
+            formatting::count(overlaps as u64))]);
+    }
+
+    if let Some(beats) = cfg.clamp_duration {
+        let max_ticks = (beats * f64::from(time_base)).round() as u64;
+        let clamped = audit.apply("clamp duration", &mut durations,
+            |notes| processing::clamp_durations(notes, max_ticks, &beat_grid));
+        for clamped in clamped {
+            log::info!("clamped note {:?} at tick {} from {} to {} ticks",
+                clamped.note, formatting::count(clamped.timestamp),
+                formatting::count(clamped.old_duration), formatting::count(clamped.new_duration));
+        }
+    }
+
+    let time_stretch = match cfg.stretch_to {
+        Some(seconds) => {
+            let start = if cfg.strip_silence_start {
+                durations.iter().map(|note| note.timestamp).min().unwrap_or(0)
+            } else {
+                0
+            };
+            let factor = processing::stretch_factor(&durations, start, &tempo_map, seconds);
+            match factor {
+                Some(factor) => log::info!("stretching timing by {:+.1}% to play for {}",
+                    (factor - 1.) * 100., formatting::duration(seconds)),
+                None => log::warn!("the song takes no time, so it can't be stretched to \
+                    play for {}", formatting::duration(seconds)),
+            }
+            factor
+        }
+        None => cfg.time_stretch,
+    };
+    if let Some(factor) = time_stretch {
+        audit.apply("time stretch", &mut durations,
+            |notes| processing::time_stretch(notes, factor));
+        tempo_map.stretch(factor);
+        beat_grid.stretch(factor);
+        log::info!("stretched timing by a factor of {}", factor);
+    }
+
+    let mut smpte_ticks = 0;
+    if cfg.apply_smpte_offset {
+        match midi.smpte_offset() {
+            Some(offset) => {
+                let micros = offset.as_seconds() * 1_000_000.;
+                smpte_ticks = (micros / f64::from(tempo_map.tempo_at(0)) * f64::from(time_base))
+                    .round() as u64;
+                audit.apply("SMPTE offset", &mut durations,
+                    |notes| processing::delay(notes, smpte_ticks));
+                tempo_map.delay_start(smpte_ticks);
+                beat_grid.delay_start(smpte_ticks);
+                log::info!("applied SMPTE offset of {} as {} ticks of leading silence",
+                    offset, formatting::count(smpte_ticks));
+            }
+            None => log::warn!("no SMPTE offset in the file to apply"),
+        }
+    }
+
+    if cfg.strip_silence_start {
+        if smpte_ticks > 0 {
+            log::warn!("stripping leading silence removes the applied SMPTE offset");
+        }
+        let stripped = audit.apply("strip leading silence", &mut durations,
+            |notes| processing::strip_leading_silence(notes));
+        // Timed by the tempo map before it's moved, since the tempo can change before the first
+        // note.
+        let seconds = tempo_map.micros_at(stripped) / 1_000_000.;
+        tempo_map.strip_start(stripped);
+        beat_grid.strip_start(stripped);
+        log::info!("stripped {} ticks ({}) of leading silence",
+            formatting::count(stripped), formatting::duration(seconds));
+    }
+
+    report_tracks(&midi, cfg.channel_one_indexed);
+
+    for ((selector, count), kept) in cfg.selectors.iter().zip(&selector_stats).zip(&selector_kept)
+    {
+        log::info!("selector {}: {} notes",
+            selector_name(selector, channel_base), formatting::count(*count));
+        if *kept == 0 {
+            let reason = if *count == 0 {
+                "it matched none".to_owned()
+            } else {
+                format!("all {} were out of range", formatting::count(*count))
+            };
+            log::warn!("selector {} contributed 0 notes after filtering: {}",
+                selector_name(selector, channel_base), reason);
+        }
+    }
+
+    if cfg.pitch_usage || cfg.pitch_usage_csv.is_some() {
+        let usage = analysis::PitchUsage::new(&durations, &tempo_map);
+        if cfg.pitch_usage {
+            log::info!("pitch usage: count (total time)");
+            log::info!("{}", usage.table().trim_end_matches('\n'));
+        }
+        if let Some(ref path) = cfg.pitch_usage_csv {
+            log::info!("Writing pitch usage to {:?}", path);
+            usage.write_csv(path)?;
+        }
+    }
+
+    if cfg.show_key_positions {
+        report_key_positions();
+    }
+
+    if cfg.detect_repeated_sections {
+        let repeats = analysis::find_repeated_measures(&durations, &beat_grid);
+        if repeats.is_empty() {
+            log::info!("no repeated measures found");
+        }
+        for (earlier, later) in repeats {
+            log::info!("measure {} repeats measure {}", later, earlier);
+        }
+    }
+
+    if !durations.is_empty() {
+        let midi_output = cfg.preview_midi_path();
+
+        match cfg.preview_tempo {
+            PreviewTempo::Original => {
+                write_preview(&midi_output, &durations, &midi, time_base, tempo_map.changes())?;
+            }
+            PreviewTempo::Flattened => {
+                let flat_tempo = tempo_map.tempo_at(0);
+                let flattened = processing::flatten_tempo(&durations, &tempo_map, flat_tempo);
+                let tempo_change = TempoChange { timestamp: 0, micros_per_beat: flat_tempo };
+                write_preview(&midi_output, &flattened, &midi, time_base, &[tempo_change])?;
+            }
+        }
+
+        let title = midi.tracks().find_map(|track| track.name.as_deref());
+        if let Some(ref path) = cfg.export_csound {
+            log::info!("Writing Csound score to {:?}", path);
+            export::csound::write_score(path, &durations, &tempo_map, title)?;
+        }
+        if let Some(ref path) = cfg.export_sc3 {
+            log::info!("Writing SuperCollider pattern to {:?}", path);
+            export::supercollider::write_pattern(
+                path, &durations, time_base, tempo_map.tempo_at(0), title)?;
+        }
+
+        if let Some(ref path) = cfg.export_chuck {
+            log::info!("Writing ChucK program to {:?}", path);
+            export::chuck::write_program(path, &durations, &tempo_map, title)?;
+        }
+        if let Some(ref path) = cfg.export_etf {
+            log::info!("Writing Finale ETF file to {:?}", path);
+            export::etf::write_etf(path, &durations, time_base, title)?;
+        }
+        // The roll itself moves at a constant speed, so to follow tempo changes its notes have to
+        // be spaced out by real time rather than by ticks.
+        let flat_tempo = tempo_map.tempo_at(0);
+        let flattened;
+        let (roll_notes, roll_tempo_map) = if cfg.real_time_spacing
+            && tempo_map.changes().iter().any(|change| change.micros_per_beat != flat_tempo)
+        {
+            log::info!("spacing the roll by real time, at {}",
+                formatting::tempo(flat_tempo, &midi.time_signature()));
+            let tempo_change = TempoChange { timestamp: 0, micros_per_beat: flat_tempo };
+            beat_grid.flatten(&tempo_map, flat_tempo);
+            flattened = (
+                processing::flatten_tempo(&durations, &tempo_map, flat_tempo),
+                TempoMap::new(time_base, vec![tempo_change]),
+            );
+            audit.record("real-time spacing", &durations, &flattened.0);
+            (&flattened.0[..], &flattened.1)
+        } else {
+            (&durations[..], &tempo_map)
+        };
+
+        let fitted;
+        let cfg = match cfg.fit_length {
+            Some(length) => {
+                let mut cfg = cfg.clone();
+                let end = roll_notes.iter()
+                    .map(|note| note.timestamp + note.duration)
+                    .max()
+                    .unwrap_or(0);
+                let leader = f64::from(cfg.roll_label.leader_length + cfg.row_offset);
+                let time_divisor = geometry::fit_time_divisor(end, length, leader)
+                    .map_err(PianoRollError::RollLength)?;
+                cfg.set_time_divisor(time_divisor);
+                log::info!("fitting the roll to {} with a time divisor of /{:.3}",
+                    formatting::length(length), cfg.time_divisor);
+                fitted = cfg;
+                &fitted
+            }
+            None => &cfg,
+        };
+
+        // The end holes aren't in the preview, so the self-check leaves them out.
+        let music = roll_notes;
+        let with_end_holes;
+        let roll_notes = if cfg.tune_change_channel.is_some() || cfg.rewind_hole.is_some()
+            || cfg.shutoff_hole.is_some()
+        {
+            let mut notes = roll_notes.to_vec();
+            let end = notes.iter()
+                .map(|note| note.timestamp + note.duration)
+                .max()
+                .unwrap_or(0);
+            let ticks = |points: f64| (points * cfg.time_divisor).round() as u64;
+            if let Some(channel) = cfg.tune_change_channel {
+                let (start, widened) = processing::insert_tune_change(&mut notes, end,
+                    MidiNote::from_pianoroll_channel(channel).unwrap(),
+                    ticks(cfg.tune_change_length).max(1), ticks(cfg.thresholds.min_bridge));
+                if widened > 0 {
+                    log::info!("widened the gap between songs by {} ticks to fit the tune change \
+                        hole", formatting::count(widened));
+                }
+                log::info!("tune change hole in channel {} at tick {}",
+                    channel, formatting::count(start));
+            }
+            for (name, hole) in [("rewind", cfg.rewind_hole), ("shutoff", cfg.shutoff_hole)] {
+                if let Some(hole) = hole {
+                    let start = processing::append_end_hole(&mut notes, end,
+                        MidiNote::from_pianoroll_channel(hole.channel).unwrap(),
+                        ticks(hole.offset), ticks(f64::from(geometry::END_HOLE_LENGTH)).max(1));
+                    log::info!("{} hole in channel {} at tick {}",
+                        name, hole.channel, formatting::count(start));
+                }
+            }
+            with_end_holes = notes;
+            &with_end_holes[..]
+        } else {
+            roll_notes
+        };
+
+        let end = roll_notes.iter().map(|note| note.timestamp + note.duration).max().unwrap_or(0);
+        let leader = f64::from(cfg.roll_label.leader_length);
+        geometry::check_time_divisor(end, cfg.time_divisor, leader)
+            .map_err(PianoRollError::RollLength)?;
+
+        if let Some(ref path) = cfg.catalog_image {
+            log::info!("Writing catalog image to {:?}", path);
+            export::catalog_image::write_png(path, roll_notes, cfg.time_divisor, cfg.row_offset,
+                &cfg.roll_label, title, cfg.image_width, cfg.image_feet)?;
+        }
+        if cfg.punching_rates.is_some() || cfg.segment_export_json.is_some() {
+            let holes = export::segments::holes(
+                roll_notes, cfg.time_divisor, cfg.row_offset, &cfg.roll_label);
+            if let Some((punch_rate, feed_rate)) = cfg.punching_rates {
+                let time = export::segments::punching_time(&holes, punch_rate, feed_rate);
+                log::info!("punching time: {} for {} punches and {:.1} in of paper feed, at {} \
+                    punches and {} in a second", formatting::duration(time.seconds),
+                    formatting::count(time.punches as u64), time.feed_inches, punch_rate,
+                    feed_rate);
+            }
+            if let Some(ref path) = cfg.segment_export_json {
+                log::info!("Writing hole positions to {:?}", path);
+                export::segments::write_json(path, &holes)?;
+            }
+        }
+
+        if let Some(ref path) = cfg.fidelity_report {
+            log::info!("Writing fidelity report to {:?}", path);
+            export::fidelity::write_json(path, &audit, roll_notes, cfg.time_divisor,
+                cfg.row_offset, &cfg.roll_label)?;
+        }
+
+        let dial = suggest_tempo_marking(roll_notes, roll_tempo_map, time_base, cfg.time_divisor);
+        let beats = beat_grid.beats();
+        render::render(roll_notes, beats, time_base, dial, title, cfg)?;
+        if let Some(ref proof) = cfg.proof {
+            render::render(roll_notes, beats, time_base, dial, title, proof)?;
+        }
+
+        if cfg.self_check {
+            log::info!("Checking {:?} against the roll", midi_output);
+            self_check(&midi_output, &render::hole_spans(music, cfg), roll_tempo_map, cfg)
+                .map_err(PianoRollError::SelfCheck)?;
+            log::info!("self-check passed: {} notes match",
+                formatting::count(music.len() as u64));
+        }
+
+        let holes = render::hole_spans(roll_notes, cfg);
+        checks.add(Category::Geometry, checks::geometry(&holes, &cfg.thresholds));
+        checks.add(Category::Polyphony, checks::polyphony(&durations, &cfg.thresholds));
+        checks.add(Category::Vacuum, checks::vacuum(&holes, &cfg.thresholds));
+        checks.add(Category::DeadAir,
+            checks::dead_air(&durations, &tempo_map, &cfg.thresholds));
+    }
+
+    for line in checks.lines(cfg.max_repeats) {
+        log::info!("{}", line);
+    }
+    if let Some(ref path) = cfg.diagnostics_json {
+        log::info!("Writing diagnostics to {:?}", path);
+        diagnostics::write_json(path, &diagnostics, &editor_notes, &checks, time_base)?;
+    }
+    if durations.is_empty() {
+        return Err(PianoRollError::NoNotesSelected);
+    }
+    Ok(checks)
+}
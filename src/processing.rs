@@ -0,0 +1,450 @@
+use crate::midi::{NoteAction, NoteEvent, NoteWithDuration};
+use crate::note::MidiNote;
+use crate::tempo::{BeatGrid, TempoMap};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+
+/// Put notes in the one order everything downstream of pairing them up relies on, so output
+/// doesn't depend on the order events happened to be read or processed in: by start, then by
+/// channel on the roll (which goes by pitch), then by where the note came from (its MIDI track),
+/// and then by length and velocity to settle what's left.
+pub fn canonical_order(notes: &mut [NoteWithDuration]) {
+    notes.sort_by_key(|note| (note.timestamp, note.note, note.track, note.duration, note.velocity));
+}
+
+/// Shift all notes earlier so that the first one starts at tick 0.
+/// Returns the number of ticks removed.
+pub fn strip_leading_silence(notes: &mut [NoteWithDuration]) -> u64 {
+    let first = match notes.iter().map(|note| note.timestamp).min() {
+        Some(timestamp) => timestamp,
+        None => return 0,
+    };
+    for note in notes.iter_mut() {
+        note.timestamp -= first;
+    }
+    first
+}
+
+/// Shift all notes later by the given number of ticks.
+pub fn delay(notes: &mut [NoteWithDuration], ticks: u64) {
+    for note in notes.iter_mut() {
+        note.timestamp += ticks;
+    }
+}
+
+/// Put a tune-change hole, `length` ticks long, for `note`'s channel in the gap after the song
+/// that ends at `song_end`, with `clearance` ticks of paper on either side of it. Notes starting at
+/// or after `song_end` belong to the next song; if the gap before it is too short to fit the hole,
+/// they're all moved later to make room. With no next song, the hole goes after the end, for
+/// rewinding the roll.
+///
+/// Returns the tick the hole starts at, and how many ticks the gap was widened by. The notes are
+/// left in canonical order.
+pub fn insert_tune_change(
+    notes: &mut Vec<NoteWithDuration>,
+    song_end: u64,
+    note: MidiNote,
+    length: u64,
+    clearance: u64,
+) -> (u64, u64) {
+    let needed = clearance + length + clearance;
+    let next_start = notes.iter()
+        .map(|note| note.timestamp)
+        .filter(|&timestamp| timestamp >= song_end)
+        .min();
+    let widened = match next_start {
+        Some(start) if start - song_end < needed => needed - (start - song_end),
+        _ => 0,
+    };
+    for later in notes.iter_mut().filter(|later| later.timestamp >= song_end) {
+        later.timestamp += widened;
+    }
+    let start = song_end + clearance;
+    notes.push(NoteWithDuration {
+        timestamp: start,
+        duration: length,
+        note,
+        track: 0,
+        channel: 0,
+        velocity: 127,
+        source: None,
+        selector: None,
+    });
+    canonical_order(notes);
+    (start, widened)
+}
+
+/// Add a hole for the player to act on, like a rewind or shutoff hole, `offset` ticks after the
+/// end of the song at `song_end`. Returns the tick it starts at. The notes are left in canonical
+/// order.
+pub fn append_end_hole(
+    notes: &mut Vec<NoteWithDuration>,
+    song_end: u64,
+    note: MidiNote,
+    offset: u64,
+    length: u64,
+) -> u64 {
+    let start = song_end + offset;
+    notes.push(NoteWithDuration {
+        timestamp: start,
+        duration: length,
+        note,
+        track: 0,
+        channel: 0,
+        velocity: 127,
+        source: None,
+        selector: None,
+    });
+    canonical_order(notes);
+    start
+}
+
+/// Move notes so that, played at a constant `tempo`, they sound at the same real times as they do
+/// under the given tempo map.
+pub fn flatten_tempo(notes: &[NoteWithDuration], tempo_map: &TempoMap, tempo: u32)
+    -> Vec<NoteWithDuration>
+{
+    // Real time only ever increases with ticks, so rounding each boundary independently can't
+    // reorder notes or make a note end before it starts.
+    let flat_timestamp = |timestamp: u64| tempo_map.flat_timestamp(timestamp, tempo);
+    notes.iter()
+        .map(|note| {
+            let start = flat_timestamp(note.timestamp);
+            let end = flat_timestamp(note.timestamp + note.duration);
+            NoteWithDuration {
+                timestamp: start,
+                duration: end - start,
+                ..note.clone()
+            }
+        })
+        .collect()
+}
+
+/// Remove every note that `too_short` says is too short to punch, like the key bounces and
+/// accidental touches in a live recording, and return how many were removed.
+pub fn drop_short_notes(
+    notes: &mut Vec<NoteWithDuration>,
+    too_short: impl Fn(&NoteWithDuration) -> bool,
+) -> usize {
+    let before = notes.len();
+    notes.retain(|note| !too_short(note));
+    before - notes.len()
+}
+
+/// Shorten each note by the factor given for it, leaving where it starts alone, so repeated notes
+/// have a gap to re-strike in. Notes are never shortened below `min_duration` ticks, the shortest
+/// hole that can be punched, nor to nothing; notes already shorter than that are left alone.
+pub fn articulate(
+    notes: &mut [NoteWithDuration],
+    min_duration: u64,
+    factor: impl Fn(&NoteWithDuration) -> f64,
+) {
+    for note in notes.iter_mut() {
+        let duration = (note.duration as f64 * factor(note)).round() as u64;
+        note.duration = duration.max(min_duration.min(note.duration)).max(1);
+    }
+}
+
+/// Scale every note's start and length by `factor`, so that at the same tempo a factor over 1
+/// plays slower and under 1 plays faster.
+pub fn time_stretch(notes: &mut [NoteWithDuration], factor: f64) {
+    let scale = |ticks: u64| (ticks as f64 * factor).round() as u64;
+    for note in notes.iter_mut() {
+        // Scale both ends rather than the duration, so notes that ended together still do.
+        let end = scale(note.timestamp + note.duration);
+        note.timestamp = scale(note.timestamp);
+        note.duration = end - note.timestamp;
+    }
+}
+
+/// The factor for `time_stretch` that makes the notes, from `start` to the end of the last one,
+/// play for the given number of seconds under the tempo map. Nothing if they take no time.
+pub fn stretch_factor(notes: &[NoteWithDuration], start: u64, tempo_map: &TempoMap, seconds: f64)
+    -> Option<f64>
+{
+    let end = notes.iter().map(|note| note.timestamp + note.duration).max()?;
+    let micros = tempo_map.micros_at(end) - tempo_map.micros_at(start);
+    if micros > 0. {
+        Some(seconds * 1_000_000. / micros)
+    } else {
+        None
+    }
+}
+
+/// Merges two channels that take turns carrying the melody into one voice, keeping only the
+/// notes of whichever one `analysis::alternating_voice` chose for each measure.
+pub struct AlternatingMerge {
+    channels: [(usize, u8); 2],
+    beat_grid: BeatGrid,
+    voices: Vec<usize>,
+    /// Notes kept that haven't been released yet, so their releases are kept too.
+    held: BTreeSet<(usize, u8, MidiNote)>,
+}
+
+impl AlternatingMerge {
+    pub fn new(channels: [(usize, u8); 2], beat_grid: BeatGrid, voices: Vec<usize>) -> Self {
+        Self { channels, beat_grid, voices, held: BTreeSet::new() }
+    }
+
+    /// Whether to keep a note event. Events on other channels are always kept. A note is kept if
+    /// it starts in a measure where its channel has the melody, and then so is its release.
+    pub fn keep(&mut self, event: &NoteEvent) -> bool {
+        let Some(voice) = self.channels.iter().position(|&c| c == (event.track, event.channel))
+        else {
+            return true;
+        };
+        let key = (event.track, event.channel, event.note);
+        match event.action {
+            NoteAction::On => {
+                let measure = self.beat_grid.downbeat_at(event.timestamp)
+                    .map_or(0, |beat| beat.measure - 1);
+                let keep = self.voices.get(measure) == Some(&voice);
+                if keep {
+                    self.held.insert(key);
+                }
+                keep
+            }
+            NoteAction::Off => self.held.remove(&key),
+        }
+    }
+}
+
+/// A selected note, with where it came from and its pitch after its selector's offset, which may
+/// be outside the roll or even outside MIDI's range. These are what `PhraseFold` works out the
+/// phrases from.
+#[derive(Debug, Clone)]
+pub struct SelectedNote {
+    /// Position of the selector that picked the note on the command line.
+    pub selector: usize,
+    pub track: usize,
+    pub channel: u8,
+    pub timestamp: u64,
+    pub end: u64,
+    /// The note as played, before the offset.
+    pub note: MidiNote,
+    pub pitch: i16,
+}
+
+/// A run of notes from one selector and channel, without any gap longer than the limit between
+/// them, that had notes off the roll.
+#[derive(Debug)]
+pub struct Phrase {
+    pub track: usize,
+    pub channel: u8,
+    pub start: u64,
+    pub end: u64,
+    /// The first note of the phrase, as played.
+    pub first_note: MidiNote,
+    pub notes: usize,
+    /// Semitones the whole phrase was moved by to fit it on the roll, or `None` if no whole number
+    /// of octaves could, and it was dropped.
+    pub shift: Option<i8>,
+}
+
+/// Moves whole phrases by octaves to keep them on the roll, so that a melody that dips below the
+/// roll is moved up all together rather than having only the notes that don't fit jump up.
+pub struct PhraseFold {
+    /// The shift for each note of a folded or dropped phrase, by selector, track, channel, start,
+    /// and note as played.
+    shifts: BTreeMap<(usize, usize, u8, u64, MidiNote), Option<i8>>,
+    /// Notes pressed that haven't been released yet, so their releases get the same shift.
+    held: BTreeMap<(usize, usize, u8, MidiNote), Option<i8>>,
+}
+
+impl PhraseFold {
+    /// Split the notes into phrases wherever there's a gap of more than `max_gap` ticks, and
+    /// work out how to fold each one that goes off the roll.
+    pub fn new(mut notes: Vec<SelectedNote>, max_gap: u64) -> (Self, Vec<Phrase>) {
+        notes.sort_by_key(|note| (note.selector, note.track, note.channel, note.timestamp));
+        let lowest = i16::from(MidiNote::PIANOROLL_LOWEST.as_i8());
+        let highest = i16::from(MidiNote::PIANOROLL_HIGHEST.as_i8());
+        let mut shifts = BTreeMap::new();
+        let mut phrases = vec![];
+        let mut start = 0;
+        while start < notes.len() {
+            let first = &notes[start];
+            let mut end = start + 1;
+            let mut sounding_until = first.end;
+            while let Some(next) = notes.get(end) {
+                if (next.selector, next.track, next.channel)
+                        != (first.selector, first.track, first.channel)
+                    || next.timestamp > sounding_until + max_gap
+                {
+                    break;
+                }
+                sounding_until = sounding_until.max(next.end);
+                end += 1;
+            }
+
+            let phrase = &notes[start .. end];
+            let low = phrase.iter().map(|note| note.pitch).min().unwrap();
+            let high = phrase.iter().map(|note| note.pitch).max().unwrap();
+            if low < lowest || high > highest {
+                // The nearest octave that brings the bottom of the phrase onto the roll, if the
+                // top still fits.
+                let octaves = if low < lowest {
+                    (lowest - low + 11) / 12
+                } else {
+                    -((high - highest + 11) / 12)
+                };
+                let shift = Some(octaves * 12)
+                    .filter(|shift| low + shift >= lowest && high + shift <= highest)
+                    .and_then(|shift| i8::try_from(shift).ok());
+                for note in phrase {
+                    shifts.insert(
+                        (note.selector, note.track, note.channel, note.timestamp, note.note),
+                        shift);
+                }
+                phrases.push(Phrase {
+                    track: first.track,
+                    channel: first.channel,
+                    start: first.timestamp,
+                    end: sounding_until,
+                    first_note: first.note,
+                    notes: phrase.len(),
+                    shift,
+                });
+            }
+            start = end;
+        }
+        (Self { shifts, held: BTreeMap::new() }, phrases)
+    }
+
+    /// The octave shift to add to a note event for the given selector: 0 if it isn't in a folded
+    /// phrase, or `None` if it's in a phrase that was dropped.
+    pub fn shift(&mut self, selector: usize, event: &NoteEvent) -> Option<i8> {
+        let held_key = (selector, event.track, event.channel, event.note);
+        match event.action {
+            NoteAction::On => {
+                let shift = self.shifts
+                    .get(&(selector, event.track, event.channel, event.timestamp, event.note))
+                    .copied()
+                    .unwrap_or(Some(0));
+                self.held.insert(held_key, shift);
+                shift
+            }
+            NoteAction::Off => self.held.remove(&held_key).unwrap_or(Some(0)),
+        }
+    }
+}
+
+/// Round a tick to the nearest multiple of `quantum`.
+pub fn snap(timestamp: u64, quantum: u64) -> u64 {
+    (timestamp + quantum / 2) / quantum * quantum
+}
+
+/// Snap every note's start to the nearest multiple of `quantum` ticks, keeping its length, or
+/// with `durations` snapping its end too, but never to less than one quantum.
+///
+/// If that makes a note run into the next one of the same pitch, the earlier one is shortened to
+/// end where the later one starts. If they now start together, they're merged into one note as
+/// long as both. Returns the number of notes merged away. The notes are left in canonical order.
+pub fn quantize(notes: &mut Vec<NoteWithDuration>, quantum: u64, durations: bool) -> usize {
+    for note in notes.iter_mut() {
+        let start = snap(note.timestamp, quantum);
+        if durations {
+            let end = snap(note.timestamp + note.duration, quantum);
+            note.duration = end.saturating_sub(start).max(quantum);
+        }
+        note.timestamp = start;
+    }
+    merge_collisions(notes)
+}
+
+/// Snap every note to a rhythmic grid `grid_ticks` apart: its start to the nearest step, and its
+/// length to the nearest whole number of steps, but never less than one.
+///
+/// Notes that start together on the grid are left together. Notes of the same pitch that now run
+/// into each other are dealt with as in `quantize`, and the number merged away is returned.
+pub fn quantize_notes(notes: &mut Vec<NoteWithDuration>, grid_ticks: u64) -> usize {
+    for note in notes.iter_mut() {
+        note.timestamp = snap(note.timestamp, grid_ticks);
+        note.duration = snap(note.duration, grid_ticks).max(grid_ticks);
+    }
+    merge_collisions(notes)
+}
+
+/// Shorten notes that run into the next one of the same pitch, and merge ones that start
+/// together, for after moving notes around. Returns the number merged away.
+fn merge_collisions(notes: &mut Vec<NoteWithDuration>) -> usize {
+    notes.sort_by_key(|note| (note.note, note.timestamp));
+    let mut merged = 0;
+    let mut kept: Vec<NoteWithDuration> = Vec::with_capacity(notes.len());
+    for note in notes.drain(..) {
+        if let Some(prev) = kept.last_mut().filter(|prev| prev.note == note.note) {
+            if prev.timestamp == note.timestamp {
+                prev.duration = prev.duration.max(note.duration);
+                merged += 1;
+                continue;
+            }
+            if prev.timestamp + prev.duration > note.timestamp {
+                prev.duration = note.timestamp - prev.timestamp;
+            }
+        }
+        kept.push(note);
+    }
+    *notes = kept;
+    canonical_order(notes);
+    merged
+}
+
+/// The transposition, in semitones, that puts the most of the given pitches on the roll, and
+/// how many still fall off it. Whole octaves from -24 to +24 are tried first, nearest first, and
+/// then unless `octaves_only`, the other semitones in that range; a shift that changes the key is
+/// only chosen if it fits strictly more notes. Returns 0 if there are no pitches.
+pub fn best_transposition(pitches: &[i16], octaves_only: bool) -> (i8, usize) {
+    let lowest = i16::from(MidiNote::PIANOROLL_LOWEST.as_i8());
+    let highest = i16::from(MidiNote::PIANOROLL_HIGHEST.as_i8());
+    let off_roll = |shift: i8| {
+        pitches.iter()
+            .filter(|&&pitch| !(lowest ..= highest).contains(&(pitch + i16::from(shift))))
+            .count()
+    };
+    let octaves = [0, 12, -12, 24, -24];
+    let semitones = (1 ..= 24i8)
+        .filter(|step| step % 12 != 0)
+        .flat_map(|step| [step, -step]);
+    let mut best = (0, off_roll(0));
+    let candidates = octaves.iter().copied()
+        .chain(semitones.filter(|_| !octaves_only));
+    for shift in candidates {
+        let off = off_roll(shift);
+        if off < best.1 {
+            best = (shift, off);
+        }
+    }
+    best
+}
+
+/// A note that was shortened by `clamp_durations`.
+#[derive(Debug)]
+pub struct ClampedNote {
+    pub timestamp: u64,
+    pub note: MidiNote,
+    pub old_duration: u64,
+    pub new_duration: u64,
+}
+
+/// Shorten every note longer than `max_ticks` so that it ends on the latest beat of `beat_grid`
+/// that keeps it within the limit, following the song's time signatures. A note that doesn't
+/// cross any beat within the limit is cut to exactly `max_ticks`.
+pub fn clamp_durations(notes: &mut [NoteWithDuration], max_ticks: u64, beat_grid: &BeatGrid)
+    -> Vec<ClampedNote>
+{
+    let mut clamped = vec![];
+    for note in notes.iter_mut().filter(|note| note.duration > max_ticks) {
+        let limit = note.timestamp + max_ticks;
+        let end = match beat_grid.beat_floor(limit) {
+            Some(beat) if beat > note.timestamp => beat,
+            _ => limit,
+        };
+        clamped.push(ClampedNote {
+            timestamp: note.timestamp,
+            note: note.note,
+            old_duration: note.duration,
+            new_duration: end - note.timestamp,
+        });
+        note.duration = end - note.timestamp;
+    }
+    clamped
+}
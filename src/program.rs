@@ -1,3 +1,8 @@
+use crate::note::MidiNote;
+
+/// General MIDI reserves this channel (channel 10, counting from one) for percussion.
+pub const PERCUSSION_CHANNEL: u8 = 9;
+
 pub const MIDI_PROGRAM: [&str; 128] = [
     "Acoustic Grand Piano",
     "Bright Acoustic Piano",
@@ -143,3 +148,71 @@ pub const MIDI_PROGRAM: [&str; 128] = [
     "Applause",
     "Gunshot",
 ];
+
+/// The name of the instrument a bank and program select, if it's a General MIDI one or a Roland
+/// GS or Yamaha XG variation of one.
+///
+/// Bank 0 is General MIDI's, and 121 is General MIDI 2's, which keeps its variations in the LSB.
+/// GS puts variations in other MSBs, and XG in the LSB of bank 0. Drum and sound effect banks
+/// (MSB 64, 120, 126, and 127) have no General MIDI name.
+pub fn instrument_name(bank_msb: u8, bank_lsb: u8, program: u8) -> Option<String> {
+    let name = MIDI_PROGRAM.get(usize::from(program))?;
+    match (bank_msb, bank_lsb) {
+        (0, 0) | (121, 0) => Some((*name).to_owned()),
+        (64 | 120 | 126 | 127, _) => None,
+        _ => Some(format!("{} (variation)", name)),
+    }
+}
+
+/// General MIDI percussion sounds (on channel 10, which is channel 9 counting from zero), by
+/// note number, with a suggested pitched note for each: low drums map to low notes, snares and
+/// hand drums to the middle of the keyboard, and cymbals and bells to high notes.
+pub const GM_PERCUSSION: [(u8, &str, MidiNote); 47] = [
+    (35, "Acoustic Bass Drum", MidiNote::B1),
+    (36, "Bass Drum 1", MidiNote::C2),
+    (37, "Side Stick", MidiNote::E4),
+    (38, "Acoustic Snare", MidiNote::F4),
+    (39, "Hand Clap", MidiNote::Gs4),
+    (40, "Electric Snare", MidiNote::Fs4),
+    (41, "Low Floor Tom", MidiNote::G2),
+    (42, "Closed Hi-Hat", MidiNote::Cs6),
+    (43, "High Floor Tom", MidiNote::A2),
+    (44, "Pedal Hi-Hat", MidiNote::C6),
+    (45, "Low Tom", MidiNote::C3),
+    (46, "Open Hi-Hat", MidiNote::D6),
+    (47, "Low-Mid Tom", MidiNote::D3),
+    (48, "Hi-Mid Tom", MidiNote::E3),
+    (49, "Crash Cymbal 1", MidiNote::F6),
+    (50, "High Tom", MidiNote::G3),
+    (51, "Ride Cymbal 1", MidiNote::E6),
+    (52, "Chinese Cymbal", MidiNote::Fs6),
+    (53, "Ride Bell", MidiNote::G6),
+    (54, "Tambourine", MidiNote::B5),
+    (55, "Splash Cymbal", MidiNote::Gs6),
+    (56, "Cowbell", MidiNote::A5),
+    (57, "Crash Cymbal 2", MidiNote::A6),
+    (58, "Vibraslap", MidiNote::As5),
+    (59, "Ride Cymbal 2", MidiNote::Ds6),
+    (60, "Hi Bongo", MidiNote::C5),
+    (61, "Low Bongo", MidiNote::As4),
+    (62, "Mute Hi Conga", MidiNote::D5),
+    (63, "Open Hi Conga", MidiNote::Cs5),
+    (64, "Low Conga", MidiNote::A4),
+    (65, "High Timbale", MidiNote::E5),
+    (66, "Low Timbale", MidiNote::Ds5),
+    (67, "High Agogo", MidiNote::Gs5),
+    (68, "Low Agogo", MidiNote::G5),
+    (69, "Cabasa", MidiNote::As6),
+    (70, "Maracas", MidiNote::B6),
+    (71, "Short Whistle", MidiNote::C7),
+    (72, "Long Whistle", MidiNote::Cs7),
+    (73, "Short Guiro", MidiNote::F5),
+    (74, "Long Guiro", MidiNote::Fs5),
+    (75, "Claves", MidiNote::D7),
+    (76, "Hi Wood Block", MidiNote::Ds7),
+    (77, "Low Wood Block", MidiNote::E7),
+    (78, "Mute Cuica", MidiNote::F7),
+    (79, "Open Cuica", MidiNote::Fs7),
+    (80, "Mute Triangle", MidiNote::G7),
+    (81, "Open Triangle", MidiNote::B4),
+];
@@ -0,0 +1,893 @@
+//! Drawing the roll: as a PDF, tiled across sheets of paper, or as SVG.
+
+use crate::analysis;
+use crate::checks::HoleSpan;
+use crate::config::{Configuration, OutputFormat, RollEnd};
+use crate::error::PianoRollError;
+use crate::export;
+use crate::formatting;
+use crate::geometry::{self, HoleShape, PaperSize, CHANNEL_WIDTH, HOLE_WIDTH, PAGE_MARGIN,
+    PAGE_WIDTH, POINTS_PER_INCH, TILE_MARGIN};
+use crate::midi::NoteWithDuration;
+use crate::note::{MidiNote, PITCH_CLASS_NAMES};
+use crate::svg::Svg;
+use crate::tempo::{self, Beat};
+use pdf_canvas::BuiltinFont;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Draw the roll to the output file, as an SVG, tiled pages, or one long PDF, according to the
+/// configuration. The title is printed on the leader if it has room for it, unless `--title`
+/// gives another.
+pub fn render(
+    notes: &[NoteWithDuration],
+    beats: &[Beat],
+    time_base: u16,
+    dial: f64,
+    title: Option<&str>,
+    cfg: &Configuration,
+) -> Result<(), PianoRollError> {
+    log::info!("Writing output to {:?}", cfg.output);
+
+    let position = roll_position(cfg);
+    let page_height = roll_length(notes, cfg);
+    log::info!("piano roll length: {}", formatting::length(f64::from(page_height)));
+
+    let proof_marks = cfg.note_range_display || cfg.show_key_positions
+        || cfg.keyboard_legend.is_some() || cfg.show_rests
+        || cfg.tempo_marking || cfg.grid_measures
+        || cfg.label_measures || !cfg.editor_notes.is_empty();
+    if cfg.output_format == OutputFormat::Csv {
+        return export::notes_csv::write_csv(&cfg.output, notes, cfg.time_divisor, cfg.row_offset,
+            &cfg.roll_label, u8::from(cfg.channel_one_indexed));
+    }
+    if cfg.output_format == OutputFormat::Svg {
+        if page_height / POINTS_PER_INCH > 200. {
+            log::warn!("exceeding PDF page height limit of 200 inches");
+        }
+        if proof_marks || cfg.roll_label.printed_length > 0. {
+            log::warn!("marks other than holes are only drawn in PDF output");
+        }
+        return render_svg(notes, time_base, f64::from(page_height), position, cfg);
+    }
+
+    if cfg.horizontal && proof_marks {
+        log::warn!("marks other than holes aren't drawn on horizontal rolls");
+    }
+
+    if let Some(paper) = cfg.tile {
+        if proof_marks || cfg.roll_label.printed_length > 0. {
+            log::warn!("marks other than holes aren't drawn on tiled pages");
+        }
+        return render_tiles(notes, time_base, f64::from(page_height), position, paper, cfg)
+            .map_err(|source| PianoRollError::PdfWrite { path: cfg.output.clone(), source });
+    }
+
+    std::fs::File::create(&cfg.output)
+        .and_then(|file| write_pdf(notes, beats, time_base, dial, title, cfg, file))
+        .map_err(|source| PianoRollError::PdfWrite { path: cfg.output.clone(), source })
+}
+
+/// Draw the roll as one long PDF, split into pages if it's longer than `max_page_length`, to any
+/// writer. This is what `render` does for PDF output that isn't tiled.
+pub fn render_pdf(
+    notes: &[NoteWithDuration],
+    beats: &[Beat],
+    time_base: u16,
+    dial: f64,
+    title: Option<&str>,
+    cfg: &Configuration,
+    mut output: impl Write,
+) -> io::Result<()> {
+    static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+    // The PDF writer has to be able to seek back in its output, so draw the roll in a temporary
+    // file and then copy it out.
+    let path = std::env::temp_dir().join(format!("pianoroll-{}-{}.pdf",
+        std::process::id(), TEMP_FILES.fetch_add(1, Ordering::Relaxed)));
+    let mut file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+    let result = (|| -> io::Result<()> {
+        write_pdf(notes, beats, time_base, dial, title, cfg, file.try_clone()?)?;
+        file.seek(SeekFrom::Start(0))?;
+        io::copy(&mut file, &mut output)?;
+        Ok(())
+    })();
+    drop(file);
+    let _ = std::fs::remove_file(&path);
+    result
+}
+
+/// Where a tick falls along the roll, in points from its start, after the leader.
+fn roll_position(cfg: &Configuration) -> impl Fn(u64) -> f64 + Copy + '_ {
+    // Positions are calculated in f64 and only narrowed to f32 for the PDF itself, so that long
+    // songs don't lose precision in the tick counts. Narrowing with `as f32` rounds to the nearest
+    // f32, ties to even, and `geometry::check_time_divisor` keeps that within `MAX_ROUNDING`.
+    let leader = f64::from(cfg.roll_label.leader_length);
+    move |ticks: u64| leader + ticks as f64 / cfg.time_divisor
+}
+
+/// Every hole on the roll, including the label's, with where it starts and ends, as they're laid
+/// out to be drawn.
+pub fn hole_spans(notes: &[NoteWithDuration], cfg: &Configuration) -> Vec<HoleSpan> {
+    notes.iter()
+        .map(|note| note_span(note, cfg))
+        .chain(cfg.roll_label.punches.iter()
+            .map(|punch| (punch.channel, f64::from(punch.y), f64::from(punch.y + punch.height))))
+        .collect()
+}
+
+/// The channel of a note's hole, and where the hole starts and ends.
+fn note_span(note: &NoteWithDuration, cfg: &Configuration) -> HoleSpan {
+    let position = roll_position(cfg);
+    let channel = note.note.pianoroll_channel().expect("note out of range");
+    let row = f64::from(geometry::row_offset(channel, cfg.row_offset));
+    (channel, position(note.timestamp) + row, position(note.timestamp + note.duration) + row)
+}
+
+/// The slots to cut for a rectangular hole from `start` to `end`: just the one, unless it's longer
+/// than `--max-slot`.
+fn slots(start: f64, end: f64, cfg: &Configuration) -> Vec<(f64, f64)> {
+    match cfg.max_slot {
+        Some(max_slot) => geometry::chain_slots(start, end, max_slot, cfg.bridge),
+        None => vec![(start, end)],
+    }
+}
+
+/// The length of the roll, to the end of its last hole.
+fn roll_length(notes: &[NoteWithDuration], cfg: &Configuration) -> f32 {
+    let end_timestamp = notes.iter()
+        .map(|elem| elem.timestamp + elem.duration)
+        .max()
+        .unwrap_or(0);
+    roll_position(cfg)(end_timestamp) as f32 + cfg.row_offset
+}
+
+fn write_pdf(
+    notes: &[NoteWithDuration],
+    beats: &[Beat],
+    time_base: u16,
+    dial: f64,
+    title: Option<&str>,
+    cfg: &Configuration,
+    file: File,
+) -> io::Result<()> {
+    // Rolls drawn with `--horizontal` have the axes swapped, with the music running across the
+    // page from left to right and the lowest notes at the bottom.
+    let note_rectangle = |canvas: &mut pdf_canvas::Canvas, channel: u8, start: f32, height: f32| {
+        if cfg.horizontal {
+            canvas.rectangle(start, geometry::hole_left(channel), height, HOLE_WIDTH)
+        } else {
+            canvas.rectangle(geometry::hole_left(channel), start, HOLE_WIDTH, height)
+        }
+    };
+    let circle = |canvas: &mut pdf_canvas::Canvas, x: f32, y: f32, radius: f32| {
+        if cfg.horizontal {
+            canvas.circle(y, x, radius)
+        } else {
+            canvas.circle(x, y, radius)
+        }
+    };
+
+    let leader = f64::from(cfg.roll_label.leader_length);
+    let position = roll_position(cfg);
+    let roll_length = f64::from(roll_length(notes, cfg));
+
+    let hole_span = |note: &NoteWithDuration| {
+        let (_, start, end) = note_span(note, cfg);
+        (start, end)
+    };
+    let holes = hole_spans(notes, cfg).into_iter()
+        .map(|(_, start, end)| (start, end))
+        .collect::<Vec<_>>();
+    let mut pages = if roll_length > cfg.max_page_length {
+        // Leave room on each page to run on into the next by the overlap.
+        let pages = geometry::page_breaks(
+            &holes, roll_length, cfg.max_page_length - cfg.page_overlap);
+        log::info!("roll is longer than the {} inch page limit, so it's split across {} pages \
+            overlapping by {} inches",
+            cfg.max_page_length / f64::from(POINTS_PER_INCH), pages.len(),
+            cfg.page_overlap / f64::from(POINTS_PER_INCH));
+        pages
+    } else {
+        vec![0.]
+    };
+    pages.push(roll_length);
+    let track_colors = notes.iter()
+        .map(|note| (note.track, note.channel))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .zip(TRACK_COLORS.iter().cycle())
+        .map(|(source, &(red, green, blue))| {
+            (source, pdf_canvas::graphicsstate::Color::rgb(red, green, blue))
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let mut pdf = pdf_canvas::Pdf::new(file)?;
+    for page in pages.windows(2) {
+        // Each page but the last repeats the start of the next, for splicing them together.
+        let (page_start, page_end) = (page[0], (page[1] + cfg.page_overlap).min(roll_length));
+        // Positions on the page, which only shows the part of the roll from `page_start` on. Holes
+        // that cross into the next page are cut off at the edge, and carry on at the start of it.
+        let y = |position: f64| (position - page_start) as f32;
+        let on_page = |(start, end): (f64, f64)| end >= page_start && start <= page_end;
+        let page_height = (page_end - page_start) as f32;
+        let (width, height) = if cfg.horizontal {
+            (page_height, PAGE_WIDTH)
+        } else {
+            (PAGE_WIDTH, page_height)
+        };
+        pdf.render_page(width, height,
+            |canvas| {
+                canvas.set_fill_color(pdf_canvas::graphicsstate::Color::gray(0))?;
+                for note in notes.iter().filter(|note| on_page(hole_span(note))) {
+                    if cfg.color_by_track {
+                        canvas.set_fill_color(track_colors[&(note.track, note.channel)])?;
+                    } else if cfg.velocity_shading {
+                        canvas.set_fill_color(velocity_gray(note.velocity))?;
+                    }
+                    // Out of range notes were already dropped, so this shouldn't happen.
+                    let channel = note.note.pianoroll_channel().expect("note out of range");
+                    let (roll_start, roll_end) = hole_span(note);
+                    let start = y(roll_start);
+                    let shape = if cfg.note_shape_by_duration {
+                        geometry::hole_shape(note.duration, time_base)
+                    } else {
+                        HoleShape::Rectangle
+                    };
+                    let radius = HOLE_WIDTH / 2.;
+                    let center = geometry::hole_center(channel);
+                    match shape {
+                        HoleShape::Rectangle => {
+                            for (start, end) in slots(roll_start, roll_end, cfg) {
+                                note_rectangle(canvas, channel, y(start), y(end) - y(start))?;
+                            }
+                        }
+                        HoleShape::Circle => {
+                            circle(canvas, center, start + radius, radius)?;
+                        }
+                        HoleShape::RoundedTop => {
+                            let top = roll_end - f64::from(radius);
+                            for (start, end) in slots(roll_start, top, cfg) {
+                                note_rectangle(canvas, channel, y(start), y(end) - y(start))?;
+                            }
+                            circle(canvas, center, y(top), radius)?;
+                        }
+                    }
+                    canvas.fill()?;
+                }
+
+                if cfg.velocity_shading || cfg.color_by_track {
+                    canvas.set_fill_color(pdf_canvas::graphicsstate::Color::gray(0))?;
+                }
+                for punch in &cfg.roll_label.punches {
+                    let start = f64::from(punch.y);
+                    if on_page((start, start + f64::from(punch.height))) {
+                        note_rectangle(canvas, punch.channel, y(start), punch.height)?;
+                        canvas.fill()?;
+                    }
+                }
+                if cfg.roll_label.printed_length > 0. && page_start == 0. {
+                    draw_title_leader(canvas, cfg.title.as_deref().or(title),
+                        cfg.composer.as_deref(), dial, cfg)?;
+                }
+                if cfg.horizontal {
+                    return Ok(());
+                }
+
+                if cfg.grid_measures || cfg.label_measures {
+                    draw_beat_grid(canvas, beats, position, page_start, page_end, cfg)?;
+                }
+
+                if cfg.tempo_marking && page_start == 0. {
+                    // In the unused channels on the left, where the leader meets the music.
+                    canvas.set_fill_color(pdf_canvas::graphicsstate::Color::gray(0))?;
+                    canvas.left_text(PAGE_MARGIN + 2., leader as f32 + 2., BuiltinFont::Helvetica,
+                        8., &format!("Tempo {}", dial))?;
+                }
+
+                if !cfg.editor_notes.is_empty() {
+                    draw_editor_notes(canvas, beats, position, page_start, page_end, cfg)?;
+                }
+
+                if cfg.note_range_display {
+                    draw_note_range(canvas, notes, page_height)?;
+                }
+
+                if cfg.show_key_positions {
+                    draw_key_positions(canvas, page_height)?;
+                }
+
+                match cfg.keyboard_legend {
+                    Some(RollEnd::Start) if page_start == 0. => {
+                        draw_keyboard_legend(canvas, notes, RollEnd::Start, page_height)?;
+                    }
+                    Some(RollEnd::End) if page_end == roll_length => {
+                        draw_keyboard_legend(canvas, notes, RollEnd::End, page_height)?;
+                    }
+                    _ => (),
+                }
+
+                if cfg.show_rests {
+                    let min_ticks = (cfg.min_rest * f64::from(time_base)).round() as u64;
+                    canvas.set_stroke_color(pdf_canvas::graphicsstate::Color::gray(96))?;
+                    canvas.set_fill_color(pdf_canvas::graphicsstate::Color::gray(96))?;
+                    canvas.set_line_width(0.5)?;
+                    for rest in analysis::find_rests(notes, min_ticks) {
+                        let channel = rest.note.pianoroll_channel().expect("note out of range");
+                        let row = f64::from(geometry::row_offset(channel, cfg.row_offset));
+                        let start = position(rest.timestamp) + row;
+                        let end = position(rest.timestamp + rest.duration) + row;
+                        if on_page((start, end)) {
+                            let beats = rest.duration as f64 / f64::from(time_base);
+                            draw_rest(canvas, channel, y(start), y(end), beats)?;
+                        }
+                    }
+                }
+
+                Ok(())
+            })?;
+    }
+
+    pdf.finish()
+}
+
+/// Print the title, composer, and tempo dial setting across the blank end of the leader, so they
+/// read the right way up when the roll is held with the music running upward.
+fn draw_title_leader(
+    canvas: &mut pdf_canvas::Canvas,
+    title: Option<&str>,
+    composer: Option<&str>,
+    dial: f64,
+    cfg: &Configuration,
+) -> Result<(), std::io::Error> {
+    let label = &cfg.roll_label;
+    let middle = label.leader_length - label.printed_length / 2.;
+    let room = PAGE_WIDTH - 2. * PAGE_MARGIN;
+    let tempo = format!("Tempo {}", dial);
+    let lines = [(title, 24.), (composer, 14.), (Some(&tempo[..]), 12.)];
+
+    canvas.gsave()?;
+    canvas.set_fill_color(pdf_canvas::graphicsstate::Color::gray(0))?;
+    // A horizontal roll runs left to right, so turn the text a quarter turn clockwise to read up
+    // it the same way.
+    if cfg.horizontal {
+        canvas.concat(pdf_canvas::graphicsstate::Matrix::translate(middle, PAGE_WIDTH / 2.))?;
+        canvas.concat(pdf_canvas::graphicsstate::Matrix::rotate_deg(-90.))?;
+    } else {
+        canvas.concat(pdf_canvas::graphicsstate::Matrix::translate(PAGE_WIDTH / 2., middle))?;
+    }
+    let mut y = 20.;
+    for (text, size) in lines {
+        let Some(text) = text else {
+            continue;
+        };
+        // Shrink a long title to fit across the roll.
+        let width = canvas.get_font(BuiltinFont::Helvetica_Bold).get_width(size, text);
+        let size = if width > room { size * room / width } else { size };
+        canvas.center_text(0., y, BuiltinFont::Helvetica_Bold, size, text)?;
+        y -= 20.;
+    }
+    canvas.grestore()
+}
+
+/// The fills for holes with `--color-by-track`, given out to the tracks and channels in order,
+/// dark enough to tell apart from the gray proofreading marks.
+const TRACK_COLORS: [(u8, u8, u8); 8] = [
+    (31, 119, 180),
+    (214, 39, 40),
+    (44, 160, 44),
+    (148, 103, 189),
+    (255, 127, 14),
+    (140, 86, 75),
+    (227, 119, 194),
+    (23, 190, 207),
+];
+
+/// The fill for a hole with `--velocity-shading`: black at full velocity, fading to light gray
+/// at zero.
+fn velocity_gray(velocity: u8) -> pdf_canvas::graphicsstate::Color {
+    const LIGHTEST: u32 = 200;
+    let shade = LIGHTEST - u32::from(velocity.min(127)) * LIGHTEST / 127;
+    pdf_canvas::graphicsstate::Color::gray(shade as u8)
+}
+
+/// Draw a thin line across the roll at the start of each measure, numbered in the margin, and with
+/// `--grid-beats`, a lighter one at each other beat, on the page running from `page_start` to
+/// `page_end`. With only `--label-measures`, just the numbers are drawn, and with
+/// `--label-beats`, each other beat is numbered within its measure too.
+fn draw_beat_grid(
+    canvas: &mut pdf_canvas::Canvas,
+    beats: &[Beat],
+    position: impl Fn(u64) -> f64,
+    page_start: f64,
+    page_end: f64,
+    cfg: &Configuration,
+) -> Result<(), std::io::Error> {
+    canvas.set_line_width(0.3)?;
+    let mut beat_number = 0;
+    for beat in beats {
+        beat_number = if beat.downbeat { 1 } else { beat_number + 1 };
+        let gray = if beat.downbeat {
+            160
+        } else if !beat.downbeat && (cfg.grid_beats || cfg.label_beats) {
+            210
+        } else {
+            continue;
+        };
+        let y = position(beat.timestamp);
+        if y < page_start || y > page_end {
+            continue;
+        }
+        let y = (y - page_start) as f32;
+        if cfg.grid_measures && (beat.downbeat || cfg.grid_beats) {
+            canvas.set_stroke_color(pdf_canvas::graphicsstate::Color::gray(gray))?;
+            canvas.line(0., y, PAGE_WIDTH, y)?;
+            canvas.stroke()?;
+        }
+        if beat.downbeat {
+            canvas.set_fill_color(pdf_canvas::graphicsstate::Color::gray(gray))?;
+            canvas.left_text(1., y + 1., BuiltinFont::Helvetica, 5., &beat.measure.to_string())?;
+        } else if cfg.label_beats {
+            // Darker than the beat lines, since the numbers are so small.
+            canvas.set_fill_color(pdf_canvas::graphicsstate::Color::gray(160))?;
+            canvas.left_text(3., y + 1., BuiltinFont::Helvetica, 3.5, &beat_number.to_string())?;
+        }
+    }
+    canvas.set_fill_color(pdf_canvas::graphicsstate::Color::gray(0))?;
+    Ok(())
+}
+
+/// Write the editor's notes in the unused channels on the left, each starting at its measure, or
+/// if that would run into the one before, just after it.
+fn draw_editor_notes(
+    canvas: &mut pdf_canvas::Canvas,
+    beats: &[Beat],
+    position: impl Fn(u64) -> f64,
+    page_start: f64,
+    page_end: f64,
+    cfg: &Configuration,
+) -> Result<(), std::io::Error> {
+    const SIZE: f32 = 6.;
+    let mut notes = cfg.editor_notes.iter()
+        .filter_map(|note| {
+            tempo::measure_start(beats, note.measure).map(|tick| (position(tick), note))
+        })
+        .collect::<Vec<_>>();
+    notes.sort_by(|a, b| a.0.total_cmp(&b.0));
+    // Keep clear of the tempo marking, which is in the same place at the start of the music.
+    let mut free_from = if cfg.tempo_marking {
+        f64::from(cfg.roll_label.leader_length) + 11.
+    } else {
+        0.
+    };
+    canvas.set_fill_color(pdf_canvas::graphicsstate::Color::rgb(160, 0, 0))?;
+    for (y, note) in notes {
+        let y = y.max(free_from);
+        free_from = y + f64::from(SIZE) + 1.;
+        if y < page_start || y > page_end {
+            continue;
+        }
+        canvas.left_text(PAGE_MARGIN + 2., (y - page_start) as f32 + 1., BuiltinFont::Helvetica,
+            SIZE, &format!("m{}: {}", note.measure, note.text))?;
+    }
+    canvas.set_fill_color(pdf_canvas::graphicsstate::Color::gray(0))?;
+    Ok(())
+}
+
+/// Draw the roll tiled across sheets of paper, to print at full size on an ordinary printer and
+/// tape together. Each tile has crop marks at its corners, dashed lines in the middle of its
+/// overlaps with its neighbors to cut and glue along, and its segment number underneath.
+fn render_tiles(
+    notes: &[NoteWithDuration],
+    time_base: u16,
+    roll_length: f64,
+    position: impl Fn(u64) -> f64,
+    paper: PaperSize,
+    cfg: &Configuration,
+) -> Result<(), std::io::Error> {
+    enum Piece {
+        Rectangle { x: f32, width: f32, start: f64, end: f64 },
+        Circle { x: f32, y: f64, radius: f32 },
+    }
+
+    let radius = HOLE_WIDTH / 2.;
+    let mut pieces = vec![];
+    for note in notes {
+        let channel = note.note.pianoroll_channel().expect("note out of range");
+        let row = f64::from(geometry::row_offset(channel, cfg.row_offset));
+        let start = position(note.timestamp) + row;
+        let end = position(note.timestamp + note.duration) + row;
+        let shape = if cfg.note_shape_by_duration {
+            geometry::hole_shape(note.duration, time_base)
+        } else {
+            HoleShape::Rectangle
+        };
+        let x = geometry::hole_left(channel);
+        let center = geometry::hole_center(channel);
+        match shape {
+            HoleShape::Rectangle => {
+                for (start, end) in slots(start, end, cfg) {
+                    pieces.push(Piece::Rectangle { x, width: HOLE_WIDTH, start, end });
+                }
+            }
+            HoleShape::Circle => {
+                pieces.push(Piece::Circle { x: center, y: start + f64::from(radius), radius });
+            }
+            HoleShape::RoundedTop => {
+                let top = end - f64::from(radius);
+                for (start, end) in slots(start, top, cfg) {
+                    pieces.push(Piece::Rectangle { x, width: HOLE_WIDTH, start, end });
+                }
+                pieces.push(Piece::Circle { x: center, y: top, radius });
+            }
+        }
+    }
+    for punch in &cfg.roll_label.punches {
+        let start = f64::from(punch.y);
+        pieces.push(Piece::Rectangle {
+            x: geometry::hole_left(punch.channel),
+            width: HOLE_WIDTH,
+            start,
+            end: start + f64::from(punch.height),
+        });
+    }
+
+    let tiles = geometry::tiles(roll_length, paper);
+    let columns = tiles.iter().map(|tile| tile.column + 1).max().unwrap_or(1);
+    log::info!("tiling the roll across {} {:?} pages", tiles.len(), paper);
+    let (paper_width, paper_height) = paper.size();
+    let f = std::fs::File::create(&cfg.output)?;
+    let mut pdf = pdf_canvas::Pdf::new(f)?;
+    for (i, tile) in tiles.iter().enumerate() {
+        let right = tile.x + tile.width;
+        let top = tile.y + tile.height;
+        // From the roll to the page.
+        let page_x = |x: f32| TILE_MARGIN + x - tile.x;
+        let page_y = |y: f64| TILE_MARGIN + (y - tile.y) as f32;
+        pdf.render_page(paper_width, paper_height, |canvas| {
+            // Holes, cut off at the edges of the tile. The overlap with the next tile is wider
+            // than any round hole, so those are only drawn where they fit whole.
+            canvas.set_fill_color(pdf_canvas::graphicsstate::Color::gray(0))?;
+            for piece in &pieces {
+                match *piece {
+                    Piece::Rectangle { x, width, start, end } => {
+                        let (left, hole_right) = (x.max(tile.x), (x + width).min(right));
+                        let (start, end) = (start.max(tile.y), end.min(top));
+                        if left < hole_right && start < end {
+                            canvas.rectangle(page_x(left), page_y(start), hole_right - left,
+                                (end - start) as f32)?;
+                            canvas.fill()?;
+                        }
+                    }
+                    Piece::Circle { x, y, radius } => {
+                        if x - radius >= tile.x && x + radius <= right
+                            && y - f64::from(radius) >= tile.y && y + f64::from(radius) <= top
+                        {
+                            canvas.circle(page_x(x), page_y(y), radius)?;
+                            canvas.fill()?;
+                        }
+                    }
+                }
+            }
+
+            // Edges of the roll, to cut along.
+            canvas.set_stroke_color(pdf_canvas::graphicsstate::Color::gray(0))?;
+            canvas.set_line_width(0.5)?;
+            let (tile_bottom, tile_top) = (page_y(tile.y), page_y(top));
+            for x in [0., PAGE_WIDTH] {
+                if x >= tile.x && x <= right {
+                    canvas.line(page_x(x), tile_bottom, page_x(x), tile_top)?;
+                }
+            }
+            for y in [0., roll_length] {
+                if y >= tile.y && y <= top {
+                    canvas.line(page_x(tile.x), page_y(y), page_x(right), page_y(y))?;
+                }
+            }
+            canvas.stroke()?;
+
+            // Crop marks pointing in at each corner of the tile, out in the margin.
+            const MARK: f32 = TILE_MARGIN * 0.6;
+            const GAP: f32 = 2.;
+            let (tile_left, tile_right) = (page_x(tile.x), page_x(right));
+            for (x, outward_x) in [(tile_left, -1.), (tile_right, 1.)] {
+                for (y, outward_y) in [(tile_bottom, -1.), (tile_top, 1.)] {
+                    canvas.line(x + outward_x * GAP, y, x + outward_x * (GAP + MARK), y)?;
+                    canvas.line(x, y + outward_y * GAP, x, y + outward_y * (GAP + MARK))?;
+                }
+            }
+            canvas.stroke()?;
+
+            // Glue lines, dashed, across the overlaps.
+            canvas.set_stroke_color(pdf_canvas::graphicsstate::Color::gray(128))?;
+            for &y in [tile.glue_before, tile.glue_after].iter().flatten() {
+                dashed_line(canvas, (tile_left, page_y(y)), (tile_right, page_y(y)))?;
+            }
+            for &x in [tile.glue_left, tile.glue_right].iter().flatten() {
+                dashed_line(canvas, (page_x(x), tile_bottom), (page_x(x), tile_top))?;
+            }
+            canvas.stroke()?;
+
+            let mut caption = format!("segment {} of {}", i + 1, tiles.len());
+            if columns > 1 {
+                caption += &format!(", column {} of {}", tile.column + 1, columns);
+            }
+            let inches = |points: f64| points / f64::from(POINTS_PER_INCH);
+            caption += &format!(", roll inches {:.1} to {:.1}",
+                inches(tile.y), inches(top.min(roll_length)));
+            canvas.left_text(TILE_MARGIN, TILE_MARGIN / 3., BuiltinFont::Helvetica, 7., &caption)
+        })?;
+    }
+    pdf.finish()
+}
+
+/// Add a dashed straight line to the path, to be stroked.
+fn dashed_line(canvas: &mut pdf_canvas::Canvas, from: (f32, f32), to: (f32, f32))
+    -> Result<(), std::io::Error>
+{
+    const DASH: f32 = 6.;
+    const SPACE: f32 = 4.;
+    let length = (to.0 - from.0).hypot(to.1 - from.1);
+    let point = |distance: f32| {
+        let t = distance / length;
+        (from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t)
+    };
+    let mut distance = 0.;
+    while distance < length {
+        let (x0, y0) = point(distance);
+        let (x1, y1) = point((distance + DASH).min(length));
+        canvas.move_to(x0, y0)?;
+        canvas.line_to(x1, y1)?;
+        distance += DASH + SPACE;
+    }
+    Ok(())
+}
+
+/// Draw the roll's holes as SVG, the same as they are in the PDF, with each track's holes in a
+/// group of their own. With `--svg-page-length`, a long roll is split into several files.
+fn render_svg(
+    notes: &[NoteWithDuration],
+    time_base: u16,
+    roll_length: f64,
+    position: impl Fn(u64) -> f64,
+    cfg: &Configuration,
+) -> Result<(), PianoRollError> {
+    let hole_span = |note: &NoteWithDuration| {
+        let channel = note.note.pianoroll_channel().expect("note out of range");
+        let row = f64::from(geometry::row_offset(channel, cfg.row_offset));
+        (position(note.timestamp) + row, position(note.timestamp + note.duration) + row)
+    };
+    let mut pages = match cfg.svg_page_length {
+        Some(page_length) if roll_length > page_length => {
+            let holes = notes.iter().map(hole_span).collect::<Vec<_>>();
+            geometry::page_breaks(&holes, roll_length, page_length)
+        }
+        _ => vec![0.],
+    };
+    let paths = if pages.len() == 1 {
+        vec![cfg.output.clone()]
+    } else {
+        log::info!("splitting the SVG into {} files", pages.len());
+        (1 ..= pages.len())
+            .map(|page| {
+                let mut name = cfg.output.file_stem().unwrap_or_default().to_owned();
+                name.push(format!("-{}.svg", page));
+                cfg.output.with_file_name(name)
+            })
+            .collect()
+    };
+    pages.push(roll_length);
+
+    let mut tracks = BTreeMap::<usize, Vec<&NoteWithDuration>>::new();
+    for note in notes {
+        tracks.entry(note.track).or_default().push(note);
+    }
+
+    let radius = HOLE_WIDTH / 2.;
+    for (page, path) in pages.windows(2).zip(&paths) {
+        let (page_start, page_end) = (page[0], page[1]);
+        if paths.len() > 1 {
+            log::info!("Writing {:?}", path);
+        }
+        // Holes are cut off at the ends of the page, so each file can be cut on its own.
+        let y = |position: f64| (position.clamp(page_start, page_end) - page_start) as f32;
+        let mut svg = Svg::new(PAGE_WIDTH, (page_end - page_start) as f32);
+        for (track, notes) in &tracks {
+            svg.begin_group(&format!("track-{}", track));
+            for note in notes {
+                let channel = note.note.pianoroll_channel().expect("note out of range");
+                let (start, end) = hole_span(note);
+                if end < page_start || start > page_end {
+                    continue;
+                }
+                let left = geometry::hole_left(channel);
+                let center = geometry::hole_center(channel);
+                let shape = if cfg.note_shape_by_duration {
+                    geometry::hole_shape(note.duration, time_base)
+                } else {
+                    HoleShape::Rectangle
+                };
+                match shape {
+                    HoleShape::Rectangle => {
+                        for (start, end) in slots(start, end, cfg) {
+                            svg.rectangle(left, y(start), HOLE_WIDTH, y(end) - y(start));
+                        }
+                    }
+                    HoleShape::Circle => svg.circle(center, y(start) + radius, radius),
+                    HoleShape::RoundedTop => {
+                        let top = end - f64::from(radius);
+                        for (start, end) in slots(start, top, cfg) {
+                            svg.rectangle(left, y(start), HOLE_WIDTH, y(end) - y(start));
+                        }
+                        if top <= page_end {
+                            svg.circle(center, y(top), radius);
+                        }
+                    }
+                }
+            }
+            svg.end_group();
+        }
+        if page_start == 0. && !cfg.roll_label.punches.is_empty() {
+            svg.begin_group("label");
+            for punch in &cfg.roll_label.punches {
+                svg.rectangle(geometry::hole_left(punch.channel), punch.y, HOLE_WIDTH,
+                    punch.height);
+            }
+            svg.end_group();
+        }
+        svg.write(path)?;
+    }
+    Ok(())
+}
+
+/// Mark the lowest and highest channels used with lines down the length of the roll, labeled with
+/// their note names, and bracket the roll on the right margin.
+fn draw_note_range(canvas: &mut pdf_canvas::Canvas, notes: &[NoteWithDuration], page_height: f32)
+    -> Result<(), std::io::Error>
+{
+    const LABEL_SIZE: f32 = 6.;
+
+    let (Some(lowest), Some(highest)) = (
+        notes.iter().map(|note| note.note).min(),
+        notes.iter().map(|note| note.note).max(),
+    ) else {
+        return Ok(());
+    };
+    let channel_edge = |channel: u8| f32::from(channel) * CHANNEL_WIDTH + PAGE_MARGIN;
+    let left = channel_edge(lowest.pianoroll_channel().expect("note out of range"));
+    let right = channel_edge(highest.pianoroll_channel().expect("note out of range") + 1);
+
+    canvas.set_stroke_color(pdf_canvas::graphicsstate::Color::gray(128))?;
+    canvas.set_line_width(0.5)?;
+    canvas.line(left, 0., left, page_height)?;
+    canvas.line(right, 0., right, page_height)?;
+    canvas.line(PAGE_WIDTH - 5., 0., PAGE_WIDTH - 5., page_height)?;
+    canvas.stroke()?;
+
+    canvas.set_fill_color(pdf_canvas::graphicsstate::Color::gray(128))?;
+    for y in [LABEL_SIZE / 2., page_height - LABEL_SIZE * 1.5] {
+        canvas.right_text(left - 2., y, BuiltinFont::Helvetica, LABEL_SIZE,
+            &format!("{:?}", lowest))?;
+        canvas.left_text(right + 2., y, BuiltinFont::Helvetica, LABEL_SIZE,
+            &format!("{:?}", highest))?;
+    }
+    Ok(())
+}
+
+/// Mark a rest with a bracket down the middle of the channel, open toward the notes either side,
+/// and label it with its length in beats if that fits inside it.
+fn draw_rest(canvas: &mut pdf_canvas::Canvas, channel: u8, start: f32, end: f32, beats: f64)
+    -> Result<(), std::io::Error>
+{
+    const LABEL_SIZE: f32 = 4.;
+    // Keep clear of the notes at either end.
+    let gap = HOLE_WIDTH / 2.;
+    let start = start + gap;
+    let end = end - gap;
+    let left = geometry::hole_left(channel);
+    let right = left + HOLE_WIDTH;
+    let center = geometry::hole_center(channel);
+
+    canvas.move_to(left, start)?;
+    canvas.line_to(center, start)?;
+    canvas.line_to(center, end)?;
+    canvas.line_to(left, end)?;
+    canvas.stroke()?;
+
+    // Rotated to run along the channel, to the right of the bracket.
+    let label = format!("{}", (beats * 100.).round() / 100.);
+    let width = canvas.get_font(BuiltinFont::Helvetica).get_width(LABEL_SIZE, &label);
+    if width < end - start {
+        canvas.gsave()?;
+        canvas.concat(pdf_canvas::graphicsstate::Matrix::translate(right, (start + end) / 2.))?;
+        canvas.concat(pdf_canvas::graphicsstate::Matrix::rotate_deg(90.))?;
+        canvas.center_text(0., 0., BuiltinFont::Helvetica, LABEL_SIZE, &label)?;
+        canvas.grestore()?;
+    }
+    Ok(())
+}
+
+/// Notes marked by `--show-key-positions`: every C, plus concert A.
+pub fn key_landmarks() -> impl Iterator<Item = MidiNote> {
+    (MidiNote::PIANOROLL_LOWEST.as_u8() ..= MidiNote::PIANOROLL_HIGHEST.as_u8())
+        .filter_map(|raw| MidiNote::try_from(raw).ok())
+        .filter(|&note| note.pitch_class() == 0 || note == MidiNote::A4)
+}
+
+/// Mark the landmark notes with a small triangle at each end of their channels, pointing into the
+/// roll.
+fn draw_key_positions(canvas: &mut pdf_canvas::Canvas, page_height: f32)
+    -> Result<(), std::io::Error>
+{
+    canvas.set_fill_color(pdf_canvas::graphicsstate::Color::gray(128))?;
+    for note in key_landmarks() {
+        let channel = note.pianoroll_channel().unwrap();
+        let left = geometry::hole_left(channel);
+        let center = geometry::hole_center(channel);
+        let right = left + HOLE_WIDTH;
+        for (base, tip) in [(0., HOLE_WIDTH), (page_height, page_height - HOLE_WIDTH)] {
+            canvas.move_to(left, base)?;
+            canvas.line_to(right, base)?;
+            canvas.line_to(center, tip)?;
+            canvas.fill()?;
+        }
+    }
+    Ok(())
+}
+
+
+/// Draw a keyboard across one end of the roll, with a key in each note's channel, named, and
+/// shaded if the song uses it, to tell which channel is which note at a glance.
+fn draw_keyboard_legend(
+    canvas: &mut pdf_canvas::Canvas,
+    notes: &[NoteWithDuration],
+    end: RollEnd,
+    page_height: f32,
+) -> Result<(), std::io::Error> {
+    const LENGTH: f32 = POINTS_PER_INCH * 0.75;
+    const BLACK_LENGTH: f32 = LENGTH * 0.6;
+    const LABEL_SIZE: f32 = 4.;
+
+    // Distance into the roll from the end the keyboard is drawn at.
+    let along = |distance: f32| match end {
+        RollEnd::Start => distance,
+        RollEnd::End => page_height - distance,
+    };
+    let used = notes.iter().map(|note| note.note).collect::<BTreeSet<_>>();
+    canvas.set_line_width(0.3)?;
+    canvas.set_stroke_color(pdf_canvas::graphicsstate::Color::gray(96))?;
+    for raw in MidiNote::PIANOROLL_LOWEST.as_u8() ..= MidiNote::PIANOROLL_HIGHEST.as_u8() {
+        let note = MidiNote::try_from(raw).unwrap();
+        let channel = note.pianoroll_channel().unwrap();
+        let left = f32::from(channel) * CHANNEL_WIDTH + PAGE_MARGIN;
+        let name = PITCH_CLASS_NAMES[usize::from(note.pitch_class())];
+        let black = name.ends_with('#');
+        let (length, gray) = match (black, used.contains(&note)) {
+            (false, false) => (LENGTH, 255),
+            (false, true) => (LENGTH, 190),
+            (true, false) => (BLACK_LENGTH, 0),
+            (true, true) => (BLACK_LENGTH, 110),
+        };
+        canvas.set_fill_color(pdf_canvas::graphicsstate::Color::gray(gray))?;
+        let bottom = along(0.).min(along(length));
+        canvas.rectangle(left, bottom, CHANNEL_WIDTH, length)?;
+        canvas.fill()?;
+        canvas.rectangle(left, bottom, CHANNEL_WIDTH, length)?;
+        canvas.stroke()?;
+
+        // Along the channel, past the black keys.
+        canvas.set_fill_color(pdf_canvas::graphicsstate::Color::gray(0))?;
+        canvas.gsave()?;
+        let label_start = along(BLACK_LENGTH).min(along(LENGTH)) + 1.;
+        canvas.concat(pdf_canvas::graphicsstate::Matrix::translate(
+            left + CHANNEL_WIDTH / 2. + LABEL_SIZE / 3., label_start))?;
+        canvas.concat(pdf_canvas::graphicsstate::Matrix::rotate_deg(90.))?;
+        canvas.left_text(0., 0., BuiltinFont::Helvetica, LABEL_SIZE,
+            &format!("{}{}", name, note.octave()))?;
+        canvas.grestore()?;
+    }
+    Ok(())
+}
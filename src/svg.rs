@@ -0,0 +1,56 @@
+//! A minimal SVG writer for drawing the roll, as an alternative to PDF.
+//!
+//! Coordinates are the same PDF points used everywhere else, with the origin at the start of the
+//! roll in the bottom left corner, so the picture comes out the same way up as the PDF.
+
+use crate::error::PianoRollError;
+use crate::geometry::POINTS_PER_INCH;
+use std::fmt::Write as _;
+use std::path::Path;
+
+pub struct Svg {
+    width: f32,
+    height: f32,
+    body: String,
+}
+
+impl Svg {
+    pub fn new(width: f32, height: f32) -> Self {
+        Self { width, height, body: String::new() }
+    }
+
+    pub fn rectangle(&mut self, x: f32, y: f32, width: f32, height: f32) {
+        writeln!(self.body, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"/>",
+            x, y, width, height).unwrap();
+    }
+
+    /// Start a group of shapes, which can be picked out and edited together by its ID.
+    pub fn begin_group(&mut self, id: &str) {
+        writeln!(self.body, "<g id=\"{}\">", id).unwrap();
+    }
+
+    pub fn end_group(&mut self) {
+        self.body.push_str("</g>\n");
+    }
+
+    pub fn circle(&mut self, x: f32, y: f32, radius: f32) {
+        writeln!(self.body, "<circle cx=\"{}\" cy=\"{}\" r=\"{}\"/>", x, y, radius).unwrap();
+    }
+
+    /// Write out the drawing, sized in inches so it prints and cuts at full size.
+    pub fn write(&self, path: &Path) -> Result<(), PianoRollError> {
+        let mut svg = String::new();
+        writeln!(svg, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>").unwrap();
+        writeln!(svg, "<svg xmlns=\"http://www.w3.org/2000/svg\" version=\"1.1\" \
+            width=\"{}in\" height=\"{}in\" viewBox=\"0 0 {} {}\">",
+            self.width / POINTS_PER_INCH, self.height / POINTS_PER_INCH,
+            self.width, self.height).unwrap();
+        // Flip the y axis so it goes up the page, like in the PDF.
+        writeln!(svg, "<g transform=\"matrix(1 0 0 -1 0 {})\" fill=\"black\">", self.height)
+            .unwrap();
+        svg.push_str(&self.body);
+        svg.push_str("</g>\n</svg>\n");
+        std::fs::write(path, svg)
+            .map_err(|source| PianoRollError::Write { path: path.to_owned(), source })
+    }
+}
@@ -0,0 +1,313 @@
+use crate::geometry::POINTS_PER_INCH;
+
+/// Tempo used until the first SetTempo event, as defined by the MIDI spec (120 beats per minute).
+pub const DEFAULT_TEMPO: u32 = 500_000;
+
+/// Player piano tempo dials are marked in tenths of a foot of paper per minute: at "Tempo 70" the
+/// roll moves 7 feet a minute.
+const DIAL_UNITS_PER_FOOT: f64 = 10.;
+
+/// Dial markings go up in steps of this much.
+pub const DIAL_STEP: f64 = 5.;
+
+/// The tempo dial setting that plays a roll at the given tempo, when it's laid out at
+/// `time_divisor` ticks per point.
+pub fn dial_setting(micros_per_beat: f64, time_base: u16, time_divisor: f64) -> f64 {
+    let points_per_second = f64::from(time_base) * 1_000_000. / micros_per_beat / time_divisor;
+    let feet_per_minute = points_per_second * 60. / f64::from(POINTS_PER_INCH) / 12.;
+    feet_per_minute * DIAL_UNITS_PER_FOOT
+}
+
+/// Round a dial setting to the nearest marking.
+pub fn round_dial(setting: f64) -> f64 {
+    ((setting / DIAL_STEP).round() * DIAL_STEP).max(DIAL_STEP)
+}
+
+/// A SetTempo event: from `timestamp` onward, each beat lasts `micros_per_beat` microseconds.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct TempoChange {
+    pub timestamp: u64,
+    pub micros_per_beat: u32,
+}
+
+/// A TimeSignature event: from `timestamp` onward, measures have `numerator` beats of a
+/// 1/`denominator` note each.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct TimeSignature {
+    pub timestamp: u64,
+    pub numerator: u8,
+    pub denominator: u8,
+}
+
+impl TimeSignature {
+    /// What a song is in until its first TimeSignature event, as defined by the MIDI spec.
+    pub const DEFAULT: TimeSignature = TimeSignature { timestamp: 0, numerator: 4, denominator: 4 };
+
+    /// Decode the event's data bytes. The denominator is stored as a power of two.
+    pub fn from_bytes(timestamp: u64, data: &[u8]) -> Option<Self> {
+        match *data {
+            [numerator, power, ..] if numerator > 0 && power <= 6 => Some(Self {
+                timestamp,
+                numerator,
+                denominator: 1 << power,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Whether the beat is a dotted note, as in 6/8 or 12/16, where the numerator counts the
+    /// beats' thirds rather than the beats themselves.
+    pub fn is_compound(&self) -> bool {
+        self.numerator > 3 && self.numerator.is_multiple_of(3) && self.denominator >= 8
+    }
+
+    pub fn beats_per_measure(&self) -> u8 {
+        if self.is_compound() { self.numerator / 3 } else { self.numerator }
+    }
+
+    /// Length of a beat in quarter notes: a dotted quarter in 6/8, a half note in 3/2.
+    pub fn beat_quarters(&self) -> f64 {
+        let dots = if self.is_compound() { 3. } else { 1. };
+        4. / f64::from(self.denominator) * dots
+    }
+
+    /// Length of a beat, in ticks. A time base is always given per quarter note.
+    pub fn beat_ticks(&self, time_base: u16) -> u64 {
+        let dots = if self.is_compound() { 3 } else { 1 };
+        (u64::from(time_base) * 4 / u64::from(self.denominator)).max(1) * dots
+    }
+
+    pub fn measure_ticks(&self, time_base: u16) -> u64 {
+        self.beat_ticks(time_base) * u64::from(self.beats_per_measure())
+    }
+
+    /// How many of this meter's beats a minute lasts at the given tempo. SetTempo events always
+    /// count quarter notes, whatever the meter.
+    pub fn beats_per_minute(&self, micros_per_quarter: u32) -> f64 {
+        quarters_per_minute(micros_per_quarter) / self.beat_quarters()
+    }
+}
+
+pub fn quarters_per_minute(micros_per_quarter: u32) -> f64 {
+    60_000_000. / f64::from(micros_per_quarter)
+}
+
+/// A beat, for drawing grid lines on the roll.
+#[derive(Debug, Clone, Copy)]
+pub struct Beat {
+    pub timestamp: u64,
+    /// Whether this is the first beat of a measure.
+    pub downbeat: bool,
+    /// The measure the beat is in, counting from 1.
+    pub measure: usize,
+}
+
+/// The tick the given measure starts at, counting from 1, if the song gets that far.
+pub fn measure_start(beats: &[Beat], measure: usize) -> Option<u64> {
+    beats.iter()
+        .find(|beat| beat.downbeat && beat.measure == measure)
+        .map(|beat| beat.timestamp)
+}
+
+/// Every beat of a song up to the given tick, kept in step with the notes as they're moved
+/// around, the same way as the tempo map.
+#[derive(Debug, Clone)]
+pub struct BeatGrid {
+    beats: Vec<Beat>,
+}
+
+impl BeatGrid {
+    /// Lay out the beats from the time signatures. A time signature change starts a new measure.
+    pub fn new(time_signatures: &[TimeSignature], time_base: u16, end: u64) -> Self {
+        let mut signatures = time_signatures.to_vec();
+        if signatures.first().map(|signature| signature.timestamp) != Some(0) {
+            signatures.insert(0, TimeSignature::DEFAULT);
+        }
+        let mut beats = vec![];
+        let mut measure = 0;
+        for (i, signature) in signatures.iter().enumerate() {
+            let until = signatures.get(i + 1).map_or(end + 1, |next| next.timestamp.min(end + 1));
+            let beat_ticks = signature.beat_ticks(time_base);
+            let mut timestamp = signature.timestamp;
+            let mut beat = 0;
+            while timestamp < until {
+                if beat == 0 {
+                    measure += 1;
+                }
+                beats.push(Beat { timestamp, downbeat: beat == 0, measure });
+                timestamp += beat_ticks;
+                beat = (beat + 1) % signature.beats_per_measure().max(1);
+            }
+        }
+        Self { beats }
+    }
+
+    pub fn beats(&self) -> &[Beat] {
+        &self.beats
+    }
+
+    /// The tick of the last beat at or before the given tick, if there is one.
+    pub fn beat_floor(&self, timestamp: u64) -> Option<u64> {
+        let after = self.beats.partition_point(|beat| beat.timestamp <= timestamp);
+        after.checked_sub(1).map(|i| self.beats[i].timestamp)
+    }
+
+    /// The downbeat of the measure the given tick is in, unless the tick comes before the first
+    /// beat.
+    pub fn downbeat_at(&self, timestamp: u64) -> Option<Beat> {
+        let after = self.beats.partition_point(|beat| beat.timestamp <= timestamp);
+        self.beats[.. after].iter().rfind(|beat| beat.downbeat).copied()
+    }
+
+    /// Move every beat to match notes stretched by `processing::time_stretch`.
+    pub fn stretch(&mut self, factor: f64) {
+        for beat in &mut self.beats {
+            beat.timestamp = (beat.timestamp as f64 * factor).round() as u64;
+        }
+    }
+
+    /// Move every beat later by the given number of ticks.
+    pub fn delay_start(&mut self, ticks: u64) {
+        for beat in &mut self.beats {
+            beat.timestamp += ticks;
+        }
+    }
+
+    /// Remove the given number of ticks from the start of the song, along with any beats in them.
+    pub fn strip_start(&mut self, ticks: u64) {
+        self.beats.retain(|beat| beat.timestamp >= ticks);
+        for beat in &mut self.beats {
+            beat.timestamp -= ticks;
+        }
+    }
+
+    /// Move every beat to match notes moved by `processing::flatten_tempo`.
+    pub fn flatten(&mut self, tempo_map: &TempoMap, tempo: u32) {
+        for beat in &mut self.beats {
+            beat.timestamp = tempo_map.flat_timestamp(beat.timestamp, tempo);
+        }
+    }
+}
+
+/// All the tempo changes in a song, for converting between MIDI ticks and real time.
+#[derive(Debug, Clone)]
+pub struct TempoMap {
+    time_base: u16,
+    changes: Vec<TempoChange>,
+}
+
+impl TempoMap {
+    pub fn new(time_base: u16, mut changes: Vec<TempoChange>) -> Self {
+        // Stable sort, so that of multiple changes at the same tick, the last one read wins.
+        changes.sort_by_key(|change| change.timestamp);
+        Self { time_base, changes }
+    }
+
+    pub fn changes(&self) -> &[TempoChange] {
+        &self.changes
+    }
+
+    /// Remove the given number of ticks from the start of the song, keeping the tempo that was in
+    /// effect at that point.
+    pub fn strip_start(&mut self, ticks: u64) {
+        let initial_tempo = self.tempo_at(ticks);
+        self.changes.retain(|change| change.timestamp > ticks);
+        for change in &mut self.changes {
+            change.timestamp -= ticks;
+        }
+        self.changes.insert(0, TempoChange { timestamp: 0, micros_per_beat: initial_tempo });
+    }
+
+    /// Add the given number of ticks to the start of the song, at the tempo the song starts with.
+    pub fn delay_start(&mut self, ticks: u64) {
+        let initial_tempo = self.tempo_at(0);
+        for change in &mut self.changes {
+            change.timestamp += ticks;
+        }
+        self.changes.insert(0, TempoChange { timestamp: 0, micros_per_beat: initial_tempo });
+    }
+
+    /// The average tempo over the first `ticks` of the song, in microseconds per beat.
+    pub fn average_tempo(&self, ticks: u64) -> f64 {
+        if ticks == 0 {
+            f64::from(self.tempo_at(0))
+        } else {
+            self.micros_at(ticks) * f64::from(self.time_base) / ticks as f64
+        }
+    }
+
+    /// The slowest and fastest tempos in effect during the first `ticks` of the song, in
+    /// microseconds per beat.
+    pub fn tempo_range(&self, ticks: u64) -> (u32, u32) {
+        let initial = self.tempo_at(0);
+        self.changes.iter()
+            .filter(|change| change.timestamp < ticks)
+            .map(|change| change.micros_per_beat)
+            .fold((initial, initial), |(slowest, fastest), tempo| {
+                (slowest.max(tempo), fastest.min(tempo))
+            })
+    }
+
+    /// Move every tempo change to match notes stretched by `processing::time_stretch`. The tempos
+    /// themselves are kept, so the song plays faster or slower.
+    pub fn stretch(&mut self, factor: f64) {
+        for change in &mut self.changes {
+            change.timestamp = (change.timestamp as f64 * factor).round() as u64;
+        }
+    }
+
+    /// The microseconds per beat in effect at the given tick.
+    pub fn tempo_at(&self, timestamp: u64) -> u32 {
+        self.changes.iter()
+            .take_while(|change| change.timestamp <= timestamp)
+            .last()
+            .map(|change| change.micros_per_beat)
+            .unwrap_or(DEFAULT_TEMPO)
+    }
+
+    /// The tick that, played at a constant `tempo`, comes at the same real time as the given tick
+    /// does under this tempo map.
+    pub fn flat_timestamp(&self, timestamp: u64, tempo: u32) -> u64 {
+        let ticks_per_micro = f64::from(self.time_base) / f64::from(tempo);
+        (self.micros_at(timestamp) * ticks_per_micro).round() as u64
+    }
+
+    /// Real time elapsed from the start of the song to the given tick, in microseconds.
+    pub fn micros_at(&self, timestamp: u64) -> f64 {
+        let mut micros = 0.;
+        let mut last_timestamp = 0;
+        let mut last_tempo = DEFAULT_TEMPO;
+        for change in &self.changes {
+            if change.timestamp >= timestamp {
+                break;
+            }
+            micros += self.span_micros(change.timestamp - last_timestamp, last_tempo);
+            last_timestamp = change.timestamp;
+            last_tempo = change.micros_per_beat;
+        }
+        micros + self.span_micros(timestamp - last_timestamp, last_tempo)
+    }
+
+    /// The tick that comes the given number of microseconds into the song, to the nearest tick:
+    /// the other way round from `micros_at`.
+    pub fn timestamp_at(&self, micros: f64) -> u64 {
+        let mut elapsed = 0.;
+        let mut last_timestamp = 0;
+        let mut last_tempo = DEFAULT_TEMPO;
+        for change in &self.changes {
+            let span = self.span_micros(change.timestamp - last_timestamp, last_tempo);
+            if elapsed + span > micros {
+                break;
+            }
+            elapsed += span;
+            last_timestamp = change.timestamp;
+            last_tempo = change.micros_per_beat;
+        }
+        let ticks = (micros - elapsed) * f64::from(self.time_base) / f64::from(last_tempo);
+        last_timestamp + ticks.round() as u64
+    }
+
+    fn span_micros(&self, ticks: u64, micros_per_beat: u32) -> f64 {
+        ticks as f64 * f64::from(micros_per_beat) / f64::from(self.time_base)
+    }
+}
@@ -0,0 +1,245 @@
+//! The option table, the parser, and project files agree.
+
+use pianoroll::config::{OptionValue, Quantize, OPTION_TABLE};
+use pianoroll::{note_map, parse_configuration, MidiNote, PianoRollError};
+use std::convert::TryFrom;
+use std::ffi::OsString;
+
+fn parse(args: &[&str]) -> Result<(), String> {
+    parse_configuration(["pianoroll", "song.mid"].iter().chain(args).map(OsString::from))
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// Parse a project file with the given contents.
+fn parse_project(name: &str, text: &str) -> Result<(), String> {
+    let path = std::env::temp_dir()
+        .join(format!("pianoroll-test-{}-{}.toml", name, std::process::id()));
+    std::fs::write(&path, text).unwrap();
+    let result = parse(&["--config", path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+    result
+}
+
+#[test]
+fn parser_knows_every_option() {
+    for spec in OPTION_TABLE {
+        let flag = format!("--{}", spec.name);
+        let values: &[&str] = match spec.value {
+            OptionValue::Switch => &[],
+            OptionValue::Number(_) | OptionValue::Integer(_) | OptionValue::NoteValue => &["1"],
+            OptionValue::Text(_) | OptionValue::Path => &["x"],
+            OptionValue::Pair(_) => &["0,0", "0,1"],
+        };
+        let args = std::iter::once(flag.as_str()).chain(values.iter().copied()).collect::<Vec<_>>();
+        if let Err(e) = parse(&args) {
+            assert!(!e.starts_with("unknown option"), "{}: {}", spec.name, e);
+        }
+    }
+}
+
+#[test]
+fn misspelled_option() {
+    assert_eq!(parse(&["--grid-measure"]).unwrap_err(),
+        "unknown option \"--grid-measure\"; did you mean \"--grid-measures\"?");
+    let e = parse_project("misspelled", "grid-measures = true\nshow_rest = true\n").unwrap_err();
+    assert!(e.ends_with(": unknown setting `show_rest` at line 2 column 1; did you mean \
+        `show-rests`?"), "{}", e);
+}
+
+#[test]
+fn wrong_type_in_project() {
+    let e = parse_project("wrong-type", "  max-slot = \"long\"\n").unwrap_err();
+    assert!(e.ends_with(": `max-slot` at line 1 column 3 should be a number of inches, not \
+        string"), "{}", e);
+    let e = parse_project("wrong-array", "grid-measures = [true]\n").unwrap_err();
+    assert!(e.ends_with("should be true or false, not array"), "{}", e);
+    parse_project("right-types", "max-slot = 2\nnote = [\"m1: a\", \"m2: b\"]\n").unwrap();
+}
+
+#[test]
+fn outputs_collide() {
+    let e = parse(&["-o", "song.pdf", "--export-csound", "song_pianoroll.mid"]).unwrap_err();
+    assert_eq!(e, "output files collide: the preview MIDI file and --export-csound would both be \
+        \"song_pianoroll.mid\"");
+    let e = parse(&["-o", "./out/../song.mid", "--proof", "song.mid"]).unwrap_err();
+    assert!(e.contains("the input and the roll would both be"), "{}", e);
+    assert!(e.contains("the roll and --proof would both be"), "{}", e);
+    parse(&["-o", "song.pdf", "--proof", "song-proof.pdf"]).unwrap();
+}
+
+#[test]
+fn end_holes() {
+    let cfg = parse_configuration(["pianoroll", "song.mid", "--rewind-hole", "87,2",
+        "--shutoff-hole", "86,0.5"].iter().map(OsString::from))
+        .unwrap();
+    assert_eq!(cfg.rewind_hole.map(|hole| (hole.channel, hole.offset)), Some((87, 144.)));
+    assert_eq!(cfg.shutoff_hole.map(|hole| (hole.channel, hole.offset)), Some((86, 36.)));
+    let e = parse(&["--rewind-hole", "87,2", "--tune-change-channel", "80"]).unwrap_err();
+    assert!(e.starts_with("--rewind-hole and --tune-change-channel can't be used together"),
+        "{}", e);
+    assert_eq!(parse(&["--rewind-hole", "87,2", "--shutoff-hole", "87,3"]).unwrap_err(),
+        "--rewind-hole and --shutoff-hole are both in channel 87");
+    assert!(parse(&["--shutoff-hole", "3,1"]).is_err());
+}
+
+#[test]
+fn fit_length_or_time_divisor() {
+    let fit = |args: &[&str]| {
+        parse_configuration(["pianoroll", "song.mid"].iter().chain(args).map(OsString::from))
+            .map(|cfg| cfg.fit_length)
+    };
+    assert_eq!(fit(&["--fit-length", "12.5ft"]).unwrap(), Some(150. * 72.));
+    assert_eq!(fit(&["--fit-length", "150in"]).unwrap(), Some(150. * 72.));
+    assert_eq!(fit(&["--fit-page"]).unwrap(), Some(199. * 72.));
+    let e = parse(&["/4", "--fit-page"]).unwrap_err();
+    assert!(e.starts_with("a time divisor (/4) and --fit-length or --fit-page can't both"),
+        "{}", e);
+}
+
+#[test]
+fn selector_labels() {
+    let selectors = |args: &[&str]| {
+        parse_configuration(["pianoroll", "song.mid"].iter().chain(args).map(OsString::from))
+            .map(|cfg| cfg.selectors.into_iter()
+                .map(|selector| (selector.label, selector.midi_channel, selector.articulation))
+                .collect::<Vec<_>>())
+            .map_err(|e| e.to_string())
+    };
+    assert_eq!(selectors(&["rh=1,0+0", "1,2", "lh=1,1-12:seg=1", "bass=name=piano*0.5"])
+        .unwrap(), [
+            ("rh".to_owned(), 0, None),
+            ("s2".to_owned(), 2, None),
+            ("lh".to_owned(), 1, None),
+            ("bass".to_owned(), 0, Some(0.5)),
+        ]);
+    assert_eq!(selectors(&["rh=1,0", "--articulation", "0.9", "--articulation", "rh=0.8"])
+        .unwrap(), [("rh".to_owned(), 0, Some(0.8))]);
+    assert_eq!(selectors(&["rh=1,0", "--articulation", "lh=0.8"]).unwrap_err(),
+        "--articulation: no selector is labeled \"lh\"");
+    assert_eq!(selectors(&["s2=1,0", "1,1"]).unwrap_err(),
+        "more than one selector is labeled \"s2\"");
+    assert_eq!(parse_project("labels", "[[selector]]\nlabel = \"rh\"\nmidi_track = 1\n\
+        midi_channel = 0\n[[selector]]\nlabel = \"rh\"\nmidi_track = 1\nmidi_channel = 1\n")
+        .unwrap_err(), "more than one selector is labeled \"rh\"");
+}
+
+#[test]
+fn track_ranges_and_wildcards() {
+    let selectors = |args: &[&str]| {
+        parse_configuration(["pianoroll", "song.mid", "--channel-one-indexed"].iter().chain(args)
+            .map(OsString::from))
+            .map(|cfg| cfg.selectors.into_iter()
+                .map(|selector| (selector.label, selector.midi_track, selector.any_channel))
+                .collect::<Vec<_>>())
+            .map_err(|e| e.to_string())
+    };
+    assert_eq!(selectors(&["mel=1-3,1", "--articulation", "mel=0.8", "*,*"]).unwrap(), [
+        ("mel.1".to_owned(), 1, false),
+        ("mel.2".to_owned(), 2, false),
+        ("mel.3".to_owned(), 3, false),
+        ("s4".to_owned(), 0, true),
+    ]);
+    assert!(selectors(&["3-1,1"]).unwrap_err().contains("the track range 3-1 is backwards"));
+}
+
+#[test]
+fn stretch_to_duration() {
+    let stretch_to = |value: &str| {
+        parse_configuration(["pianoroll", "song.mid", "--stretch-to", value].iter()
+            .map(OsString::from))
+            .map(|cfg| cfg.stretch_to)
+    };
+    assert_eq!(stretch_to("2:30").unwrap(), Some(150.));
+    assert_eq!(stretch_to("1:00:01.5").unwrap(), Some(3601.5));
+    assert!(stretch_to("90").is_err());
+    assert!(stretch_to("1:60").is_err());
+    assert_eq!(parse(&["--stretch-to", "1:00", "--time-stretch", "2"]).unwrap_err(),
+        "--time-stretch and --stretch-to can't both be given");
+}
+
+#[test]
+fn info_writes_nothing() {
+    // So the output can't collide with the input.
+    parse(&["--info", "0,0", "-o", "song.mid"]).unwrap();
+    parse(&["0,0", "-o", "song.mid"]).unwrap_err();
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_paths() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    let path = |bytes| OsStr::from_bytes(bytes).to_owned();
+    let args = vec![OsString::from("pianoroll"), path(b"take \xff/song \xff.v2.mid"), "0,0".into()];
+    let cfg = parse_configuration(args.into_iter()).unwrap();
+    assert_eq!(cfg.input.as_os_str(), path(b"take \xff/song \xff.v2.mid"));
+    assert_eq!(cfg.output.as_os_str(), path(b"take \xff/song \xff.v2.pdf"));
+    assert_eq!(cfg.preview_midi_path().as_os_str(),
+        path(b"take \xff/song \xff.v2_pianoroll.mid"));
+}
+
+#[test]
+fn quantize_grid() {
+    let quantize = |value: &str| {
+        parse_configuration(["pianoroll", "song.mid", "0,0", "--quantize", value].iter()
+            .map(OsString::from))
+            .map(|cfg| cfg.quantize)
+    };
+    assert_eq!(quantize("24").unwrap(), Some(Quantize::Ticks(24)));
+    assert_eq!(quantize("1/16").unwrap(), Some(Quantize::NoteValue(16)));
+    assert_eq!(Quantize::NoteValue(16).grid_ticks(96), 24);
+    assert_eq!(Quantize::NoteValue(12).grid_ticks(96), 32);
+    assert!(quantize("1/0").is_err());
+    assert!(quantize("3/16").is_err());
+
+    // In a project file, as a note value or a number of ticks.
+    let from_project = |text: &str| {
+        let path = std::env::temp_dir()
+            .join(format!("pianoroll-test-quantize-{}.toml", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        let cfg = parse_configuration(["pianoroll", "song.mid", "0,0", "--config",
+            path.to_str().unwrap()].iter().map(OsString::from));
+        std::fs::remove_file(&path).unwrap();
+        cfg.map(|cfg| cfg.quantize)
+    };
+    assert_eq!(from_project("quantize = \"1/16\"\n").unwrap(), Some(Quantize::NoteValue(16)));
+    assert_eq!(from_project("quantize = 24\n").unwrap(), Some(Quantize::Ticks(24)));
+    let e = from_project("quantize = 1.5\n").unwrap_err();
+    assert!(e.to_string().contains("or a string: a note value like 1/16"), "{}", e);
+}
+
+#[test]
+fn punching_rates() {
+    let rates = |punch: &str, feed: &str| {
+        parse_configuration(["pianoroll", "song.mid", "0,0", "--punch-rate", punch,
+            "--feed-rate", feed].iter().map(OsString::from))
+            .map(|cfg| cfg.punching_rates)
+    };
+    assert_eq!(rates("10", "0.5").unwrap(), Some((10., 0.5)));
+    assert!(rates("10", "0").is_err());
+    assert!(rates("10", "-2").is_err());
+    assert!(rates("10", "inf").is_err());
+    assert!(rates("10", "NaN").is_err());
+    // So small that the feed would take forever.
+    assert!(rates("10", "1e-310").is_err());
+    assert!(rates("inf", "0.5").is_err());
+    assert_eq!(parse(&["0,0", "--feed-rate", "0.5"]).unwrap_err(),
+        "--punch-rate and --feed-rate have to be given together");
+}
+
+#[test]
+fn note_map_file() {
+    let path = std::env::temp_dir()
+        .join(format!("pianoroll-test-note-map-{}.txt", std::process::id()));
+    std::fs::write(&path, "# kick -> C2\n36 36\n38 40 # snare\n").unwrap();
+    let map = note_map::read(&path).unwrap();
+    assert_eq!(map.get(&MidiNote::try_from(38).unwrap()), Some(&MidiNote::try_from(40).unwrap()));
+
+    std::fs::write(&path, "36 36\n38 40 42\n").unwrap();
+    let e = note_map::read(&path).unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(e, PianoRollError::NoteMapParse { .. }), "{:?}", e);
+    assert!(e.to_string().ends_with("line 2: unexpected text after the second note number"));
+    assert_eq!(e.exit_code(), 21);
+}
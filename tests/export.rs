@@ -0,0 +1,141 @@
+//! The exporters write what they should, and say where they couldn't.
+
+use pianoroll::audit::Audit;
+use pianoroll::label::Label;
+use pianoroll::tempo::{TempoChange, TempoMap};
+use pianoroll::{analysis, export, formatting, parse_configuration, MidiNote, NoteWithDuration};
+use pianoroll::PianoRollError;
+use std::ffi::OsString;
+use std::path::Path;
+
+fn note(timestamp: u64, duration: u64, note: MidiNote) -> NoteWithDuration {
+    NoteWithDuration { timestamp, duration, note, track: 0, channel: 0, velocity: 64,
+        source: None, selector: None }
+}
+
+#[test]
+fn unwritable_path() {
+    let notes = [note(0, 96, MidiNote::C4), note(96, 48, MidiNote::E4)];
+    let tempo_map = TempoMap::new(96, vec![TempoChange { timestamp: 0, micros_per_beat: 500_000 }]);
+    let label = Label { punches: vec![], leader_length: 0., printed_length: 0. };
+    let path = Path::new("/nonexistent/song");
+    let check = |result: Result<(), PianoRollError>| match result {
+        Err(PianoRollError::Write { path: p, .. }) => assert_eq!(p, path),
+        other => panic!("{:?}", other),
+    };
+    check(export::csound::write_score(path, &notes, &tempo_map, None));
+    check(export::supercollider::write_pattern(path, &notes, 96, 500_000, None));
+    check(export::chuck::write_program(path, &notes, &tempo_map, None));
+    check(export::etf::write_etf(path, &notes, 96, None));
+    check(export::catalog_image::write_png(path, &notes, 4., 0., &label, None, 200, None));
+    check(export::segments::write_json(path, &[]));
+    check(analysis::PitchUsage::new(&notes, &tempo_map).write_csv(path));
+    let audit = Audit::new(&[], &notes);
+    check(export::fidelity::write_json(path, &audit, &notes, 4., 0., &label));
+}
+
+#[test]
+fn scores_play_note_velocities() {
+    // The second note was made without a velocity, so it's played at the default of 90.
+    let notes = [
+        NoteWithDuration { velocity: 100, ..note(0, 96, MidiNote::C4) },
+        NoteWithDuration { velocity: 0, ..note(96, 48, MidiNote::E4) },
+    ];
+    let tempo_map = TempoMap::new(96, vec![TempoChange { timestamp: 0, micros_per_beat: 500_000 }]);
+    let path = std::env::temp_dir().join(format!("pianoroll-test-velocity-{}", std::process::id()));
+    let written = |result: Result<(), PianoRollError>| {
+        result.unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        text
+    };
+
+    let score = written(export::csound::write_score(&path, &notes, &tempo_map, None));
+    assert!(score.contains("i1 0.000000 0.500000 60 100 8.00\n"), "{}", score);
+    assert!(score.contains("i1 0.500000 0.250000 64 90 8.04\n"), "{}", score);
+
+    let program = written(export::chuck::write_program(&path, &notes, &tempo_map, None));
+    assert!(program.contains("spork ~ play(60, 100, 0.500000::second);"), "{}", program);
+    assert!(program.contains("spork ~ play(64, 90, 0.250000::second);"), "{}", program);
+
+    let pattern = written(export::supercollider::write_pattern(&path, &notes, 96, 500_000, None));
+    assert!(pattern.contains("\\amp, Pseq([0.7874])"), "{}", pattern);
+    assert!(pattern.contains("\\amp, Pseq([0.0000, 0.7087])"), "{}", pattern);
+}
+
+#[test]
+fn pitch_usage_table() {
+    // Half a second a beat: 1,200 C4s of a beat each, three E4s of a beat and a half, and a G5
+    // held for two hours.
+    let mut notes = (0 .. 1200).map(|i| note(i * 96, 96, MidiNote::C4)).collect::<Vec<_>>();
+    notes.extend((0 .. 3).map(|i| note(i * 144, 144, MidiNote::E4)));
+    notes.push(note(0, 96 * 14_400, MidiNote::G5));
+    let tempo_map = TempoMap::new(96, vec![TempoChange { timestamp: 0, micros_per_beat: 500_000 }]);
+    let table = analysis::PitchUsage::new(&notes, &tempo_map).table();
+    assert_eq!(table, concat!(
+        "              octave 4          octave 5\n",
+        "C     1,200 (10:00.00)                 -\n",
+        "C#                   -                 -\n",
+        "D                    -                 -\n",
+        "D#                   -                 -\n",
+        "E          3 (0:02.25)                 -\n",
+        "F                    -                 -\n",
+        "F#                   -                 -\n",
+        "G                    -    1 (2:00:00.00)\n",
+        "G#                   -                 -\n",
+        "A                    -                 -\n",
+        "A#                   -                 -\n",
+        "B                    -                 -\n",
+    ));
+
+    // Roll lengths, as the summary gives them.
+    assert_eq!(formatting::length(198. * 72.), "16 ft 6.0 in (5,029 mm)");
+    assert_eq!(formatting::length(11.96 * 72.), "1 ft 0.0 in (304 mm)");
+    assert_eq!(formatting::length(36.), "0.5 in (13 mm)");
+}
+
+#[test]
+fn catalog_image_size_and_holes() {
+    let cfg = parse_configuration(["pianoroll", "song.mid", "0,0", "--image-width",
+        "1620", "--image-feet", "1"].iter().map(OsString::from))
+        .unwrap();
+    // Two holes in one channel and one in another, then one past the first foot.
+    let notes = [note(0, 96, MidiNote::C4), note(192, 96, MidiNote::C4), note(0, 96, MidiNote::G4),
+        note(900, 96, MidiNote::C5)];
+    let label = Label { punches: vec![], leader_length: 0., printed_length: 0. };
+    let path = std::env::temp_dir()
+        .join(format!("pianoroll-test-catalog-{}.png", std::process::id()));
+    export::catalog_image::write_png(&path, &notes, 1., 0., &label, None, cfg.image_width,
+        cfg.image_feet)
+        .unwrap();
+    let mut reader = png::Decoder::new(std::fs::File::open(&path).unwrap()).read_info().unwrap();
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    // Two pixels to the point: 11.25 inches across, and a foot long.
+    assert_eq!((info.width, info.height), (1620, 2 * 12 * 72));
+    let (width, height) = (info.width as usize, info.height as usize);
+    let mut dark = pixels.chunks(3).map(|rgb| rgb[0] < 120).collect::<Vec<_>>();
+    let mut clusters = 0;
+    for start in 0 .. dark.len() {
+        if !dark[start] {
+            continue;
+        }
+        clusters += 1;
+        dark[start] = false;
+        let mut stack = vec![start];
+        while let Some(pixel) = stack.pop() {
+            let (x, y) = (pixel % width, pixel / width);
+            let neighbors = [(x > 0, pixel.wrapping_sub(1)), (x + 1 < width, pixel + 1),
+                (y > 0, pixel.wrapping_sub(width)), (y + 1 < height, pixel + width)];
+            for (inside, neighbor) in neighbors {
+                if inside && dark[neighbor] {
+                    dark[neighbor] = false;
+                    stack.push(neighbor);
+                }
+            }
+        }
+    }
+    assert_eq!(clusters, 3);
+}
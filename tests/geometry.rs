@@ -0,0 +1,119 @@
+use pianoroll::geometry::{chain_slots, check_time_divisor, fit_time_divisor, page_breaks, tiles,
+    PaperSize, PAGE_WIDTH, TILE_OVERLAP};
+
+#[test]
+fn time_divisor_in_range() {
+    // Five minutes at 480 ticks per beat and 120 bpm, with a 1 in leader.
+    assert_eq!(check_time_divisor(288_000, 4., 72.), Ok(()));
+    assert_eq!(check_time_divisor(288_000, 100., 72.), Ok(()));
+}
+
+#[test]
+fn time_divisor_too_small() {
+    let e = check_time_divisor(288_000, 0.001, 72.).unwrap_err();
+    assert!(e.starts_with("a time divisor of 0.001 makes the roll "), "{}", e);
+    assert!(e.ends_with("too long to place holes within 0.05 points of where they belong; use a \
+        time divisor of at least 1"), "{}", e);
+}
+
+#[test]
+fn time_divisor_too_large() {
+    assert_eq!(check_time_divisor(288_000, 10_000., 72.).unwrap_err(),
+        "a time divisor of 10000 makes the music only 0.4 in (10 mm) long on the roll; use a time \
+        divisor of at most 4000");
+}
+
+#[test]
+fn chain_long_slots() {
+    assert_eq!(chain_slots(0., 50., 100., 10.), [(0., 50.)]);
+    assert_eq!(chain_slots(0., 250., 100., 10.), [(0., 100.), (110., 210.), (220., 250.)]);
+    // Not a sliver at the end, but a slightly longer slot.
+    assert_eq!(chain_slots(0., 115., 100., 10.), [(0., 115.)]);
+}
+
+#[test]
+fn fit_roll_length() {
+    // 100 inches of roll, one of them leader, for a song ending at tick 71,280.
+    assert_eq!(fit_time_divisor(71_280, 7200., 72.), Ok(10.));
+    let e = fit_time_divisor(71_280, 100., 72.).unwrap_err();
+    assert!(e.starts_with("a roll 1.4 in"), "{}", e);
+}
+
+#[test]
+fn split_into_pages() {
+    // A roll that fits on one page stays on one.
+    assert_eq!(page_breaks(&[(0., 150.)], 200., 200.), [0.]);
+    // With no gaps at all, pages are cut as long as they can be.
+    assert_eq!(page_breaks(&[(0., 500.)], 500., 200.), [0., 200., 400.]);
+    // The second page starts where the hole it would cut starts, the last gap before it.
+    assert_eq!(page_breaks(&[(0., 150.), (160., 260.)], 300., 200.), [0., 160.]);
+    // ... but not if that would make the first page less than half as long as it could be.
+    assert_eq!(page_breaks(&[(0., 90.), (95., 260.)], 300., 200.), [0., 200.]);
+    // Holes that touch are cut through together.
+    assert_eq!(page_breaks(&[(150., 260.), (0., 150.)], 300., 200.), [0., 200.]);
+    // A page can end anywhere in a gap.
+    assert_eq!(page_breaks(&[(0., 150.), (210., 260.)], 300., 200.), [0., 200.]);
+    // Each page after a break is measured from the break.
+    assert_eq!(page_breaks(&[(0., 150.), (160., 330.), (340., 400.)], 400., 200.),
+        [0., 160., 340.]);
+}
+
+/// How many rows of tiles a roll takes.
+fn tile_rows(roll_length: f64, paper: PaperSize) -> usize {
+    tiles(roll_length, paper).iter().filter(|tile| tile.column == 0).count()
+}
+
+#[test]
+fn tile_letter_paper() {
+    // Letter paper, less its margins, is 8 by 10.5 in: two columns across the 11.25 in roll, and
+    // rows 10 in apart along it.
+    let tiles = tiles(2000., PaperSize::Letter);
+    let places = tiles.iter().map(|tile| (tile.column, tile.x, tile.y)).collect::<Vec<_>>();
+    assert_eq!(places, [
+        (0, 0., 0.), (1, 234., 0.),
+        (0, 0., 720.), (1, 234., 720.),
+        (0, 0., 1440.), (1, 234., 1440.),
+    ]);
+    assert!(tiles.iter().all(|tile| tile.width == 576. && tile.height == 756.));
+
+    // Glue lines are halfway through the overlaps, the same on both tiles.
+    assert_eq!(tiles[0].glue_after, Some(738.));
+    assert_eq!(tiles[2].glue_before, Some(738.));
+    assert_eq!(tiles[2].glue_after, Some(1458.));
+    assert_eq!((tiles[0].glue_before, tiles[4].glue_after), (None, None));
+    assert_eq!((tiles[0].glue_left, tiles[0].glue_right), (None, Some(405.)));
+    assert_eq!((tiles[1].glue_left, tiles[1].glue_right), (Some(405.), None));
+
+    // A short roll takes one row.
+    assert_eq!(tile_rows(100., PaperSize::Letter), 1);
+}
+
+#[test]
+fn tiles_cover_the_roll() {
+    for &paper in &[PaperSize::Letter, PaperSize::A4] {
+        for &roll_length in &[50., 755., 757., 14_400.] {
+            let tiles = tiles(roll_length, paper);
+            let columns = tiles.iter().map(|tile| tile.column).max().unwrap() + 1;
+            for pair in tiles.chunks(columns) {
+                // Across the roll, from edge to edge, with enough overlap to glue.
+                assert_eq!(pair[0].x, 0.);
+                assert_eq!(pair[columns - 1].x + pair[columns - 1].width, PAGE_WIDTH);
+                for (left, right) in pair.iter().zip(&pair[1 ..]) {
+                    assert!(left.x + left.width - right.x >= TILE_OVERLAP,
+                        "{:?} {:?}", left, right);
+                }
+            }
+            // Along it, from the start past the end.
+            let rows = tiles.iter().filter(|tile| tile.column == 0).collect::<Vec<_>>();
+            assert_eq!(rows[0].y, 0.);
+            let last = rows.last().unwrap();
+            assert!(last.y + last.height >= roll_length, "{:?}: {}", paper, roll_length);
+            for (before, after) in rows.iter().zip(&rows[1 ..]) {
+                assert!(before.y + before.height - after.y >= f64::from(TILE_OVERLAP));
+                let glue = before.glue_after.unwrap();
+                assert_eq!(after.glue_before, Some(glue));
+                assert!(after.y < glue && glue < before.y + before.height);
+            }
+        }
+    }
+}
@@ -0,0 +1,39 @@
+//! Lay out text punched into the leader.
+
+use pianoroll::label::{Label, GLYPH_HEIGHT};
+
+#[test]
+fn glyph_punch_counts() {
+    let punches = |text| Label::new(text).unwrap().punches.len();
+    // 1, 2, 1, 1, 1, 1, and 3 dots across its rows.
+    assert_eq!(punches("1"), 10);
+    assert_eq!(punches("8"), 17);
+    assert_eq!(punches("-"), 5);
+    assert_eq!(punches(" "), 0);
+    assert_eq!(punches("18-"), 32);
+    assert_eq!(punches(""), 0);
+    assert!(Label::new("a").is_err());
+
+    // The 1 takes up the middle three of its five columns, which are in the middle of the roll.
+    let label = Label::new("1").unwrap();
+    let mut channels = label.punches.iter().map(|punch| punch.channel).collect::<Vec<_>>();
+    channels.sort_unstable();
+    channels.dedup();
+    assert_eq!(channels, [47, 48, 49]);
+}
+
+#[test]
+fn punches_stay_apart_in_the_leader() {
+    let label = Label::new("18 TEMPO 90").unwrap();
+    for (i, a) in label.punches.iter().enumerate() {
+        assert!(a.y > 0. && a.y + a.height < label.leader_length, "{:?}", a);
+        for b in &label.punches[i + 1 ..] {
+            let overlap = a.channel == b.channel && a.y < b.y + b.height && b.y < a.y + a.height;
+            assert!(!overlap, "{:?} and {:?} overlap", a, b);
+        }
+    }
+    let rows = label.punches.iter()
+        .map(|punch| (punch.y * 1000.).round() as i64)
+        .collect::<std::collections::BTreeSet<_>>();
+    assert_eq!(rows.len(), usize::from(GLYPH_HEIGHT));
+}
@@ -0,0 +1,1101 @@
+//! Read and write small MIDI files through the library and pair up their notes.
+
+use pianoroll::checks::Category;
+use pianoroll::midi::{OutputTrack, OverlapPolicy, PedalEvent, SmpteOffset};
+use pianoroll::{analysis, config, diagnostics, geometry, pipeline, processing, program};
+use pianoroll::tempo::{BeatGrid, TempoChange, TempoMap, TimeSignature};
+use pianoroll::{note_durations, parse_configuration, render_pdf, Diagnostic, Midi, MidiNote,
+    NoteAction, NoteEvent, NoteWithDuration, PianoRollError};
+use std::convert::TryFrom;
+use std::ffi::OsString;
+
+/// One track at 96 ticks per beat: middle C for a beat, then E and G together for half a beat.
+const SONG: &[u8] = &[
+    // Header: format 0, one track, 96 ticks per beat.
+    b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0, 96,
+    b'M', b'T', b'r', b'k', 0, 0, 0, 35,
+    // Tempo: 500,000 microseconds per beat.
+    0x00, 0xFF, 0x51, 3, 0x07, 0xA1, 0x20,
+    0x00, 0x90, 60, 64,
+    0x60, 0x80, 60, 0,
+    0x00, 0x90, 64, 80,
+    0x00, 0x90, 67, 100,
+    0x30, 0x80, 64, 0,
+    0x00, 0x80, 67, 0,
+    0x00, 0xFF, 0x2F, 0,
+];
+
+/// Read the bytes as a MIDI file, by way of a temporary file named after the test.
+fn read(name: &str, bytes: &[u8]) -> Result<Midi, PianoRollError> {
+    let path = std::env::temp_dir()
+        .join(format!("pianoroll-test-{}-{}.mid", name, std::process::id()));
+    std::fs::write(&path, bytes).unwrap();
+    let mut midi = Midi::new();
+    let result = midi.read(&path);
+    std::fs::remove_file(&path).unwrap();
+    result.map(|()| midi)
+}
+
+/// Keeps what the library logs on each thread, so a test can look at what it was told.
+struct CaptureLogger;
+
+thread_local! {
+    static LOGGED: std::cell::RefCell<Vec<String>> = const { std::cell::RefCell::new(vec![]) };
+}
+
+impl log::Log for CaptureLogger {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        metadata.target().starts_with("pianoroll")
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if self.enabled(record.metadata()) {
+            let line = format!("{}: {}", record.level(), record.args());
+            LOGGED.with(|logged| logged.borrow_mut().push(line));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Run `f`, and return what it logged along with what it returned.
+fn logged<T>(f: impl FnOnce() -> T) -> (T, Vec<String>) {
+    static INIT: std::sync::Once = std::sync::Once::new();
+    INIT.call_once(|| {
+        log::set_logger(&CaptureLogger).unwrap();
+        log::set_max_level(log::LevelFilter::Info);
+    });
+    LOGGED.with(|logged| logged.borrow_mut().clear());
+    let result = f();
+    (result, LOGGED.with(|logged| logged.take()))
+}
+
+#[test]
+fn read_and_pair_notes() {
+    let midi = read("pair", SONG).unwrap();
+
+    assert_eq!(midi.time_base(), Some(96));
+    assert_eq!(midi.tempo(), Some(500_000));
+
+    let (notes, diagnostics) = note_durations(midi.notes(), &[], 96, OverlapPolicy::Error,
+        |_| Some((0, 0)));
+    assert!(diagnostics.is_empty(), "unexpected diagnostics: {:?}", diagnostics);
+    let mut notes = notes.iter()
+        .map(|note| (note.timestamp, note.duration, note.note.as_u8(), note.velocity))
+        .collect::<Vec<_>>();
+    notes.sort();
+    assert_eq!(notes, [(0, 96, 60, 64), (96, 48, 64, 80), (96, 48, 67, 100)]);
+}
+
+/// Notes with no tempo or time signature, at 96 ticks per beat: middle C for ten beats.
+const NOTES_ONLY: &[u8] = &[
+    b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0, 96,
+    b'M', b'T', b'r', b'k', 0, 0, 0, 13,
+    0x00, 0x90, 60, 64,
+    0x87, 0x40, 0x80, 60, 0,
+    0x00, 0xFF, 0x2F, 0,
+];
+
+/// The timing for `NOTES_ONLY`, at 192 ticks per beat: two measures of 3/4, then 2/4.
+const CONDUCTOR: &[u8] = &[
+    b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0, 192,
+    b'M', b'T', b'r', b'k', 0, 0, 0, 28,
+    0x00, 0xFF, 0x58, 4, 3, 2, 24, 8,
+    0x00, 0xFF, 0x51, 3, 0x07, 0xA1, 0x20,
+    0x89, 0x00, 0xFF, 0x58, 4, 2, 2, 24, 8,
+    0x00, 0xFF, 0x2F, 0,
+];
+
+#[test]
+fn conductor_timing() {
+    let mut midi = read("conductor-notes", NOTES_ONLY).unwrap();
+    let conductor = read("conductor", CONDUCTOR).unwrap();
+    assert_eq!(midi.tempo(), None);
+
+    let e = midi.take_timing_from(&conductor, "conductor.mid".as_ref(), false).unwrap_err();
+    assert!(matches!(e, PianoRollError::MidiParse { .. }), "{:?}", e);
+
+    midi.take_timing_from(&conductor, "conductor.mid".as_ref(), true).unwrap();
+    assert_eq!(midi.tempo(), Some(500_000));
+    assert_eq!(midi.notes().count(), 2);
+    let grid = BeatGrid::new(midi.time_signatures(), 96, 960);
+    let downbeats = grid.beats().iter()
+        .filter(|beat| beat.downbeat)
+        .map(|beat| beat.timestamp)
+        .collect::<Vec<_>>();
+    assert_eq!(downbeats, [0, 288, 576, 768, 960]);
+}
+
+/// Two notes in a file timed by timecode, at 25 frames per second and 40 ticks per frame: middle
+/// C for a second, then E for half a second. The tempo in it should be ignored.
+const TIMECODE: &[u8] = &[
+    b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0xE7, 40,
+    b'M', b'T', b'r', b'k', 0, 0, 0, 29,
+    0x00, 0xFF, 0x51, 3, 0x07, 0xA1, 0x20,
+    0x00, 0x90, 60, 64,
+    0x87, 0x68, 0x80, 60, 0,
+    0x00, 0x90, 64, 80,
+    0x83, 0x74, 0x80, 64, 0,
+    0x00, 0xFF, 0x2F, 0,
+];
+
+/// The same as `TIMECODE`, at 1000 ticks per beat and a beat a second.
+const TIMECODE_METRICAL: &[u8] = &[
+    b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0x03, 0xE8,
+    b'M', b'T', b'r', b'k', 0, 0, 0, 29,
+    0x00, 0xFF, 0x51, 3, 0x0F, 0x42, 0x40,
+    0x00, 0x90, 60, 64,
+    0x87, 0x68, 0x80, 60, 0,
+    0x00, 0x90, 64, 80,
+    0x83, 0x74, 0x80, 64, 0,
+    0x00, 0xFF, 0x2F, 0,
+];
+
+#[test]
+fn timecode_division() {
+    let roll = |midi: &Midi| {
+        let time_base = midi.time_base().unwrap();
+        let (notes, _) = note_durations(midi.notes(), &[], time_base, OverlapPolicy::Error,
+            |_| Some((0, 0)));
+        let cfg = parse_configuration(["pianoroll", "song.mid", "0,0"].iter()
+            .map(OsString::from))
+            .unwrap();
+        let mut pdf = vec![];
+        render_pdf(&notes, &[], time_base, 60., None, &cfg, &mut pdf).unwrap();
+        let notes = notes.iter()
+            .map(|note| (note.timestamp, note.duration, note.note.as_u8()))
+            .collect::<Vec<_>>();
+        (time_base, midi.tempo(), notes, pdf)
+    };
+    let timecode = roll(&read("timecode", TIMECODE).unwrap());
+    let metrical = roll(&read("timecode-metrical", TIMECODE_METRICAL).unwrap());
+    assert_eq!((timecode.0, timecode.1), (1000, Some(1_000_000)));
+    assert_eq!(timecode.2, [(0, 1000, 60), (1000, 500, 64)]);
+    assert_eq!(timecode.2, metrical.2);
+    assert!(timecode.3 == metrical.3, "the rolls differ");
+}
+
+#[test]
+fn smpte_offsets() {
+    // The frame rate is in bits 5 and 6 of the hours byte.
+    let offset = |rate: u8, hours, frames, fractional_frames| {
+        SmpteOffset::from_bytes(&[rate << 5 | hours, 1, 2, frames, fractional_frames]).unwrap()
+    };
+    let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+
+    let film = offset(0, 1, 12, 50);
+    assert!(close(film.as_seconds(), 3662. + 12.5 / 24.), "{}", film.as_seconds());
+    assert_eq!(film.to_string(), "01:01:02:12 (24.00 fps)");
+
+    let pal = offset(1, 0, 20, 0);
+    assert!(close(pal.as_seconds(), 62.8), "{}", pal.as_seconds());
+    assert_eq!(pal.to_string(), "00:01:02:20 (25.00 fps)");
+
+    // 30 frames of drop-frame time code take a little over a second.
+    let ntsc = offset(2, 0, 15, 0);
+    assert!(close(ntsc.as_seconds(), 62. + 15. * 1.001 / 30.), "{}", ntsc.as_seconds());
+    assert_eq!(ntsc.to_string(), "00:01:02:15 (29.97 fps)");
+
+    let video = offset(3, 23, 29, 99);
+    assert!(close(video.as_seconds(), 82862. + 29.99 / 30.), "{}", video.as_seconds());
+
+    assert!(SmpteOffset::from_bytes(&[0, 0, 0, 0]).is_none());
+}
+
+#[test]
+fn expand_wildcard_selectors() {
+    let midi = read("wildcards", SONG).unwrap();
+    let expand = |args: &[&str]| {
+        let cfg = parse_configuration(["pianoroll", "song.mid"].iter().chain(args)
+            .map(OsString::from))
+            .unwrap();
+        config::expand_wildcards(cfg.selectors, &midi).into_iter()
+            .map(|selector| (selector.label, selector.midi_track, selector.midi_channel,
+                selector.offset))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(expand(&["1,0", "top=*,*+12"]), [
+        ("s1".to_owned(), 1, 0, 0),
+        ("top.1".to_owned(), 0, 0, 12),
+    ]);
+    assert_eq!(expand(&["1,*"]), []);
+}
+
+/// Format 1 at 96 ticks per beat. Track 0, "Click", sets the tempo and plays middle C on channel 0
+/// with program 5 for a beat, then changes tempo; track 1, "Bass", plays C3 on channel 1 from
+/// half a beat in.
+const FORMAT_1: &[u8] = &[
+    b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 1, 0, 2, 0, 96,
+    b'M', b'T', b'r', b'k', 0, 0, 0, 38,
+    0x00, 0xFF, 0x03, 5, b'C', b'l', b'i', b'c', b'k',
+    0x00, 0xFF, 0x51, 3, 0x07, 0xA1, 0x20,
+    0x00, 0xC0, 5,
+    0x00, 0x90, 60, 64,
+    0x60, 0x80, 60, 0,
+    0x00, 0xFF, 0x51, 3, 0x0F, 0x42, 0x40,
+    0x00, 0xFF, 0x2F, 0,
+    b'M', b'T', b'r', b'k', 0, 0, 0, 20,
+    0x00, 0xFF, 0x03, 4, b'B', b'a', b's', b's',
+    0x30, 0x91, 48, 80,
+    0x30, 0x81, 48, 0,
+    0x00, 0xFF, 0x2F, 0,
+];
+
+#[test]
+fn tracks_numbered_and_timed_alike() {
+    let midi = read("format-1", FORMAT_1).unwrap();
+    let tracks = midi.tracks()
+        .map(|track| (track.midi_track, track.name.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(tracks, [(0, Some("Click".to_owned())), (1, Some("Bass".to_owned()))]);
+    let channels = midi.channels()
+        .map(|channel| (channel.midi_track, channel.midi_channel, channel.program,
+            channel.program_segments.iter().map(|segment| segment.notes).sum::<u64>()))
+        .collect::<Vec<_>>();
+    assert_eq!(channels, [(0, 0, 5, 1), (1, 1, 0, 1)]);
+    let notes = midi.notes()
+        .map(|event| (event.timestamp, event.track, event.channel, event.note.as_u8(),
+            event.action))
+        .collect::<Vec<_>>();
+    assert_eq!(notes, [
+        (0, 0, 0, 60, NoteAction::On),
+        (96, 0, 0, 60, NoteAction::Off),
+        (48, 1, 1, 48, NoteAction::On),
+        (96, 1, 1, 48, NoteAction::Off),
+    ]);
+    let tempo_changes = midi.tempo_map().unwrap().changes().iter()
+        .map(|change| (change.timestamp, change.micros_per_beat))
+        .collect::<Vec<_>>();
+    assert_eq!(tempo_changes, [(0, 500_000), (96, 1_000_000)]);
+}
+
+#[test]
+fn header_track_count_mismatch() {
+    let tracks = |midi: &Midi| midi.tracks().map(|track| track.name.clone()).collect::<Vec<_>>();
+    let both = [Some("Click".to_owned()), Some("Bass".to_owned())];
+
+    // Claiming a third track or only one, both tracks that are there are read anyway.
+    for (declared, warning) in [(3, "header claims 3 tracks, found 2"),
+        (1, "header claims 1 tracks, found 2")]
+    {
+        let mut bytes = FORMAT_1.to_vec();
+        bytes[11] = declared;
+        let (midi, lines) = logged(|| read("track-count", &bytes));
+        let midi = midi.unwrap();
+        assert_eq!(tracks(&midi), both);
+        assert_eq!(midi.notes().count(), 4);
+        assert!(lines.iter().any(|line| line.starts_with(&format!("WARN: {}", warning))),
+            "{:?}", lines);
+    }
+
+    // A bad status byte in the second track stops the reading there, and what was read of both
+    // tracks before it is kept.
+    let mut bytes = FORMAT_1.to_vec();
+    let bass_note = bytes.len() - 12;
+    assert_eq!(bytes[bass_note ..][.. 2], [0x30, 0x91]);
+    bytes[bass_note + 1] = 0xF4;
+    let (midi, lines) = logged(|| read("parse-error", &bytes));
+    let midi = midi.unwrap();
+    assert_eq!(tracks(&midi), both);
+    let notes = midi.notes().map(|event| (event.track, event.note.as_u8())).collect::<Vec<_>>();
+    assert_eq!(notes, [(0, 60), (0, 60)]);
+    assert!(lines.iter().any(|line| line.contains("using the 2 tracks read before the error")),
+        "{:?}", lines);
+}
+
+/// One channel at 96 ticks per beat playing a C and an E on piano, then switching to violin for a
+/// G.
+const PROGRAM_CHANGE: &[u8] = &[
+    b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0, 96,
+    b'M', b'T', b'r', b'k', 0, 0, 0, 41,
+    0x00, 0xFF, 0x51, 3, 0x07, 0xA1, 0x20,
+    0x00, 0xC0, 0,
+    0x00, 0x90, 60, 64,
+    0x60, 0x80, 60, 0,
+    0x00, 0x90, 64, 64,
+    0x60, 0x80, 64, 0,
+    0x00, 0xC0, 40,
+    0x00, 0x90, 67, 64,
+    0x60, 0x80, 67, 0,
+    0x00, 0xFF, 0x2F, 0,
+];
+
+#[test]
+fn select_program_segments() {
+    let midi = read("segments", PROGRAM_CHANGE).unwrap();
+    let segments = midi.channels()
+        .flat_map(|channel| &channel.program_segments)
+        .map(|segment| (segment.start, segment.program, segment.notes))
+        .collect::<Vec<_>>();
+    assert_eq!(segments, [(0, 0, 2), (192, 40, 1)]);
+
+    let dir = std::env::temp_dir().join(format!("pianoroll-test-segments-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let song = dir.join("song.mid");
+    std::fs::write(&song, PROGRAM_CHANGE).unwrap();
+    let csv = dir.join("song.csv");
+    // The violin's segment picked by its program, an octave down, and the piano's by its index.
+    let program = ["pianoroll", song.to_str().unwrap(), "violin=0,0-12:prog=Violin",
+        "piano=0,0:seg=0", "--format", "csv", "-o", csv.to_str().unwrap()];
+    let cfg = parse_configuration(program.iter().map(OsString::from)).unwrap();
+    let (result, lines) = logged(|| pipeline::run(cfg, std::io::sink()));
+    result.unwrap();
+    let counts = lines.iter()
+        .filter(|line| line.starts_with("INFO: selector "))
+        .collect::<Vec<_>>();
+    assert_eq!(counts, [
+        "INFO: selector violin (0,0-12): 1 notes",
+        "INFO: selector piano (0,0+0): 2 notes",
+    ]);
+    let notes = std::fs::read_to_string(&csv).unwrap().lines()
+        .skip(1)
+        .map(|line| line.split(',').nth(4).unwrap().parse::<u8>().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(notes, [60, 64, 55]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn missing_file() {
+    let mut midi = Midi::new();
+    let e = midi.read("/nonexistent/song.mid".as_ref()).unwrap_err();
+    assert!(matches!(e, PianoRollError::Read { .. }), "{:?}", e);
+    assert_eq!(e.exit_code(), 3);
+}
+
+#[test]
+fn not_a_midi_file() {
+    let e = read("garbage", b"RIFF\0\0\0\0WAVEfmt ").unwrap_err();
+    assert!(matches!(e, PianoRollError::MidiParse { .. }), "{:?}", e);
+}
+
+#[test]
+fn no_tempo() {
+    // The same song without its tempo event.
+    let mut song = SONG[.. 22].to_vec();
+    song[21] -= 7;
+    song.extend_from_slice(&SONG[29 ..]);
+    let midi = read("no-tempo", &song).unwrap();
+    assert!(matches!(midi.tempo_or(None), Err(PianoRollError::NoTempo)));
+    assert_eq!(midi.tempo_or(Some(600_000)).unwrap(), 600_000);
+}
+
+#[test]
+fn bank_select_lsb() {
+    let song = [
+        b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0, 96,
+        b'M', b'T', b'r', b'k', 0, 0, 0, 33,
+        0x00, 0xFF, 0x51, 3, 0x07, 0xA1, 0x20,
+        // A Roland GS variation of the steel string guitar, bank 8 with an LSB of 0.
+        0x00, 0xB0, 0, 8,
+        0x00, 0xB0, 32, 0,
+        0x00, 0xC0, 25,
+        // A Yamaha XG variation, in the LSB of bank 0.
+        0x00, 0xB1, 0, 0,
+        0x00, 0xB1, 32, 3,
+        0x00, 0xC1, 0,
+        0x00, 0xFF, 0x2F, 0,
+    ];
+    let midi = read("bank-lsb", &song).unwrap();
+    let names = midi.channels()
+        .map(|channel| (channel.bank, channel.bank_lsb,
+            program::instrument_name(channel.bank, channel.bank_lsb, channel.program)))
+        .collect::<Vec<_>>();
+    assert_eq!(names, [
+        (8, 0, Some("Acoustic Guitar (steel) (variation)".to_owned())),
+        (0, 3, Some("Acoustic Grand Piano (variation)".to_owned())),
+    ]);
+    assert_eq!(program::instrument_name(121, 0, 0).as_deref(), Some("Acoustic Grand Piano"));
+    assert_eq!(program::instrument_name(127, 0, 0), None);
+}
+
+fn event(timestamp: u64, track: usize, note: u8, action: NoteAction) -> NoteEvent {
+    NoteEvent {
+        timestamp,
+        track,
+        channel: 0,
+        note: MidiNote::try_from(note).unwrap(),
+        action,
+        velocity: if action == NoteAction::On { 64 } else { 0 },
+        segment: 0,
+        program: 0,
+    }
+}
+
+#[test]
+fn sustain_pedal_defers_releases() {
+    let notes = [
+        event(0, 0, 60, NoteAction::On),
+        event(48, 0, 60, NoteAction::Off),
+        event(48, 0, 64, NoteAction::On),
+        event(96, 0, 64, NoteAction::Off),
+        // Striking C again cuts off its sustain.
+        event(150, 0, 60, NoteAction::On),
+        event(160, 0, 60, NoteAction::Off),
+        // After the pedal comes up, notes end when they're released.
+        event(200, 0, 67, NoteAction::On),
+        event(240, 0, 67, NoteAction::Off),
+    ];
+    let pedal = [
+        PedalEvent { timestamp: 24, track: 0, channel: 0, down: true },
+        PedalEvent { timestamp: 192, track: 0, channel: 0, down: false },
+    ];
+    let (notes, diagnostics) = note_durations(notes.iter(), &pedal, 96, OverlapPolicy::Error,
+        |_| Some((0, 0)));
+    assert!(diagnostics.is_empty(), "unexpected diagnostics: {:?}", diagnostics);
+    let mut notes = notes.iter()
+        .map(|note| (note.timestamp, note.duration, note.note.as_u8()))
+        .collect::<Vec<_>>();
+    notes.sort();
+    assert_eq!(notes, [(0, 150, 60), (48, 144, 64), (150, 42, 60), (200, 40, 67)]);
+}
+
+#[test]
+fn overlapping_presses() {
+    // Two tracks doubling a C, the second coming in late and holding on longer, then the first
+    // playing another C that overlaps the second's release.
+    let notes = [
+        event(0, 0, 60, NoteAction::On),
+        event(48, 1, 60, NoteAction::On),
+        event(96, 0, 60, NoteAction::Off),
+        event(144, 0, 60, NoteAction::On),
+        event(192, 1, 60, NoteAction::Off),
+        event(240, 0, 60, NoteAction::Off),
+    ];
+    let spans = |overlap| {
+        let (notes, diagnostics) = note_durations(notes.iter(), &[], 96, overlap, |_| Some((0, 0)));
+        assert!(overlap == OverlapPolicy::Error || diagnostics.is_empty(),
+            "unexpected diagnostics: {:?}", diagnostics);
+        notes.iter().map(|note| (note.timestamp, note.duration, note.track)).collect::<Vec<_>>()
+    };
+    assert_eq!(spans(OverlapPolicy::MergeUnion), [(0, 240, 0)]);
+    assert_eq!(spans(OverlapPolicy::KeepFirst), [(0, 96, 0)]);
+    assert_eq!(spans(OverlapPolicy::KeepLongest), [(48, 144, 1)]);
+    // The first release ends the note, and the second press is reported.
+    let (notes, diagnostics) = note_durations(notes.iter(), &[], 96, OverlapPolicy::Error,
+        |_| Some((0, 0)));
+    assert_eq!(notes.len(), 2);
+    assert_eq!((notes[0].timestamp, notes[0].duration), (0, 96));
+    assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+}
+
+#[test]
+fn merge_alternating_across_meter_change() {
+    // A measure of 3/4, then 2/4 from tick 288: the hands take turns a measure at a time, and
+    // the left hand's note in the third measure is under the right hand's, so it's left out.
+    let notes = [
+        event(0, 0, 72, NoteAction::On),
+        event(96, 0, 72, NoteAction::Off),
+        event(288, 1, 60, NoteAction::On),
+        event(384, 1, 60, NoteAction::Off),
+        event(480, 0, 72, NoteAction::On),
+        event(500, 1, 48, NoteAction::On),
+        event(540, 1, 48, NoteAction::Off),
+        event(576, 0, 72, NoteAction::Off),
+    ];
+    let signatures = [
+        TimeSignature { timestamp: 0, numerator: 3, denominator: 4 },
+        TimeSignature { timestamp: 288, numerator: 2, denominator: 4 },
+    ];
+    let grid = BeatGrid::new(&signatures, 96, 576);
+    let channels = [(0, 0), (1, 0)];
+    let voices = analysis::alternating_voice(notes.iter(), channels, &grid, true);
+    assert_eq!(voices, [0, 1, 0]);
+
+    let mut merge = processing::AlternatingMerge::new(channels, grid, voices);
+    let kept = notes.iter()
+        .filter(|event| merge.keep(event))
+        .map(|event| (event.timestamp, event.track))
+        .collect::<Vec<_>>();
+    assert_eq!(kept, [(0, 0), (96, 0), (288, 1), (384, 1), (480, 0), (576, 0)]);
+}
+
+#[test]
+fn doubled_selectors_pair_separately() {
+    // A C and then an E, picked up by two selectors: one putting it on the roll as it is, and one
+    // an octave up. The octave-up C lands on the same roll note as a third selector's C5.
+    let notes = [
+        event(0, 0, 60, NoteAction::On),
+        event(0, 1, 72, NoteAction::On),
+        event(96, 0, 60, NoteAction::Off),
+        event(96, 0, 64, NoteAction::On),
+        event(144, 1, 72, NoteAction::Off),
+        event(192, 0, 64, NoteAction::Off),
+    ];
+    let (notes, diagnostics) = note_durations(notes.iter(), &[], 96, OverlapPolicy::Error,
+        |event| if event.track == 0 { vec![(0, 0), (1, 12)] } else { vec![(2, 0)] });
+    assert!(diagnostics.is_empty(), "unexpected diagnostics: {:?}", diagnostics);
+    let mut notes = notes.iter()
+        .map(|note| (note.timestamp, note.duration, note.note.as_u8(), note.track))
+        .collect::<Vec<_>>();
+    notes.sort();
+    assert_eq!(notes, [
+        (0, 96, 60, 0), (0, 96, 72, 0), (0, 144, 72, 1), (96, 96, 64, 0), (96, 96, 76, 0),
+    ]);
+}
+
+#[test]
+fn doubled_selectors_sustain_separately() {
+    // A C held by the pedal on the first track, and a C an octave up on the second, which has no
+    // pedal, that a second selector moves down onto the same roll note while the first is held.
+    let notes = [
+        event(0, 0, 60, NoteAction::On),
+        event(48, 0, 60, NoteAction::Off),
+        event(96, 1, 72, NoteAction::On),
+        event(120, 1, 72, NoteAction::Off),
+    ];
+    let pedal = [
+        PedalEvent { timestamp: 24, track: 0, channel: 0, down: true },
+        PedalEvent { timestamp: 192, track: 0, channel: 0, down: false },
+    ];
+    let (notes, diagnostics) = note_durations(notes.iter(), &pedal, 96, OverlapPolicy::Error,
+        |event| Some(if event.track == 0 { (0, 0) } else { (1, -12) }));
+    assert!(diagnostics.is_empty(), "unexpected diagnostics: {:?}", diagnostics);
+    let mut notes = notes.iter()
+        .map(|note| (note.timestamp, note.duration, note.note.as_u8(), note.selector))
+        .collect::<Vec<_>>();
+    notes.sort();
+    // The other selector's C doesn't strike the held one again, so the pedal holds it to the end.
+    assert_eq!(notes, [(0, 192, 60, Some(0)), (96, 24, 60, Some(1))]);
+}
+
+#[test]
+fn problems_returned_as_diagnostics() {
+    // A release with no press, then a note an octave above the top of the roll.
+    let notes = [
+        event(0, 0, 60, NoteAction::Off),
+        event(96, 0, 96, NoteAction::On),
+        event(192, 0, 96, NoteAction::Off),
+    ];
+    let (notes, diagnostics) = note_durations(notes.iter(), &[], 96, OverlapPolicy::Error,
+        |event| Some((0, if event.note.as_u8() == 96 { 12 } else { 0 })));
+    assert!(notes.is_empty());
+    assert!(matches!(diagnostics[..], [
+        Diagnostic::NoteNotPressed { timestamp: 0, .. },
+        Diagnostic::NoteOutOfRange { timestamp: 96, offset: 12, .. },
+        Diagnostic::NoteOutOfRange { timestamp: 192, offset: 12, .. },
+    ]), "{:?}", diagnostics);
+}
+
+#[test]
+fn aggregate_repeated_diagnostics() {
+    // The same note on the same channel, with releases out of turn interleaved with stuck
+    // presses: the two kinds are counted up separately, even though they alternate.
+    let not_pressed = |timestamp, note| Diagnostic::NoteNotPressed { timestamp, track: 1,
+        channel: 0, note };
+    let stuck = |timestamp, note| Diagnostic::StuckNote { timestamp, track: 1, channel: 0, note };
+    let found = [
+        not_pressed(0, MidiNote::C4),
+        stuck(10, MidiNote::C4),
+        not_pressed(20, MidiNote::C4),
+        stuck(30, MidiNote::C4),
+        not_pressed(40, MidiNote::D4),
+        not_pressed(50, MidiNote::C4),
+        stuck(60, MidiNote::C4),
+        stuck(70, MidiNote::C4),
+    ];
+    let lines = diagnostics::aggregated_report(&found, 1);
+    assert_eq!(lines, [
+        found[0].to_string(),
+        found[1].to_string(),
+        found[4].to_string(),
+        "ERROR: 2 more not-pressed errors for note C4 on track 1 channel 0 (3 in all, from 0 to 50)"
+            .to_owned(),
+        "ERROR: 3 more stuck-note errors for note C4 on track 1 channel 0 (4 in all, from 10 to 70)"
+            .to_owned(),
+    ]);
+
+    // With room for all of them, they're all printed in order.
+    let lines = diagnostics::aggregated_report(&found, 4);
+    assert_eq!(lines, found.iter().map(ToString::to_string).collect::<Vec<_>>());
+}
+
+#[test]
+fn write_track_per_group() {
+    let note = |timestamp, note, velocity| NoteWithDuration {
+        timestamp,
+        duration: 48,
+        note: MidiNote::try_from(note).unwrap(),
+        track: 0,
+        channel: 0,
+        velocity,
+        source: None,
+        selector: None,
+    };
+    let melody = [note(0, 72, 100), note(48, 74, 30)];
+    let bass = [note(0, 48, 64)];
+    let tracks = [
+        OutputTrack { name: Some("Melody"), channel: 0, notes: &melody },
+        OutputTrack { name: None, channel: 1, notes: &bass },
+    ];
+    let path = std::env::temp_dir()
+        .join(format!("pianoroll-test-write-tracks-{}.mid", std::process::id()));
+    Midi::write(&path, &tracks, 96, &[]).unwrap();
+    let mut midi = Midi::new();
+    let result = midi.read(&path);
+    std::fs::remove_file(&path).unwrap();
+    result.unwrap();
+
+    let names = midi.tracks().map(|track| track.name.clone()).collect::<Vec<_>>();
+    assert_eq!(names, [None, Some("Melody".to_owned()), None]);
+    let mut notes = midi.notes()
+        .filter(|event| event.action == NoteAction::On)
+        .map(|event| (event.track, event.channel, event.note.as_u8(), event.velocity))
+        .collect::<Vec<_>>();
+    notes.sort();
+    // Each note keeps how hard it was played.
+    assert_eq!(notes, [(1, 0, 72, 100), (1, 0, 74, 30), (2, 1, 48, 64)]);
+}
+
+#[test]
+fn flattened_preview_keeps_real_time() {
+    // A beat a second, then twice as fast from tick 96, then slower again from tick 150, with
+    // notes either side of and across the changes, at odd ticks so the flattening has to round.
+    let changes = vec![
+        TempoChange { timestamp: 0, micros_per_beat: 1_000_000 },
+        TempoChange { timestamp: 96, micros_per_beat: 500_000 },
+        TempoChange { timestamp: 150, micros_per_beat: 750_000 },
+    ];
+    let tempo_map = TempoMap::new(96, changes.clone());
+    let note = |timestamp, duration, note| NoteWithDuration { timestamp, duration,
+        note: MidiNote::try_from(note).unwrap(), track: 0, channel: 0, velocity: 64,
+        source: None, selector: None };
+    let notes = [note(0, 96, 60), note(90, 13, 62), note(101, 7, 64), note(131, 77, 65),
+        note(149, 2, 67), note(211, 35, 69)];
+
+    // Write the notes both ways and read them back, as a player would.
+    let flat_tempo = tempo_map.tempo_at(0);
+    let flattened = processing::flatten_tempo(&notes, &tempo_map, flat_tempo);
+    let flat_changes = [TempoChange { timestamp: 0, micros_per_beat: flat_tempo }];
+    let onsets = |name: &str, notes: &[NoteWithDuration], changes: &[TempoChange]| {
+        let path = std::env::temp_dir()
+            .join(format!("pianoroll-test-{}-{}.mid", name, std::process::id()));
+        let tracks = [OutputTrack { name: None, channel: 0, notes }];
+        Midi::write(&path, &tracks, 96, changes).unwrap();
+        let mut midi = Midi::new();
+        let result = midi.read(&path);
+        std::fs::remove_file(&path).unwrap();
+        result.unwrap();
+        let tempo_map = midi.tempo_map().unwrap();
+        let (notes, diagnostics) = note_durations(midi.notes(), &[], 96, OverlapPolicy::Error,
+            |_| Some((0, 0)));
+        assert!(diagnostics.is_empty(), "unexpected diagnostics: {:?}", diagnostics);
+        notes.iter()
+            .map(|note| {
+                let seconds = |timestamp| tempo_map.micros_at(timestamp) / 1_000_000.;
+                (note.note, seconds(note.timestamp), seconds(note.timestamp + note.duration))
+            })
+            .collect::<Vec<_>>()
+    };
+    let original = onsets("preview-original", &notes, &changes);
+    let flat = onsets("preview-flattened", &flattened, &flat_changes);
+    assert_eq!(original.len(), notes.len());
+    assert_eq!(flat.len(), notes.len());
+
+    // Rounding to the nearest tick at a second a beat puts a note off by at most half a tick.
+    let tolerance = 0.5 / 96. + 1e-9;
+    for (want, got) in original.iter().zip(&flat) {
+        assert_eq!(want.0, got.0);
+        assert!((want.1 - got.1).abs() <= tolerance && (want.2 - got.2).abs() <= tolerance,
+            "{:?} became {:?}", want, got);
+    }
+}
+
+#[test]
+fn timestamps_past_u32() {
+    // Notes as far apart as a MIDI file allows, running past 2^32 ticks.
+    const MAX_DELTA: u64 = 0x0FFF_FFFF;
+    let note = |timestamp| NoteWithDuration { timestamp, duration: 1, note: MidiNote::C4,
+        track: 0, channel: 0, velocity: 64, source: None, selector: None };
+    let notes = (0 .. 18).map(|i| note(i * (MAX_DELTA + 1))).collect::<Vec<_>>();
+    assert!(notes.last().unwrap().timestamp > 1 << 32);
+    let path = std::env::temp_dir()
+        .join(format!("pianoroll-test-long-{}.mid", std::process::id()));
+    let tracks = [OutputTrack { name: None, channel: 0, notes: &notes }];
+    Midi::write(&path, &tracks, 960, &[]).unwrap();
+    let mut midi = Midi::new();
+    let result = midi.read(&path);
+    std::fs::remove_file(&path).unwrap();
+    result.unwrap();
+    let (read_back, diagnostics) = note_durations(midi.notes(), &[], 960, OverlapPolicy::Error,
+        |_| Some((0, 0)));
+    assert!(diagnostics.is_empty(), "unexpected diagnostics: {:?}", diagnostics);
+    let timestamps = |notes: &[NoteWithDuration]| {
+        notes.iter().map(|note| (note.timestamp, note.duration)).collect::<Vec<_>>()
+    };
+    assert_eq!(timestamps(&read_back), timestamps(&notes));
+
+    // A gap one tick longer can't be written at all, rather than being cut short.
+    let notes = [note(0), note(MAX_DELTA + 2)];
+    let tracks = [OutputTrack { name: None, channel: 0, notes: &notes }];
+    let e = Midi::write(&path, &tracks, 960, &[]).unwrap_err();
+    assert!(matches!(e, PianoRollError::MidiWrite { .. }), "{:?}", e);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn stretch_to_duration() {
+    let note = |timestamp, duration| NoteWithDuration {
+        timestamp,
+        duration,
+        note: MidiNote::C4,
+        track: 0,
+        channel: 0,
+        velocity: 64,
+        source: None,
+        selector: None,
+    };
+    let mut notes = vec![note(0, 96), note(96, 96), note(480, 300)];
+    // Half a second a beat, then twice as slow from the third note.
+    let mut tempo_map = TempoMap::new(96, vec![
+        TempoChange { timestamp: 0, micros_per_beat: 500_000 },
+        TempoChange { timestamp: 480, micros_per_beat: 1_000_000 },
+    ]);
+    let factor = processing::stretch_factor(&notes, 0, &tempo_map, 42.).unwrap();
+    processing::time_stretch(&mut notes, factor);
+    tempo_map.stretch(factor);
+    let end = notes.iter().map(|note| note.timestamp + note.duration).max().unwrap();
+
+    let path = std::env::temp_dir()
+        .join(format!("pianoroll-test-stretch-to-{}.mid", std::process::id()));
+    let tracks = [OutputTrack { name: None, channel: 0, notes: &notes }];
+    Midi::write(&path, &tracks, 96, tempo_map.changes()).unwrap();
+    let mut preview = Midi::new();
+    let result = preview.read(&path);
+    std::fs::remove_file(&path).unwrap();
+    result.unwrap();
+    let played = preview.tempo_map().unwrap().micros_at(end) / 1_000_000.;
+    assert!((played - 42.).abs() < 0.01, "the preview plays for {} seconds", played);
+
+    // With the time divisor worked out by --fit-length, the roll is the right length too.
+    let leader = 72.;
+    let time_divisor = geometry::fit_time_divisor(end, 42. * 72., leader).unwrap();
+    assert!((leader + end as f64 / time_divisor - 42. * 72.).abs() < 0.01);
+}
+
+#[test]
+fn summarize_whole_song() {
+    let midi = read("summary", SONG).unwrap();
+    let summary = analysis::SongSummary::new(&midi, 96, &[], OverlapPolicy::Error);
+    assert_eq!(summary.ranges.into_iter().collect::<Vec<_>>(),
+        vec![((0, 0), (MidiNote::C4, MidiNote::G4))]);
+    assert_eq!(summary.end, 144);
+}
+
+#[test]
+fn run_the_program() {
+    let dir = std::env::temp_dir().join(format!("pianoroll-test-run-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let song = dir.join("song.mid");
+    std::fs::write(&song, SONG).unwrap();
+    let run = |args: &[&str]| {
+        let program = ["pianoroll", song.to_str().unwrap()];
+        let cfg = parse_configuration(program.iter().chain(args).map(OsString::from)).unwrap();
+        let mut listing = vec![];
+        pipeline::run(cfg, &mut listing)
+            .map(|report| (report.exit_code(), String::from_utf8(listing).unwrap()))
+    };
+
+    let (exit_code, listing) = run(&["--list-tracks-json"]).unwrap();
+    assert_eq!(exit_code, 0);
+    assert!(listing.contains("\"notes\": 3"), "{}", listing);
+
+    let roll = dir.join("song.pdf");
+    let (exit_code, listing) = run(&["0,0", "-o", roll.to_str().unwrap(), "--self-check"])
+        .unwrap();
+    assert_eq!((exit_code, listing.as_str()), (0, ""));
+    assert!(roll.exists() && dir.join("song_pianoroll.mid").exists());
+
+    // Spread out to C0 and G8, the notes span 104 notes, more than the roll has.
+    let e = run(&["0,0-48", "0,0+48", "--strict-range"]).unwrap_err();
+    assert!(matches!(e, PianoRollError::NoteOutOfRange { .. }), "{:?}", e);
+    assert_eq!(e.exit_code(), 19);
+    let e = run(&["1,0"]).unwrap_err();
+    assert!(matches!(e, PianoRollError::NoNotesSelected), "{:?}", e);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// A beat at 120 bpm, then a beat at 240 before the first note: a C for a beat.
+const TEMPO_CHANGE_BEFORE_NOTES: &[u8] = &[
+    b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0, 96,
+    b'M', b'T', b'r', b'k', 0, 0, 0, 26,
+    0x00, 0xFF, 0x51, 3, 0x07, 0xA1, 0x20,
+    0x60, 0xFF, 0x51, 3, 0x03, 0xD0, 0x90,
+    0x60, 0x90, 60, 64,
+    0x60, 0x80, 60, 0,
+    0x00, 0xFF, 0x2F, 0,
+];
+
+#[test]
+fn strip_silence_across_tempo_change() {
+    let dir = std::env::temp_dir().join(format!("pianoroll-test-strip-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let song = dir.join("song.mid");
+    std::fs::write(&song, TEMPO_CHANGE_BEFORE_NOTES).unwrap();
+    let csv = dir.join("song.csv");
+    let program = ["pianoroll", song.to_str().unwrap(), "0,0", "--strip-silence-start",
+        "--format", "csv", "-o", csv.to_str().unwrap()];
+    let cfg = parse_configuration(program.iter().map(OsString::from)).unwrap();
+    let (result, lines) = logged(|| pipeline::run(cfg, std::io::sink()));
+    result.unwrap();
+    // Half a second for the first beat and a quarter for the second, not two beats at 240.
+    let stripped = "INFO: stripped 192 ticks (0:00.75) of leading silence";
+    assert!(lines.iter().any(|line| line == stripped), "{:?}", lines);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn track_listing_json_with_a_warning() {
+    let dir = std::env::temp_dir().join(format!("pianoroll-test-listing-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    // The header claims a second track that isn't there, which is warned about.
+    let mut song = SONG.to_vec();
+    song[11] = 2;
+    let path = dir.join("song.mid");
+    std::fs::write(&path, song).unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_pianoroll"))
+        .arg(&path)
+        .arg("--list-tracks-json")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("WARNING: header claims 2 tracks, found 1"), "{}", stderr);
+    let listing = serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap();
+    assert_eq!(listing["tracks"][0]["channels"][0]["notes"], 3, "{}", listing);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn every_output_with_an_empty_selector() {
+    let dir = std::env::temp_dir().join(format!("pianoroll-test-empty-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let song = dir.join("song.mid");
+    std::fs::write(&song, SONG).unwrap();
+    let file = |name: &str| dir.join(name).to_str().unwrap().to_owned();
+    let outputs = [
+        ("--export-csound", file("song.sco")),
+        ("--export-sc3", file("song.scd")),
+        ("--export-chuck", file("song.ck")),
+        ("--export-etf", file("song.etf")),
+        ("--export-percussion-map", file("percussion.txt")),
+        ("--catalog-image", file("song.png")),
+        ("--segment-export-json", file("cuts.json")),
+        ("--diagnostics-json", file("diagnostics.json")),
+        ("--fidelity-report", file("fidelity.json")),
+        ("--pitch-usage-csv", file("pitch-usage.csv")),
+        ("--proof", file("proof.pdf")),
+    ];
+    // The song is all piano, so there's nothing for the second selector.
+    let mut args = vec!["pianoroll".to_owned(), song.to_str().unwrap().to_owned(), "0,0".to_owned(),
+        "empty=0,0:prog=Violin".to_owned(), "-o".to_owned(), file("song.pdf"),
+        "--pitch-usage".to_owned()];
+    for (flag, path) in &outputs {
+        args.extend([flag.to_string(), path.clone()]);
+    }
+    let cfg = parse_configuration(args.iter().map(OsString::from)).unwrap();
+    let (result, lines) = logged(|| pipeline::run(cfg, std::io::sink()));
+    result.unwrap();
+    for (flag, path) in &outputs {
+        assert!(std::path::Path::new(path).exists(), "{} wasn't written", flag);
+    }
+    let warning = "WARN: selector empty (0,0+0) contributed 0 notes after filtering: \
+        it matched none";
+    assert!(lines.iter().any(|line| line == warning), "{:?}", lines);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn articulation_of_one_changes_nothing() {
+    let dir = std::env::temp_dir().join(format!("pianoroll-test-artic-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let song = dir.join("song.mid");
+    std::fs::write(&song, SONG).unwrap();
+    let csv = dir.join("song.csv");
+    let dump = |args: &[&str]| {
+        let program = ["pianoroll", song.to_str().unwrap(), "--format", "csv", "-o",
+            csv.to_str().unwrap()];
+        let cfg = parse_configuration(program.iter().chain(args).map(OsString::from)).unwrap();
+        pipeline::run(cfg, std::io::sink()).unwrap();
+        std::fs::read(&csv).unwrap()
+    };
+    let plain = dump(&["0,0"]);
+    assert_eq!(dump(&["0,0*1.0"]), plain);
+    assert_eq!(dump(&["0,0", "--articulation", "1"]), plain);
+    assert_ne!(dump(&["0,0*0.5"]), plain);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn doubled_selectors_keep_their_articulation() {
+    let dir = std::env::temp_dir().join(format!("pianoroll-test-doubled-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let song = dir.join("song.mid");
+    std::fs::write(&song, SONG).unwrap();
+    let csv = dir.join("song.csv");
+    // Both selectors pick the same notes onto the same roll notes, each shortening them its own
+    // way.
+    let program = ["pianoroll", song.to_str().unwrap(), "a=0,0*0.5", "b=0,0*0.25", "--format",
+        "csv", "-o", csv.to_str().unwrap()];
+    let cfg = parse_configuration(program.iter().map(OsString::from)).unwrap();
+    pipeline::run(cfg, std::io::sink()).unwrap();
+    let mut notes = std::fs::read_to_string(&csv).unwrap().lines()
+        .skip(1)
+        .map(|line| {
+            let fields = line.split(',').collect::<Vec<_>>();
+            (fields[4].parse::<u8>().unwrap(), fields[0].parse::<u64>().unwrap(),
+                fields[1].parse::<u64>().unwrap())
+        })
+        .collect::<Vec<_>>();
+    notes.sort_unstable();
+    assert_eq!(notes, [
+        (60, 0, 24), (60, 0, 48),
+        (64, 96, 12), (64, 96, 24),
+        (67, 96, 12), (67, 96, 24),
+    ]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// One track at 96 ticks per beat with a problem of each kind: a D released without being pressed,
+/// an E never released, and a G9, higher than the roll goes.
+const PROBLEMS: &[u8] = &[
+    b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0, 96,
+    b'M', b'T', b'r', b'k', 0, 0, 0, 35,
+    0x00, 0xFF, 0x51, 3, 0x07, 0xA1, 0x20,
+    0x00, 0x90, 60, 64,
+    0x30, 0x80, 62, 0,
+    0x30, 0x80, 60, 0,
+    0x00, 0x90, 64, 64,
+    0x00, 0x90, 127, 64,
+    0x30, 0x80, 127, 0,
+    0x00, 0xFF, 0x2F, 0,
+];
+
+#[test]
+fn diagnostics_json_report() {
+    let dir = std::env::temp_dir().join(format!("pianoroll-test-json-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let song = dir.join("problems.mid");
+    std::fs::write(&song, PROBLEMS).unwrap();
+    let roll = dir.join("problems.pdf");
+    let json = dir.join("diagnostics.json");
+    let args = [song.to_str().unwrap(), "0,0", "-o", roll.to_str().unwrap(),
+        "--diagnostics-json", json.to_str().unwrap(), "--check", "range=error"];
+    let cfg = parse_configuration(std::iter::once("pianoroll").chain(args).map(OsString::from))
+        .unwrap();
+    let exit_code = pipeline::run(cfg, std::io::sink()).unwrap().exit_code();
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // The G9 is out of range when it's pressed and again when it's released.
+    let codes = report["diagnostics"].as_array().unwrap().iter()
+        .map(|diagnostic| {
+            (diagnostic["code"].as_str().unwrap(), diagnostic["tick"].as_u64().unwrap())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(codes,
+        [("not-pressed", 48), ("out-of-range", 96), ("out-of-range", 144), ("stuck-note", 96)]);
+    assert_eq!(report["summary"],
+        serde_json::json!({ "not-pressed": 1, "out-of-range": 2, "stuck-note": 1 }));
+    let levels = report["checks"].as_array().unwrap().iter()
+        .map(|check| (check["category"].as_str().unwrap(), check["level"].as_str().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(levels, [("range", "error"), ("overlap", "warning")]);
+    // Only the range check is an error, so only its bit is set.
+    assert_eq!(exit_code, Category::Range.exit_bit());
+    assert_eq!(report["exit_code"], exit_code);
+}
+
+#[test]
+fn check_failures_set_exit_bits() {
+    let dir = std::env::temp_dir().join(format!("pianoroll-test-exit-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let song = dir.join("problems.mid");
+    std::fs::write(&song, PROBLEMS).unwrap();
+    let roll = dir.join("problems.pdf");
+    let project = dir.join("problems.toml");
+    // The project file makes overlaps errors, and the command line can overrule it.
+    std::fs::write(&project, "[checks]\noverlap = \"error\"\n").unwrap();
+    let exit_code = |args: &[&str]| {
+        let program = ["pianoroll", song.to_str().unwrap(), "0,0", "-o", roll.to_str().unwrap(),
+            "--config", project.to_str().unwrap()];
+        let cfg = parse_configuration(program.iter().chain(args).map(OsString::from)).unwrap();
+        pipeline::run(cfg, std::io::sink()).unwrap().exit_code()
+    };
+
+    let range = Category::Range.exit_bit();
+    let overlap = Category::Overlap.exit_bit();
+    assert_eq!((range, overlap), (8, 4));
+    assert_eq!(exit_code(&[]), overlap);
+    assert_eq!(exit_code(&["--check", "range=error"]), range | overlap);
+    assert_eq!(exit_code(&["--check", "range=error", "--check", "overlap=warning"]), range);
+    assert_eq!(exit_code(&["--check", "overlap=off"]), 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn same_tick_chords_come_out_the_same() {
+    // Two octaves of notes struck together for a beat and then all struck again, from two tracks
+    // with the same notes doubled at different velocities, so the order they come out in depends
+    // on more than their start.
+    let mut chords = vec![];
+    for (timestamp, track, velocity) in [(0, 0, 64), (0, 1, 90), (96, 0, 40), (96, 1, 70)] {
+        for pitch in 48 .. 72 {
+            chords.push(NoteWithDuration { timestamp, duration: 96,
+                note: MidiNote::try_from(pitch).unwrap(), track, channel: 0, velocity,
+                source: None, selector: None });
+        }
+    }
+
+    let written = |name: &str, notes: &[NoteWithDuration]| {
+        let mut notes = notes.to_vec();
+        processing::canonical_order(&mut notes);
+        let path = std::env::temp_dir()
+            .join(format!("pianoroll-test-chords-{}-{}.mid", name, std::process::id()));
+        Midi::write(&path, &[OutputTrack { name: None, channel: 0, notes: &notes }], 96, &[])
+            .unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let notes = notes.iter()
+            .map(|note| (note.timestamp, note.note, note.track, note.velocity))
+            .collect::<Vec<_>>();
+        (notes, bytes)
+    };
+    let ordered = written("ordered", &chords);
+
+    // Shuffle the input a few different ways, with a fixed linear congruential generator so the
+    // test itself is the same every time, and write each one twice.
+    let mut state = 0x2545_f491_u64;
+    for shuffle in 0 .. 4 {
+        let mut shuffled = chords.clone();
+        for i in (1 .. shuffled.len()).rev() {
+            state = state.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            shuffled.swap(i, (state >> 33) as usize % (i + 1));
+        }
+        for run in 0 .. 2 {
+            let name = format!("shuffled-{}-{}", shuffle, run);
+            assert!(written(&name, &shuffled) == ordered, "shuffle {} run {}", shuffle, run);
+        }
+    }
+    let bytes = ordered.1;
+
+    // Reading it back, the releases at beat 1 come before the presses there, and each comes in
+    // order of note.
+    let mut midi = Midi::new();
+    let path = std::env::temp_dir()
+        .join(format!("pianoroll-test-chords-read-{}.mid", std::process::id()));
+    std::fs::write(&path, &bytes).unwrap();
+    let result = midi.read(&path);
+    std::fs::remove_file(&path).unwrap();
+    result.unwrap();
+    let second_beat = midi.notes()
+        .filter(|event| event.timestamp == 96)
+        .map(|event| (event.action == NoteAction::On, event.note.as_u8()))
+        .collect::<Vec<_>>();
+    let mut sorted = second_beat.clone();
+    sorted.sort();
+    assert_eq!(second_beat, sorted);
+    assert_eq!(second_beat.iter().filter(|(on, _)| !on).count(), 48);
+    assert_eq!(second_beat.len(), 96);
+}
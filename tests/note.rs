@@ -0,0 +1,39 @@
+//! Convert notes to and from their names.
+
+use pianoroll::MidiNote;
+use std::convert::TryFrom;
+
+#[test]
+fn note_names() {
+    assert_eq!(MidiNote::try_from("C4"), Ok(MidiNote::C4));
+    assert_eq!(MidiNote::try_from("A4").unwrap().as_u8(), 69);
+    assert_eq!(MidiNote::try_from("Bb3"), Ok(MidiNote::As3));
+    assert_eq!(MidiNote::try_from("F#0"), Ok(MidiNote::Fs0));
+    assert_eq!(MidiNote::try_from("Cb4"), Ok(MidiNote::B3));
+    assert_eq!(MidiNote::try_from("G9"), Ok(MidiNote::G9));
+    for name in &["", "H4", "c4", "C", "C10", "C#", "Cx4", "G#9"] {
+        assert!(MidiNote::try_from(*name).is_err(), "{:?} parsed", name);
+    }
+
+    assert_eq!(MidiNote::Cs4.to_string(), "C#4");
+    assert_eq!(MidiNote::C1n.to_string(), "C-1");
+    for raw in 12 ..= 127 {
+        let note = MidiNote::try_from(raw).unwrap();
+        assert_eq!(MidiNote::try_from(note.to_string().as_str()), Ok(note));
+    }
+    assert!(MidiNote::try_from(128u8).is_err());
+}
+
+#[test]
+fn pitch_class_and_octave() {
+    let place = |note: MidiNote| (note.pitch_class(), note.octave());
+    assert_eq!(place(MidiNote::C4), (0, 4));
+    assert_eq!(place(MidiNote::A4), (9, 4));
+    // The octave number changes between B and C, not A and B.
+    assert_eq!(place(MidiNote::B3), (11, 3));
+    assert_eq!(place(MidiNote::Cs4), (1, 4));
+    assert_eq!(place(MidiNote::C1n), (0, -1));
+    assert_eq!(place(MidiNote::B1n), (11, -1));
+    assert_eq!(place(MidiNote::C0), (0, 0));
+    assert_eq!(place(MidiNote::G9), (7, 9));
+}
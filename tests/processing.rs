@@ -0,0 +1,249 @@
+use pianoroll::analysis;
+use pianoroll::audit::{Audit, NoteState};
+use pianoroll::checks::Thresholds;
+use pianoroll::midi::{NoteAction, NoteEvent, NoteWithDuration};
+use pianoroll::note::MidiNote;
+use pianoroll::processing::{self, append_end_hole, insert_tune_change, PhraseFold, SelectedNote};
+use pianoroll::tempo::{BeatGrid, TempoChange, TempoMap, TimeSignature};
+
+fn note(timestamp: u64, duration: u64, note: MidiNote) -> NoteWithDuration {
+    NoteWithDuration { timestamp, duration, note, track: 0, channel: 0, velocity: 64,
+        source: None, selector: None }
+}
+
+fn starts(notes: &[NoteWithDuration]) -> Vec<(u64, MidiNote)> {
+    notes.iter().map(|note| (note.timestamp, note.note)).collect()
+}
+
+#[test]
+fn tune_change_fits_in_gap() {
+    // The first song ends at 100 and the second starts at 400.
+    let mut notes = vec![note(0, 100, MidiNote::C4), note(400, 50, MidiNote::E4)];
+    assert_eq!(insert_tune_change(&mut notes, 100, MidiNote::C1, 200, 10), (110, 0));
+    assert_eq!(starts(&notes), [(0, MidiNote::C4), (110, MidiNote::C1), (400, MidiNote::E4)]);
+}
+
+#[test]
+fn tune_change_widens_gap() {
+    let mut notes = vec![note(0, 100, MidiNote::C4), note(150, 50, MidiNote::E4)];
+    assert_eq!(insert_tune_change(&mut notes, 100, MidiNote::C1, 200, 10), (110, 170));
+    // The second song starts the clearance after the end of the hole.
+    assert_eq!(starts(&notes), [(0, MidiNote::C4), (110, MidiNote::C1), (320, MidiNote::E4)]);
+}
+
+#[test]
+fn tune_change_at_end() {
+    let mut notes = vec![note(0, 100, MidiNote::C4)];
+    assert_eq!(insert_tune_change(&mut notes, 100, MidiNote::C1, 200, 10), (110, 0));
+    assert_eq!(notes[1].duration, 200);
+}
+
+#[test]
+fn end_holes_after_last_note() {
+    let mut notes = vec![note(0, 100, MidiNote::C4), note(50, 150, MidiNote::E4)];
+    assert_eq!(append_end_hole(&mut notes, 200, MidiNote::C8, 40, 10), 240);
+    assert_eq!(append_end_hole(&mut notes, 200, MidiNote::B7, 0, 10), 200);
+    assert_eq!(starts(&notes),
+        [(0, MidiNote::C4), (50, MidiNote::E4), (200, MidiNote::B7), (240, MidiNote::C8)]);
+}
+
+#[test]
+fn audit_follows_note_through_transforms() {
+    let press = |timestamp, note| NoteEvent { timestamp, track: 1, channel: 2, note,
+        action: NoteAction::On, velocity: 90, segment: 0, program: 0 };
+    let events = [press(10, MidiNote::C4), press(16, MidiNote::E4)];
+    let mut notes = vec![
+        NoteWithDuration { source: Some(0), ..note(10, 40, MidiNote::C4) },
+        NoteWithDuration { source: Some(1), ..note(16, 10, MidiNote::E4) },
+    ];
+    let mut audit = Audit::new(&events.iter().collect::<Vec<_>>(), &notes);
+    audit.apply("quantize", &mut notes, |notes| processing::quantize(notes, 8, false));
+    audit.apply("articulate", &mut notes, |notes| processing::articulate(notes, 1, |_| 0.5));
+    audit.apply("time stretch", &mut notes, |notes| processing::time_stretch(notes, 2.));
+
+    let history = audit.history((0, MidiNote::C4)).unwrap();
+    assert_eq!((history.original.timestamp, history.original.velocity), (10, 90));
+    assert_eq!((history.original.track, history.original.channel), (1, 2));
+    let state = |timestamp, duration| NoteState { timestamp, duration, note: MidiNote::C4 };
+    let chain = history.steps.iter()
+        .map(|step| (step.transform.as_str(), step.before, step.after))
+        .collect::<Vec<_>>();
+    assert_eq!(chain, [
+        ("quantize", state(10, 40), Some(state(8, 40))),
+        ("articulate", state(8, 40), Some(state(8, 20))),
+        ("time stretch", state(8, 20), Some(state(16, 40))),
+    ]);
+    // The other note was already on the grid, so quantizing didn't change it.
+    let other = audit.history((1, MidiNote::E4)).unwrap();
+    assert_eq!(other.steps[0].transform, "articulate");
+}
+
+#[test]
+fn drop_notes_shorter_than_minimum() {
+    // Twice as fast from tick 192, so the same number of ticks takes half as long.
+    let tempo_map = TempoMap::new(96, vec![
+        TempoChange { timestamp: 0, micros_per_beat: 500_000 },
+        TempoChange { timestamp: 192, micros_per_beat: 250_000 },
+    ]);
+    let mut notes = vec![
+        note(0, 3, MidiNote::C4),
+        note(0, 6, MidiNote::E4),
+        note(300, 6, MidiNote::G4),
+        note(300, 12, MidiNote::C5),
+    ];
+    let dropped = processing::drop_short_notes(&mut notes, |note| {
+        tempo_map.micros_at(note.timestamp + note.duration) - tempo_map.micros_at(note.timestamp)
+            < 20_000.
+    });
+    assert_eq!(dropped, 2);
+    assert_eq!(starts(&notes), [(0, MidiNote::E4), (300, MidiNote::C5)]);
+}
+
+#[test]
+fn articulate_stops_at_the_shortest_hole() {
+    // The default shortest hole of 2 points is 8 ticks at a time divisor of 4.
+    let min_hole = (Thresholds::default().min_hole * 4.).ceil() as u64;
+    let mut notes = vec![
+        note(0, 48, MidiNote::C4),
+        note(0, 200, MidiNote::E4),
+        note(96, 5, MidiNote::G4),
+    ];
+    processing::articulate(&mut notes, min_hole, |_| 0.1);
+    let durations = notes.iter().map(|note| note.duration).collect::<Vec<_>>();
+    // A tenth of 48 ticks would be a hole too short to punch, and the short note isn't lengthened.
+    assert_eq!(durations, [8, 20, 5]);
+}
+
+#[test]
+fn quantize_notes_to_grid() {
+    // A sixteenth note grid at 96 ticks per beat.
+    let mut notes = vec![
+        note(3, 50, MidiNote::C4),
+        note(20, 4, MidiNote::E4),
+        note(25, 30, MidiNote::G4),
+        // Runs into the next C5 once that's moved earlier.
+        note(40, 40, MidiNote::C5),
+        note(70, 20, MidiNote::C5),
+    ];
+    assert_eq!(processing::quantize_notes(&mut notes, 24), 0);
+    let lengths = notes.iter()
+        .map(|note| (note.timestamp, note.duration, note.note))
+        .collect::<Vec<_>>();
+    assert_eq!(lengths, [
+        (0, 48, MidiNote::C4),
+        // E4 and G4 land together and stay that way; the short E4 is kept one step long.
+        (24, 24, MidiNote::E4),
+        (24, 24, MidiNote::G4),
+        (48, 24, MidiNote::C5),
+        (72, 24, MidiNote::C5),
+    ]);
+}
+
+#[test]
+fn clamp_to_the_meter() {
+    // A measure of 3/4, and then 6/8, whose beats are dotted quarters: 0, 96, 192, then 288, 432,
+    // and the next barline at 576.
+    let signatures = [
+        TimeSignature { timestamp: 0, numerator: 3, denominator: 4 },
+        TimeSignature { timestamp: 288, numerator: 6, denominator: 8 },
+    ];
+    let grid = BeatGrid::new(&signatures, 96, 1000);
+    let clamp = |timestamp, max_ticks| {
+        let mut notes = [note(timestamp, 400, MidiNote::C4)];
+        let clamped = processing::clamp_durations(&mut notes, max_ticks, &grid);
+        assert_eq!(clamped.len(), usize::from(max_ticks < 400));
+        notes[0].duration
+    };
+    // Right up to the barline.
+    assert_eq!(clamp(300, 276), 276);
+    // Up to 500, mid-beat, so back to the beat before, 432, rather than the quarter note at 480.
+    assert_eq!(clamp(300, 200), 132);
+    // No beat between 440 and 540, so it's cut to exactly the limit.
+    assert_eq!(clamp(440, 100), 100);
+    assert_eq!(clamp(0, 400), 400);
+}
+
+#[test]
+fn repeated_measures_across_meter_change() {
+    // A measure of 3/4, then 2/4 from tick 288. The third measure repeats the second, while the
+    // fourth has the same note half a beat late.
+    let signatures = [
+        TimeSignature { timestamp: 0, numerator: 3, denominator: 4 },
+        TimeSignature { timestamp: 288, numerator: 2, denominator: 4 },
+    ];
+    let grid = BeatGrid::new(&signatures, 96, 864);
+    let notes = [
+        note(0, 96, MidiNote::C4),
+        note(96, 96, MidiNote::E4),
+        note(288, 48, MidiNote::C4),
+        note(480, 48, MidiNote::C4),
+        note(720, 48, MidiNote::C4),
+    ];
+    assert_eq!(analysis::find_repeated_measures(&notes, &grid), [(2, 3)]);
+}
+
+#[test]
+fn rests_between_notes() {
+    // At 96 ticks per beat, marking rests of half a beat or more: C4 has an eighth rest and then a
+    // half rest, E4 a gap too short to mark, and G4 is held over a shorter G4, so its rest only
+    // starts when the longer one ends.
+    let notes = [
+        note(0, 96, MidiNote::C4),
+        note(144, 48, MidiNote::C4),
+        note(384, 96, MidiNote::C4),
+        note(0, 90, MidiNote::E4),
+        note(96, 96, MidiNote::E4),
+        note(0, 200, MidiNote::G4),
+        note(100, 50, MidiNote::G4),
+        note(300, 96, MidiNote::G4),
+    ];
+    let rests = analysis::find_rests(&notes, 48).iter()
+        .map(|rest| (rest.note, rest.timestamp, rest.duration as f64 / 96.))
+        .collect::<Vec<_>>();
+    assert_eq!(rests, [
+        (MidiNote::C4, 96, 0.5),
+        (MidiNote::C4, 192, 2.),
+        (MidiNote::G4, 200, 100. / 96.),
+    ]);
+}
+
+#[test]
+fn fold_a_whole_phrase() {
+    // A descending run of eighth notes that dips two notes below the roll's C1, then after a rest
+    // a phrase that fits, and a phrase too wide to fit any way.
+    let run = [
+        (0, MidiNote::E1), (48, MidiNote::D1), (96, MidiNote::C1), (144, MidiNote::B0),
+        (192, MidiNote::A0),
+        (480, MidiNote::C3), (528, MidiNote::E3),
+        (960, MidiNote::A0), (1008, MidiNote::C8),
+    ];
+    let selected = run.iter()
+        .map(|&(timestamp, note)| SelectedNote { selector: 0, track: 1, channel: 0, timestamp,
+            end: timestamp + 48, note, pitch: i16::from(note.as_i8()) })
+        .collect::<Vec<_>>();
+    let (mut fold, phrases) = PhraseFold::new(selected, 96);
+    let phrases = phrases.iter()
+        .map(|phrase| (phrase.start, phrase.end, phrase.first_note, phrase.notes, phrase.shift))
+        .collect::<Vec<_>>();
+    assert_eq!(phrases, [
+        (0, 240, MidiNote::E1, 5, Some(12)),
+        (960, 1056, MidiNote::A0, 2, None),
+    ]);
+
+    // The whole run moves up an octave together, not just the notes off the roll, and each
+    // release goes with its press.
+    let mut shifts = vec![];
+    for &(timestamp, note) in &run {
+        let press_and_release = [(timestamp, NoteAction::On), (timestamp + 48, NoteAction::Off)];
+        for (timestamp, action) in press_and_release {
+            let event = NoteEvent { timestamp, track: 1, channel: 0, note, action, velocity: 64,
+                segment: 0, program: 0 };
+            shifts.push(fold.shift(0, &event));
+        }
+    }
+    let presses = shifts.iter().step_by(2).copied().collect::<Vec<_>>();
+    assert_eq!(presses, [
+        Some(12), Some(12), Some(12), Some(12), Some(12), Some(0), Some(0), None, None,
+    ]);
+    assert!(shifts.chunks(2).all(|pair| pair[0] == pair[1]), "{:?}", shifts);
+}
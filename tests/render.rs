@@ -0,0 +1,185 @@
+//! Draw a roll through the library into memory.
+
+use pianoroll::export::segments;
+use pianoroll::tempo::BeatGrid;
+use pianoroll::{checks, geometry, render};
+use pianoroll::{parse_configuration, render_pdf, MidiNote, NoteWithDuration};
+use std::convert::TryFrom;
+use std::ffi::OsString;
+
+fn chord() -> Vec<NoteWithDuration> {
+    [60, 64, 67].iter()
+        .map(|&note| NoteWithDuration {
+            timestamp: 0,
+            duration: 96,
+            note: MidiNote::try_from(note).unwrap(),
+            track: 0,
+            channel: 0,
+            velocity: 64,
+            source: None,
+            selector: None,
+        })
+        .collect()
+}
+
+/// Whether the PDF sets any fill or stroke color other than a gray.
+fn has_color(pdf: &[u8]) -> bool {
+    String::from_utf8_lossy(pdf).lines().any(|line| line.ends_with(" sc") || line.ends_with(" SC"))
+}
+
+#[test]
+fn render_pdf_to_memory() {
+    let cfg = parse_configuration(["pianoroll", "song.mid", "0,0"].iter().map(OsString::from))
+        .unwrap();
+    let notes = chord();
+    let mut pdf = vec![];
+    render_pdf(&notes, &[], 96, 100., None, &cfg, &mut pdf).unwrap();
+    assert!(pdf.starts_with(b"%PDF-"));
+    assert!(pdf.ends_with(b"%%EOF\n"), "PDF isn't finished");
+}
+
+#[test]
+fn proof_alongside_roll() {
+    let cfg = parse_configuration(["pianoroll", "song.mid", "0,0", "--proof", "proof.pdf"].iter()
+        .map(OsString::from))
+        .unwrap();
+    let proof = cfg.proof.as_deref().expect("no proof configuration");
+    let notes = chord();
+    let mut pdf = vec![];
+    render_pdf(&notes, &[], 96, 100., None, &cfg, &mut pdf).unwrap();
+    let mut proof_pdf = vec![];
+    render_pdf(&notes, &[], 96, 100., None, proof, &mut proof_pdf).unwrap();
+    assert!(!has_color(&pdf), "roll to be punched has colors");
+    assert!(has_color(&proof_pdf), "proof has no colors");
+}
+
+#[test]
+fn title_leader() {
+    let cfg = parse_configuration(["pianoroll", "song.mid", "0,0", "--composer", "Paul Desmond"]
+        .iter().map(OsString::from))
+        .unwrap();
+    assert_eq!(cfg.roll_label.leader_length, 12. * 72.);
+    let mut pdf = vec![];
+    render_pdf(&chord(), &[], 96, 100., Some("Take Five"), &cfg, &mut pdf).unwrap();
+    let pdf = String::from_utf8_lossy(&pdf);
+    for text in ["(Take Five)", "(Paul Desmond)", "(Tempo 100)"] {
+        assert!(pdf.contains(text), "leader is missing {}", text);
+    }
+}
+
+#[test]
+fn compare_holes_finds_a_corrupted_hole() {
+    let cfg = parse_configuration(["pianoroll", "song.mid", "0,0"].iter().map(OsString::from))
+        .unwrap();
+    let holes = render::hole_spans(&chord(), &cfg);
+    assert_eq!(holes.len(), 3);
+    let tick = 1. / cfg.time_divisor;
+    let tolerance = 1.01 * tick;
+    let mut read_back = holes.clone();
+    read_back.reverse();
+    read_back[0].1 += tick;
+    checks::compare_holes(&holes, &read_back, tolerance).unwrap();
+
+    // Two ticks late.
+    read_back[0].1 += tick;
+    let e = checks::compare_holes(&holes, &read_back, tolerance).unwrap_err();
+    assert!(e.starts_with("expected a hole in channel"), "{}", e);
+
+    let mut read_back = holes.clone();
+    read_back[1].0 += 1;
+    assert!(checks::compare_holes(&holes, &read_back, tolerance).is_err());
+    assert_eq!(checks::compare_holes(&holes, &holes[1 ..], tolerance).unwrap_err(),
+        "expected 3 holes but found 2");
+}
+
+#[test]
+fn hole_positions_past_u32() {
+    // Far enough along that an f32 couldn't tell the start of a note from its end.
+    let cfg = parse_configuration(["pianoroll", "song.mid", "0,0"].iter().map(OsString::from))
+        .unwrap();
+    let start = (1 << 32) + 5;
+    let notes = [NoteWithDuration { timestamp: start, duration: 3, note: MidiNote::C4, track: 0,
+        channel: 0, velocity: 64, source: None, selector: None }];
+    let holes = render::hole_spans(&notes, &cfg);
+    let (_, top, bottom) = holes[0];
+    assert_eq!(bottom - top, 3. / cfg.time_divisor);
+    assert_eq!((bottom as f32 - top as f32), 0.);
+}
+
+#[test]
+fn second_row_offset() {
+    let spans = |args: &[&str]| {
+        let program = ["pianoroll", "song.mid", "0,0"];
+        let cfg = parse_configuration(program.iter().chain(args).map(OsString::from)).unwrap();
+        let note = |note| NoteWithDuration { timestamp: 96, duration: 48, note, track: 0,
+            channel: 0, velocity: 64, source: None, selector: None };
+        let notes = [note(MidiNote::C4), note(MidiNote::Cs4), note(MidiNote::D4)];
+        let holes = render::hole_spans(&notes, &cfg);
+        assert_eq!(holes.iter().map(|hole| hole.0).collect::<Vec<_>>(), [44, 45, 46]);
+        holes
+    };
+    let level = spans(&[]);
+    assert!(level.windows(2).all(|pair| (pair[0].1, pair[0].2) == (pair[1].1, pair[1].2)));
+
+    // A quarter of an inch is 18 points, for the odd channel only.
+    let staggered = spans(&["--row-offset", "0.25"]);
+    assert_eq!(staggered[1].1 - staggered[0].1, 18.);
+    assert_eq!(staggered[1].2 - staggered[0].2, 18.);
+    assert_eq!((staggered[0].1, staggered[0].2), (staggered[2].1, staggered[2].2));
+    assert_eq!((staggered[0].1, staggered[0].2), (level[0].1, level[0].2));
+}
+
+#[test]
+fn editor_note_beside_its_measure() {
+    let cfg = parse_configuration(["pianoroll", "song.mid", "0,0", "--note", "m2: bass muddy"]
+        .iter().map(OsString::from))
+        .unwrap();
+    let notes = [NoteWithDuration { timestamp: 0, duration: 768, note: MidiNote::C4, track: 0,
+        channel: 0, velocity: 64, source: None, selector: None }];
+    let grid = BeatGrid::new(&[], 96, 768);
+    let mut pdf = vec![];
+    render_pdf(&notes, grid.beats(), 96, 100., None, &cfg, &mut pdf).unwrap();
+    let pdf = String::from_utf8_lossy(&pdf);
+
+    // Measure 2 of 4/4 starts at tick 384; the note sits just above it, in the channels left of
+    // the music.
+    let y = f64::from(cfg.roll_label.leader_length) + 384. / cfg.time_divisor + 1.;
+    let placed = format!("{} {} Td\n(m2: bass muddy) Tj", geometry::PAGE_MARGIN + 2., y as f32);
+    assert!(pdf.contains(&placed), "no {:?} in the PDF", placed);
+}
+
+#[test]
+fn editor_notes_only_in_pdf() {
+    let dir = std::env::temp_dir()
+        .join(format!("pianoroll-test-editor-notes-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let notes = chord();
+    let grid = BeatGrid::new(&[], 96, 96);
+    let configure = |output: &str, args: &[&str]| {
+        let program = ["pianoroll", "song.mid", "0,0", "--note", "m1: bass muddy", "-o"];
+        let output = dir.join(output);
+        let cfg = program.iter().map(OsString::from)
+            .chain(std::iter::once(output.clone().into_os_string()))
+            .chain(args.iter().map(OsString::from));
+        (parse_configuration(cfg).unwrap(), output)
+    };
+
+    let (cfg, pdf) = configure("roll.pdf", &[]);
+    render::render(&notes, grid.beats(), 96, 100., None, &cfg).unwrap();
+    let (cfg, svg) = configure("roll.svg", &["--format", "svg"]);
+    render::render(&notes, grid.beats(), 96, 100., None, &cfg).unwrap();
+    let (cfg, tiles) = configure("tiles.pdf", &["--tile", "letter"]);
+    render::render(&notes, grid.beats(), 96, 100., None, &cfg).unwrap();
+    let read = |path| String::from_utf8_lossy(&std::fs::read(path).unwrap()).into_owned();
+    assert!(read(&pdf).contains("(m1: bass muddy)"));
+    assert!(!read(&svg).contains("bass muddy"), "editor's note in the SVG");
+    assert!(!read(&tiles).contains("bass muddy"), "editor's note on the tiles");
+
+    // Nothing but the notes and the label's holes goes to the punch.
+    let holes = segments::holes(&notes, cfg.time_divisor, cfg.row_offset, &cfg.roll_label);
+    assert_eq!(holes.len(), render::hole_spans(&notes, &cfg).len());
+    let path = dir.join("segments.json");
+    segments::write_json(&path, &holes).unwrap();
+    assert!(!read(&path).contains("bass muddy"), "editor's note in the segments");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,27 @@
+use pianoroll::export::segments::{holes, punching_time};
+use pianoroll::label::Label;
+use pianoroll::{MidiNote, NoteWithDuration};
+
+#[test]
+fn punching_time_follows_hole_order() {
+    let note = |timestamp, note| NoteWithDuration { timestamp, duration: 36, note, track: 0,
+        channel: 0, velocity: 64, source: None, selector: None };
+    // At a time divisor of 1, each tick is a point, so the holes start 0, 1, and 2 in along.
+    let notes = [note(0, MidiNote::C4), note(144, MidiNote::E4), note(72, MidiNote::G4)];
+    let label = Label { punches: vec![], leader_length: 0., printed_length: 0. };
+    let mut holes = holes(&notes, 1., 0., &label);
+    let inches = holes.iter().map(|hole| (hole.y_start_mm / 25.4 * 1e6).round() / 1e6);
+    assert_eq!(inches.collect::<Vec<_>>(), [0., 1., 2.]);
+    let close = |a: f64, b: f64| (a - b).abs() < 1e-6;
+
+    // 3 punches at 2 a second, and 2 in of feed at half an inch a second.
+    let time = punching_time(&holes, 2., 0.5);
+    assert_eq!(time.punches, 3);
+    assert!(close(time.feed_inches, 2.) && close(time.seconds, 5.5), "{:?}", time);
+
+    // Out of order, the paper has to go 2 in forward and then 1 in back.
+    holes.swap(1, 2);
+    let time = punching_time(&holes, 2., 0.5);
+    assert_eq!(time.punches, 3);
+    assert!(close(time.feed_inches, 3.) && close(time.seconds, 7.5), "{:?}", time);
+}
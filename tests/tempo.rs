@@ -0,0 +1,61 @@
+use pianoroll::formatting;
+use pianoroll::tempo::{self, TempoChange, TempoMap, TimeSignature};
+
+fn meter(numerator: u8, denominator: u8) -> TimeSignature {
+    TimeSignature { timestamp: 0, numerator, denominator }
+}
+
+#[test]
+fn beat_units() {
+    // A dotted quarter in 6/8, and a half note in 3/2 and 2/2.
+    assert_eq!(meter(6, 8).beat_ticks(480), 720);
+    assert_eq!(meter(6, 8).measure_ticks(480), 1440);
+    assert_eq!(meter(3, 2).beat_ticks(480), 960);
+    assert_eq!(meter(2, 2).beats_per_minute(500_000), 60.);
+    assert_eq!(meter(3, 4).beats_per_minute(500_000), 120.);
+}
+
+#[test]
+fn tempo_in_beat_units() {
+    assert_eq!(formatting::tempo(500_000, &meter(6, 8)), "♩=120, ♩.=80 in 6/8");
+    assert_eq!(formatting::tempo(500_000, &meter(3, 2)), "♩=120, 𝅗𝅥=60 in 3/2");
+    assert_eq!(formatting::tempo(400_000, &meter(2, 2)), "♩=150, 𝅗𝅥=75 in 2/2");
+    assert_eq!(formatting::tempo(500_000, &meter(12, 16)), "♩=120, ♪.=160 in 12/16");
+    assert_eq!(formatting::tempo(700_000, &meter(4, 4)), "♩=85.7 in 4/4");
+}
+
+#[test]
+fn ticks_from_real_time() {
+    // Half a second a beat, then a second a beat from the second beat.
+    let tempo_map = TempoMap::new(96, vec![
+        TempoChange { timestamp: 0, micros_per_beat: 500_000 },
+        TempoChange { timestamp: 96, micros_per_beat: 1_000_000 },
+    ]);
+    for &(timestamp, micros) in &[(0, 0.), (48, 250_000.), (96, 500_000.), (144, 1_000_000.)] {
+        assert_eq!(tempo_map.micros_at(timestamp), micros);
+        assert_eq!(tempo_map.timestamp_at(micros), timestamp);
+    }
+    assert_eq!(tempo_map.timestamp_at(1_004_000.), 144);
+    assert_eq!(tempo_map.timestamp_at(1_006_000.), 145);
+}
+
+#[test]
+fn dial_calibrations() {
+    let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+    // Tempo 70 is 7 feet a minute: 100.8 points a second.
+    let seventy = tempo::dial_setting(96_000_000. / 100.8, 96, 1.);
+    assert!(close(seventy, 70.), "{}", seventy);
+    // At 96 ticks a beat and 120 beats a minute, two ticks to a point is 96 points a second.
+    let setting = tempo::dial_setting(500_000., 96, 2.);
+    assert!(close(setting, 200. / 3.), "{}", setting);
+    assert_eq!(tempo::round_dial(setting), 65.);
+    // Twice as many ticks a beat, spread twice as far, comes to the same.
+    assert!(close(tempo::dial_setting(500_000., 192, 4.), setting));
+    // 480 ticks a beat at 100 beats a minute, 8 ticks to a point: 100 points a second.
+    let setting = tempo::dial_setting(600_000., 480, 8.);
+    assert!(close(setting, 6250. / 90.), "{}", setting);
+    assert_eq!(tempo::round_dial(setting), 70.);
+
+    assert_eq!(tempo::round_dial(67.5), 70.);
+    assert_eq!(tempo::round_dial(1.), tempo::DIAL_STEP);
+}